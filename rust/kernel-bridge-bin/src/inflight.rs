@@ -0,0 +1,93 @@
+use std::sync::{Arc, Mutex};
+
+use finger_kernel_protocol::{Event, EventMsg, Op, Submission};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Caps how many user turns the bridge has handed to the kernel without
+/// seeing them finish, so stdin is only read as fast as turns complete.
+///
+/// Other ops (interrupts, approvals, shutdown) never wait: they are what
+/// lets a blocked turn make progress.
+#[derive(Clone)]
+pub struct InflightLimiter {
+    permits: Arc<Semaphore>,
+    default_session_id: String,
+    pending: Arc<Mutex<Vec<PendingTurn>>>,
+}
+
+struct PendingTurn {
+    submission_id: String,
+    session_key: String,
+    started: bool,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl InflightLimiter {
+    /// `default_session_id` must match the runtime's `KernelConfig`, since
+    /// turns without a session id join the task running under it.
+    pub fn new(max_inflight: usize, default_session_id: impl Into<String>) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(max_inflight)),
+            default_session_id: default_session_id.into(),
+            pending: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Waits for a free slot when `submission` is a user turn.
+    pub async fn acquire(&self, submission: &Submission) {
+        let Op::UserTurn { options, .. } = &submission.op else {
+            return;
+        };
+        let permit = Arc::clone(&self.permits)
+            .acquire_owned()
+            .await
+            .expect("inflight semaphore is never closed");
+        let session_key = options
+            .session_id
+            .clone()
+            .unwrap_or_else(|| self.default_session_id.clone());
+        self.lock_pending().push(PendingTurn {
+            submission_id: submission.id.clone(),
+            session_key,
+            started: false,
+            _permit: permit,
+        });
+    }
+
+    /// Frees the slots of turns that `event` reports as finished.
+    pub fn acknowledge(&self, event: &Event) {
+        let mut pending = self.lock_pending();
+        match &event.msg {
+            EventMsg::TaskStarted(_) => {
+                if let Some(turn) = pending
+                    .iter_mut()
+                    .find(|turn| turn.submission_id == event.id)
+                {
+                    turn.started = true;
+                }
+            }
+            EventMsg::TaskComplete(_) | EventMsg::TurnAborted(_) => {
+                let Some(session_key) = pending
+                    .iter()
+                    .find(|turn| turn.submission_id == event.id)
+                    .map(|turn| turn.session_key.clone())
+                else {
+                    return;
+                };
+                // Follow-ups injected into the finished task never start a
+                // task of their own, so they finish along with it.
+                pending.retain(|turn| {
+                    turn.submission_id != event.id
+                        && (turn.started || turn.session_key != session_key)
+                });
+            }
+            _ => {}
+        }
+    }
+
+    fn lock_pending(&self) -> std::sync::MutexGuard<'_, Vec<PendingTurn>> {
+        self.pending
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
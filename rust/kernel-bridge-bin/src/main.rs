@@ -1,21 +1,55 @@
+mod inflight;
+mod socket;
+
 use std::io;
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use finger_kernel_config::load_local_model_config;
 use finger_kernel_core::{ChatEngine as ChatEngineTrait, EchoChatEngine, KernelConfig, KernelRuntime};
-use finger_kernel_model::FingerChatEngine;
+use finger_kernel_model::{ClientOptions, FingerChatEngine};
 use finger_kernel_protocol::{EventMsg, Submission};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use inflight::InflightLimiter;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::mpsc;
+
+const MAX_INFLIGHT_FLAG: &str = "--max-inflight";
+const LISTEN_FLAG: &str = "--listen";
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct BridgeArgs {
+    max_inflight: Option<usize>,
+    /// Serve clients on this Unix socket instead of stdin/stdout.
+    listen: Option<PathBuf>,
+}
 
 #[tokio::main]
 async fn main() -> io::Result<()> {
+    let args = parse_args(std::env::args().skip(1))
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+
     let chat_engine: Arc<dyn ChatEngineTrait> = match load_local_model_config() {
         Ok(model_config) => {
             eprintln!(
                 "wire_api: {:?}, model: {}, base_url: {}",
                 model_config.wire_api, model_config.model, model_config.base_url
             );
-            Arc::new(FingerChatEngine::new(model_config))
+            let engine =
+                FingerChatEngine::with_client_options(model_config, ClientOptions::default())
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+            let engine = Arc::new(engine);
+            // Logged in the background so a slow provider does not delay startup.
+            let probe = Arc::clone(&engine);
+            tokio::spawn(async move {
+                match probe.check_health().await {
+                    Ok(health) => eprintln!(
+                        "provider health: {:?}, status: {:?}, latency_ms: {}",
+                        health.state, health.status, health.latency_ms
+                    ),
+                    Err(err) => eprintln!("provider health check failed: {err}"),
+                }
+            });
+            engine
         }
         Err(err) => {
             let _ = tokio::io::stderr()
@@ -25,32 +59,33 @@ async fn main() -> io::Result<()> {
         }
     };
 
-    let mut runtime = KernelRuntime::spawn_with_engine(KernelConfig::default(), chat_engine);
-    let submission_tx = runtime.submission_sender();
-
-    let stdin_task = tokio::spawn(async move {
-        let mut lines = BufReader::new(tokio::io::stdin()).lines();
-        while let Some(line) = lines.next_line().await? {
-            if line.trim().is_empty() {
-                continue;
-            }
+    let kernel_config = KernelConfig::default();
+    let limiter = args
+        .max_inflight
+        .map(|max_inflight| InflightLimiter::new(max_inflight, kernel_config.session_id.clone()));
+    let mut runtime = KernelRuntime::spawn_with_engine(kernel_config, chat_engine);
+    if let Some(path) = args.listen.as_deref() {
+        return socket::serve(path, runtime, args.max_inflight, async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await;
+    }
 
-            let submission = match serde_json::from_str::<Submission>(&line) {
-                Ok(item) => item,
-                Err(err) => {
-                    let _ = tokio::io::stderr()
-                        .write_all(format!("invalid submission json: {err}\n").as_bytes())
-                        .await;
-                    continue;
-                }
-            };
+    let submission_tx = runtime
+        .submission_sender()
+        .map_err(|err| io::Error::other(err.to_string()))?;
+    // The stdin task owns the only sender, so EOF closes the channel and the
+    // runtime drains in-flight turns before emitting `ShutdownComplete`.
+    runtime.close_submissions();
 
-            if submission_tx.send(submission).await.is_err() {
-                break;
-            }
-        }
-
-        Ok::<(), io::Error>(())
+    let stdin_limiter = limiter.clone();
+    let stdin_task = tokio::spawn(async move {
+        pump_submissions(
+            BufReader::new(tokio::io::stdin()),
+            submission_tx,
+            stdin_limiter,
+        )
+        .await
     });
 
     let mut stdout = tokio::io::stdout();
@@ -60,6 +95,9 @@ async fn main() -> io::Result<()> {
         stdout.write_all(line.as_bytes()).await?;
         stdout.write_all(b"\n").await?;
         stdout.flush().await?;
+        if let Some(limiter) = limiter.as_ref() {
+            limiter.acknowledge(&event);
+        }
 
         if matches!(event.msg, EventMsg::ShutdownComplete) {
             break;
@@ -78,3 +116,155 @@ async fn main() -> io::Result<()> {
 
     Ok(())
 }
+
+/// Forwards one submission per non-empty stdin line. With a limiter, a user
+/// turn is only read once an earlier one has finished.
+async fn pump_submissions<R>(
+    reader: R,
+    submission_tx: mpsc::Sender<Submission>,
+    limiter: Option<InflightLimiter>,
+) -> io::Result<()>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let mut lines = reader.lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let submission = match serde_json::from_str::<Submission>(&line) {
+            Ok(item) => item,
+            Err(err) => {
+                let _ = tokio::io::stderr()
+                    .write_all(format!("invalid submission json: {err}\n").as_bytes())
+                    .await;
+                continue;
+            }
+        };
+
+        if let Some(limiter) = limiter.as_ref() {
+            limiter.acquire(&submission).await;
+        }
+        if submission_tx.send(submission).await.is_err() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<BridgeArgs, String> {
+    let mut args = args.into_iter();
+    let mut parsed = BridgeArgs::default();
+    while let Some(arg) = args.next() {
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+            None => (arg, None),
+        };
+        if flag != MAX_INFLIGHT_FLAG && flag != LISTEN_FLAG {
+            return Err(format!("unknown argument: {flag}"));
+        }
+        let value = inline_value
+            .or_else(|| args.next())
+            .ok_or_else(|| format!("{flag} requires a value"))?;
+        if flag == LISTEN_FLAG {
+            parsed.listen = Some(PathBuf::from(value));
+            continue;
+        }
+        parsed.max_inflight = match value.parse::<usize>() {
+            Ok(limit) if limit > 0 => Some(limit),
+            _ => {
+                return Err(format!(
+                    "{MAX_INFLIGHT_FLAG} must be a positive integer, got {value:?}"
+                ))
+            }
+        };
+    }
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use finger_kernel_protocol::{Event, TaskCompleteEvent, TaskStartedEvent};
+    use std::time::Duration;
+
+    fn turn_line(id: &str) -> String {
+        format!(
+            r#"{{"id":"{id}","op":{{"type":"user_turn","items":[{{"type":"text","text":"hi"}}],"options":{{"session_id":"{id}"}}}}}}"#
+        )
+    }
+
+    #[test]
+    fn flags_accept_separate_and_inline_values() {
+        let args = |items: &[&str]| items.iter().map(ToString::to_string).collect::<Vec<_>>();
+        assert_eq!(parse_args(args(&[])), Ok(BridgeArgs::default()));
+        assert_eq!(
+            parse_args(args(&["--max-inflight", "4", "--listen=/tmp/finger.sock"])),
+            Ok(BridgeArgs {
+                max_inflight: Some(4),
+                listen: Some(PathBuf::from("/tmp/finger.sock")),
+            })
+        );
+        assert_eq!(
+            parse_args(args(&["--max-inflight=2"])).map(|parsed| parsed.max_inflight),
+            Ok(Some(2))
+        );
+        assert!(parse_args(args(&["--max-inflight", "0"])).is_err());
+        assert!(parse_args(args(&["--listen"])).is_err());
+        assert!(parse_args(args(&["--verbose"])).is_err());
+    }
+
+    #[tokio::test]
+    async fn stdin_reads_pause_until_inflight_turns_finish() {
+        let input = ["sub-1", "sub-2", "sub-3"]
+            .iter()
+            .map(|id| turn_line(id) + "\n")
+            .collect::<String>();
+        let (submission_tx, mut submission_rx) = mpsc::channel(16);
+        let limiter = InflightLimiter::new(2, "finger-kernel");
+        let pump = tokio::spawn(pump_submissions(
+            BufReader::new(std::io::Cursor::new(input.into_bytes())),
+            submission_tx,
+            Some(limiter.clone()),
+        ));
+
+        for expected in ["sub-1", "sub-2"] {
+            let submission = tokio::time::timeout(Duration::from_secs(1), submission_rx.recv())
+                .await
+                .expect("submission within cap")
+                .expect("channel open");
+            assert_eq!(submission.id, expected);
+        }
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), submission_rx.recv())
+                .await
+                .is_err(),
+            "third turn must wait for a free slot"
+        );
+
+        limiter.acknowledge(&Event {
+            id: "sub-1".to_string(),
+            seq: 1,
+            msg: EventMsg::TaskStarted(TaskStartedEvent {
+                model_context_window: None,
+            }),
+        });
+        limiter.acknowledge(&Event {
+            id: "sub-1".to_string(),
+            seq: 2,
+            msg: EventMsg::TaskComplete(TaskCompleteEvent {
+                last_agent_message: None,
+                metadata_json: None,
+            }),
+        });
+
+        let third = tokio::time::timeout(Duration::from_secs(1), submission_rx.recv())
+            .await
+            .expect("third turn after acknowledgement")
+            .expect("channel open");
+        assert_eq!(third.id, "sub-3");
+        pump.await.expect("join pump").expect("pump result");
+    }
+}
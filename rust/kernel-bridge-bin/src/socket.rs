@@ -0,0 +1,530 @@
+//! Serves the bridge's line-delimited JSON protocol over a Unix domain
+//! socket, so clients can connect and disconnect from a long-lived kernel.
+//!
+//! Every connection runs as its own kernel session: user turns are pinned to
+//! the connection's session id and submission ids are prefixed with the
+//! connection id, which is how events find their way back. Approvals are
+//! only accepted for calls whose request went to the same connection.
+
+use std::collections::HashMap;
+use std::fs;
+use std::future::Future;
+use std::io;
+use std::os::unix::fs::FileTypeExt;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use finger_kernel_core::KernelRuntime;
+use finger_kernel_protocol::{
+    ApprovalKind, ApprovalRequestEvent, ErrorEvent, Event, EventMsg, Op, SessionConfiguredEvent,
+    Submission,
+};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{mpsc, watch};
+
+use crate::inflight::InflightLimiter;
+
+type Connections = Arc<Mutex<HashMap<String, Connection>>>;
+
+struct Connection {
+    events_tx: mpsc::UnboundedSender<Event>,
+    limiter: Option<InflightLimiter>,
+    /// Approval requests sent to this connection and not yet answered.
+    pending_approvals: HashMap<String, ApprovalKind>,
+}
+
+/// Accepts connections on `path` until `shutdown` resolves, then stops
+/// accepting, removes the socket file and lets in-flight turns finish before
+/// every client receives `ShutdownComplete`.
+pub async fn serve(
+    path: &Path,
+    mut runtime: KernelRuntime,
+    max_inflight: Option<usize>,
+    shutdown: impl Future<Output = ()>,
+) -> io::Result<()> {
+    remove_stale_socket(path).await?;
+    let listener = UnixListener::bind(path)?;
+
+    let submission_tx = runtime
+        .submission_sender()
+        .map_err(|err| io::Error::other(err.to_string()))?;
+    runtime.close_submissions();
+    let connections: Connections = Arc::default();
+    let (stop_tx, stop_rx) = watch::channel(false);
+    let dispatcher = tokio::spawn(dispatch_events(runtime, Arc::clone(&connections)));
+
+    tokio::pin!(shutdown);
+    let mut connection_count = 0_u64;
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => break,
+            accepted = listener.accept() => {
+                let stream = match accepted {
+                    Ok((stream, _)) => stream,
+                    Err(err) => {
+                        eprintln!("failed to accept bridge connection: {err}");
+                        continue;
+                    }
+                };
+                connection_count += 1;
+                tokio::spawn(serve_connection(
+                    stream,
+                    format!("conn-{connection_count}"),
+                    submission_tx.clone(),
+                    Arc::clone(&connections),
+                    max_inflight,
+                    stop_rx.clone(),
+                ));
+            }
+        }
+    }
+
+    drop(listener);
+    let _ = fs::remove_file(path);
+    // Readers drop their senders on stop; once all are gone the runtime
+    // drains and emits `ShutdownComplete`.
+    let _ = stop_tx.send(true);
+    drop(submission_tx);
+
+    dispatcher
+        .await
+        .map_err(|err| io::Error::other(err.to_string()))?
+}
+
+/// Removes a socket file left behind by an earlier run, which would make bind
+/// fail. Anything else at `path` (a regular file, or a socket another bridge
+/// is still listening on) is reported as in use rather than deleted.
+async fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err),
+    };
+    let in_use = |reason: &str| {
+        io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("{}: {reason}", path.display()),
+        )
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(in_use("exists and is not a socket"));
+    }
+    match UnixStream::connect(path).await {
+        Ok(_) => Err(in_use("another process is listening on it")),
+        Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => fs::remove_file(path),
+        Err(err) => Err(err),
+    }
+}
+
+async fn serve_connection(
+    stream: UnixStream,
+    connection_id: String,
+    submission_tx: mpsc::Sender<Submission>,
+    connections: Connections,
+    max_inflight: Option<usize>,
+    mut stop_rx: watch::Receiver<bool>,
+) {
+    let (reader, mut writer) = stream.into_split();
+    let (events_tx, mut events_rx) = mpsc::unbounded_channel::<Event>();
+    let limiter =
+        max_inflight.map(|max_inflight| InflightLimiter::new(max_inflight, connection_id.clone()));
+    // Not emitted by the kernel, so it takes no place in its sequence.
+    let _ = events_tx.send(Event {
+        id: "session".to_string(),
+        seq: 0,
+        msg: EventMsg::SessionConfigured(SessionConfiguredEvent {
+            session_id: connection_id.clone(),
+        }),
+    });
+    lock_connections(&connections).insert(
+        connection_id.clone(),
+        Connection {
+            events_tx,
+            limiter: limiter.clone(),
+            pending_approvals: HashMap::new(),
+        },
+    );
+
+    // Ends once the connection is unregistered, which drops its sender.
+    let writer_task = tokio::spawn(async move {
+        while let Some(event) = events_rx.recv().await {
+            let Ok(line) = serde_json::to_string(&event) else {
+                continue;
+            };
+            if writer.write_all(line.as_bytes()).await.is_err()
+                || writer.write_all(b"\n").await.is_err()
+                || writer.flush().await.is_err()
+            {
+                break;
+            }
+        }
+    });
+
+    let mut lines = BufReader::new(reader).lines();
+    let mut stopping = false;
+    loop {
+        let line = tokio::select! {
+            line = lines.next_line() => line,
+            _ = stop_rx.changed() => {
+                stopping = true;
+                break;
+            }
+        };
+        let Ok(Some(line)) = line else {
+            break;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let submission = match serde_json::from_str::<Submission>(&line) {
+            Ok(item) => item,
+            Err(err) => {
+                eprintln!("invalid submission json on {connection_id}: {err}");
+                continue;
+            }
+        };
+        if !claim_approval(&connections, &connection_id, &submission) {
+            continue;
+        }
+        let Some(submission) = scope_submission(submission, &connection_id) else {
+            break;
+        };
+
+        if let Some(limiter) = limiter.as_ref() {
+            limiter.acquire(&submission).await;
+        }
+        if submission_tx.send(submission).await.is_err() {
+            break;
+        }
+    }
+
+    if !stopping {
+        // The client hung up, so nobody is left to read this session's turn.
+        lock_connections(&connections).remove(&connection_id);
+        let _ = submission_tx
+            .send(Submission {
+                id: format!("{connection_id}:disconnect"),
+                op: Op::Interrupt {
+                    target: Some(connection_id.clone()),
+                },
+            })
+            .await;
+    }
+    drop(submission_tx);
+    let _ = writer_task.await;
+}
+
+/// Pins a client's submission to its connection's session. `Shutdown` only
+/// closes the connection; it must not stop the kernel other clients share.
+fn scope_submission(mut submission: Submission, connection_id: &str) -> Option<Submission> {
+    match &mut submission.op {
+        Op::UserTurn { options, .. } => options.session_id = Some(connection_id.to_string()),
+        Op::Interrupt { target } => *target = Some(connection_id.to_string()),
+        Op::Shutdown => return None,
+        Op::ExecApproval { .. } | Op::PatchApproval { .. } => {}
+    }
+    submission.id = format!("{connection_id}:{}", submission.id);
+    Some(submission)
+}
+
+/// Lets an approval through only when it answers a call this connection was
+/// asked about; anything else gets the kernel's unknown-call error, so one
+/// client cannot decide (or probe for) another client's calls.
+fn claim_approval(connections: &Connections, connection_id: &str, submission: &Submission) -> bool {
+    let (call_id, kind) = match &submission.op {
+        Op::ExecApproval { id, .. } => (id, ApprovalKind::Exec),
+        Op::PatchApproval { id, .. } => (id, ApprovalKind::Patch),
+        _ => return true,
+    };
+    let mut connections = lock_connections(connections);
+    let Some(connection) = connections.get_mut(connection_id) else {
+        return false;
+    };
+    if connection.pending_approvals.get(call_id) == Some(&kind) {
+        connection.pending_approvals.remove(call_id);
+        return true;
+    }
+    let _ = connection.events_tx.send(Event {
+        id: submission.id.clone(),
+        seq: 0,
+        msg: EventMsg::Error(ErrorEvent {
+            message: format!("no pending approval for call id {call_id}"),
+        }),
+    });
+    false
+}
+
+async fn dispatch_events(mut runtime: KernelRuntime, connections: Connections) -> io::Result<()> {
+    while let Some(event) = runtime.events_mut().recv().await {
+        if matches!(event.msg, EventMsg::ShutdownComplete) {
+            for (_, connection) in lock_connections(&connections).drain() {
+                let _ = connection.events_tx.send(event.clone());
+            }
+            break;
+        }
+
+        let Some((connection_id, id)) = event.id.split_once(':') else {
+            continue;
+        };
+        let mut connections = lock_connections(&connections);
+        let Some(connection) = connections.get_mut(connection_id) else {
+            continue;
+        };
+        if let Some(limiter) = connection.limiter.as_ref() {
+            limiter.acknowledge(&event);
+        }
+        match &event.msg {
+            EventMsg::ExecApprovalRequest(ApprovalRequestEvent { call_id, .. }) => {
+                connection
+                    .pending_approvals
+                    .insert(call_id.clone(), ApprovalKind::Exec);
+            }
+            EventMsg::PatchApprovalRequest(ApprovalRequestEvent { call_id, .. }) => {
+                connection
+                    .pending_approvals
+                    .insert(call_id.clone(), ApprovalKind::Patch);
+            }
+            _ => {}
+        }
+        let _ = connection.events_tx.send(Event {
+            id: id.to_string(),
+            seq: event.seq,
+            msg: event.msg.clone(),
+        });
+    }
+    lock_connections(&connections).clear();
+
+    runtime
+        .join()
+        .await
+        .map_err(|err| io::Error::other(err.to_string()))
+}
+
+fn lock_connections(connections: &Connections) -> MutexGuard<'_, HashMap<String, Connection>> {
+    connections
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use finger_kernel_core::{
+        ApprovalBroker, ChatEngine, KernelConfig, TurnMetrics, TurnRequest, TurnRunResult,
+    };
+    use finger_kernel_protocol::ReviewDecision;
+    use serde_json::Value;
+    use std::time::Duration;
+    use tokio::io::Lines;
+    use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+    use tokio::sync::oneshot;
+
+    async fn next_event(lines: &mut Lines<BufReader<OwnedReadHalf>>) -> Value {
+        let line = tokio::time::timeout(Duration::from_secs(2), lines.next_line())
+            .await
+            .expect("event within timeout")
+            .expect("read event")
+            .expect("connection open");
+        serde_json::from_str(&line).expect("event json")
+    }
+
+    async fn connect(path: &Path) -> (Lines<BufReader<OwnedReadHalf>>, OwnedWriteHalf) {
+        for _ in 0..50 {
+            if let Ok(stream) = UnixStream::connect(path).await {
+                let (reader, writer) = stream.into_split();
+                return (BufReader::new(reader).lines(), writer);
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("connect to bridge socket");
+    }
+
+    async fn next_event_of_type(
+        lines: &mut Lines<BufReader<OwnedReadHalf>>,
+        event_type: &str,
+    ) -> Value {
+        loop {
+            let event = next_event(lines).await;
+            if event["msg"]["type"] == event_type {
+                return event;
+            }
+        }
+    }
+
+    struct ApprovalTestEngine;
+
+    #[async_trait]
+    impl ChatEngine for ApprovalTestEngine {
+        async fn run_turn(
+            &self,
+            _request: &TurnRequest,
+            _progress_tx: Option<mpsc::UnboundedSender<EventMsg>>,
+        ) -> Result<TurnRunResult, String> {
+            Err("approval broker required".to_string())
+        }
+
+        async fn run_turn_with_approvals(
+            &self,
+            _request: &TurnRequest,
+            progress_tx: Option<mpsc::UnboundedSender<EventMsg>>,
+            approvals: ApprovalBroker,
+        ) -> Result<TurnRunResult, String> {
+            let decision_rx = approvals.register("call_1", ApprovalKind::Exec, "shell.exec");
+            if let Some(tx) = progress_tx {
+                let _ = tx.send(EventMsg::ExecApprovalRequest(ApprovalRequestEvent {
+                    seq: 1,
+                    call_id: "call_1".to_string(),
+                    tool_name: "shell.exec".to_string(),
+                    input: serde_json::json!({"command":"rm -rf build"}),
+                }));
+            }
+            let message = match decision_rx.await.unwrap_or(ReviewDecision::Abort) {
+                ReviewDecision::Approved | ReviewDecision::ApprovedForSession => "ran",
+                ReviewDecision::Denied => "denied",
+                ReviewDecision::Abort => "aborted",
+            };
+            Ok(TurnRunResult {
+                last_agent_message: Some(message.to_string()),
+                metadata_json: None,
+                metrics: TurnMetrics::default(),
+                finish_reason: None,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn user_turn_over_unix_socket_streams_events_back() {
+        let path = std::env::temp_dir().join(format!("finger-bridge-{}.sock", std::process::id()));
+        let runtime = KernelRuntime::spawn(KernelConfig::default());
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+        let server_path = path.clone();
+        let server = tokio::spawn(async move {
+            serve(&server_path, runtime, None, async {
+                let _ = shutdown_rx.await;
+            })
+            .await
+        });
+
+        let (mut lines, mut writer) = connect(&path).await;
+
+        let configured = next_event(&mut lines).await;
+        assert_eq!(configured["msg"]["type"], "session_configured");
+        assert_eq!(configured["msg"]["session_id"], "conn-1");
+
+        writer
+            .write_all(
+                b"{\"id\":\"t1\",\"op\":{\"type\":\"user_turn\",\"items\":[{\"type\":\"text\",\"text\":\"hello\"}]}}\n",
+            )
+            .await
+            .expect("write submission");
+
+        let started = next_event(&mut lines).await;
+        assert_eq!(started["id"], "t1");
+        assert_eq!(started["msg"]["type"], "task_started");
+        let complete = loop {
+            let event = next_event(&mut lines).await;
+            if event["msg"]["type"] == "task_complete" {
+                break event;
+            }
+        };
+        assert_eq!(complete["id"], "t1");
+        assert_eq!(complete["msg"]["last_agent_message"], "hello");
+
+        shutdown_tx.send(()).expect("trigger shutdown");
+        let shutdown = next_event(&mut lines).await;
+        assert_eq!(shutdown["msg"]["type"], "shutdown_complete");
+        server.await.expect("join server").expect("server result");
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn serve_only_replaces_stale_sockets() {
+        let dir = std::env::temp_dir().join(format!("finger-bridge-stale-{}", std::process::id()));
+        fs::create_dir_all(&dir).expect("create temp dir");
+
+        let regular = dir.join("not-a-socket");
+        fs::write(&regular, "keep me").expect("write regular file");
+        let runtime = KernelRuntime::spawn(KernelConfig::default());
+        let err = serve(&regular, runtime, None, async {})
+            .await
+            .expect_err("regular file must not be replaced");
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+        assert_eq!(fs::read_to_string(&regular).expect("file kept"), "keep me");
+
+        let live = dir.join("live.sock");
+        let _listener = UnixListener::bind(&live).expect("bind live socket");
+        let runtime = KernelRuntime::spawn(KernelConfig::default());
+        let err = serve(&live, runtime, None, async {})
+            .await
+            .expect_err("live socket must not be taken over");
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+
+        let stale = dir.join("stale.sock");
+        drop(std::os::unix::net::UnixListener::bind(&stale).expect("bind stale socket"));
+        let runtime = KernelRuntime::spawn(KernelConfig::default());
+        serve(&stale, runtime, None, async {})
+            .await
+            .expect("stale socket is replaced");
+        assert!(!stale.exists());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn approvals_are_only_accepted_from_the_requesting_connection() {
+        let path = std::env::temp_dir().join(format!(
+            "finger-bridge-approval-{}.sock",
+            std::process::id()
+        ));
+        let runtime =
+            KernelRuntime::spawn_with_engine(KernelConfig::default(), Arc::new(ApprovalTestEngine));
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+        let server_path = path.clone();
+        let server = tokio::spawn(async move {
+            serve(&server_path, runtime, None, async {
+                let _ = shutdown_rx.await;
+            })
+            .await
+        });
+
+        let (mut owner_lines, mut owner_writer) = connect(&path).await;
+        next_event_of_type(&mut owner_lines, "session_configured").await;
+        let (mut other_lines, mut other_writer) = connect(&path).await;
+        next_event_of_type(&mut other_lines, "session_configured").await;
+
+        owner_writer
+            .write_all(
+                b"{\"id\":\"t1\",\"op\":{\"type\":\"user_turn\",\"items\":[{\"type\":\"text\",\"text\":\"clean\"}]}}\n",
+            )
+            .await
+            .expect("write turn");
+        let request = next_event_of_type(&mut owner_lines, "exec_approval_request").await;
+        assert_eq!(request["msg"]["call_id"], "call_1");
+
+        other_writer
+            .write_all(
+                b"{\"id\":\"a1\",\"op\":{\"type\":\"exec_approval\",\"id\":\"call_1\",\"decision\":\"approved\"}}\n",
+            )
+            .await
+            .expect("write foreign approval");
+        let rejected = next_event_of_type(&mut other_lines, "error").await;
+        assert_eq!(rejected["id"], "a1");
+
+        owner_writer
+            .write_all(
+                b"{\"id\":\"a2\",\"op\":{\"type\":\"exec_approval\",\"id\":\"call_1\",\"decision\":\"denied\"}}\n",
+            )
+            .await
+            .expect("write owner approval");
+        let complete = next_event_of_type(&mut owner_lines, "task_complete").await;
+        assert_eq!(complete["id"], "t1");
+        assert_eq!(complete["msg"]["last_agent_message"], "denied");
+
+        shutdown_tx.send(()).expect("trigger shutdown");
+        next_event_of_type(&mut owner_lines, "shutdown_complete").await;
+        server.await.expect("join server").expect("server result");
+    }
+}
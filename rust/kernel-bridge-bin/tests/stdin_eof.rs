@@ -0,0 +1,122 @@
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use serde_json::Value;
+
+#[test]
+fn stdin_eof_drains_submissions_and_shuts_down() {
+    // An unparsable config makes the bridge fall back to the echo engine.
+    let config_path =
+        std::env::temp_dir().join(format!("finger-bridge-eof-{}.json", std::process::id()));
+    fs::write(&config_path, "not json").expect("write config");
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_finger-kernel-bridge-bin"))
+        .env("FINGER_CONFIG_PATH", &config_path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("spawn bridge");
+    {
+        let mut stdin = child.stdin.take().expect("stdin");
+        writeln!(
+            stdin,
+            r#"{{"id":"sub-1","op":{{"type":"user_turn","items":[{{"type":"text","text":"first"}}],"options":{{"session_id":"a"}}}}}}"#
+        )
+        .expect("write first submission");
+        writeln!(
+            stdin,
+            r#"{{"id":"sub-2","op":{{"type":"user_turn","items":[{{"type":"text","text":"second"}}],"options":{{"session_id":"b"}}}}}}"#
+        )
+        .expect("write second submission");
+    }
+
+    let output = child.wait_with_output().expect("bridge output");
+    let _ = fs::remove_file(&config_path);
+    assert!(output.status.success());
+
+    let events: Vec<Value> = String::from_utf8(output.stdout)
+        .expect("utf8 stdout")
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("event json"))
+        .collect();
+    let position = |id: &str, kind: &str| {
+        events
+            .iter()
+            .position(|event| event["id"] == id && event["msg"]["type"] == kind)
+            .unwrap_or_else(|| panic!("missing {kind} for {id}: {events:?}"))
+    };
+
+    let shutdown = position("shutdown", "shutdown_complete");
+    assert_eq!(shutdown, events.len() - 1);
+    for id in ["sub-1", "sub-2"] {
+        assert!(position(id, "task_started") < position(id, "task_complete"));
+        assert!(position(id, "task_complete") < shutdown);
+    }
+    assert_eq!(
+        events[position("sub-2", "task_complete")]["msg"]["last_agent_message"],
+        "second"
+    );
+}
+
+#[test]
+fn stdin_eof_aborts_turns_waiting_for_approval() {
+    let mut server = mockito::Server::new();
+    let _response_mock = server
+        .mock("POST", "/v1/responses")
+        .with_status(200)
+        .with_header("content-type", "text/event-stream")
+        .with_body(concat!(
+            "event: response.completed\n",
+            "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_1\",\"output\":[{\"type\":\"function_call\",\"id\":\"fc_1\",\"call_id\":\"call_1\",\"name\":\"shell_exec\",\"arguments\":\"{\\\"cmd\\\":\\\"rm -rf build\\\"}\"}]}}\n\n",
+            "data: [DONE]\n\n"
+        ))
+        .create();
+    let config_path = std::env::temp_dir().join(format!(
+        "finger-bridge-eof-approval-{}.json",
+        std::process::id()
+    ));
+    fs::write(
+        &config_path,
+        format!(
+            r#"{{"kernel":{{"provider":"mock","providers":{{"mock":{{"base_url":"{}"}}}}}}}}"#,
+            server.url()
+        ),
+    )
+    .expect("write config");
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_finger-kernel-bridge-bin"))
+        .env("FINGER_CONFIG_PATH", &config_path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("spawn bridge");
+    {
+        let mut stdin = child.stdin.take().expect("stdin");
+        writeln!(
+            stdin,
+            r#"{{"id":"sub-1","op":{{"type":"user_turn","items":[{{"type":"text","text":"clean"}}],"options":{{"tools":[{{"name":"shell.exec","approval":"exec"}}],"tool_execution":{{"daemon_url":"{}","agent_id":"chat-codex"}}}}}}}}"#,
+            server.url()
+        )
+        .expect("write submission");
+    }
+
+    let output = child.wait_with_output().expect("bridge output");
+    let _ = fs::remove_file(&config_path);
+    assert!(output.status.success());
+
+    let events: Vec<Value> = String::from_utf8(output.stdout)
+        .expect("utf8 stdout")
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("event json"))
+        .collect();
+    let types: Vec<&str> = events
+        .iter()
+        .map(|event| event["msg"]["type"].as_str().unwrap_or_default())
+        .collect();
+    assert!(types.contains(&"exec_approval_request"), "{events:?}");
+    assert!(types.contains(&"task_complete"), "{events:?}");
+    assert_eq!(types.last(), Some(&"shutdown_complete"));
+}
@@ -1,7 +1,10 @@
+use std::collections::HashMap;
 use std::env;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::Command;
 
+use finger_kernel_protocol::ResponsesRequestOptions;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
@@ -18,6 +21,7 @@ pub const KERNEL_PROVIDER_ENV: &str = "FINGER_KERNEL_PROVIDER";
 pub const FINGER_CONFIG_PATH_ENV: &str = "FINGER_CONFIG_PATH";
 pub const FINGER_TOOL_DAEMON_URL_ENV: &str = "FINGER_TOOL_DAEMON_URL";
 pub const FINGER_TOOL_AGENT_ID_ENV: &str = "FINGER_TOOL_AGENT_ID";
+pub const FINGER_TOOL_DAEMON_TOKEN_ENV: &str = "FINGER_TOOL_DAEMON_TOKEN";
 pub const DEFAULT_TOOL_DAEMON_URL: &str = "http://127.0.0.1:9999";
 pub const DEFAULT_TOOL_AGENT_ID: &str = "chat-codex";
 const LOCAL_DEV_API_KEY: &str = "local-dev-key";
@@ -48,7 +52,53 @@ impl WireApi {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+/// Azure OpenAI deployment. Requests go to the deployment's Responses
+/// endpoint with `api-version` and authenticate with the `api-key` header.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AzureDeployment {
+    pub deployment: String,
+    pub api_version: String,
+}
+
+impl AzureDeployment {
+    /// Both fields are spliced into the request URL verbatim, so they must
+    /// be non-empty, limited to URL-unreserved characters and not made only
+    /// of dots; anything else could rewrite the path (`..` is resolved as a
+    /// parent segment) or smuggle in extra query parameters.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        for (field, value) in [
+            ("deployment", &self.deployment),
+            ("api_version", &self.api_version),
+        ] {
+            let invalid = |reason: &str| ConfigError::InvalidAzureDeployment {
+                field: field.to_string(),
+                reason: reason.to_string(),
+            };
+            if value.is_empty() {
+                return Err(invalid("value is empty"));
+            }
+            let is_unreserved = |ch: char| ch.is_ascii_alphanumeric() || "-._~".contains(ch);
+            if !value.chars().all(is_unreserved) {
+                return Err(invalid(
+                    "value may only contain ASCII letters, digits, '-', '.', '_' and '~'",
+                ));
+            }
+            if value.chars().all(|ch| ch == '.') {
+                return Err(invalid("value must not be made only of dots"));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn responses_path(&self) -> String {
+        format!(
+            "/openai/deployments/{}/responses?api-version={}",
+            self.deployment, self.api_version
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct LocalModelConfig {
     pub provider_id: String,
     pub provider_name: String,
@@ -59,6 +109,23 @@ pub struct LocalModelConfig {
     pub model: String,
     pub tool_daemon_url: String,
     pub tool_agent_id: String,
+    /// Bearer token for the tool daemon, when it requires authentication.
+    pub tool_daemon_token: Option<String>,
+    /// Sent on every model and tool daemon request, e.g. gateway auth or
+    /// routing headers.
+    pub extra_headers: HashMap<String, String>,
+    /// Provider-wide request defaults; per-turn `responses` options override
+    /// them field by field.
+    pub responses: Option<ResponsesRequestOptions>,
+    /// `false` for gateways that reject the `reasoning.encrypted_content`
+    /// include; reasoning then relies on `store = true` instead.
+    pub supports_encrypted_reasoning: bool,
+    /// Responses endpoint under `base_url`, for gateways that mount it
+    /// elsewhere; unset means `/v1/responses`.
+    pub responses_path: Option<String>,
+    /// Targets an Azure OpenAI deployment instead of `responses_path`; only
+    /// applies to the Responses wire API.
+    pub azure: Option<AzureDeployment>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
@@ -77,6 +144,13 @@ struct ProviderDefaults {
     wire_api: WireApi,
     env_key: String,
     model: String,
+    extra_headers: HashMap<String, String>,
+    api_key_file: Option<String>,
+    api_key_command: Option<String>,
+    responses: Option<ResponsesRequestOptions>,
+    supports_encrypted_reasoning: bool,
+    responses_path: Option<String>,
+    azure: Option<AzureDeployment>,
 }
 
 #[derive(Debug, Clone, Default, Deserialize)]
@@ -90,8 +164,9 @@ struct KernelUserConfig {
     provider: Option<String>,
     tool_daemon_url: Option<String>,
     tool_agent_id: Option<String>,
+    tool_daemon_token: Option<String>,
     #[serde(default)]
-    providers: std::collections::HashMap<String, KernelProviderConfig>,
+    providers: HashMap<String, KernelProviderConfig>,
 }
 
 #[derive(Debug, Clone, Default, Deserialize)]
@@ -101,6 +176,17 @@ struct KernelProviderConfig {
     wire_api: Option<String>,
     env_key: Option<String>,
     model: Option<String>,
+    #[serde(default)]
+    extra_headers: HashMap<String, String>,
+    /// Path to a file holding the API key; takes precedence over `env_key`.
+    api_key_file: Option<String>,
+    /// Shell command whose stdout is the API key; takes precedence over
+    /// `api_key_file` and `env_key`.
+    api_key_command: Option<String>,
+    responses: Option<ResponsesRequestOptions>,
+    supports_encrypted_reasoning: Option<bool>,
+    responses_path: Option<String>,
+    azure: Option<AzureDeployment>,
 }
 
 #[derive(Debug, Error)]
@@ -109,6 +195,14 @@ pub enum ConfigError {
     MissingEnvVar(String),
     #[error("failed to parse config file '{path}': {error}")]
     ParseConfig { path: String, error: String },
+    #[error("invalid extra header '{name}': {reason}")]
+    InvalidHeader { name: String, reason: String },
+    #[error("invalid azure {field}: {reason}")]
+    InvalidAzureDeployment { field: String, reason: String },
+    #[error("failed to read api key file '{path}': {error}")]
+    ApiKeyFile { path: String, error: String },
+    #[error("api key command '{command}' failed: {error}")]
+    ApiKeyCommand { command: String, error: String },
 }
 
 pub fn load_crsb_config() -> Result<LocalModelConfig, ConfigError> {
@@ -135,25 +229,23 @@ pub fn load_local_model_config_with(
     let provider_id = resolve_provider_id(&overrides, file_config.as_ref());
     let mut defaults = provider_defaults(&provider_id);
     apply_file_provider_overrides(&mut defaults, file_config.as_ref(), &provider_id);
+    for (name, value) in &defaults.extra_headers {
+        validate_extra_header(name, value)?;
+    }
+    if let Some(azure) = defaults.azure.as_ref() {
+        azure.validate()?;
+    }
 
     let resolved_base_url = overrides
         .base_url
         .clone()
         .unwrap_or_else(|| defaults.base_url.clone());
-    let env_key = overrides.env_key.unwrap_or(defaults.env_key);
-    let api_key = match env::var(&env_key) {
-        Ok(value) => value,
-        Err(_) => {
-            if is_local_base_url(&resolved_base_url) {
-                LOCAL_DEV_API_KEY.to_string()
-            } else {
-                return Err(ConfigError::MissingEnvVar(env_key.clone()));
-            }
-        }
-    };
+    let env_key = overrides.env_key.unwrap_or(defaults.env_key.clone());
+    let api_key = resolve_api_key(&defaults, &env_key, &resolved_base_url)?;
 
     let tool_daemon_url = resolve_tool_daemon_url(file_config.as_ref());
     let tool_agent_id = resolve_tool_agent_id(file_config.as_ref());
+    let tool_daemon_token = resolve_tool_daemon_token(file_config.as_ref());
 
     Ok(LocalModelConfig {
         provider_id,
@@ -165,6 +257,12 @@ pub fn load_local_model_config_with(
         model: overrides.model.unwrap_or(defaults.model),
         tool_daemon_url,
         tool_agent_id,
+        tool_daemon_token,
+        extra_headers: defaults.extra_headers,
+        responses: defaults.responses,
+        supports_encrypted_reasoning: defaults.supports_encrypted_reasoning,
+        responses_path: defaults.responses_path,
+        azure: defaults.azure,
     })
 }
 
@@ -177,6 +275,13 @@ fn provider_defaults(provider_id: &str) -> ProviderDefaults {
             wire_api: WireApi::Responses,
             env_key: DEFAULT_ENV_KEY_CRSA.to_string(),
             model: DEFAULT_MODEL.to_string(),
+            extra_headers: HashMap::new(),
+            api_key_file: None,
+            api_key_command: None,
+            responses: None,
+            supports_encrypted_reasoning: true,
+            responses_path: None,
+            azure: None,
         },
         _ => ProviderDefaults {
             provider_id: DEFAULT_PROVIDER_ID.to_string(),
@@ -185,6 +290,13 @@ fn provider_defaults(provider_id: &str) -> ProviderDefaults {
             wire_api: WireApi::Responses,
             env_key: DEFAULT_ENV_KEY.to_string(),
             model: DEFAULT_MODEL.to_string(),
+            extra_headers: HashMap::new(),
+            api_key_file: None,
+            api_key_command: None,
+            responses: None,
+            supports_encrypted_reasoning: true,
+            responses_path: None,
+            azure: None,
         },
     }
 }
@@ -258,6 +370,21 @@ fn resolve_tool_agent_id(file_config: Option<&FingerUserConfig>) -> String {
     DEFAULT_TOOL_AGENT_ID.to_string()
 }
 
+fn resolve_tool_daemon_token(file_config: Option<&FingerUserConfig>) -> Option<String> {
+    if let Ok(value) = env::var(FINGER_TOOL_DAEMON_TOKEN_ENV) {
+        let trimmed = value.trim();
+        if !trimmed.is_empty() {
+            return Some(trimmed.to_string());
+        }
+    }
+
+    file_config
+        .and_then(|cfg| cfg.kernel.tool_daemon_token.as_ref())
+        .map(|item| item.trim())
+        .filter(|item| !item.is_empty())
+        .map(|item| item.to_string())
+}
+
 fn apply_file_provider_overrides(
     defaults: &mut ProviderDefaults,
     file_config: Option<&FingerUserConfig>,
@@ -308,6 +435,131 @@ fn apply_file_provider_overrides(
     {
         defaults.model = model.to_string();
     }
+    if let Some(api_key_file) = provider_cfg
+        .api_key_file
+        .as_ref()
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+    {
+        defaults.api_key_file = Some(api_key_file.to_string());
+    }
+    if let Some(api_key_command) = provider_cfg
+        .api_key_command
+        .as_ref()
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+    {
+        defaults.api_key_command = Some(api_key_command.to_string());
+    }
+    if let Some(responses_path) = provider_cfg
+        .responses_path
+        .as_ref()
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+    {
+        defaults.responses_path = Some(responses_path.to_string());
+    }
+    if let Some(azure) = provider_cfg.azure.as_ref() {
+        defaults.azure = Some(azure.clone());
+    }
+    if let Some(supports_encrypted_reasoning) = provider_cfg.supports_encrypted_reasoning {
+        defaults.supports_encrypted_reasoning = supports_encrypted_reasoning;
+    }
+    if let Some(responses) = provider_cfg.responses.as_ref() {
+        defaults.responses = Some(responses.clone());
+    }
+    defaults.extra_headers.extend(
+        provider_cfg
+            .extra_headers
+            .iter()
+            .map(|(name, value)| (name.trim().to_string(), value.trim().to_string())),
+    );
+}
+
+/// Rejects names that are not HTTP tokens and values that are not visible
+/// ASCII (tabs allowed), so a config entry cannot smuggle in extra header
+/// lines or carry bytes the HTTP client would refuse at startup.
+pub fn validate_extra_header(name: &str, value: &str) -> Result<(), ConfigError> {
+    let invalid = |reason: &str| ConfigError::InvalidHeader {
+        name: name.to_string(),
+        reason: reason.to_string(),
+    };
+    if name.is_empty() {
+        return Err(invalid("name is empty"));
+    }
+    let is_token_char = |ch: char| ch.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(ch);
+    if !name.chars().all(is_token_char) {
+        return Err(invalid("name must be an HTTP token"));
+    }
+    if value.chars().any(|ch| ch.is_control() && ch != '\t') {
+        return Err(invalid("value contains control characters"));
+    }
+    if !value.is_ascii() {
+        return Err(invalid("value must be visible ASCII"));
+    }
+    Ok(())
+}
+
+/// Sources are tried in a fixed order: `api_key_command`, then
+/// `api_key_file`, then the `env_key` variable. A configured source that fails
+/// is an error rather than a fall-through, so a broken helper is not masked.
+fn resolve_api_key(
+    defaults: &ProviderDefaults,
+    env_key: &str,
+    base_url: &str,
+) -> Result<String, ConfigError> {
+    if let Some(command) = defaults.api_key_command.as_deref() {
+        return read_api_key_from_command(command);
+    }
+    if let Some(path) = defaults.api_key_file.as_deref() {
+        return read_api_key_from_file(path);
+    }
+    match env::var(env_key) {
+        Ok(value) => Ok(value),
+        Err(_) if is_local_base_url(base_url) => Ok(LOCAL_DEV_API_KEY.to_string()),
+        Err(_) => Err(ConfigError::MissingEnvVar(env_key.to_string())),
+    }
+}
+
+fn read_api_key_from_file(path: &str) -> Result<String, ConfigError> {
+    let resolved = match path.strip_prefix("~/") {
+        Some(rest) => {
+            PathBuf::from(env::var("HOME").unwrap_or_else(|_| ".".to_string())).join(rest)
+        }
+        None => PathBuf::from(path),
+    };
+    let error = |error: String| ConfigError::ApiKeyFile {
+        path: path.to_string(),
+        error,
+    };
+    let key = fs::read_to_string(&resolved).map_err(|err| error(err.to_string()))?;
+    let key = key.trim();
+    if key.is_empty() {
+        return Err(error("file is empty".to_string()));
+    }
+    Ok(key.to_string())
+}
+
+fn read_api_key_from_command(command: &str) -> Result<String, ConfigError> {
+    let error = |error: String| ConfigError::ApiKeyCommand {
+        command: command.to_string(),
+        error,
+    };
+    let output = if cfg!(windows) {
+        Command::new("cmd").args(["/C", command]).output()
+    } else {
+        Command::new("sh").args(["-c", command]).output()
+    }
+    .map_err(|err| error(err.to_string()))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        return Err(error(format!("exited with {}: {stderr}", output.status)));
+    }
+    let key = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if key.is_empty() {
+        return Err(error("command printed no key".to_string()));
+    }
+    Ok(key)
 }
 
 fn is_local_base_url(base_url: &str) -> bool {
@@ -317,12 +569,16 @@ fn is_local_base_url(base_url: &str) -> bool {
 }
 
 fn load_finger_user_config() -> Result<Option<FingerUserConfig>, ConfigError> {
-    let path = resolve_finger_config_path();
+    load_finger_user_config_from(&resolve_finger_config_path())
+}
+
+/// Parses `path` as TOML when it has a `.toml` extension, JSON otherwise.
+fn load_finger_user_config_from(path: &Path) -> Result<Option<FingerUserConfig>, ConfigError> {
     if !path.exists() {
         return Ok(None);
     }
 
-    let raw = match fs::read_to_string(&path) {
+    let raw = match fs::read_to_string(path) {
         Ok(content) => content,
         Err(_) => return Ok(None),
     };
@@ -330,12 +586,18 @@ fn load_finger_user_config() -> Result<Option<FingerUserConfig>, ConfigError> {
         return Ok(None);
     }
 
-    serde_json::from_str::<FingerUserConfig>(&raw)
-        .map(Some)
-        .map_err(|error| ConfigError::ParseConfig {
-            path: path.to_string_lossy().to_string(),
-            error: error.to_string(),
-        })
+    let is_toml = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("toml"));
+    let parsed = if is_toml {
+        toml::from_str::<FingerUserConfig>(&raw).map_err(|error| error.to_string())
+    } else {
+        serde_json::from_str::<FingerUserConfig>(&raw).map_err(|error| error.to_string())
+    };
+    parsed.map(Some).map_err(|error| ConfigError::ParseConfig {
+        path: path.to_string_lossy().to_string(),
+        error,
+    })
 }
 
 fn resolve_finger_config_path() -> PathBuf {
@@ -347,7 +609,18 @@ fn resolve_finger_config_path() -> PathBuf {
     }
 
     let home = env::var("HOME").unwrap_or_else(|_| ".".to_string());
-    PathBuf::from(home).join(".finger").join("config.json")
+    resolve_config_file_in(&PathBuf::from(home).join(".finger"))
+}
+
+/// `config.json` wins over `config.toml` when both exist, so existing setups
+/// keep loading the file they always have.
+fn resolve_config_file_in(dir: &Path) -> PathBuf {
+    let json_path = dir.join("config.json");
+    let toml_path = dir.join("config.toml");
+    if !json_path.exists() && toml_path.exists() {
+        return toml_path;
+    }
+    json_path
 }
 
 #[cfg(test)]
@@ -404,5 +677,226 @@ mod tests {
         assert_eq!(cfg.provider_id, "crsb");
         assert_eq!(cfg.api_key, "test-key");
         assert_eq!(cfg.wire_api, WireApi::Responses);
+        assert!(cfg.extra_headers.is_empty());
+    }
+
+    fn temp_config_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!(
+            "finger-config-{name}-{}-{}",
+            std::process::id(),
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_nanos())
+                .unwrap_or_default()
+        ));
+        fs::create_dir_all(&dir).expect("create temp config dir");
+        dir
+    }
+
+    #[test]
+    fn toml_config_matches_equivalent_json_config() {
+        let dir = temp_config_dir("toml");
+        let toml_path = dir.join("config.toml");
+        fs::write(
+            &toml_path,
+            r#"
+[kernel]
+provider = "gateway"
+tool_daemon_url = "http://127.0.0.1:7777"
+tool_daemon_token = "daemon-secret"
+
+[kernel.providers.gateway]
+base_url = "https://gateway.example.com/v1"
+wire_api = "chat"
+env_key = "GATEWAY_KEY"
+model = "llama-3"
+supports_encrypted_reasoning = false
+responses_path = "/openai/responses"
+
+[kernel.providers.gateway.extra_headers]
+x-api-key = "secret"
+
+[kernel.providers.gateway.azure]
+deployment = "gpt4o-prod"
+api_version = "2025-04-01-preview"
+
+[kernel.providers.gateway.responses]
+reasoning = { effort = "high" }
+text = { verbosity = "low" }
+"#,
+        )
+        .expect("write toml config");
+        let json_path = dir.join("config.json");
+        fs::write(
+            &json_path,
+            r#"{"kernel":{"provider":"gateway","tool_daemon_url":"http://127.0.0.1:7777","tool_daemon_token":"daemon-secret","providers":{"gateway":{"base_url":"https://gateway.example.com/v1","wire_api":"chat","env_key":"GATEWAY_KEY","model":"llama-3","supports_encrypted_reasoning":false,"responses_path":"/openai/responses","extra_headers":{"x-api-key":"secret"},"azure":{"deployment":"gpt4o-prod","api_version":"2025-04-01-preview"},"responses":{"reasoning":{"effort":"high"},"text":{"verbosity":"low"}}}}}}"#,
+        )
+        .expect("write json config");
+
+        let from_toml = load_finger_user_config_from(&toml_path)
+            .expect("parse toml")
+            .expect("toml config present");
+        let from_json = load_finger_user_config_from(&json_path)
+            .expect("parse json")
+            .expect("json config present");
+        assert_eq!(format!("{from_toml:?}"), format!("{from_json:?}"));
+        assert_eq!(
+            resolve_tool_daemon_token(Some(&from_toml)).as_deref(),
+            Some("daemon-secret")
+        );
+
+        let mut defaults = provider_defaults("gateway");
+        apply_file_provider_overrides(&mut defaults, Some(&from_toml), "gateway");
+        assert_eq!(defaults.base_url, "https://gateway.example.com/v1");
+        assert_eq!(defaults.wire_api, WireApi::OpenAIChat);
+        assert_eq!(defaults.model, "llama-3");
+        assert!(!defaults.supports_encrypted_reasoning);
+        assert_eq!(
+            defaults.responses_path.as_deref(),
+            Some("/openai/responses")
+        );
+        assert_eq!(
+            defaults
+                .azure
+                .as_ref()
+                .map(AzureDeployment::responses_path)
+                .as_deref(),
+            Some("/openai/deployments/gpt4o-prod/responses?api-version=2025-04-01-preview")
+        );
+        assert_eq!(
+            defaults.extra_headers.get("x-api-key").map(String::as_str),
+            Some("secret")
+        );
+        let responses = defaults.responses.expect("provider responses defaults");
+        assert_eq!(
+            responses
+                .reasoning
+                .and_then(|reasoning| reasoning.effort)
+                .as_deref(),
+            Some("high")
+        );
+        assert_eq!(
+            responses.text.and_then(|text| text.verbosity).as_deref(),
+            Some("low")
+        );
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn json_config_wins_when_both_formats_exist() {
+        let dir = temp_config_dir("precedence");
+        assert_eq!(resolve_config_file_in(&dir), dir.join("config.json"));
+
+        fs::write(dir.join("config.toml"), "[kernel]\n").expect("write toml config");
+        assert_eq!(resolve_config_file_in(&dir), dir.join("config.toml"));
+
+        fs::write(dir.join("config.json"), "{}").expect("write json config");
+        assert_eq!(resolve_config_file_in(&dir), dir.join("config.json"));
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn invalid_toml_surfaces_parse_config_error() {
+        let dir = temp_config_dir("invalid");
+        let path = dir.join("config.toml");
+        fs::write(&path, "[kernel\nprovider = ").expect("write invalid toml");
+
+        let result = load_finger_user_config_from(&path);
+        assert!(matches!(result, Err(ConfigError::ParseConfig { .. })));
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn api_key_is_read_from_file_and_command() {
+        let dir = temp_config_dir("api-key");
+        let key_path = dir.join("key.txt");
+        fs::write(&key_path, "  file-key\n").expect("write key file");
+        let key_path = key_path.to_string_lossy().to_string();
+
+        let mut defaults = provider_defaults("crsb");
+        defaults.api_key_file = Some(key_path.clone());
+        assert_eq!(
+            resolve_api_key(&defaults, "FINGER_TEST_UNSET_KEY", DEFAULT_BASE_URL)
+                .expect("file key"),
+            "file-key"
+        );
+
+        defaults.api_key_command = Some("echo command-key".to_string());
+        assert_eq!(
+            resolve_api_key(&defaults, "FINGER_TEST_UNSET_KEY", DEFAULT_BASE_URL)
+                .expect("command key"),
+            "command-key"
+        );
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn failing_api_key_sources_are_reported() {
+        let mut defaults = provider_defaults("crsb");
+        defaults.api_key_file = Some("/nonexistent/finger/key.txt".to_string());
+        assert!(matches!(
+            resolve_api_key(&defaults, "FINGER_TEST_UNSET_KEY", DEFAULT_BASE_URL),
+            Err(ConfigError::ApiKeyFile { .. })
+        ));
+
+        defaults.api_key_command = Some("exit 3".to_string());
+        assert!(matches!(
+            resolve_api_key(&defaults, "FINGER_TEST_UNSET_KEY", DEFAULT_BASE_URL),
+            Err(ConfigError::ApiKeyCommand { .. })
+        ));
+
+        defaults.api_key_command = Some("true".to_string());
+        assert!(matches!(
+            resolve_api_key(&defaults, "FINGER_TEST_UNSET_KEY", DEFAULT_BASE_URL),
+            Err(ConfigError::ApiKeyCommand { .. })
+        ));
+    }
+
+    #[test]
+    fn validate_extra_header_rejects_injection() {
+        assert!(validate_extra_header("x-api-key", "secret").is_ok());
+        assert!(validate_extra_header("cf-aig-authorization", "Bearer a\tb").is_ok());
+        assert!(matches!(
+            validate_extra_header("x-api-key", "secret\r\nx-evil: 1"),
+            Err(ConfigError::InvalidHeader { .. })
+        ));
+        assert!(validate_extra_header("bad name", "v").is_err());
+        assert!(validate_extra_header("", "v").is_err());
+    }
+
+    #[test]
+    fn validate_extra_header_rejects_non_ascii_values() {
+        assert!(matches!(
+            validate_extra_header("x-team", "équipe"),
+            Err(ConfigError::InvalidHeader { .. })
+        ));
+    }
+
+    #[test]
+    fn azure_deployment_rejects_values_that_are_not_url_safe() {
+        let azure = |deployment: &str, api_version: &str| AzureDeployment {
+            deployment: deployment.to_string(),
+            api_version: api_version.to_string(),
+        };
+        assert!(azure("gpt4o-prod", "2025-04-01-preview").validate().is_ok());
+        for (deployment, api_version) in [
+            ("../models", "2025-04-01-preview"),
+            ("gpt4o prod", "2025-04-01-preview"),
+            ("gpt4o-prod", "2025-04-01-preview&x=1"),
+            ("gpt4o-prod?", "2025-04-01-preview"),
+            ("", "2025-04-01-preview"),
+            (".", "2025-04-01-preview"),
+            ("..", "2025-04-01-preview"),
+            ("gpt4o-prod", ".."),
+        ] {
+            assert!(matches!(
+                azure(deployment, api_version).validate(),
+                Err(ConfigError::InvalidAzureDeployment { .. })
+            ));
+        }
     }
 }
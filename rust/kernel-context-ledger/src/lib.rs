@@ -1,17 +1,23 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet, VecDeque};
 use std::fs::{self, File, OpenOptions};
-use std::io::{BufRead, BufReader, Write};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Mutex;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use thiserror::Error;
 use time::format_description::well_known::Rfc3339;
 use time::OffsetDateTime;
 
+/// Overrides the root `default_root_dir` resolves to, e.g. a mounted volume.
+pub const FINGER_LEDGER_ROOT_ENV: &str = "FINGER_LEDGER_ROOT";
+
+const DEFAULT_PREVIEW_MAX_CHARS: usize = 160;
+
 static ENTRY_COUNTER: AtomicU64 = AtomicU64::new(1);
 static LEDGER_WRITE_MUTEX: Mutex<()> = Mutex::new(());
 
@@ -40,6 +46,13 @@ pub struct ContextLedgerConfig {
     pub readable_agents: Vec<String>,
     pub focus_enabled: bool,
     pub focus_max_chars: usize,
+    /// Once an append would grow `context-ledger.jsonl` past this size, the
+    /// file is rotated to `context-ledger.<seq>.jsonl` and a fresh one started.
+    #[serde(default)]
+    pub max_segment_bytes: Option<u64>,
+    /// Rotated segments to keep; the oldest are deleted beyond this.
+    #[serde(default)]
+    pub max_retained_segments: Option<usize>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
@@ -49,11 +62,47 @@ pub struct LedgerQueryRequest {
     pub mode: Option<String>,
     pub since_ms: Option<u64>,
     pub until_ms: Option<u64>,
+    /// RFC3339 form of `since_ms`; ignored when `since_ms` is set.
+    #[serde(default)]
+    pub since_iso: Option<String>,
+    /// RFC3339 form of `until_ms`; ignored when `until_ms` is set.
+    #[serde(default)]
+    pub until_iso: Option<String>,
     pub limit: Option<usize>,
     pub contains: Option<String>,
     #[serde(default)]
     pub fuzzy: bool,
+    /// Regular expression matched against the payload's JSON text, or
+    /// against the value at `payload_path` when one is given.
+    #[serde(default)]
+    pub regex: Option<String>,
+    /// Dotted path into the payload (e.g. `tool_name` or `args.0.cmd`) that
+    /// scopes `regex`; entries without a value there never match.
+    #[serde(default)]
+    pub payload_path: Option<String>,
     pub event_types: Vec<String>,
+    /// Keyset cursor: only entries older than this entry id are returned.
+    #[serde(default)]
+    pub before_id: Option<String>,
+    /// Keyset cursor by time, used when `before_id` is absent or unknown.
+    #[serde(default)]
+    pub before_ms: Option<u64>,
+    /// Characters of payload shown per timeline point (default 160); `0`
+    /// omits previews when only the timeline metadata is needed.
+    #[serde(default)]
+    pub preview_max_chars: Option<usize>,
+    /// Fill `LedgerQueryResponse::stats` for the whole filtered set.
+    #[serde(default)]
+    pub include_stats: bool,
+}
+
+/// One `session/agent/mode` ledger to include in [`ContextLedger::query_multi`].
+/// Unset fields default to this ledger's own values, as in `query`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct QueryTarget {
+    pub session_id: Option<String>,
+    pub agent_id: Option<String>,
+    pub mode: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -63,6 +112,50 @@ pub struct LedgerQueryResponse {
     pub total: usize,
     pub truncated: bool,
     pub source: String,
+    /// Id of the oldest returned entry when older entries remain; pass it back
+    /// as `before_id` to fetch the previous page.
+    #[serde(default)]
+    pub next_cursor: Option<String>,
+    /// Present when the request set `include_stats`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stats: Option<LedgerStats>,
+}
+
+/// Event-type counts and time span of every entry matching a query, not
+/// just the page returned.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct LedgerStats {
+    pub event_type_counts: BTreeMap<String, usize>,
+    pub min_timestamp_ms: Option<u64>,
+    pub max_timestamp_ms: Option<u64>,
+}
+
+impl LedgerStats {
+    fn record(&mut self, event_type: &str, timestamp_ms: u64) {
+        *self
+            .event_type_counts
+            .entry(event_type.to_string())
+            .or_default() += 1;
+        self.min_timestamp_ms = Some(
+            self.min_timestamp_ms
+                .map_or(timestamp_ms, |min| min.min(timestamp_ms)),
+        );
+        self.max_timestamp_ms = Some(
+            self.max_timestamp_ms
+                .map_or(timestamp_ms, |max| max.max(timestamp_ms)),
+        );
+    }
+
+    fn merge(&mut self, other: LedgerStats) {
+        for (event_type, count) in other.event_type_counts {
+            *self.event_type_counts.entry(event_type).or_default() += count;
+        }
+        self.min_timestamp_ms = match (self.min_timestamp_ms, other.min_timestamp_ms) {
+            (Some(left), Some(right)) => Some(left.min(right)),
+            (left, right) => left.or(right),
+        };
+        self.max_timestamp_ms = self.max_timestamp_ms.max(other.max_timestamp_ms);
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -73,15 +166,76 @@ pub struct LedgerTimelinePoint {
     pub event_type: String,
     pub agent_id: String,
     pub mode: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub preview: String,
 }
 
+/// One line of `context-ledger-index.jsonl`: where an entry lives in the ledger
+/// file plus the fields `query` can filter on without parsing the entry.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+struct LedgerIndexRecord {
+    id: String,
+    offset: u64,
+    len: u64,
+    timestamp_ms: u64,
+    event_type: String,
+    prompt_like: bool,
+}
+
+impl LedgerIndexRecord {
+    fn for_entry(entry: &LedgerEntry, offset: u64, len: u64) -> Self {
+        Self {
+            id: entry.id.clone(),
+            offset,
+            len,
+            timestamp_ms: entry.timestamp_ms,
+            event_type: entry.event_type.clone(),
+            prompt_like: contains_prompt_like_block(entry.payload.to_string().as_str()),
+        }
+    }
+
+    fn end(&self) -> u64 {
+        self.offset + self.len + 1
+    }
+}
+
+struct LedgerSelection {
+    entries: Vec<LedgerEntry>,
+    total: usize,
+    /// Ledger lines parsed to answer the query.
+    #[cfg_attr(not(test), allow(dead_code))]
+    lines_read: usize,
+    stats: LedgerStats,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct FocusInsertResult {
     pub chars: usize,
     pub truncated: bool,
 }
 
+/// One entry of `focus-history.jsonl`: the focus slot as a write left it.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FocusVersion {
+    pub timestamp_ms: u64,
+    pub timestamp_iso: String,
+    pub append: bool,
+    pub chars: usize,
+    pub truncated: bool,
+    pub text: String,
+}
+
+/// A `session/agent/mode` ledger found under a root. Ids are the directory
+/// names, which round-trip through [`ContextLedgerConfig`] to the same ledger.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SessionSummary {
+    pub session_id: String,
+    pub agent_id: String,
+    pub mode: String,
+    pub entry_count: usize,
+    pub latest_timestamp_ms: Option<u64>,
+}
+
 #[derive(Debug, Error)]
 pub enum ContextLedgerError {
     #[error("invalid config: {0}")]
@@ -173,13 +327,37 @@ impl ContextLedger {
         let _guard = LEDGER_WRITE_MUTEX.lock().map_err(|_| {
             ContextLedgerError::InvalidConfig("ledger write lock poisoned".to_string())
         })?;
-        let mut file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(self.ledger_path())?;
+        let ledger_path = self.ledger_path();
+        // The process-wide mutex does not cover other processes writing the
+        // same ledger, so the append also holds an advisory file lock.
+        let open_ledger =
+            || open_locked(&ledger_path, OpenOptions::new().create(true).append(true));
+        let mut file = open_ledger()?;
+        let mut offset = file.metadata()?.len();
+        let segment_full = self
+            .cfg
+            .max_segment_bytes
+            .is_some_and(|max| offset > 0 && offset + line.len() as u64 + 1 > max);
+        if segment_full {
+            self.rotate_segment(&ledger_path)?;
+            file.unlock()?;
+            file = open_ledger()?;
+            offset = file.metadata()?.len();
+        }
         file.write_all(line.as_bytes())?;
         file.write_all(b"\n")?;
         file.flush()?;
+
+        // Extend the index with just this line when it already covers
+        // everything before it; otherwise catch it up from the ledger.
+        let index_path = ledger_index_path(&ledger_path);
+        if read_indexed_end(&index_path)? == offset {
+            let record = LedgerIndexRecord::for_entry(&entry, offset, line.len() as u64);
+            append_index_records(&index_path, &[record])?;
+        } else {
+            sync_ledger_index(&ledger_path)?;
+        }
+        file.unlock()?;
         Ok(())
     }
 
@@ -210,9 +388,11 @@ impl ContextLedger {
             .create(true)
             .append(true)
             .open(self.compact_memory_path())?;
+        file.lock()?;
         file.write_all(line.as_bytes())?;
         file.write_all(b"\n")?;
         file.flush()?;
+        file.unlock()?;
         self.rebuild_compact_memory_index()?;
         Ok(())
     }
@@ -221,16 +401,22 @@ impl ContextLedger {
         if !self.cfg.focus_enabled {
             return Ok(None);
         }
-        let path = self.focus_path();
-        if !path.exists() {
-            return Ok(None);
-        }
-        let content = fs::read_to_string(path)?;
-        let trimmed = content.trim();
-        if trimmed.is_empty() {
-            return Ok(None);
-        }
-        Ok(Some(trimmed.to_string()))
+        read_focus_slot(&self.focus_path())
+    }
+
+    /// Reads another agent's focus slot in this session, subject to the same
+    /// permission check as `query`.
+    pub fn read_focus_for(
+        &self,
+        agent_id: &str,
+        mode: &str,
+    ) -> Result<Option<String>, ContextLedgerError> {
+        let ledger_path = self.resolve_target_ledger_path(&QueryTarget {
+            session_id: None,
+            agent_id: Some(agent_id.to_string()),
+            mode: Some(mode.to_string()),
+        })?;
+        read_focus_slot(&ledger_path.with_file_name("focus-slot.txt"))
     }
 
     pub fn insert_focus(
@@ -266,7 +452,16 @@ impl ContextLedger {
             truncated = true;
         }
 
-        fs::write(self.focus_path(), merged.as_bytes())?;
+        write_locked(&self.focus_path(), merged.as_bytes())?;
+        let (timestamp_ms, timestamp_iso) = now_timestamp();
+        self.append_focus_version(&FocusVersion {
+            timestamp_ms,
+            timestamp_iso,
+            append,
+            chars: merged.chars().count(),
+            truncated,
+            text: merged.clone(),
+        })?;
         let _ = self.append_event(
             "focus_insert",
             serde_json::json!({
@@ -282,71 +477,283 @@ impl ContextLedger {
         })
     }
 
-    pub fn query(
+    /// The most recent `limit` focus writes, newest first.
+    pub fn read_focus_history(
         &self,
-        request: &LedgerQueryRequest,
-    ) -> Result<LedgerQueryResponse, ContextLedgerError> {
-        let target_session = request
-            .session_id
-            .as_deref()
-            .map(sanitize_component)
-            .filter(|item| !item.is_empty())
-            .unwrap_or_else(|| sanitize_component(self.cfg.session_id.as_str()));
-        let target_agent = request
-            .agent_id
-            .as_deref()
-            .map(sanitize_component)
-            .filter(|item| !item.is_empty())
-            .unwrap_or_else(|| sanitize_component(self.cfg.agent_id.as_str()));
-        let target_mode = request
-            .mode
-            .as_deref()
-            .map(sanitize_component)
-            .filter(|item| !item.is_empty())
-            .unwrap_or_else(|| sanitize_component(self.cfg.mode.as_str()));
+        limit: usize,
+    ) -> Result<Vec<FocusVersion>, ContextLedgerError> {
+        let path = self.focus_history_path();
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        let mut versions = Vec::new();
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            versions.push(serde_json::from_str::<FocusVersion>(&line)?);
+        }
+        Ok(versions.into_iter().rev().take(limit).collect())
+    }
 
-        if target_agent != sanitize_component(self.cfg.agent_id.as_str())
-            && !self.cfg.can_read_all
-            && !self.readable_agent_set.contains(target_agent.as_str())
-        {
-            return Err(ContextLedgerError::PermissionDenied {
-                agent_id: target_agent,
-            });
+    fn append_focus_version(&self, version: &FocusVersion) -> Result<(), ContextLedgerError> {
+        let line = serde_json::to_string(version)?;
+        let _guard = LEDGER_WRITE_MUTEX.lock().map_err(|_| {
+            ContextLedgerError::InvalidConfig("focus history write lock poisoned".to_string())
+        })?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.focus_history_path())?;
+        file.lock()?;
+        file.write_all(line.as_bytes())?;
+        file.write_all(b"\n")?;
+        file.flush()?;
+        file.unlock()?;
+        Ok(())
+    }
+
+    /// Replaces the payload of the entry with `id` by `{"redacted": true}`,
+    /// keeping its id, timestamps and event type so the timeline stays intact.
+    /// Returns `false` when no entry matches.
+    pub fn redact_entry(&self, id: &str) -> Result<bool, ContextLedgerError> {
+        let id = id.trim();
+        if id.is_empty() {
+            return Err(ContextLedgerError::InvalidConfig(
+                "entry id cannot be empty".to_string(),
+            ));
+        }
+        let _guard = LEDGER_WRITE_MUTEX.lock().map_err(|_| {
+            ContextLedgerError::InvalidConfig("ledger write lock poisoned".to_string())
+        })?;
+        for segment_path in ledger_segment_paths(&self.ledger_path())? {
+            if redact_in_segment(&segment_path, id)? {
+                return Ok(true);
+            }
         }
+        Ok(false)
+    }
 
-        let ledger_path = Self::resolve_ledger_path(
-            &self.cfg.root_dir,
-            target_session.as_str(),
-            target_agent.as_str(),
-            target_mode.as_str(),
-        );
+    pub fn query(
+        &self,
+        request: &LedgerQueryRequest,
+    ) -> Result<LedgerQueryResponse, ContextLedgerError> {
+        let request = &resolve_iso_bounds(request)?;
+        let ledger_path = self.resolve_target_ledger_path(&QueryTarget {
+            session_id: request.session_id.clone(),
+            agent_id: request.agent_id.clone(),
+            mode: request.mode.clone(),
+        })?;
 
-        let entries = read_entries(ledger_path.as_path())?;
-        let filtered = filter_entries(entries, request);
-        let total = filtered.len();
         let limit = request.limit.unwrap_or(50).max(1).min(500);
+        let pattern = compile_query_regex(request)?;
+        let selection = select_entries(ledger_path.as_path(), request, pattern.as_ref(), limit)?;
+        let total = selection.total;
         let truncated = total > limit;
-        let final_entries = if truncated {
-            filtered[total - limit..].to_vec()
+        let final_entries = selection.entries;
+        let stats = request.include_stats.then_some(selection.stats);
+        let next_cursor = if truncated {
+            final_entries.first().map(|entry| entry.id.clone())
         } else {
-            filtered
+            None
         };
 
         Ok(LedgerQueryResponse {
-            timeline: build_timeline(&final_entries),
+            timeline: build_timeline(&final_entries, request.preview_max_chars),
             entries: final_entries,
             total,
             truncated,
             source: ledger_path.to_string_lossy().to_string(),
+            next_cursor,
+            stats,
+        })
+    }
+
+    /// Lazily reads the target ledger's segments line by line, yielding the
+    /// entries that pass `request`'s filters in file order. Permission and
+    /// regex errors are returned up front; `limit` and the paging cursors are
+    /// ignored.
+    pub fn iter_entries(
+        &self,
+        request: &LedgerQueryRequest,
+    ) -> Result<LedgerEntryIter, ContextLedgerError> {
+        let request = &resolve_iso_bounds(request)?;
+        let ledger_path = self.resolve_target_ledger_path(&QueryTarget {
+            session_id: request.session_id.clone(),
+            agent_id: request.agent_id.clone(),
+            mode: request.mode.clone(),
+        })?;
+        let pattern = compile_query_regex(request)?;
+        Ok(LedgerEntryIter {
+            segments: ledger_segment_paths(&ledger_path)?.into(),
+            reader: None,
+            filter: EntryFilter::new(request, pattern.as_ref()),
+            buf: Vec::new(),
+        })
+    }
+
+    /// Runs `request`'s filters over several ledgers and merges the results
+    /// into one timestamp-ordered timeline. The request's own
+    /// `session_id`/`agent_id`/`mode` are ignored in favour of `targets`, and
+    /// the call fails if any target is not readable. When paging, pass the
+    /// oldest entry's `timestamp_ms` as `before_ms` alongside `before_id`,
+    /// since the cursor id only exists in one of the ledgers.
+    pub fn query_multi(
+        &self,
+        targets: &[QueryTarget],
+        request: &LedgerQueryRequest,
+    ) -> Result<LedgerQueryResponse, ContextLedgerError> {
+        if targets.is_empty() {
+            return Err(ContextLedgerError::InvalidConfig(
+                "query_multi requires at least one target".to_string(),
+            ));
+        }
+        let request = &resolve_iso_bounds(request)?;
+        let mut ledger_paths = Vec::with_capacity(targets.len());
+        for target in targets {
+            let path = self.resolve_target_ledger_path(target)?;
+            if !ledger_paths.contains(&path) {
+                ledger_paths.push(path);
+            }
+        }
+
+        let limit = request.limit.unwrap_or(50).clamp(1, 500);
+        let pattern = compile_query_regex(request)?;
+        let mut total = 0;
+        let mut stats = LedgerStats::default();
+        let mut merged = Vec::new();
+        for ledger_path in &ledger_paths {
+            // Each ledger's newest `limit` entries cover the merged newest `limit`.
+            let selection =
+                select_entries(ledger_path.as_path(), request, pattern.as_ref(), limit)?;
+            total += selection.total;
+            stats.merge(selection.stats);
+            merged.extend(selection.entries);
+        }
+        merged.sort_by_key(|entry| entry.timestamp_ms);
+        let final_entries = merged.split_off(merged.len().saturating_sub(limit));
+        let truncated = total > limit;
+        let next_cursor = if truncated {
+            final_entries.first().map(|entry| entry.id.clone())
+        } else {
+            None
+        };
+
+        Ok(LedgerQueryResponse {
+            timeline: build_timeline(&final_entries, request.preview_max_chars),
+            entries: final_entries,
+            total,
+            truncated,
+            source: ledger_paths
+                .iter()
+                .map(|path| path.to_string_lossy().to_string())
+                .collect::<Vec<_>>()
+                .join(","),
+            next_cursor,
+            stats: request.include_stats.then_some(stats),
         })
     }
 
+    /// Rebuilds API-shaped history items (user and assistant messages, tool
+    /// calls and their outputs) from this ledger's `turn_start`,
+    /// `model_round`, `tool_call`, `tool_result` and `tool_error` events, in
+    /// timeline order, so a restarted process can pass them back as
+    /// `history_items`. Redacted entries are skipped.
+    pub fn replay_history(&self) -> Result<Vec<Value>, ContextLedgerError> {
+        let entries = self.read_timeline_entries()?;
+        Ok(entries.iter().filter_map(replay_history_item).collect())
+    }
+
+    /// Writes every turn that reached `turn_complete` as one line in the
+    /// OpenAI fine-tuning chat format (`{"messages": [...]}` with user,
+    /// assistant and tool messages) and returns how many lines were written.
+    /// The ledger does not record system prompts, so none are emitted; text
+    /// carrying prompt-like context blocks is dropped, and a turn left
+    /// without a user message or final answer is skipped.
+    pub fn export_training_jsonl(&self, out: &mut impl Write) -> Result<usize, ContextLedgerError> {
+        let mut written = 0;
+        let mut turn: Option<TrainingTurn> = None;
+        for entry in self.read_timeline_entries()? {
+            if entry.payload.get("redacted").and_then(Value::as_bool) == Some(true) {
+                continue;
+            }
+            match entry.event_type.as_str() {
+                "turn_start" => turn = Some(TrainingTurn::default()),
+                "turn_error" => turn = None,
+                "turn_complete" => {
+                    if let Some(messages) = turn.take().and_then(TrainingTurn::finish) {
+                        serde_json::to_writer(
+                            &mut *out,
+                            &serde_json::json!({ "messages": messages }),
+                        )?;
+                        out.write_all(b"\n")?;
+                        written += 1;
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+            if let Some(turn) = turn.as_mut() {
+                turn.observe(&entry);
+            }
+        }
+        out.flush()?;
+        Ok(written)
+    }
+
+    /// All entries across segments in timeline order.
+    fn read_timeline_entries(&self) -> Result<Vec<LedgerEntry>, ContextLedgerError> {
+        let mut entries = Vec::new();
+        for segment_path in ledger_segment_paths(&self.ledger_path())? {
+            let reader = BufReader::new(File::open(&segment_path)?);
+            for line in reader.lines() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                entries.push(serde_json::from_str::<LedgerEntry>(&line)?);
+            }
+        }
+        // Stable, so same-millisecond events keep their append order.
+        entries.sort_by_key(|entry| entry.timestamp_ms);
+        Ok(entries)
+    }
+
+    /// `$FINGER_LEDGER_ROOT` when set, else `$HOME/.finger/sessions`.
     pub fn default_root_dir() -> PathBuf {
-        std::env::var("HOME")
-            .map(PathBuf::from)
-            .unwrap_or_else(|_| PathBuf::from("."))
-            .join(".finger")
-            .join("sessions")
+        resolve_root_dir(
+            std::env::var(FINGER_LEDGER_ROOT_ENV).ok(),
+            std::env::var("HOME").ok(),
+        )
+    }
+
+    /// Every ledger under `root`, sorted by session, agent and mode. Counts
+    /// come from the ledger index, read without writing anything under
+    /// `root`. Directories without a ledger file, and ledgers that cannot be
+    /// read or parsed, are skipped; a missing root lists nothing.
+    pub fn list_sessions(root: &Path) -> Result<Vec<SessionSummary>, ContextLedgerError> {
+        let mut sessions = Vec::new();
+        for (session_id, session_dir) in child_dirs(root)? {
+            for (agent_id, agent_dir) in child_dirs(&session_dir).unwrap_or_default() {
+                for (mode, mode_dir) in child_dirs(&agent_dir).unwrap_or_default() {
+                    let ledger_path = mode_dir.join("context-ledger.jsonl");
+                    let Ok(Some((entry_count, latest_timestamp_ms))) =
+                        summarize_ledger(&ledger_path)
+                    else {
+                        continue;
+                    };
+                    sessions.push(SessionSummary {
+                        session_id: session_id.clone(),
+                        agent_id: agent_id.clone(),
+                        mode,
+                        entry_count,
+                        latest_timestamp_ms,
+                    });
+                }
+            }
+        }
+        Ok(sessions)
     }
 
     pub fn root_dir(&self) -> &Path {
@@ -377,6 +784,46 @@ impl ContextLedger {
         self.cfg.focus_max_chars
     }
 
+    fn resolve_target_ledger_path(
+        &self,
+        target: &QueryTarget,
+    ) -> Result<PathBuf, ContextLedgerError> {
+        let target_session = target
+            .session_id
+            .as_deref()
+            .map(sanitize_component)
+            .filter(|item| !item.is_empty())
+            .unwrap_or_else(|| sanitize_component(self.cfg.session_id.as_str()));
+        let target_agent = target
+            .agent_id
+            .as_deref()
+            .map(sanitize_component)
+            .filter(|item| !item.is_empty())
+            .unwrap_or_else(|| sanitize_component(self.cfg.agent_id.as_str()));
+        let target_mode = target
+            .mode
+            .as_deref()
+            .map(sanitize_component)
+            .filter(|item| !item.is_empty())
+            .unwrap_or_else(|| sanitize_component(self.cfg.mode.as_str()));
+
+        if target_agent != sanitize_component(self.cfg.agent_id.as_str())
+            && !self.cfg.can_read_all
+            && !self.readable_agent_set.contains(target_agent.as_str())
+        {
+            return Err(ContextLedgerError::PermissionDenied {
+                agent_id: target_agent,
+            });
+        }
+
+        Ok(Self::resolve_ledger_path(
+            &self.cfg.root_dir,
+            target_session.as_str(),
+            target_agent.as_str(),
+            target_mode.as_str(),
+        ))
+    }
+
     fn ledger_path(&self) -> PathBuf {
         Self::resolve_ledger_path(
             &self.cfg.root_dir,
@@ -386,6 +833,38 @@ impl ContextLedger {
         )
     }
 
+    /// Moves the active ledger (and its index, whose offsets stay valid) to
+    /// the next segment number, then drops segments beyond the retention cap.
+    /// The caller holds `LEDGER_WRITE_MUTEX`.
+    fn rotate_segment(&self, ledger_path: &Path) -> Result<(), ContextLedgerError> {
+        let mut segments = ledger_segment_paths(ledger_path)?;
+        segments.retain(|path| path != ledger_path);
+        let next_seq = segments
+            .last()
+            .and_then(|path| segment_seq(path))
+            .map(|seq| seq + 1)
+            .unwrap_or(1);
+        let segment_path = ledger_path.with_file_name(format!("context-ledger.{next_seq}.jsonl"));
+        let index_path = ledger_index_path(ledger_path);
+        if index_path.exists() {
+            fs::rename(&index_path, ledger_index_path(&segment_path))?;
+        }
+        fs::rename(ledger_path, &segment_path)?;
+        segments.push(segment_path);
+
+        if let Some(max_retained) = self.cfg.max_retained_segments {
+            let excess = segments.len().saturating_sub(max_retained);
+            for path in &segments[..excess] {
+                fs::remove_file(path)?;
+                let index_path = ledger_index_path(path);
+                if index_path.exists() {
+                    fs::remove_file(index_path)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
     fn focus_path(&self) -> PathBuf {
         Self::resolve_base_dir(
             &self.cfg.root_dir,
@@ -396,6 +875,16 @@ impl ContextLedger {
         .join("focus-slot.txt")
     }
 
+    fn focus_history_path(&self) -> PathBuf {
+        Self::resolve_base_dir(
+            &self.cfg.root_dir,
+            self.cfg.session_id.as_str(),
+            self.cfg.agent_id.as_str(),
+            self.cfg.mode.as_str(),
+        )
+        .join("focus-history.jsonl")
+    }
+
     fn compact_memory_path(&self) -> PathBuf {
         Self::resolve_base_dir(
             &self.cfg.root_dir,
@@ -494,74 +983,981 @@ impl ContextLedger {
     }
 }
 
-fn read_entries(path: &Path) -> Result<Vec<LedgerEntry>, ContextLedgerError> {
-    if !path.exists() {
+/// Entry count and latest timestamp across a ledger's segments, or `None`
+/// when there is no ledger at `ledger_path`.
+fn summarize_ledger(
+    ledger_path: &Path,
+) -> Result<Option<(usize, Option<u64>)>, ContextLedgerError> {
+    let segments = ledger_segment_paths(ledger_path)?;
+    if segments.is_empty() {
+        return Ok(None);
+    }
+    let mut entry_count = 0;
+    let mut latest_timestamp_ms = None;
+    for segment_path in &segments {
+        let records = load_ledger_index(segment_path, false)?;
+        entry_count += records.len();
+        latest_timestamp_ms =
+            latest_timestamp_ms.max(records.iter().map(|record| record.timestamp_ms).max());
+    }
+    Ok(Some((entry_count, latest_timestamp_ms)))
+}
+
+/// `default_root_dir` with the environment passed in: a non-blank
+/// `ledger_root` wins, else `<home>/.finger/sessions`.
+fn resolve_root_dir(ledger_root: Option<String>, home: Option<String>) -> PathBuf {
+    if let Some(root) = ledger_root {
+        let trimmed = root.trim();
+        if !trimmed.is_empty() {
+            return PathBuf::from(trimmed);
+        }
+    }
+    home.map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join(".finger")
+        .join("sessions")
+}
+
+/// Subdirectories of `dir` with UTF-8 names, sorted by name; empty when
+/// `dir` does not exist.
+fn child_dirs(dir: &Path) -> Result<Vec<(String, PathBuf)>, ContextLedgerError> {
+    if !dir.is_dir() {
         return Ok(Vec::new());
     }
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
-    let mut entries = Vec::new();
-    for line in reader.lines() {
-        let raw = line?;
-        let trimmed = raw.trim();
-        if trimmed.is_empty() {
+    let mut children = Vec::new();
+    for dir_entry in fs::read_dir(dir)? {
+        let dir_entry = dir_entry?;
+        if !dir_entry.file_type()?.is_dir() {
             continue;
         }
-        let parsed = serde_json::from_str::<LedgerEntry>(trimmed)?;
-        entries.push(parsed);
+        if let Ok(name) = dir_entry.file_name().into_string() {
+            children.push((name, dir_entry.path()));
+        }
     }
-    Ok(entries)
+    children.sort();
+    Ok(children)
 }
 
-fn filter_entries(entries: Vec<LedgerEntry>, request: &LedgerQueryRequest) -> Vec<LedgerEntry> {
-    let since_ms = request.since_ms;
-    let until_ms = request.until_ms;
-    let contains = request
-        .contains
-        .as_ref()
-        .map(|item| item.trim().to_lowercase())
-        .filter(|item| !item.is_empty());
-    let event_types = request
-        .event_types
-        .iter()
-        .map(|item| item.trim().to_lowercase())
-        .filter(|item| !item.is_empty())
-        .collect::<HashSet<_>>();
+fn read_focus_slot(path: &Path) -> Result<Option<String>, ContextLedgerError> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let content = fs::read_to_string(path)?;
+    let trimmed = content.trim();
+    if trimmed.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(trimmed.to_string()))
+}
 
-    let mut filtered = entries
-        .into_iter()
-        .filter(|entry| {
-            since_ms
-                .map(|cutoff| entry.timestamp_ms >= cutoff)
+/// Replaces a file's contents under an exclusive advisory lock, so concurrent
+/// writers never leave a mix of both payloads behind.
+fn write_locked(path: &Path, content: &[u8]) -> Result<(), ContextLedgerError> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(false)
+        .open(path)?;
+    file.lock()?;
+    file.set_len(0)?;
+    file.write_all(content)?;
+    file.flush()?;
+    file.unlock()?;
+    Ok(())
+}
+
+/// Writes `content` to a sibling temp file, syncs it and renames it over
+/// `path`, so a crash never leaves a truncated file behind.
+fn replace_file_atomically(path: &Path, content: &[u8]) -> Result<(), ContextLedgerError> {
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+    let mut tmp = File::create(&tmp_path)?;
+    tmp.write_all(content)?;
+    tmp.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+/// Opens `path` and takes its exclusive advisory lock. A redaction or
+/// rotation in another process may rename a new file over `path` while this
+/// one waits for the lock; the open is then retried, so writes never land in
+/// the replaced file.
+fn open_locked(path: &Path, options: &OpenOptions) -> Result<File, ContextLedgerError> {
+    loop {
+        let file = options.open(path)?;
+        file.lock()?;
+        match fs::metadata(path) {
+            Ok(current) if is_same_file(&file.metadata()?, &current) => return Ok(file),
+            Ok(_) => {}
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {}
+            Err(error) => return Err(error.into()),
+        }
+        file.unlock()?;
+    }
+}
+
+#[cfg(unix)]
+fn is_same_file(left: &fs::Metadata, right: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    left.dev() == right.dev() && left.ino() == right.ino()
+}
+
+/// Elsewhere an open file cannot be renamed over, so the file at the path is
+/// always the one that was opened.
+#[cfg(not(unix))]
+fn is_same_file(_left: &fs::Metadata, _right: &fs::Metadata) -> bool {
+    true
+}
+
+/// Rewrites the entry with `id` in one ledger segment; the caller holds
+/// `LEDGER_WRITE_MUTEX`, and the segment's advisory lock is held from the
+/// read through the rename so appends from other processes wait for it.
+fn redact_in_segment(ledger_path: &Path, id: &str) -> Result<bool, ContextLedgerError> {
+    let file = open_locked(ledger_path, OpenOptions::new().read(true))?;
+    let mut content = String::new();
+    let mut redacted = false;
+    for line in BufReader::new(&file).lines() {
+        let line = line?;
+        let matched = serde_json::from_str::<Value>(&line)
+            .ok()
+            .filter(|value| value.get("id").and_then(Value::as_str) == Some(id));
+        match matched {
+            Some(mut value) => {
+                value["payload"] = serde_json::json!({ "redacted": true });
+                content.push_str(&serde_json::to_string(&value)?);
+                redacted = true;
+            }
+            None => content.push_str(&line),
+        }
+        content.push('\n');
+    }
+    if redacted {
+        replace_file_atomically(ledger_path, content.as_bytes())?;
+        // Offsets after the redacted line have shifted; the next append
+        // rebuilds the index from scratch.
+        let index_path = ledger_index_path(ledger_path);
+        if index_path.exists() {
+            fs::remove_file(index_path)?;
+        }
+    }
+    file.unlock()?;
+    Ok(redacted)
+}
+
+/// Fills `since_ms`/`until_ms` from their ISO forms where unset.
+fn resolve_iso_bounds(
+    request: &LedgerQueryRequest,
+) -> Result<LedgerQueryRequest, ContextLedgerError> {
+    let mut resolved = request.clone();
+    if resolved.since_ms.is_none() {
+        resolved.since_ms = parse_iso_millis(request.since_iso.as_deref(), "since_iso")?;
+    }
+    if resolved.until_ms.is_none() {
+        resolved.until_ms = parse_iso_millis(request.until_iso.as_deref(), "until_iso")?;
+    }
+    Ok(resolved)
+}
+
+fn parse_iso_millis(value: Option<&str>, field: &str) -> Result<Option<u64>, ContextLedgerError> {
+    let Some(value) = value.map(str::trim).filter(|value| !value.is_empty()) else {
+        return Ok(None);
+    };
+    let parsed = OffsetDateTime::parse(value, &Rfc3339).map_err(|err| {
+        ContextLedgerError::InvalidConfig(format!("invalid {field} '{value}': {err}"))
+    })?;
+    let millis = parsed.unix_timestamp_nanos() / 1_000_000;
+    Ok(Some(millis.max(0) as u64))
+}
+
+fn compile_query_regex(request: &LedgerQueryRequest) -> Result<Option<Regex>, ContextLedgerError> {
+    let Some(pattern) = request
+        .regex
+        .as_deref()
+        .filter(|pattern| !pattern.is_empty())
+    else {
+        return Ok(None);
+    };
+    Regex::new(pattern)
+        .map(Some)
+        .map_err(|err| ContextLedgerError::InvalidConfig(format!("invalid query regex: {err}")))
+}
+
+fn select_entries(
+    ledger_path: &Path,
+    request: &LedgerQueryRequest,
+    pattern: Option<&Regex>,
+    limit: usize,
+) -> Result<LedgerSelection, ContextLedgerError> {
+    if ledger_segment_paths(ledger_path)?.is_empty() {
+        return Ok(LedgerSelection {
+            entries: Vec::new(),
+            total: 0,
+            lines_read: 0,
+            stats: LedgerStats::default(),
+        });
+    }
+    match select_indexed_entries(ledger_path, request, pattern, limit, false)? {
+        Some(selection) => Ok(selection),
+        // The ledger was rewritten underneath the index; rebuild it and retry.
+        None => {
+            select_indexed_entries(ledger_path, request, pattern, limit, true)?.ok_or_else(|| {
+                ContextLedgerError::InvalidConfig(format!(
+                    "ledger index for {} does not match ledger contents",
+                    ledger_path.display()
+                ))
+            })
+        }
+    }
+}
+
+fn select_indexed_entries(
+    ledger_path: &Path,
+    request: &LedgerQueryRequest,
+    pattern: Option<&Regex>,
+    limit: usize,
+    force_rebuild: bool,
+) -> Result<Option<LedgerSelection>, ContextLedgerError> {
+    let segment_paths = ledger_segment_paths(ledger_path)?;
+    let mut indexes = Vec::with_capacity(segment_paths.len());
+    for segment_path in &segment_paths {
+        indexes.push(load_ledger_index(segment_path, force_rebuild)?);
+    }
+    let event_types = normalized_event_types(request);
+    // Candidates carry the position of the segment they were read from.
+    let mut candidates = indexes
+        .iter()
+        .enumerate()
+        .flat_map(|(segment, index)| index.iter().map(move |record| (segment, record)))
+        .filter(|(_, record)| {
+            request
+                .since_ms
+                .map(|cutoff| record.timestamp_ms >= cutoff)
                 .unwrap_or(true)
-                && until_ms
-                    .map(|cutoff| entry.timestamp_ms <= cutoff)
+                && request
+                    .until_ms
+                    .map(|cutoff| record.timestamp_ms <= cutoff)
                     .unwrap_or(true)
                 && (event_types.is_empty()
-                    || event_types.contains(entry.event_type.trim().to_lowercase().as_str()))
-                && !contains_prompt_like_block(entry.payload.to_string().as_str())
-                && contains
-                    .as_ref()
-                    .map(|needle| {
-                        let payload_text = entry.payload.to_string().to_lowercase();
-                        if payload_text.contains(needle)
-                            || entry.event_type.to_lowercase().contains(needle)
-                        {
-                            return true;
-                        }
-                        if request.fuzzy {
-                            return fuzzy_score(payload_text.as_str(), needle.as_str()) >= 0.18;
-                        }
-                        false
-                    })
-                    .unwrap_or(true)
+                    || event_types.contains(record.event_type.trim().to_lowercase().as_str()))
         })
         .collect::<Vec<_>>();
-    filtered.sort_by_key(|entry| entry.timestamp_ms);
-    filtered
+    candidates.sort_by_key(|(_, record)| record.timestamp_ms);
+
+    let mut files = segment_paths
+        .iter()
+        .map(File::open)
+        .collect::<Result<Vec<_>, _>>()?;
+    let has_contains = request
+        .contains
+        .as_deref()
+        .map(|item| !item.trim().is_empty())
+        .unwrap_or(false);
+    if has_contains || pattern.is_some() {
+        // Text matching needs payloads, so every candidate in range is read,
+        // but only the newest `limit` matches are held at a time.
+        let filter = EntryFilter::new(request, pattern);
+        let mut window = MatchWindow::new(request, limit);
+        let mut lines_read = 0;
+        for (segment, record) in &candidates {
+            if window.cursor_found() {
+                break;
+            }
+            let Some(entry) = read_indexed_entry(&mut files[*segment], record)? else {
+                return Ok(None);
+            };
+            lines_read += 1;
+            if filter.matches(&entry) {
+                window.push(entry);
+            }
+        }
+        let (entries, total, stats) = window.finish();
+        return Ok(Some(LedgerSelection {
+            entries,
+            total,
+            lines_read,
+            stats,
+        }));
+    }
+
+    candidates.retain(|(_, record)| !record.prompt_like);
+    candidates.truncate(cursor_end(&candidates, request, |(_, record)| {
+        (record.id.as_str(), record.timestamp_ms)
+    }));
+    let total = candidates.len();
+    let mut stats = LedgerStats::default();
+    for (_, record) in &candidates {
+        stats.record(&record.event_type, record.timestamp_ms);
+    }
+    let mut entries = Vec::with_capacity(total.min(limit));
+    for (segment, record) in &candidates[total.saturating_sub(limit)..] {
+        let Some(entry) = read_indexed_entry(&mut files[*segment], record)? else {
+            return Ok(None);
+        };
+        entries.push(entry);
+    }
+    let lines_read = entries.len();
+    Ok(Some(LedgerSelection {
+        entries,
+        total,
+        lines_read,
+        stats,
+    }))
 }
 
-fn build_timeline(entries: &[LedgerEntry]) -> Vec<LedgerTimelinePoint> {
+fn read_indexed_entry(
+    file: &mut File,
+    record: &LedgerIndexRecord,
+) -> Result<Option<LedgerEntry>, ContextLedgerError> {
+    let mut buf = vec![0_u8; record.len as usize];
+    file.seek(SeekFrom::Start(record.offset))?;
+    if file.read_exact(&mut buf).is_err() {
+        return Ok(None);
+    }
+    let Ok(entry) = serde_json::from_slice::<LedgerEntry>(buf.trim_ascii()) else {
+        return Ok(None);
+    };
+    if entry.id != record.id
+        || entry.timestamp_ms != record.timestamp_ms
+        || entry.event_type != record.event_type
+    {
+        return Ok(None);
+    }
+    Ok(Some(entry))
+}
+
+/// Number of leading items (sorted oldest first) that precede the request's cursor.
+fn cursor_end<T>(
+    items: &[T],
+    request: &LedgerQueryRequest,
+    key: impl Fn(&T) -> (&str, u64),
+) -> usize {
+    let before_id = request_before_id(request);
+    if let Some(before_id) = before_id {
+        if let Some(position) = items.iter().position(|item| key(item).0 == before_id) {
+            return position;
+        }
+    }
+    match request.before_ms {
+        Some(before_ms) => items.partition_point(|item| key(item).1 < before_ms),
+        // An unknown id cannot be placed, so nothing older can be returned.
+        None if before_id.is_some() => 0,
+        None => items.len(),
+    }
+}
+
+fn request_before_id(request: &LedgerQueryRequest) -> Option<&str> {
+    request
+        .before_id
+        .as_deref()
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+}
+
+fn ledger_index_path(ledger_path: &Path) -> PathBuf {
+    match segment_seq(ledger_path) {
+        Some(seq) => ledger_path.with_file_name(format!("context-ledger.{seq}-index.jsonl")),
+        None => ledger_path.with_file_name("context-ledger-index.jsonl"),
+    }
+}
+
+/// The sequence number of a rotated `context-ledger.<seq>.jsonl` segment.
+fn segment_seq(path: &Path) -> Option<u64> {
+    path.file_name()?
+        .to_str()?
+        .strip_prefix("context-ledger.")?
+        .strip_suffix(".jsonl")?
+        .parse()
+        .ok()
+}
+
+/// Rotated segments oldest first, followed by the active ledger if present.
+fn ledger_segment_paths(ledger_path: &Path) -> Result<Vec<PathBuf>, ContextLedgerError> {
+    let mut segments = Vec::new();
+    if let Some(dir) = ledger_path.parent().filter(|dir| dir.exists()) {
+        for dir_entry in fs::read_dir(dir)? {
+            let path = dir_entry?.path();
+            if let Some(seq) = segment_seq(&path) {
+                segments.push((seq, path));
+            }
+        }
+    }
+    segments.sort_by_key(|(seq, _)| *seq);
+    let mut paths = segments
+        .into_iter()
+        .map(|(_, path)| path)
+        .collect::<Vec<_>>();
+    if ledger_path.exists() {
+        paths.push(ledger_path.to_path_buf());
+    }
+    Ok(paths)
+}
+
+/// How `read_ledger_index` found the on-disk index compared to the ledger.
+enum IndexUpdate {
+    UpToDate,
+    /// Records from this position on are not in the index file yet.
+    Append(usize),
+    /// The index file is missing, unreadable or stale.
+    Rewrite,
+}
+
+/// Loads the ledger index for a query, indexing any lines appended since it
+/// was last written (including by other writers) or rebuilding it when it is
+/// missing, unreadable, or longer than the ledger itself. The result stays in
+/// memory; only `sync_ledger_index` writes index files.
+fn load_ledger_index(
+    ledger_path: &Path,
+    force_rebuild: bool,
+) -> Result<Vec<LedgerIndexRecord>, ContextLedgerError> {
+    Ok(read_ledger_index(ledger_path, force_rebuild)?.0)
+}
+
+/// Brings the index file up to date with the ledger. Callers hold
+/// `LEDGER_WRITE_MUTEX` and the ledger's advisory lock, so index files are
+/// only ever written by the append path.
+fn sync_ledger_index(ledger_path: &Path) -> Result<(), ContextLedgerError> {
+    let (records, update) = read_ledger_index(ledger_path, false)?;
+    let index_path = ledger_index_path(ledger_path);
+    match update {
+        IndexUpdate::UpToDate => {}
+        IndexUpdate::Append(start) => append_index_records(&index_path, &records[start..])?,
+        IndexUpdate::Rewrite => {
+            let mut content = String::new();
+            for record in &records {
+                content.push_str(&serde_json::to_string(record)?);
+                content.push('\n');
+            }
+            fs::write(&index_path, content)?;
+        }
+    }
+    Ok(())
+}
+
+fn read_ledger_index(
+    ledger_path: &Path,
+    force_rebuild: bool,
+) -> Result<(Vec<LedgerIndexRecord>, IndexUpdate), ContextLedgerError> {
+    let index_path = ledger_index_path(ledger_path);
+    let mut records = if force_rebuild {
+        None
+    } else {
+        read_index_records(&index_path)
+    }
+    .unwrap_or_default();
+    let ledger_len = fs::metadata(ledger_path)?.len();
+    let mut indexed_end = records.last().map(LedgerIndexRecord::end).unwrap_or(0);
+    let mut rewrite = force_rebuild || !index_path.exists();
+    let last_record_moved = match records.last() {
+        Some(last) if indexed_end < ledger_len => {
+            read_indexed_entry(&mut File::open(ledger_path)?, last)?.is_none()
+        }
+        _ => false,
+    };
+    if indexed_end > ledger_len || last_record_moved {
+        records.clear();
+        indexed_end = 0;
+        rewrite = true;
+    }
+
+    let indexed_count = records.len();
+    if indexed_end < ledger_len {
+        records.extend(scan_ledger_records(ledger_path, indexed_end)?);
+    }
+    let update = if rewrite {
+        IndexUpdate::Rewrite
+    } else if records.len() > indexed_count {
+        IndexUpdate::Append(indexed_count)
+    } else {
+        IndexUpdate::UpToDate
+    };
+    Ok((records, update))
+}
+
+fn read_index_records(index_path: &Path) -> Option<Vec<LedgerIndexRecord>> {
+    let content = fs::read_to_string(index_path).ok()?;
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str::<LedgerIndexRecord>(line).ok())
+        .collect()
+}
+
+fn scan_ledger_records(
+    ledger_path: &Path,
+    start: u64,
+) -> Result<Vec<LedgerIndexRecord>, ContextLedgerError> {
+    let mut file = File::open(ledger_path)?;
+    file.seek(SeekFrom::Start(start))?;
+    let mut reader = BufReader::new(file);
+    let mut records = Vec::new();
+    let mut offset = start;
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        let read = reader.read_until(b'\n', &mut buf)?;
+        // A line without its newline may still be mid-write; leave it for later.
+        if read == 0 || buf.last() != Some(&b'\n') {
+            break;
+        }
+        let line = &buf[..read - 1];
+        let trimmed = line.trim_ascii();
+        if !trimmed.is_empty() {
+            let entry = serde_json::from_slice::<LedgerEntry>(trimmed)?;
+            records.push(LedgerIndexRecord::for_entry(
+                &entry,
+                offset,
+                line.len() as u64,
+            ));
+        }
+        offset += read as u64;
+    }
+    Ok(records)
+}
+
+fn read_indexed_end(index_path: &Path) -> Result<u64, ContextLedgerError> {
+    if !index_path.exists() {
+        return Ok(0);
+    }
+    let mut file = File::open(index_path)?;
+    let len = file.metadata()?.len();
+    let tail_start = len.saturating_sub(4096);
+    file.seek(SeekFrom::Start(tail_start))?;
+    let mut tail = String::new();
+    file.read_to_string(&mut tail)?;
+    let end = tail
+        .lines()
+        .rev()
+        .find(|line| !line.trim().is_empty())
+        .and_then(|line| serde_json::from_str::<LedgerIndexRecord>(line).ok())
+        .map(|record| record.end())
+        // An unreadable tail never matches a real offset, so the next append
+        // rebuilds the index instead of extending it.
+        .unwrap_or(u64::MAX);
+    Ok(if len == 0 { 0 } else { end })
+}
+
+fn append_index_records(
+    index_path: &Path,
+    records: &[LedgerIndexRecord],
+) -> Result<(), ContextLedgerError> {
+    let mut content = String::new();
+    for record in records {
+        content.push_str(&serde_json::to_string(record)?);
+        content.push('\n');
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(index_path)?;
+    file.write_all(content.as_bytes())?;
+    file.flush()?;
+    Ok(())
+}
+
+fn normalized_event_types(request: &LedgerQueryRequest) -> HashSet<String> {
+    request
+        .event_types
+        .iter()
+        .map(|item| item.trim().to_lowercase())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+/// The per-entry half of a query: time window, event types, prompt-like
+/// payloads and the text filters, which need the parsed payload.
+struct EntryFilter {
+    request: LedgerQueryRequest,
+    pattern: Option<Regex>,
+    contains: Option<String>,
+    event_types: HashSet<String>,
+}
+
+impl EntryFilter {
+    fn new(request: &LedgerQueryRequest, pattern: Option<&Regex>) -> Self {
+        Self {
+            request: request.clone(),
+            pattern: pattern.cloned(),
+            contains: request
+                .contains
+                .as_ref()
+                .map(|item| item.trim().to_lowercase())
+                .filter(|item| !item.is_empty()),
+            event_types: normalized_event_types(request),
+        }
+    }
+
+    fn matches(&self, entry: &LedgerEntry) -> bool {
+        self.request
+            .since_ms
+            .map(|cutoff| entry.timestamp_ms >= cutoff)
+            .unwrap_or(true)
+            && self
+                .request
+                .until_ms
+                .map(|cutoff| entry.timestamp_ms <= cutoff)
+                .unwrap_or(true)
+            && (self.event_types.is_empty()
+                || self
+                    .event_types
+                    .contains(entry.event_type.trim().to_lowercase().as_str()))
+            && !contains_prompt_like_block(entry.payload.to_string().as_str())
+            && self
+                .contains
+                .as_ref()
+                .map(|needle| {
+                    let payload_text = entry.payload.to_string().to_lowercase();
+                    if payload_text.contains(needle)
+                        || entry.event_type.to_lowercase().contains(needle)
+                    {
+                        return true;
+                    }
+                    if self.request.fuzzy {
+                        return fuzzy_score(payload_text.as_str(), needle.as_str()) >= 0.18;
+                    }
+                    false
+                })
+                .unwrap_or(true)
+            && self
+                .pattern
+                .as_ref()
+                .map(|pattern| regex_matches_payload(pattern, entry, &self.request))
+                .unwrap_or(true)
+    }
+}
+
+/// Iterator returned by [`ContextLedger::iter_entries`]; holds one segment
+/// open at a time and one line in memory.
+pub struct LedgerEntryIter {
+    segments: VecDeque<PathBuf>,
+    reader: Option<BufReader<File>>,
+    filter: EntryFilter,
+    buf: Vec<u8>,
+}
+
+impl Iterator for LedgerEntryIter {
+    type Item = Result<LedgerEntry, ContextLedgerError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let reader = match self.reader.as_mut() {
+                Some(reader) => reader,
+                None => {
+                    let segment_path = self.segments.pop_front()?;
+                    match File::open(&segment_path) {
+                        Ok(file) => self.reader.insert(BufReader::new(file)),
+                        Err(err) => return Some(Err(err.into())),
+                    }
+                }
+            };
+            self.buf.clear();
+            let read = match reader.read_until(b'\n', &mut self.buf) {
+                Ok(read) => read,
+                Err(err) => {
+                    self.reader = None;
+                    return Some(Err(err.into()));
+                }
+            };
+            // A line without its newline may still be mid-write; skip it.
+            if read == 0 || self.buf.last() != Some(&b'\n') {
+                self.reader = None;
+                continue;
+            }
+            let line = self.buf.trim_ascii();
+            if line.is_empty() {
+                continue;
+            }
+            match serde_json::from_slice::<LedgerEntry>(line) {
+                Ok(entry) if self.filter.matches(&entry) => return Some(Ok(entry)),
+                Ok(_) => continue,
+                Err(err) => return Some(Err(err.into())),
+            }
+        }
+    }
+}
+
+/// Keeps the newest `limit` matches that precede the request's cursor while
+/// entries stream past oldest first, with the same result as `cursor_end`
+/// over the full list of matches.
+struct MatchWindow<'a> {
+    limit: usize,
+    before_id: Option<&'a str>,
+    before_ms: Option<u64>,
+    newest: VecDeque<LedgerEntry>,
+    matched: usize,
+    stats: LedgerStats,
+    /// `newest`/`matched`/`stats` as of the first match at or after `before_ms`.
+    before_ms_cut: Option<(VecDeque<LedgerEntry>, usize, LedgerStats)>,
+    cursor_found: bool,
+}
+
+impl<'a> MatchWindow<'a> {
+    fn new(request: &'a LedgerQueryRequest, limit: usize) -> Self {
+        Self {
+            limit,
+            before_id: request_before_id(request),
+            before_ms: request.before_ms,
+            newest: VecDeque::with_capacity(limit),
+            matched: 0,
+            stats: LedgerStats::default(),
+            before_ms_cut: None,
+            cursor_found: false,
+        }
+    }
+
+    /// Nothing after the cursor entry can be returned, so reading may stop.
+    fn cursor_found(&self) -> bool {
+        self.cursor_found
+    }
+
+    fn push(&mut self, entry: LedgerEntry) {
+        if self.before_id == Some(entry.id.as_str()) {
+            self.cursor_found = true;
+            return;
+        }
+        if self.before_ms_cut.is_none()
+            && self
+                .before_ms
+                .is_some_and(|before_ms| entry.timestamp_ms >= before_ms)
+        {
+            self.before_ms_cut = Some((self.newest.clone(), self.matched, self.stats.clone()));
+        }
+        if self.newest.len() == self.limit {
+            self.newest.pop_front();
+        }
+        self.stats.record(&entry.event_type, entry.timestamp_ms);
+        self.newest.push_back(entry);
+        self.matched += 1;
+    }
+
+    /// The kept entries, oldest first, plus how many matches preceded the
+    /// cursor and their stats.
+    fn finish(self) -> (Vec<LedgerEntry>, usize, LedgerStats) {
+        let (newest, matched, stats) = if self.cursor_found {
+            (self.newest, self.matched, self.stats)
+        } else if self.before_ms.is_some() {
+            self.before_ms_cut
+                .unwrap_or((self.newest, self.matched, self.stats))
+        } else if self.before_id.is_some() {
+            // An unknown id cannot be placed, so nothing older can be returned.
+            (VecDeque::new(), 0, LedgerStats::default())
+        } else {
+            (self.newest, self.matched, self.stats)
+        };
+        (newest.into(), matched, stats)
+    }
+}
+
+fn regex_matches_payload(
+    pattern: &Regex,
+    entry: &LedgerEntry,
+    request: &LedgerQueryRequest,
+) -> bool {
+    let Some(path) = request
+        .payload_path
+        .as_deref()
+        .map(str::trim)
+        .filter(|path| !path.is_empty())
+    else {
+        return pattern.is_match(entry.payload.to_string().as_str());
+    };
+    let Some(value) = resolve_payload_path(&entry.payload, path) else {
+        return false;
+    };
+    match value {
+        Value::String(text) => pattern.is_match(text),
+        other => pattern.is_match(other.to_string().as_str()),
+    }
+}
+
+fn resolve_payload_path<'a>(payload: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.')
+        .try_fold(payload, |value, segment| match value {
+            Value::Array(items) => segment
+                .parse::<usize>()
+                .ok()
+                .and_then(|index| items.get(index)),
+            _ => value.get(segment),
+        })
+}
+
+fn replay_history_item(entry: &LedgerEntry) -> Option<Value> {
+    let payload = &entry.payload;
+    if payload.get("redacted").and_then(Value::as_bool) == Some(true) {
+        return None;
+    }
+    let text_field = |field: &str| {
+        payload
+            .get(field)
+            .and_then(Value::as_str)
+            .filter(|text| !text.trim().is_empty())
+    };
+    match entry.event_type.as_str() {
+        "turn_start" => text_field("user_text").map(|text| {
+            serde_json::json!({
+                "role": "user",
+                "content": [{ "type": "input_text", "text": text }],
+            })
+        }),
+        "model_round" => text_field("output_text").map(|text| {
+            serde_json::json!({
+                "role": "assistant",
+                "content": [{ "type": "output_text", "text": text }],
+            })
+        }),
+        "tool_call" => {
+            let call_id = text_field("call_id")?;
+            let arguments = text_field("arguments")
+                .map(str::to_string)
+                .unwrap_or_else(|| {
+                    payload
+                        .get("input")
+                        .map(Value::to_string)
+                        .unwrap_or_else(|| "{}".to_string())
+                });
+            Some(serde_json::json!({
+                "type": "function_call",
+                "call_id": call_id,
+                "name": text_field("name").or_else(|| text_field("tool_name"))?,
+                "arguments": arguments,
+            }))
+        }
+        "tool_result" | "tool_error" => {
+            let call_id = text_field("call_id")?;
+            let tool = payload.get("tool_name").cloned().unwrap_or(Value::Null);
+            // Mirrors the output payload the model saw for the call.
+            let output = if entry.event_type == "tool_result" {
+                serde_json::json!({
+                    "ok": true,
+                    "tool": tool,
+                    "result": payload.get("output").cloned().unwrap_or(Value::Null),
+                })
+            } else {
+                serde_json::json!({
+                    "ok": false,
+                    "tool": tool,
+                    "error": payload.get("error").cloned().unwrap_or(Value::Null),
+                })
+            };
+            Some(serde_json::json!({
+                "type": "function_call_output",
+                "call_id": call_id,
+                "output": output.to_string(),
+            }))
+        }
+        _ => None,
+    }
+}
+
+/// Chat-format messages of one turn being rebuilt for training export.
+#[derive(Default)]
+struct TrainingTurn {
+    messages: Vec<Value>,
+}
+
+impl TrainingTurn {
+    fn observe(&mut self, entry: &LedgerEntry) {
+        let payload = &entry.payload;
+        let text_field = |field: &str| {
+            payload
+                .get(field)
+                .and_then(Value::as_str)
+                .map(str::trim)
+                .filter(|text| !text.is_empty())
+        };
+        match entry.event_type.as_str() {
+            "turn_start" => {
+                if let Some(text) =
+                    text_field("user_text").filter(|text| !contains_prompt_like_block(text))
+                {
+                    self.messages
+                        .push(serde_json::json!({ "role": "user", "content": text }));
+                }
+            }
+            "model_round" => {
+                let content = text_field("output_text")
+                    .filter(|text| !contains_prompt_like_block(text))
+                    .map_or(Value::Null, |text| Value::String(text.to_string()));
+                self.messages
+                    .push(serde_json::json!({ "role": "assistant", "content": content }));
+            }
+            "tool_call" => {
+                let (Some(call_id), Some(name)) = (
+                    text_field("call_id"),
+                    text_field("name").or_else(|| text_field("tool_name")),
+                ) else {
+                    return;
+                };
+                let arguments = text_field("arguments")
+                    .map(str::to_string)
+                    .unwrap_or_else(|| {
+                        payload
+                            .get("input")
+                            .map(Value::to_string)
+                            .unwrap_or_else(|| "{}".to_string())
+                    });
+                let tool_call = serde_json::json!({
+                    "id": call_id,
+                    "type": "function",
+                    "function": { "name": name, "arguments": arguments },
+                });
+                // Calls attach to the round's assistant message until a tool
+                // result follows it.
+                match self.messages.last_mut() {
+                    Some(last) if last["role"] == "assistant" => {
+                        match last.get_mut("tool_calls").and_then(Value::as_array_mut) {
+                            Some(tool_calls) => tool_calls.push(tool_call),
+                            None => last["tool_calls"] = Value::Array(vec![tool_call]),
+                        }
+                    }
+                    _ => self.messages.push(serde_json::json!({
+                        "role": "assistant",
+                        "content": null,
+                        "tool_calls": [tool_call],
+                    })),
+                }
+            }
+            "tool_result" | "tool_error" => {
+                let Some(call_id) = text_field("call_id") else {
+                    return;
+                };
+                if let Some(item) = replay_history_item(entry) {
+                    self.messages.push(serde_json::json!({
+                        "role": "tool",
+                        "tool_call_id": call_id,
+                        "content": item["output"],
+                    }));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The turn's messages, or `None` when it has no user message or does
+    /// not end in an assistant answer.
+    fn finish(self) -> Option<Vec<Value>> {
+        let messages = self
+            .messages
+            .into_iter()
+            .filter(|message| {
+                message["role"] != "assistant"
+                    || !message["content"].is_null()
+                    || message.get("tool_calls").is_some()
+            })
+            .collect::<Vec<_>>();
+        let has_user = messages.iter().any(|message| message["role"] == "user");
+        let ends_with_answer = messages.last().is_some_and(|message| {
+            message["role"] == "assistant" && message["content"].is_string()
+        });
+        (has_user && ends_with_answer).then_some(messages)
+    }
+}
+
+fn build_timeline(
+    entries: &[LedgerEntry],
+    preview_max_chars: Option<usize>,
+) -> Vec<LedgerTimelinePoint> {
+    let preview_max_chars = preview_max_chars.unwrap_or(DEFAULT_PREVIEW_MAX_CHARS);
     entries
         .iter()
         .map(|entry| LedgerTimelinePoint {
@@ -571,12 +1967,15 @@ fn build_timeline(entries: &[LedgerEntry]) -> Vec<LedgerTimelinePoint> {
             event_type: entry.event_type.clone(),
             agent_id: entry.agent_id.clone(),
             mode: entry.mode.clone(),
-            preview: build_preview(entry.payload.to_string().as_str(), 160),
+            preview: build_preview(entry.payload.to_string().as_str(), preview_max_chars),
         })
         .collect()
 }
 
 fn build_preview(input: &str, max_chars: usize) -> String {
+    if max_chars == 0 {
+        return String::new();
+    }
     let normalized = input.replace('\n', " ").trim().to_string();
     if normalized.chars().count() <= max_chars {
         return normalized;
@@ -689,6 +2088,8 @@ mod tests {
             readable_agents: vec![],
             focus_enabled: true,
             focus_max_chars: 20_000,
+            max_segment_bytes: None,
+            max_retained_segments: None,
         })
         .expect("create ledger");
 
@@ -722,6 +2123,8 @@ mod tests {
             readable_agents: vec![],
             focus_enabled: true,
             focus_max_chars: 10,
+            max_segment_bytes: None,
+            max_retained_segments: None,
         })
         .expect("create ledger");
 
@@ -734,6 +2137,39 @@ mod tests {
         assert_eq!(focus.chars().count(), 10);
     }
 
+    #[test]
+    fn focus_writes_are_kept_as_versioned_history() {
+        let root = temp_root("focus-history");
+        let ledger = ContextLedger::new(ContextLedgerConfig {
+            focus_enabled: true,
+            ..indexed_test_ledger(root.clone()).cfg
+        })
+        .expect("create ledger");
+
+        ledger.insert_focus("plan A", false).expect("first focus");
+        ledger.insert_focus("plan B", false).expect("second focus");
+        ledger.insert_focus("step 2", true).expect("appended focus");
+        assert_eq!(
+            ledger.read_focus().expect("read").as_deref(),
+            Some("plan B\nstep 2")
+        );
+
+        let history = ledger.read_focus_history(10).expect("history");
+        let texts = history
+            .iter()
+            .map(|version| version.text.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(texts, vec!["plan B\nstep 2", "plan B", "plan A"]);
+        assert!(history[0].append);
+        assert!(!history[1].append);
+        assert_eq!(history[0].chars, 13);
+        assert!(history[0].timestamp_ms >= history[2].timestamp_ms);
+
+        let latest = ledger.read_focus_history(1).expect("limited history");
+        assert_eq!(latest, history[..1].to_vec());
+        let _ = fs::remove_dir_all(root);
+    }
+
     #[test]
     fn query_respects_permissions() {
         let root = temp_root("permissions");
@@ -747,6 +2183,8 @@ mod tests {
             readable_agents: vec![],
             focus_enabled: true,
             focus_max_chars: 20_000,
+            max_segment_bytes: None,
+            max_retained_segments: None,
         })
         .expect("create ledger");
         agent_a
@@ -763,6 +2201,8 @@ mod tests {
             readable_agents: vec![],
             focus_enabled: true,
             focus_max_chars: 20_000,
+            max_segment_bytes: None,
+            max_retained_segments: None,
         })
         .expect("create ledger");
 
@@ -775,6 +2215,52 @@ mod tests {
         assert!(matches!(err, ContextLedgerError::PermissionDenied { .. }));
     }
 
+    #[test]
+    fn read_focus_for_respects_permissions() {
+        let root = temp_root("focus-for");
+        let config = |agent_id: &str, readable_agents: Vec<String>| ContextLedgerConfig {
+            root_dir: root.clone(),
+            session_id: "s-focus".to_string(),
+            agent_id: agent_id.to_string(),
+            mode: "main".to_string(),
+            role: None,
+            can_read_all: false,
+            readable_agents,
+            focus_enabled: true,
+            focus_max_chars: 20_000,
+            max_segment_bytes: None,
+            max_retained_segments: None,
+        };
+        let worker = ContextLedger::new(config("worker", vec![])).expect("create ledger");
+        worker
+            .insert_focus("worker findings", false)
+            .expect("insert focus");
+        let supervisor = ContextLedger::new(config("supervisor", vec!["worker".to_string()]))
+            .expect("create ledger");
+        let outsider = ContextLedger::new(config("outsider", vec![])).expect("create ledger");
+
+        assert_eq!(
+            supervisor
+                .read_focus_for("worker", "main")
+                .expect("authorized read"),
+            Some("worker findings".to_string())
+        );
+        assert_eq!(
+            supervisor
+                .read_focus_for("worker", "review")
+                .expect("empty slot"),
+            None
+        );
+        let err = outsider
+            .read_focus_for("worker", "main")
+            .expect_err("should deny");
+        assert!(matches!(
+            err,
+            ContextLedgerError::PermissionDenied { ref agent_id } if agent_id == "worker"
+        ));
+        let _ = fs::remove_dir_all(root);
+    }
+
     #[test]
     fn append_compact_memory_writes_jsonl() {
         let root = temp_root("compact-memory");
@@ -788,6 +2274,8 @@ mod tests {
             readable_agents: vec![],
             focus_enabled: true,
             focus_max_chars: 20_000,
+            max_segment_bytes: None,
+            max_retained_segments: None,
         })
         .expect("create ledger");
 
@@ -834,4 +2322,1068 @@ mod tests {
             .expect("second timestamp");
         assert!(first_ms <= second_ms);
     }
+
+    fn indexed_test_ledger(root: PathBuf) -> ContextLedger {
+        ContextLedger::new(ContextLedgerConfig {
+            root_dir: root,
+            session_id: "s-index".to_string(),
+            agent_id: "a-index".to_string(),
+            mode: "main".to_string(),
+            role: None,
+            can_read_all: false,
+            readable_agents: vec![],
+            focus_enabled: false,
+            focus_max_chars: 1_000,
+            max_segment_bytes: None,
+            max_retained_segments: None,
+        })
+        .expect("create ledger")
+    }
+
+    #[test]
+    fn indexed_query_reads_only_entries_in_requested_range() {
+        let root = temp_root("index-range");
+        let ledger = indexed_test_ledger(root.clone());
+        for index in 0..3_000 {
+            ledger
+                .append_event("tool_call", serde_json::json!({ "index": index }))
+                .expect("append");
+        }
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        let since_ms = now_millis();
+        for index in 0..10 {
+            ledger
+                .append_event("tool_result", serde_json::json!({ "recent": index }))
+                .expect("append");
+        }
+
+        let request = LedgerQueryRequest {
+            since_ms: Some(since_ms),
+            contains: Some("recent".to_string()),
+            limit: Some(50),
+            ..LedgerQueryRequest::default()
+        };
+        let selection =
+            select_entries(ledger.ledger_path().as_path(), &request, None, 50).expect("select");
+        assert_eq!(selection.total, 10);
+        assert_eq!(selection.lines_read, 10);
+        assert_eq!(selection.entries[9].payload["recent"], 9);
+
+        let latest = select_entries(
+            ledger.ledger_path().as_path(),
+            &LedgerQueryRequest::default(),
+            None,
+            5,
+        )
+        .expect("select latest");
+        assert_eq!(latest.total, 3_010);
+        assert_eq!(latest.lines_read, 5);
+
+        let response = ledger.query(&request).expect("query");
+        assert_eq!(response.total, 10);
+        assert!(!response.truncated);
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn indexed_query_catches_up_with_external_writes_and_rewrites() {
+        let root = temp_root("index-external");
+        let ledger = indexed_test_ledger(root.clone());
+        ledger
+            .append_event("turn_start", serde_json::json!({ "text": "first" }))
+            .expect("append");
+        assert_eq!(
+            ledger
+                .query(&LedgerQueryRequest::default())
+                .expect("query")
+                .total,
+            1
+        );
+
+        // Another writer appends directly to the ledger file.
+        let mut external = serde_json::to_value(LedgerEntry {
+            id: "led-external".to_string(),
+            timestamp_ms: now_millis(),
+            timestamp_iso: "external".to_string(),
+            session_id: "s-index".to_string(),
+            agent_id: "a-index".to_string(),
+            mode: "main".to_string(),
+            role: None,
+            event_type: "external_event".to_string(),
+            payload: serde_json::json!({ "text": "from ts" }),
+        })
+        .expect("entry json");
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(ledger.ledger_path())
+            .expect("open ledger");
+        writeln!(file, "{external}").expect("write external entry");
+        ledger
+            .append_event("turn_complete", serde_json::json!({ "text": "last" }))
+            .expect("append");
+
+        let response = ledger.query(&LedgerQueryRequest::default()).expect("query");
+        let event_types = response
+            .entries
+            .iter()
+            .map(|entry| entry.event_type.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            event_types,
+            vec!["turn_start", "external_event", "turn_complete"]
+        );
+
+        // The ledger is rewritten wholesale with a longer entry.
+        external["event_type"] = Value::String("migrated_event_with_longer_name".to_string());
+        external["payload"] = serde_json::json!({ "text": "x".repeat(512) });
+        fs::write(ledger.ledger_path(), format!("{external}\n")).expect("rewrite ledger");
+        let response = ledger
+            .query(&LedgerQueryRequest::default())
+            .expect("query after rewrite");
+        assert_eq!(response.total, 1);
+        assert_eq!(
+            response.entries[0].event_type,
+            "migrated_event_with_longer_name"
+        );
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn query_pages_backward_with_cursor_without_gaps_or_duplicates() {
+        let root = temp_root("cursor-pages");
+        let ledger = indexed_test_ledger(root.clone());
+        for index in 0..200 {
+            ledger
+                .append_event("tool_call", serde_json::json!({ "index": index }))
+                .expect("append");
+        }
+
+        let mut pages = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let response = ledger
+                .query(&LedgerQueryRequest {
+                    limit: Some(50),
+                    before_id: cursor.clone(),
+                    ..LedgerQueryRequest::default()
+                })
+                .expect("query page");
+            assert!(response.entries.len() <= 50);
+            pages.push(response.entries);
+            match response.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+
+        assert_eq!(pages.len(), 4);
+        let indexes = pages
+            .into_iter()
+            .rev()
+            .flatten()
+            .map(|entry| entry.payload["index"].as_u64().expect("index"))
+            .collect::<Vec<_>>();
+        assert_eq!(indexes, (0..200).collect::<Vec<u64>>());
+        let _ = fs::remove_dir_all(root);
+    }
+
+    /// Set on the child processes the cross-process append test spawns; they
+    /// append to the ledger under this root instead of running the test.
+    const APPEND_WRITER_ROOT_ENV: &str = "FINGER_LEDGER_TEST_APPEND_WRITER_ROOT";
+
+    #[test]
+    fn concurrent_appends_from_separate_processes_keep_lines_intact() {
+        // Each writer is its own process, so `LEDGER_WRITE_MUTEX` does not
+        // serialize them and only the advisory file lock keeps lines whole.
+        if let Some(root) = std::env::var_os(APPEND_WRITER_ROOT_ENV) {
+            let ledger = indexed_test_ledger(PathBuf::from(root));
+            for index in 0..200 {
+                ledger
+                    .append_event(
+                        "tool_call",
+                        serde_json::json!({
+                            "pid": std::process::id(),
+                            "index": index,
+                            "padding": "x".repeat(2_048),
+                        }),
+                    )
+                    .expect("append");
+            }
+            return;
+        }
+
+        let root = temp_root("concurrent-append");
+        fs::create_dir_all(&root).expect("create root");
+        let test_binary = std::env::current_exe().expect("test binary");
+        let writers = (0..4)
+            .map(|_| {
+                std::process::Command::new(&test_binary)
+                    .args([
+                        "--exact",
+                        "tests::concurrent_appends_from_separate_processes_keep_lines_intact",
+                        "--quiet",
+                    ])
+                    .env(APPEND_WRITER_ROOT_ENV, &root)
+                    .stdout(std::process::Stdio::null())
+                    .spawn()
+                    .expect("spawn writer")
+            })
+            .collect::<Vec<_>>();
+        for mut writer in writers {
+            assert!(writer.wait().expect("wait for writer").success());
+        }
+
+        let ledger = indexed_test_ledger(root.clone());
+        let content = fs::read_to_string(ledger.ledger_path()).expect("read ledger");
+        let lines = content.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 800);
+        for line in lines {
+            serde_json::from_str::<LedgerEntry>(line).expect("valid ledger entry");
+        }
+        let response = ledger
+            .query(&LedgerQueryRequest {
+                limit: Some(500),
+                ..LedgerQueryRequest::default()
+            })
+            .expect("query");
+        assert_eq!(response.total, 800);
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn redact_entry_scrubs_payload_and_keeps_timeline() {
+        let root = temp_root("redact");
+        let ledger = indexed_test_ledger(root.clone());
+        for index in 0..5 {
+            ledger
+                .append_event(
+                    "tool_call",
+                    serde_json::json!({ "index": index, "secret": format!("ssn-{index}") }),
+                )
+                .expect("append");
+        }
+        let before = ledger
+            .query(&LedgerQueryRequest::default())
+            .expect("query before")
+            .entries;
+        let target = before[2].clone();
+
+        assert!(ledger.redact_entry(&target.id).expect("redact"));
+        assert!(!ledger.redact_entry("led-missing").expect("redact missing"));
+
+        let after = ledger
+            .query(&LedgerQueryRequest::default())
+            .expect("query after")
+            .entries;
+        assert_eq!(after.len(), 5);
+        assert_eq!(after[2].id, target.id);
+        assert_eq!(after[2].timestamp_ms, target.timestamp_ms);
+        assert_eq!(after[2].timestamp_iso, target.timestamp_iso);
+        assert_eq!(after[2].event_type, target.event_type);
+        assert_eq!(after[2].payload, serde_json::json!({ "redacted": true }));
+        for position in [0, 1, 3, 4] {
+            assert_eq!(after[position], before[position]);
+        }
+        let raw = fs::read_to_string(ledger.ledger_path()).expect("read ledger");
+        assert!(!raw.contains("ssn-2"));
+        assert!(!ledger
+            .ledger_path()
+            .with_file_name("context-ledger.jsonl.tmp")
+            .exists());
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn query_multi_merges_sessions_in_timestamp_order() {
+        let root = temp_root("query-multi");
+        let ledger_for = |session_id: &str| {
+            ContextLedger::new(ContextLedgerConfig {
+                root_dir: root.clone(),
+                session_id: session_id.to_string(),
+                agent_id: "a-multi".to_string(),
+                mode: "main".to_string(),
+                role: None,
+                can_read_all: false,
+                readable_agents: vec![],
+                focus_enabled: false,
+                focus_max_chars: 1_000,
+                max_segment_bytes: None,
+                max_retained_segments: None,
+            })
+            .expect("create ledger")
+        };
+        let first = ledger_for("s-one");
+        let second = ledger_for("s-two");
+        for step in 0..6 {
+            let ledger = if step % 2 == 0 { &first } else { &second };
+            ledger
+                .append_event("tool_call", serde_json::json!({ "step": step }))
+                .expect("append");
+            std::thread::sleep(std::time::Duration::from_millis(2));
+        }
+
+        let targets = [
+            QueryTarget {
+                session_id: Some("s-one".to_string()),
+                ..QueryTarget::default()
+            },
+            QueryTarget {
+                session_id: Some("s-two".to_string()),
+                ..QueryTarget::default()
+            },
+        ];
+        let response = first
+            .query_multi(&targets, &LedgerQueryRequest::default())
+            .expect("query multi");
+        let steps = response
+            .entries
+            .iter()
+            .map(|entry| entry.payload["step"].as_u64().expect("step"))
+            .collect::<Vec<_>>();
+        assert_eq!(steps, vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(response.total, 6);
+        assert_eq!(response.timeline.len(), 6);
+        assert!(response.source.contains("s-one"));
+        assert!(response.source.contains("s-two"));
+
+        let limited = first
+            .query_multi(
+                &targets,
+                &LedgerQueryRequest {
+                    limit: Some(4),
+                    ..LedgerQueryRequest::default()
+                },
+            )
+            .expect("query multi limited");
+        let steps = limited
+            .entries
+            .iter()
+            .map(|entry| entry.payload["step"].as_u64().expect("step"))
+            .collect::<Vec<_>>();
+        assert_eq!(steps, vec![2, 3, 4, 5]);
+        assert!(limited.truncated);
+
+        let denied = first.query_multi(
+            &[
+                targets[0].clone(),
+                QueryTarget {
+                    agent_id: Some("someone-else".to_string()),
+                    ..QueryTarget::default()
+                },
+            ],
+            &LedgerQueryRequest::default(),
+        );
+        assert!(matches!(
+            denied,
+            Err(ContextLedgerError::PermissionDenied { .. })
+        ));
+        let _ = fs::remove_dir_all(root);
+    }
+
+    fn segmented_test_ledger(
+        root: PathBuf,
+        max_segment_bytes: u64,
+        max_retained_segments: Option<usize>,
+    ) -> ContextLedger {
+        ContextLedger::new(ContextLedgerConfig {
+            max_segment_bytes: Some(max_segment_bytes),
+            max_retained_segments,
+            ..indexed_test_ledger(root).cfg
+        })
+        .expect("create ledger")
+    }
+
+    #[test]
+    fn rotated_segments_are_queried_as_one_timeline() {
+        let root = temp_root("segments");
+        let ledger = segmented_test_ledger(root.clone(), 1_024, None);
+        for index in 0..30 {
+            ledger
+                .append_event("tool_call", serde_json::json!({ "index": index }))
+                .expect("append");
+        }
+
+        let segments = ledger_segment_paths(&ledger.ledger_path()).expect("segments");
+        assert!(segments.len() > 2);
+        for segment in &segments {
+            assert!(fs::metadata(segment).expect("segment metadata").len() <= 1_024);
+        }
+
+        let response = ledger
+            .query(&LedgerQueryRequest {
+                limit: Some(100),
+                ..LedgerQueryRequest::default()
+            })
+            .expect("query");
+        assert_eq!(response.total, 30);
+        let indexes = response
+            .entries
+            .iter()
+            .map(|entry| entry.payload["index"].as_u64().unwrap_or_default())
+            .collect::<Vec<_>>();
+        assert_eq!(indexes, (0..30).collect::<Vec<_>>());
+
+        // Paging by id crosses segment boundaries.
+        let older = ledger
+            .query(&LedgerQueryRequest {
+                limit: Some(20),
+                before_id: Some(response.entries[25].id.clone()),
+                ..LedgerQueryRequest::default()
+            })
+            .expect("page");
+        assert_eq!(
+            older.entries.first().map(|entry| &entry.payload["index"]),
+            Some(&serde_json::json!(5))
+        );
+        assert_eq!(
+            older.entries.last().map(|entry| &entry.payload["index"]),
+            Some(&serde_json::json!(24))
+        );
+
+        let target = response.entries[1].id.clone();
+        assert!(ledger
+            .redact_entry(&target)
+            .expect("redact in rotated segment"));
+        let redacted = ledger
+            .query(&LedgerQueryRequest {
+                limit: Some(100),
+                ..LedgerQueryRequest::default()
+            })
+            .expect("query after redact");
+        assert_eq!(redacted.total, 30);
+        assert_eq!(
+            redacted.entries[1].payload,
+            serde_json::json!({ "redacted": true })
+        );
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn rotation_drops_segments_beyond_retention_cap() {
+        let root = temp_root("segment-cap");
+        let ledger = segmented_test_ledger(root.clone(), 1_024, Some(1));
+        for index in 0..30 {
+            ledger
+                .append_event("tool_call", serde_json::json!({ "index": index }))
+                .expect("append");
+        }
+
+        let segments = ledger_segment_paths(&ledger.ledger_path()).expect("segments");
+        assert_eq!(segments.len(), 2);
+        let response = ledger
+            .query(&LedgerQueryRequest {
+                limit: Some(100),
+                ..LedgerQueryRequest::default()
+            })
+            .expect("query");
+        assert!(response.total < 30);
+        assert_eq!(
+            response.entries.last().expect("newest").payload["index"],
+            29
+        );
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn iter_entries_folds_the_same_matches_query_counts() {
+        let root = temp_root("iter-entries");
+        let ledger = segmented_test_ledger(root.clone(), 4_096, None);
+        for index in 0..600 {
+            let event_type = if index % 3 == 0 {
+                "tool_call"
+            } else {
+                "model_round"
+            };
+            ledger
+                .append_event(event_type, serde_json::json!({ "index": index }))
+                .expect("append");
+        }
+        assert!(
+            ledger_segment_paths(&ledger.ledger_path())
+                .expect("segments")
+                .len()
+                > 1
+        );
+
+        let request = LedgerQueryRequest {
+            event_types: vec!["tool_call".to_string()],
+            ..LedgerQueryRequest::default()
+        };
+        let (count, index_sum) = ledger
+            .iter_entries(&request)
+            .expect("iterate")
+            .try_fold((0, 0), |(count, sum), entry| {
+                entry.map(|entry| {
+                    (
+                        count + 1,
+                        sum + entry.payload["index"].as_u64().unwrap_or(0),
+                    )
+                })
+            })
+            .expect("read entries");
+        let response = ledger.query(&request).expect("query");
+        assert_eq!(count, response.total);
+        assert_eq!(count, 200);
+        assert_eq!(index_sum, (0..600).step_by(3).sum::<u64>());
+
+        let denied = ledger.iter_entries(&LedgerQueryRequest {
+            agent_id: Some("someone-else".to_string()),
+            ..LedgerQueryRequest::default()
+        });
+        assert!(matches!(
+            denied,
+            Err(ContextLedgerError::PermissionDenied { .. })
+        ));
+        let _ = fs::remove_dir_all(root);
+    }
+
+    /// Reference for the streaming text path: every entry parsed into
+    /// memory, filtered, then cut at the cursor.
+    fn full_load_selection(
+        ledger: &ContextLedger,
+        request: &LedgerQueryRequest,
+    ) -> (Vec<String>, usize) {
+        let pattern = compile_query_regex(request).expect("regex");
+        let filter = EntryFilter::new(request, pattern.as_ref());
+        let mut entries = Vec::new();
+        for segment in ledger_segment_paths(&ledger.ledger_path()).expect("segments") {
+            for line in fs::read_to_string(segment).expect("read segment").lines() {
+                let entry = serde_json::from_str::<LedgerEntry>(line).expect("entry");
+                if filter.matches(&entry) {
+                    entries.push(entry);
+                }
+            }
+        }
+        entries.sort_by_key(|entry| entry.timestamp_ms);
+        entries.truncate(cursor_end(&entries, request, |entry| {
+            (entry.id.as_str(), entry.timestamp_ms)
+        }));
+        let total = entries.len();
+        let limit = request.limit.unwrap_or(50).clamp(1, 500);
+        let ids = entries[total.saturating_sub(limit)..]
+            .iter()
+            .map(|entry| entry.id.clone())
+            .collect();
+        (ids, total)
+    }
+
+    #[test]
+    fn streaming_text_queries_match_full_load_results() {
+        let root = temp_root("streaming");
+        let ledger = segmented_test_ledger(root.clone(), 64 * 1_024, None);
+        for index in 0..4_000 {
+            let event_type = if index % 3 == 0 {
+                "tool_call"
+            } else {
+                "turn_end"
+            };
+            let tag = if index % 7 == 0 { "needle" } else { "hay" };
+            ledger
+                .append_event(
+                    event_type,
+                    serde_json::json!({ "index": index, "tag": tag, "text": format!("step {index} {tag}") }),
+                )
+                .expect("append");
+        }
+        let all = ledger
+            .query(&LedgerQueryRequest {
+                limit: Some(500),
+                contains: Some("step".to_string()),
+                ..LedgerQueryRequest::default()
+            })
+            .expect("query all");
+        let middle = &all.entries[250];
+
+        let requests = vec![
+            LedgerQueryRequest {
+                contains: Some("needle".to_string()),
+                limit: Some(40),
+                ..LedgerQueryRequest::default()
+            },
+            LedgerQueryRequest {
+                regex: Some(r"step \d*5 ".to_string()),
+                event_types: vec!["tool_call".to_string()],
+                limit: Some(25),
+                ..LedgerQueryRequest::default()
+            },
+            LedgerQueryRequest {
+                contains: Some("step".to_string()),
+                since_ms: Some(all.entries[100].timestamp_ms),
+                until_ms: Some(middle.timestamp_ms),
+                limit: Some(30),
+                ..LedgerQueryRequest::default()
+            },
+            LedgerQueryRequest {
+                contains: Some("step".to_string()),
+                before_id: Some(middle.id.clone()),
+                limit: Some(20),
+                ..LedgerQueryRequest::default()
+            },
+            LedgerQueryRequest {
+                regex: Some("needle".to_string()),
+                payload_path: Some("tag".to_string()),
+                before_id: Some("led-unknown".to_string()),
+                before_ms: Some(middle.timestamp_ms),
+                limit: Some(15),
+                ..LedgerQueryRequest::default()
+            },
+            LedgerQueryRequest {
+                contains: Some("needle".to_string()),
+                before_id: Some("led-unknown".to_string()),
+                ..LedgerQueryRequest::default()
+            },
+        ];
+        for request in &requests {
+            let streamed = ledger.query(request).expect("streamed query");
+            let streamed_ids = streamed
+                .entries
+                .iter()
+                .map(|entry| entry.id.clone())
+                .collect::<Vec<_>>();
+            assert_eq!(
+                (streamed_ids, streamed.total),
+                full_load_selection(&ledger, request),
+                "{request:?}"
+            );
+        }
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn iso_time_bounds_parse_and_yield_to_millisecond_bounds() {
+        assert_eq!(
+            parse_iso_millis(Some("2026-01-02T03:04:05.678Z"), "since_iso").expect("utc"),
+            Some(1_767_323_045_678)
+        );
+        assert_eq!(
+            parse_iso_millis(Some("2026-01-02T11:04:05.678+08:00"), "since_iso").expect("offset"),
+            Some(1_767_323_045_678)
+        );
+
+        let root = temp_root("iso-bounds");
+        let ledger = indexed_test_ledger(root.clone());
+        for index in 0..3 {
+            ledger
+                .append_event("tool_call", serde_json::json!({ "index": index }))
+                .expect("append");
+        }
+        let query_total = |request: LedgerQueryRequest| ledger.query(&request).map(|r| r.total);
+
+        let future = "2999-01-01T00:00:00Z".to_string();
+        assert_eq!(
+            query_total(LedgerQueryRequest {
+                since_iso: Some(future.clone()),
+                ..LedgerQueryRequest::default()
+            })
+            .expect("since iso"),
+            0
+        );
+        assert_eq!(
+            query_total(LedgerQueryRequest {
+                since_ms: Some(0),
+                since_iso: Some(future.clone()),
+                until_iso: Some(future),
+                ..LedgerQueryRequest::default()
+            })
+            .expect("ms precedence"),
+            3
+        );
+        assert!(matches!(
+            query_total(LedgerQueryRequest {
+                until_iso: Some("yesterday".to_string()),
+                ..LedgerQueryRequest::default()
+            }),
+            Err(ContextLedgerError::InvalidConfig(_))
+        ));
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn timeline_preview_length_follows_request() {
+        let root = temp_root("preview");
+        let ledger = indexed_test_ledger(root.clone());
+        ledger
+            .append_event(
+                "turn_end",
+                serde_json::json!({ "text": "焦点".repeat(200) }),
+            )
+            .expect("append");
+
+        let default = ledger
+            .query(&LedgerQueryRequest::default())
+            .expect("default preview");
+        assert_eq!(default.timeline[0].preview.chars().count(), 160 + 3);
+
+        let custom = ledger
+            .query(&LedgerQueryRequest {
+                preview_max_chars: Some(20),
+                ..LedgerQueryRequest::default()
+            })
+            .expect("custom preview");
+        let preview = &custom.timeline[0].preview;
+        assert_eq!(preview.chars().count(), 20 + 3);
+        assert!(preview.starts_with("{\"text\":\"焦点"));
+        assert!(preview.ends_with("..."));
+
+        let omitted = ledger
+            .query(&LedgerQueryRequest {
+                preview_max_chars: Some(0),
+                ..LedgerQueryRequest::default()
+            })
+            .expect("omitted preview");
+        assert!(omitted.timeline[0].preview.is_empty());
+        let point = serde_json::to_value(&omitted.timeline[0]).expect("timeline json");
+        assert!(point.get("preview").is_none());
+        assert_eq!(point["event_type"], "turn_end");
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn stats_count_every_filtered_entry_despite_limit() {
+        let root = temp_root("stats");
+        let ledger = indexed_test_ledger(root.clone());
+        let mix = [("tool_call", 5), ("tool_result", 4), ("model_round", 3)];
+        for (event_type, count) in mix {
+            for index in 0..count {
+                ledger
+                    .append_event(event_type, serde_json::json!({ "index": index }))
+                    .expect("append");
+            }
+        }
+
+        let response = ledger
+            .query(&LedgerQueryRequest {
+                limit: Some(2),
+                include_stats: true,
+                ..LedgerQueryRequest::default()
+            })
+            .expect("query with stats");
+        assert_eq!(response.entries.len(), 2);
+        assert!(response.truncated);
+        let stats = response.stats.expect("stats");
+        let expected = mix
+            .iter()
+            .map(|(event_type, count)| (event_type.to_string(), *count))
+            .collect::<BTreeMap<_, _>>();
+        assert_eq!(stats.event_type_counts, expected);
+        assert_eq!(
+            stats.max_timestamp_ms,
+            Some(response.entries[1].timestamp_ms)
+        );
+        assert!(stats.min_timestamp_ms <= stats.max_timestamp_ms);
+
+        let filtered = ledger
+            .query(&LedgerQueryRequest {
+                limit: Some(1),
+                contains: Some("index".to_string()),
+                event_types: vec!["tool_result".to_string(), "model_round".to_string()],
+                include_stats: true,
+                ..LedgerQueryRequest::default()
+            })
+            .expect("filtered query with stats");
+        let filtered_stats = filtered.stats.expect("filtered stats");
+        assert_eq!(
+            filtered_stats.event_type_counts.get("tool_result"),
+            Some(&4)
+        );
+        assert_eq!(
+            filtered_stats.event_type_counts.get("model_round"),
+            Some(&3)
+        );
+        assert!(!filtered_stats.event_type_counts.contains_key("tool_call"));
+
+        let without = ledger
+            .query(&LedgerQueryRequest::default())
+            .expect("query without stats");
+        assert!(without.stats.is_none());
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn regex_query_matches_payload_text() {
+        let root = temp_root("regex-query");
+        let ledger = indexed_test_ledger(root.clone());
+        for command in ["cargo test -p core", "git status", "cargo build"] {
+            ledger
+                .append_event("tool_call", serde_json::json!({ "cmd": command }))
+                .expect("append");
+        }
+
+        let response = ledger
+            .query(&LedgerQueryRequest {
+                regex: Some(r"cargo (test|build)\b".to_string()),
+                ..LedgerQueryRequest::default()
+            })
+            .expect("regex query");
+        let commands = response
+            .entries
+            .iter()
+            .map(|entry| entry.payload["cmd"].as_str().unwrap_or_default())
+            .collect::<Vec<_>>();
+        assert_eq!(commands, vec!["cargo test -p core", "cargo build"]);
+
+        let invalid = ledger.query(&LedgerQueryRequest {
+            regex: Some("cargo (".to_string()),
+            ..LedgerQueryRequest::default()
+        });
+        assert!(matches!(invalid, Err(ContextLedgerError::InvalidConfig(_))));
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn regex_query_scoped_to_payload_path_ignores_other_fields() {
+        let root = temp_root("regex-path");
+        let ledger = indexed_test_ledger(root.clone());
+        ledger
+            .append_event(
+                "tool_call",
+                serde_json::json!({ "tool_name": "shell.exec", "input": { "cmd": "ls" } }),
+            )
+            .expect("append");
+        ledger
+            .append_event(
+                "tool_call",
+                serde_json::json!({ "tool_name": "file.read", "input": { "cmd": "shell.exec" } }),
+            )
+            .expect("append");
+
+        let scoped = ledger
+            .query(&LedgerQueryRequest {
+                regex: Some("^shell\\.".to_string()),
+                payload_path: Some("tool_name".to_string()),
+                ..LedgerQueryRequest::default()
+            })
+            .expect("scoped query");
+        assert_eq!(scoped.total, 1);
+        assert_eq!(scoped.entries[0].payload["tool_name"], "shell.exec");
+
+        let nested = ledger
+            .query(&LedgerQueryRequest {
+                regex: Some("^shell\\.".to_string()),
+                payload_path: Some("input.cmd".to_string()),
+                ..LedgerQueryRequest::default()
+            })
+            .expect("nested query");
+        assert_eq!(nested.total, 1);
+        assert_eq!(nested.entries[0].payload["tool_name"], "file.read");
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn training_export_writes_completed_turns_as_chat_messages() {
+        let root = temp_root("training-export");
+        let ledger = indexed_test_ledger(root.clone());
+        let events = [
+            (
+                "turn_start",
+                serde_json::json!({ "user_text": "list files" }),
+            ),
+            (
+                "model_round",
+                serde_json::json!({ "round": 1, "output_text": null }),
+            ),
+            (
+                "tool_call",
+                serde_json::json!({
+                    "call_id": "call-1",
+                    "name": "shell_exec",
+                    "arguments": "{\"cmd\":\"ls\"}",
+                    "tool_name": "shell.exec",
+                }),
+            ),
+            (
+                "tool_result",
+                serde_json::json!({ "call_id": "call-1", "tool_name": "shell.exec", "output": "a.txt" }),
+            ),
+            (
+                "model_round",
+                serde_json::json!({ "round": 2, "output_text": "Found a.txt." }),
+            ),
+            (
+                "turn_complete",
+                serde_json::json!({ "finish_reason": "stop" }),
+            ),
+            (
+                "turn_start",
+                serde_json::json!({ "user_text": "<environment_context>cwd</environment_context>" }),
+            ),
+            (
+                "model_round",
+                serde_json::json!({ "round": 1, "output_text": "ok" }),
+            ),
+            ("turn_complete", serde_json::json!({})),
+            (
+                "turn_start",
+                serde_json::json!({ "user_text": "fail please" }),
+            ),
+            ("turn_error", serde_json::json!({ "error": "boom" })),
+        ];
+        for (event_type, payload) in events {
+            ledger.append_event(event_type, payload).expect("append");
+        }
+
+        let mut out = Vec::new();
+        let written = ledger.export_training_jsonl(&mut out).expect("export");
+        assert_eq!(written, 1);
+        let text = String::from_utf8(out).expect("utf8");
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 1);
+        let example: Value = serde_json::from_str(lines[0]).expect("json line");
+        let output = serde_json::json!({ "ok": true, "tool": "shell.exec", "result": "a.txt" });
+        assert_eq!(
+            example,
+            serde_json::json!({
+                "messages": [
+                    { "role": "user", "content": "list files" },
+                    {
+                        "role": "assistant",
+                        "content": null,
+                        "tool_calls": [{
+                            "id": "call-1",
+                            "type": "function",
+                            "function": { "name": "shell_exec", "arguments": "{\"cmd\":\"ls\"}" },
+                        }],
+                    },
+                    { "role": "tool", "tool_call_id": "call-1", "content": output.to_string() },
+                    { "role": "assistant", "content": "Found a.txt." },
+                ]
+            })
+        );
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn queries_build_the_index_in_memory_without_writing_it() {
+        let root = temp_root("index-read-only");
+        let ledger = indexed_test_ledger(root.clone());
+        ledger
+            .append_event("turn_start", serde_json::json!({ "text": "first" }))
+            .expect("append");
+        let index_path = ledger_index_path(&ledger.ledger_path());
+        fs::remove_file(&index_path).expect("remove index");
+
+        let response = ledger.query(&LedgerQueryRequest::default()).expect("query");
+        assert_eq!(response.total, 1);
+        assert!(!index_path.exists());
+
+        ledger
+            .append_event("turn_complete", serde_json::json!({ "text": "last" }))
+            .expect("append");
+        let ledger_len = fs::metadata(ledger.ledger_path()).expect("ledger").len();
+        assert_eq!(
+            read_indexed_end(&index_path).expect("index end"),
+            ledger_len
+        );
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn list_sessions_finds_every_ledger_under_the_root() {
+        let root = temp_root("list-sessions");
+        let open = |session_id: &str, agent_id: &str, mode: &str| {
+            ContextLedger::new(ContextLedgerConfig {
+                root_dir: root.clone(),
+                session_id: session_id.to_string(),
+                agent_id: agent_id.to_string(),
+                mode: mode.to_string(),
+                role: None,
+                can_read_all: false,
+                readable_agents: vec![],
+                focus_enabled: false,
+                focus_max_chars: 20_000,
+                max_segment_bytes: None,
+                max_retained_segments: None,
+            })
+            .expect("create ledger")
+        };
+        let first = open("s-list-1", "coder", "main");
+        first
+            .append_event("turn_start", serde_json::json!({"text":"hello"}))
+            .expect("append");
+        first
+            .append_event("turn_complete", serde_json::json!({"text":"bye"}))
+            .expect("append");
+        open("s-list-2", "reviewer", "review")
+            .append_event("turn_start", serde_json::json!({"text":"check"}))
+            .expect("append");
+        fs::create_dir_all(root.join("notes").join("drafts").join("misc")).expect("create dir");
+        let corrupt_dir = root.join("s-list-3").join("coder").join("main");
+        fs::create_dir_all(&corrupt_dir).expect("create dir");
+        fs::write(corrupt_dir.join("context-ledger.jsonl"), "not json\n").expect("write ledger");
+        fs::write(root.join("README.txt"), "not a ledger").expect("write file");
+
+        let sessions = ContextLedger::list_sessions(&root).expect("list sessions");
+
+        let triples = sessions
+            .iter()
+            .map(|session| {
+                (
+                    session.session_id.as_str(),
+                    session.agent_id.as_str(),
+                    session.mode.as_str(),
+                    session.entry_count,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            triples,
+            vec![
+                ("s-list-1", "coder", "main", 2),
+                ("s-list-2", "reviewer", "review", 1),
+            ]
+        );
+        assert!(sessions
+            .iter()
+            .all(|session| session.latest_timestamp_ms.is_some()));
+        assert!(!corrupt_dir.join("context-ledger-index.jsonl").exists());
+        assert!(ContextLedger::list_sessions(&root.join("missing"))
+            .expect("list missing root")
+            .is_empty());
+    }
+
+    #[test]
+    fn root_dir_prefers_ledger_root_over_home() {
+        assert_eq!(
+            resolve_root_dir(
+                Some(" /srv/ledgers ".to_string()),
+                Some("/home/me".to_string())
+            ),
+            PathBuf::from("/srv/ledgers")
+        );
+        assert_eq!(
+            resolve_root_dir(Some("  ".to_string()), Some("/home/me".to_string())),
+            PathBuf::from("/home/me/.finger/sessions")
+        );
+        assert_eq!(
+            resolve_root_dir(None, None),
+            PathBuf::from("./.finger/sessions")
+        );
+    }
+
+    #[test]
+    fn open_locked_follows_a_ledger_replaced_while_waiting() {
+        let root = temp_root("open-locked");
+        fs::create_dir_all(&root).expect("create root");
+        let path = root.join("context-ledger.jsonl");
+        fs::write(&path, "old\n").expect("write ledger");
+
+        // Stands in for a redaction in another process holding the lock.
+        let holder = File::open(&path).expect("open ledger");
+        holder.lock().expect("lock ledger");
+        let waiter = {
+            let path = path.clone();
+            std::thread::spawn(move || open_locked(&path, OpenOptions::new().append(true)))
+        };
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        replace_file_atomically(&path, b"new\n").expect("replace ledger");
+        holder.unlock().expect("unlock ledger");
+
+        let mut file = waiter.join().expect("join waiter").expect("open locked");
+        file.write_all(b"appended\n").expect("append");
+        assert_eq!(
+            fs::read_to_string(&path).expect("read ledger"),
+            "new\nappended\n"
+        );
+        let _ = fs::remove_dir_all(root);
+    }
 }
@@ -1,14 +1,18 @@
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 use async_trait::async_trait;
+use finger_kernel_context_ledger::{ContextLedger, ContextLedgerConfig};
 use finger_kernel_protocol::{
-    ErrorEvent, Event, EventMsg, InputItem, Op, SessionConfiguredEvent, Submission,
-    TaskCompleteEvent, TaskStartedEvent, TurnAbortReason, TurnAbortedEvent, UserTurnOptions,
+    ApprovalKind, ErrorEvent, Event, EventMsg, InputItem, ModelRoundEvent, Op, ReviewDecision,
+    SessionConfiguredEvent, Submission, TaskCompleteEvent, TaskStartedEvent, TurnAbortReason,
+    TurnAbortedEvent, UsageEvent, UserTurnOptions,
 };
 use serde_json::Value;
 use thiserror::Error;
 use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio::sync::oneshot;
 use tokio::task::JoinHandle;
 
 #[derive(Debug, Clone)]
@@ -16,6 +20,16 @@ pub struct KernelConfig {
     pub session_id: String,
     pub channel_capacity: usize,
     pub task_idle_timeout: Duration,
+    /// Wall-clock cap on a single `run_turn`; `None` lets turns run unbounded.
+    /// Also bounds how long in-flight turns may keep running once every
+    /// submitter hung up; turns still running then are aborted.
+    pub turn_timeout: Option<Duration>,
+    /// When set, a `UserTurn` for a session with a running task aborts that
+    /// task (`TaskReplaced`) and starts a fresh one instead of being injected.
+    pub replace_running_task: bool,
+    /// Ledger every emitted event is appended to before it is delivered;
+    /// `None` keeps events in memory only.
+    pub event_ledger: Option<ContextLedgerConfig>,
 }
 
 impl Default for KernelConfig {
@@ -24,6 +38,9 @@ impl Default for KernelConfig {
             session_id: "finger-kernel".to_string(),
             channel_capacity: 128,
             task_idle_timeout: Duration::from_millis(200),
+            turn_timeout: None,
+            replace_running_task: false,
+            event_ledger: None,
         }
     }
 }
@@ -32,6 +49,26 @@ impl Default for KernelConfig {
 pub struct TurnRunResult {
     pub last_agent_message: Option<String>,
     pub metadata_json: Option<String>,
+    pub metrics: TurnMetrics,
+    /// Set when the turn stopped without a final message, e.g.
+    /// `tool_loop_exceeded`.
+    pub finish_reason: Option<String>,
+}
+
+/// Counts and wall-clock timings for one turn; token counts are summed over
+/// every model round.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TurnMetrics {
+    pub rounds: u64,
+    pub tool_calls: u64,
+    pub tool_errors: u64,
+    pub total_duration_ms: u64,
+    /// Time spent waiting on provider requests, retries included.
+    pub model_duration_ms: u64,
+    /// Time spent running tool batches, approvals included.
+    pub tool_duration_ms: u64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -47,6 +84,137 @@ pub trait ChatEngine: Send + Sync {
         request: &TurnRequest,
         progress_tx: Option<UnboundedSender<EventMsg>>,
     ) -> Result<TurnRunResult, String>;
+
+    /// Runs a turn whose approval-gated tool calls wait on `approvals`.
+    /// Engines without approval support run the turn unchanged.
+    async fn run_turn_with_approvals(
+        &self,
+        request: &TurnRequest,
+        progress_tx: Option<UnboundedSender<EventMsg>>,
+        approvals: ApprovalBroker,
+    ) -> Result<TurnRunResult, String> {
+        let _ = approvals;
+        self.run_turn(request, progress_tx).await
+    }
+}
+
+/// Routes `ExecApproval`/`PatchApproval` decisions to the tool call waiting
+/// on them, keyed by call id. Shared by every task of a runtime, so
+/// `ApprovedForSession` lasts for the whole session.
+#[derive(Debug, Clone, Default)]
+pub struct ApprovalBroker {
+    state: Arc<Mutex<ApprovalState>>,
+    owner: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct ApprovalState {
+    pending: HashMap<String, PendingApproval>,
+    approved_for_session: HashSet<String>,
+    /// Set once nobody is left to answer; later registrations abort at once.
+    closed: bool,
+}
+
+#[derive(Debug)]
+struct PendingApproval {
+    kind: ApprovalKind,
+    tool_name: String,
+    owner: Option<String>,
+    decision_tx: oneshot::Sender<ReviewDecision>,
+}
+
+impl ApprovalBroker {
+    /// Returns a handle sharing this broker's state whose registrations are
+    /// attributed to the task running under `task_key`.
+    fn for_task(&self, task_key: &str) -> Self {
+        Self {
+            state: Arc::clone(&self.state),
+            owner: Some(task_key.to_string()),
+        }
+    }
+
+    /// Registers a pending approval. Call this before emitting the request
+    /// event so a fast decision cannot arrive first. A dropped sender (e.g.
+    /// the runtime shut down) reads as `Abort`.
+    pub fn register(
+        &self,
+        call_id: &str,
+        kind: ApprovalKind,
+        tool_name: &str,
+    ) -> oneshot::Receiver<ReviewDecision> {
+        let (decision_tx, decision_rx) = oneshot::channel();
+        let mut state = self.lock_state();
+        if !state.closed {
+            state.pending.insert(
+                call_id.to_string(),
+                PendingApproval {
+                    kind,
+                    tool_name: tool_name.to_string(),
+                    owner: self.owner.clone(),
+                    decision_tx,
+                },
+            );
+        }
+        decision_rx
+    }
+
+    pub fn is_pending(&self, call_id: &str, kind: ApprovalKind) -> bool {
+        self.lock_state()
+            .pending
+            .get(call_id)
+            .is_some_and(|pending| pending.kind == kind)
+    }
+
+    fn pending_owner(&self, call_id: &str) -> Option<String> {
+        self.lock_state()
+            .pending
+            .get(call_id)
+            .and_then(|pending| pending.owner.clone())
+    }
+
+    pub fn is_approved_for_session(&self, tool_name: &str) -> bool {
+        self.lock_state().approved_for_session.contains(tool_name)
+    }
+
+    /// Delivers `decision` to the matching pending call. Returns `false` when
+    /// no call of that kind is waiting on `call_id`.
+    pub fn resolve(&self, call_id: &str, kind: ApprovalKind, decision: ReviewDecision) -> bool {
+        let mut state = self.lock_state();
+        let pending = match state.pending.remove(call_id) {
+            Some(pending) if pending.kind == kind => pending,
+            Some(other) => {
+                state.pending.insert(call_id.to_string(), other);
+                return false;
+            }
+            None => return false,
+        };
+        if decision == ReviewDecision::ApprovedForSession {
+            state.approved_for_session.insert(pending.tool_name);
+        }
+        let _ = pending.decision_tx.send(decision);
+        true
+    }
+
+    /// Drops the calls registered by the task under `task_key`, so a late
+    /// decision cannot resolve a call whose turn is gone.
+    fn cancel_task(&self, task_key: &str) {
+        self.lock_state()
+            .pending
+            .retain(|_, pending| pending.owner.as_deref() != Some(task_key));
+    }
+
+    /// Aborts every waiting call, and every call registered from now on.
+    fn close(&self) {
+        let mut state = self.lock_state();
+        state.closed = true;
+        state.pending.clear();
+    }
+
+    fn lock_state(&self) -> std::sync::MutexGuard<'_, ApprovalState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
 }
 
 pub struct EchoChatEngine;
@@ -60,29 +228,116 @@ impl ChatEngine for EchoChatEngine {
     ) -> Result<TurnRunResult, String> {
         let last = request.items.iter().rev().find_map(|item| match item {
             InputItem::Text { text } => Some(text.clone()),
-            InputItem::Image { .. } | InputItem::LocalImage { .. } => None,
+            InputItem::Image { .. } | InputItem::LocalImage { .. } | InputItem::File { .. } => None,
         });
         Ok(TurnRunResult {
             last_agent_message: last,
             metadata_json: None,
+            metrics: TurnMetrics::default(),
+            finish_reason: None,
         })
     }
 }
 
+/// Offline engine that answers each turn with the next recorded result, for
+/// deterministic tests of event handling and metadata parsing. Every turn
+/// emits one `ModelRound` per recorded round and a closing `Usage` event;
+/// once the recording runs out, turns fail.
+#[derive(Debug, Default)]
+pub struct ReplayChatEngine {
+    recorded: Mutex<VecDeque<TurnRunResult>>,
+}
+
+impl ReplayChatEngine {
+    pub fn new(recorded: impl IntoIterator<Item = TurnRunResult>) -> Self {
+        Self {
+            recorded: Mutex::new(recorded.into_iter().collect()),
+        }
+    }
+
+    /// Results not yet replayed.
+    pub fn remaining(&self) -> usize {
+        self.lock_recorded().len()
+    }
+
+    fn lock_recorded(&self) -> std::sync::MutexGuard<'_, VecDeque<TurnRunResult>> {
+        self.recorded
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[async_trait]
+impl ChatEngine for ReplayChatEngine {
+    async fn run_turn(
+        &self,
+        request: &TurnRequest,
+        progress_tx: Option<UnboundedSender<EventMsg>>,
+    ) -> Result<TurnRunResult, String> {
+        let result = self
+            .lock_recorded()
+            .pop_front()
+            .ok_or_else(|| "replay engine has no recorded results left".to_string())?;
+        if let Some(tx) = progress_tx {
+            let metrics = result.metrics;
+            let rounds = metrics.rounds.max(1);
+            let history_items_count = (request.options.history_items.len() + 1) as u64;
+            for round in 1..=rounds {
+                let last = round == rounds;
+                let _ = tx.send(EventMsg::ModelRound(ModelRoundEvent {
+                    seq: round,
+                    round,
+                    function_calls_count: 0,
+                    reasoning_count: 0,
+                    history_items_count,
+                    has_output_text: last && result.last_agent_message.is_some(),
+                    finish_reason: result.finish_reason.clone().filter(|_| last),
+                    response_status: Some("completed".to_string()),
+                    response_incomplete_reason: None,
+                    response_id: None,
+                    request_id: None,
+                    input_tokens: last.then_some(metrics.input_tokens),
+                    output_tokens: last.then_some(metrics.output_tokens),
+                    total_tokens: last.then_some(metrics.input_tokens + metrics.output_tokens),
+                    estimated_tokens_in_context_window: None,
+                    estimated_tokens_compactable: None,
+                    context_usage_percent: None,
+                    max_input_tokens: None,
+                    threshold_percent: None,
+                }));
+            }
+            let _ = tx.send(EventMsg::Usage(UsageEvent {
+                seq: rounds + 1,
+                input_tokens: metrics.input_tokens,
+                output_tokens: metrics.output_tokens,
+                total_tokens: metrics.input_tokens + metrics.output_tokens,
+                reasoning_tokens: None,
+                rounds,
+            }));
+        }
+        Ok(result)
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum KernelError {
     #[error("failed to send submission: runtime channel closed")]
     SubmissionChannelClosed,
+    #[error("submissions to this runtime handle were closed")]
+    RuntimeClosed,
     #[error("kernel join failed: {0}")]
     Join(#[from] tokio::task::JoinError),
 }
 
 pub struct KernelRuntime {
-    submission_tx: mpsc::Sender<Submission>,
+    /// `None` once `close_submissions` has run.
+    submission_tx: Option<mpsc::Sender<Submission>>,
     event_rx: mpsc::Receiver<Event>,
     loop_handle: JoinHandle<()>,
 }
 
+/// Running tasks are keyed by session id, so follow-up turns for a session
+/// are injected into its task while other sessions run alongside it.
 struct RunningTask {
     sub_id: String,
     input_tx: mpsc::Sender<TurnRequest>,
@@ -97,30 +352,44 @@ impl KernelRuntime {
     pub fn spawn_with_engine(config: KernelConfig, chat_engine: Arc<dyn ChatEngine>) -> Self {
         let (submission_tx, submission_rx) = mpsc::channel(config.channel_capacity);
         let (event_tx, event_rx) = mpsc::channel(config.channel_capacity);
+        let event_tx = match config.event_ledger.clone() {
+            Some(ledger_config) => {
+                spawn_event_persister(ledger_config, event_tx, config.channel_capacity)
+            }
+            None => event_tx,
+        };
 
         let loop_handle = tokio::spawn(submission_loop(
             config,
             submission_rx,
-            event_tx,
+            EventSender::new(event_tx),
             chat_engine,
         ));
 
         Self {
-            submission_tx,
+            submission_tx: Some(submission_tx),
             event_rx,
             loop_handle,
         }
     }
 
     pub async fn submit(&self, submission: Submission) -> Result<(), KernelError> {
-        self.submission_tx
+        self.submission_sender()?
             .send(submission)
             .await
             .map_err(|_| KernelError::SubmissionChannelClosed)
     }
 
-    pub fn submission_sender(&self) -> mpsc::Sender<Submission> {
-        self.submission_tx.clone()
+    pub fn submission_sender(&self) -> Result<mpsc::Sender<Submission>, KernelError> {
+        self.submission_tx.clone().ok_or(KernelError::RuntimeClosed)
+    }
+
+    /// Drops this handle's submission sender, after which `submit` fails with
+    /// `RuntimeClosed`. Once every sender from `submission_sender` is gone
+    /// too, the runtime lets running turns finish, within `turn_timeout` when
+    /// set, and then emits `ShutdownComplete`.
+    pub fn close_submissions(&mut self) {
+        self.submission_tx = None;
     }
 
     pub fn events_mut(&mut self) -> &mut mpsc::Receiver<Event> {
@@ -133,37 +402,91 @@ impl KernelRuntime {
     }
 }
 
+/// Returns a sender whose events are appended to the ledger described by
+/// `ledger_config` and then forwarded to `event_tx`, in order. If the ledger
+/// cannot be opened, events go straight to `event_tx`.
+fn spawn_event_persister(
+    ledger_config: ContextLedgerConfig,
+    event_tx: mpsc::Sender<Event>,
+    channel_capacity: usize,
+) -> mpsc::Sender<Event> {
+    let ledger = match ContextLedger::new(ledger_config) {
+        Ok(ledger) => ledger,
+        Err(error) => {
+            eprintln!("event ledger disabled: {error}");
+            return event_tx;
+        }
+    };
+    let ledger = Arc::new(ledger);
+    let (persist_tx, mut persist_rx) = mpsc::channel::<Event>(channel_capacity);
+    tokio::spawn(async move {
+        while let Some(event) = persist_rx.recv().await {
+            if let Ok(payload) = serde_json::to_value(&event) {
+                let event_type = payload["msg"]["type"]
+                    .as_str()
+                    .unwrap_or("event")
+                    .to_string();
+                // Appends lock the file and fsync, so they stay off the
+                // async workers; awaiting each one keeps ledger order.
+                let ledger = Arc::clone(&ledger);
+                let appended = tokio::task::spawn_blocking(move || {
+                    ledger
+                        .append_event(&event_type, payload)
+                        .map_err(|error| format!("failed to persist {event_type} event: {error}"))
+                })
+                .await;
+                match appended {
+                    Ok(Ok(_)) => {}
+                    Ok(Err(message)) => eprintln!("{message}"),
+                    Err(error) => eprintln!("event persister failed: {error}"),
+                }
+            }
+            if event_tx.send(event).await.is_err() {
+                break;
+            }
+        }
+    });
+    persist_tx
+}
+
 async fn submission_loop(
     config: KernelConfig,
     mut submission_rx: mpsc::Receiver<Submission>,
-    event_tx: mpsc::Sender<Event>,
+    event_tx: EventSender,
     chat_engine: Arc<dyn ChatEngine>,
 ) {
     let _ = send_event(
         &event_tx,
-        Event {
-            id: "session".to_string(),
-            msg: EventMsg::SessionConfigured(SessionConfiguredEvent {
-                session_id: config.session_id.clone(),
-            }),
-        },
+        "session",
+        EventMsg::SessionConfigured(SessionConfiguredEvent {
+            session_id: config.session_id.clone(),
+        }),
     )
     .await;
 
-    let mut running_task: Option<RunningTask> = None;
+    let mut running_tasks: HashMap<String, RunningTask> = HashMap::new();
+    let approvals = ApprovalBroker::default();
 
     while let Some(submission) = submission_rx.recv().await {
-        if running_task
-            .as_ref()
-            .is_some_and(|task| task.handle.is_finished())
-        {
-            running_task = None;
-        }
+        running_tasks.retain(|_, task| !task.handle.is_finished());
 
         match submission.op {
             Op::UserTurn { items, options } => {
+                let task_key = options
+                    .session_id
+                    .clone()
+                    .unwrap_or_else(|| config.session_id.clone());
                 let mut request = TurnRequest { items, options };
-                if let Some(task) = running_task.as_ref() {
+                if config.replace_running_task {
+                    abort_task(
+                        &mut running_tasks,
+                        &task_key,
+                        TurnAbortReason::TaskReplaced,
+                        &approvals,
+                        &event_tx,
+                    )
+                    .await;
+                } else if let Some(task) = running_tasks.get(&task_key) {
                     match task.input_tx.send(request).await {
                         Ok(()) => continue,
                         Err(send_error) => {
@@ -176,73 +499,181 @@ async fn submission_loop(
                     submission.id,
                     request,
                     config.task_idle_timeout,
+                    config.turn_timeout,
                     event_tx.clone(),
                     Arc::clone(&chat_engine),
+                    approvals.for_task(&task_key),
                 );
-                running_task = Some(task);
+                running_tasks.insert(task_key, task);
             }
-            Op::Interrupt => {
-                if let Some(task) = running_task.take() {
-                    task.handle.abort();
-                    let _ = send_event(
+            Op::Interrupt { target } => {
+                let task_keys: Vec<String> = running_tasks
+                    .iter()
+                    .filter(|(key, task)| {
+                        target
+                            .as_deref()
+                            .is_none_or(|target| target == key.as_str() || target == task.sub_id)
+                    })
+                    .map(|(key, _)| key.clone())
+                    .collect();
+                for task_key in task_keys {
+                    abort_task(
+                        &mut running_tasks,
+                        &task_key,
+                        TurnAbortReason::UserInterrupt,
+                        &approvals,
                         &event_tx,
-                        Event {
-                            id: task.sub_id,
-                            msg: EventMsg::TurnAborted(TurnAbortedEvent {
-                                reason: TurnAbortReason::UserInterrupt,
-                            }),
-                        },
                     )
                     .await;
                 }
             }
             Op::Shutdown => {
-                if let Some(task) = running_task.take() {
-                    task.handle.abort();
-                    let _ = send_event(
+                let mut task_keys: Vec<String> = running_tasks.keys().cloned().collect();
+                task_keys.sort();
+                for task_key in task_keys {
+                    abort_task(
+                        &mut running_tasks,
+                        &task_key,
+                        TurnAbortReason::Shutdown,
+                        &approvals,
                         &event_tx,
-                        Event {
-                            id: task.sub_id,
-                            msg: EventMsg::TurnAborted(TurnAbortedEvent {
-                                reason: TurnAbortReason::Shutdown,
-                            }),
-                        },
                     )
                     .await;
                 }
 
-                let _ = send_event(
+                let _ = send_event(&event_tx, submission.id, EventMsg::ShutdownComplete).await;
+                return;
+            }
+            Op::ExecApproval { id, decision } => {
+                handle_approval(
+                    submission.id,
+                    id,
+                    ApprovalKind::Exec,
+                    decision,
+                    &approvals,
+                    &mut running_tasks,
+                    &event_tx,
+                )
+                .await;
+            }
+            Op::PatchApproval { id, decision } => {
+                handle_approval(
+                    submission.id,
+                    id,
+                    ApprovalKind::Patch,
+                    decision,
+                    &approvals,
+                    &mut running_tasks,
                     &event_tx,
-                    Event {
-                        id: submission.id,
-                        msg: EventMsg::ShutdownComplete,
-                    },
                 )
                 .await;
-                break;
             }
-            Op::ExecApproval { .. } | Op::PatchApproval { .. } => {
+        }
+    }
+
+    // Every submitter hung up: drain in-flight turns instead of aborting them.
+    // Nobody is left to answer approvals, so those calls abort, and with a
+    // `turn_timeout` a turn still running at that deadline is aborted too.
+    approvals.close();
+    let deadline = config
+        .turn_timeout
+        .map(|turn_timeout| tokio::time::Instant::now() + turn_timeout);
+    let mut task_keys: Vec<String> = running_tasks.keys().cloned().collect();
+    task_keys.sort();
+    for task_key in task_keys {
+        if let Some(mut task) = running_tasks.remove(&task_key) {
+            drop(task.input_tx);
+            let finished = match deadline {
+                Some(deadline) => tokio::time::timeout_at(deadline, &mut task.handle)
+                    .await
+                    .is_ok(),
+                None => {
+                    let _ = (&mut task.handle).await;
+                    true
+                }
+            };
+            if !finished {
+                task.handle.abort();
                 let _ = send_event(
                     &event_tx,
-                    Event {
-                        id: submission.id,
-                        msg: EventMsg::Error(ErrorEvent {
-                            message: "approval flow is not implemented in M2 yet".to_string(),
-                        }),
-                    },
+                    task.sub_id,
+                    EventMsg::TurnAborted(TurnAbortedEvent {
+                        reason: TurnAbortReason::Shutdown,
+                    }),
                 )
                 .await;
             }
         }
     }
+    let _ = send_event(&event_tx, "shutdown", EventMsg::ShutdownComplete).await;
+}
+
+async fn abort_task(
+    running_tasks: &mut HashMap<String, RunningTask>,
+    task_key: &str,
+    reason: TurnAbortReason,
+    approvals: &ApprovalBroker,
+    event_tx: &EventSender,
+) {
+    let Some(task) = running_tasks.remove(task_key) else {
+        return;
+    };
+    task.handle.abort();
+    approvals.cancel_task(task_key);
+    let _ = send_event(
+        event_tx,
+        task.sub_id,
+        EventMsg::TurnAborted(TurnAbortedEvent { reason }),
+    )
+    .await;
+}
+
+async fn handle_approval(
+    submission_id: String,
+    call_id: String,
+    kind: ApprovalKind,
+    decision: ReviewDecision,
+    approvals: &ApprovalBroker,
+    running_tasks: &mut HashMap<String, RunningTask>,
+    event_tx: &EventSender,
+) {
+    if !approvals.is_pending(&call_id, kind) {
+        let _ = send_event(
+            event_tx,
+            submission_id,
+            EventMsg::Error(ErrorEvent {
+                message: format!("no pending approval for call id {call_id}"),
+            }),
+        )
+        .await;
+        return;
+    }
+
+    if decision == ReviewDecision::Abort {
+        // Abort the task before releasing the waiting call so the turn never
+        // observes the decision and reports it as a tool error.
+        if let Some(task_key) = approvals.pending_owner(&call_id) {
+            abort_task(
+                running_tasks,
+                &task_key,
+                TurnAbortReason::ApprovalAborted,
+                approvals,
+                event_tx,
+            )
+            .await;
+        }
+    }
+    approvals.resolve(&call_id, kind, decision);
 }
 
 fn spawn_task(
     sub_id: String,
     initial_request: TurnRequest,
     task_idle_timeout: Duration,
-    event_tx: mpsc::Sender<Event>,
+    turn_timeout: Option<Duration>,
+    event_tx: EventSender,
     chat_engine: Arc<dyn ChatEngine>,
+    approvals: ApprovalBroker,
 ) -> RunningTask {
     let (input_tx, mut input_rx) = mpsc::channel::<TurnRequest>(32);
     let task_sub_id = sub_id.clone();
@@ -250,16 +681,14 @@ fn spawn_task(
     let handle = tokio::spawn(async move {
         let _ = send_event(
             &event_tx,
-            Event {
-                id: task_sub_id.clone(),
-                msg: EventMsg::TaskStarted(TaskStartedEvent {
-                    model_context_window: initial_request
-                        .options
-                        .context_window
-                        .as_ref()
-                        .and_then(|cfg| cfg.max_input_tokens),
-                }),
-            },
+            task_sub_id.clone(),
+            EventMsg::TaskStarted(TaskStartedEvent {
+                model_context_window: initial_request
+                    .options
+                    .context_window
+                    .as_ref()
+                    .and_then(|cfg| cfg.max_input_tokens),
+            }),
         )
         .await;
 
@@ -278,30 +707,60 @@ fn spawn_task(
                 let progress_event_id = task_sub_id.clone();
                 let forwarder = tokio::spawn(async move {
                     while let Some(progress_msg) = progress_rx.recv().await {
-                        let _ = send_event(
-                            &progress_event_tx,
-                            Event {
-                                id: progress_event_id.clone(),
-                                msg: progress_msg,
-                            },
-                        )
-                        .await;
+                        let _ =
+                            send_event(&progress_event_tx, progress_event_id.clone(), progress_msg)
+                                .await;
                     }
                 });
 
-                let turn_result = chat_engine.run_turn(&pending, Some(progress_tx)).await;
+                let turn = chat_engine.run_turn_with_approvals(
+                    &pending,
+                    Some(progress_tx),
+                    approvals.clone(),
+                );
+                let turn_result = match turn_timeout {
+                    Some(limit) => tokio::time::timeout(limit, turn).await,
+                    None => Ok(turn.await),
+                };
+                // Dropping a timed-out turn also drops its progress sender, so
+                // the forwarder still drains and exits.
                 if let Err(error) = forwarder.await {
                     let message = format!("progress forwarder failed: {error}");
                     eprintln!("{message}");
                     let _ = send_event(
                         &event_tx,
-                        Event {
-                            id: task_sub_id.clone(),
-                            msg: EventMsg::Error(ErrorEvent { message }),
-                        },
+                        task_sub_id.clone(),
+                        EventMsg::Error(ErrorEvent { message }),
                     )
                     .await;
                 }
+                let Ok(turn_result) = turn_result else {
+                    let _ = send_event(
+                        &event_tx,
+                        task_sub_id.clone(),
+                        EventMsg::TurnAborted(TurnAbortedEvent {
+                            reason: TurnAbortReason::Timeout,
+                        }),
+                    )
+                    .await;
+                    if let Some(task_key) = approvals.owner.as_deref() {
+                        approvals.cancel_task(task_key);
+                    }
+                    // Closing first makes later submissions start a fresh
+                    // task; turns already queued here are reported, not lost.
+                    input_rx.close();
+                    while input_rx.try_recv().is_ok() {
+                        let _ = send_event(
+                            &event_tx,
+                            task_sub_id.clone(),
+                            EventMsg::Error(ErrorEvent {
+                                message: "queued turn dropped: the task timed out".to_string(),
+                            }),
+                        )
+                        .await;
+                    }
+                    return;
+                };
                 match turn_result {
                     Ok(turn_result) => {
                         if turn_result.last_agent_message.is_some() {
@@ -321,12 +780,10 @@ fn spawn_task(
                     Err(err) => {
                         let _ = send_event(
                             &event_tx,
-                            Event {
-                                id: task_sub_id.clone(),
-                                msg: EventMsg::Error(ErrorEvent {
-                                    message: format!("run_turn failed: {err}"),
-                                }),
-                            },
+                            task_sub_id.clone(),
+                            EventMsg::Error(ErrorEvent {
+                                message: format!("run_turn failed: {err}"),
+                            }),
                         )
                         .await;
                         break;
@@ -345,13 +802,11 @@ fn spawn_task(
 
         let _ = send_event(
             &event_tx,
-            Event {
-                id: task_sub_id,
-                msg: EventMsg::TaskComplete(TaskCompleteEvent {
-                    last_agent_message,
-                    metadata_json: last_metadata_json,
-                }),
-            },
+            task_sub_id,
+            EventMsg::TaskComplete(TaskCompleteEvent {
+                last_agent_message,
+                metadata_json: last_metadata_json,
+            }),
         )
         .await;
     });
@@ -370,19 +825,46 @@ fn extract_api_history_items_from_metadata(metadata_json: &str) -> Option<Vec<Va
     Some(api_history.to_vec())
 }
 
+/// Session-wide event channel. The sequence lock is held across the send,
+/// so events leave in `seq` order even when tasks emit concurrently.
+#[derive(Clone)]
+struct EventSender {
+    tx: mpsc::Sender<Event>,
+    last_seq: Arc<tokio::sync::Mutex<u64>>,
+}
+
+impl EventSender {
+    fn new(tx: mpsc::Sender<Event>) -> Self {
+        Self {
+            tx,
+            last_seq: Arc::default(),
+        }
+    }
+}
+
 async fn send_event(
-    event_tx: &mpsc::Sender<Event>,
-    event: Event,
+    event_tx: &EventSender,
+    id: impl Into<String>,
+    msg: EventMsg,
 ) -> Result<(), mpsc::error::SendError<Event>> {
-    event_tx.send(event).await
+    let mut last_seq = event_tx.last_seq.lock().await;
+    *last_seq += 1;
+    event_tx
+        .tx
+        .send(Event {
+            id: id.into(),
+            seq: *last_seq,
+            msg,
+        })
+        .await
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use finger_kernel_protocol::{
-        EventMsg, InputItem, ModelRoundEvent, Op, Submission, ToolCallEvent, ToolResultEvent,
-        TurnAbortReason, UserTurnOptions,
+        ApprovalRequestEvent, EventMsg, InputItem, ModelRoundEvent, Op, Submission, ToolCallEvent,
+        ToolResultEvent, TurnAbortReason, UserTurnOptions,
     };
     use std::sync::{Arc, Mutex};
 
@@ -474,7 +956,7 @@ mod tests {
         runtime
             .submit(Submission {
                 id: "interrupt".to_string(),
-                op: Op::Interrupt,
+                op: Op::Interrupt { target: None },
             })
             .await
             .expect("submit interrupt");
@@ -552,6 +1034,128 @@ mod tests {
         runtime.join().await.expect("join runtime");
     }
 
+    #[tokio::test]
+    async fn second_user_turn_replaces_running_task_when_configured() {
+        let mut runtime = KernelRuntime::spawn(KernelConfig {
+            task_idle_timeout: Duration::from_secs(5),
+            replace_running_task: true,
+            ..KernelConfig::default()
+        });
+        let _ = recv_event(runtime.events_mut()).await;
+
+        for (id, text) in [("sub-1", "first"), ("sub-2", "second")] {
+            runtime
+                .submit(Submission {
+                    id: id.to_string(),
+                    op: Op::UserTurn {
+                        items: vec![InputItem::Text {
+                            text: text.to_string(),
+                        }],
+                        options: UserTurnOptions::default(),
+                    },
+                })
+                .await
+                .expect("submit turn");
+            if id == "sub-1" {
+                let started = recv_event(runtime.events_mut()).await;
+                assert!(matches!(started.msg, EventMsg::TaskStarted(_)));
+            }
+        }
+
+        let replaced = recv_event(runtime.events_mut()).await;
+        assert_eq!(replaced.id, "sub-1");
+        assert!(matches!(
+            replaced.msg,
+            EventMsg::TurnAborted(TurnAbortedEvent {
+                reason: TurnAbortReason::TaskReplaced
+            })
+        ));
+        let restarted = recv_event(runtime.events_mut()).await;
+        assert_eq!(restarted.id, "sub-2");
+        assert!(matches!(restarted.msg, EventMsg::TaskStarted(_)));
+
+        runtime
+            .submit(Submission {
+                id: "shutdown".to_string(),
+                op: Op::Shutdown,
+            })
+            .await
+            .expect("submit shutdown");
+        runtime.join().await.expect("join runtime");
+    }
+
+    #[tokio::test]
+    async fn event_ledger_records_emitted_events_in_order() {
+        let ts = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .expect("duration since epoch")
+            .as_nanos();
+        let root = std::env::temp_dir().join(format!("finger-kernel-core-events-{ts}"));
+        let ledger_config = ContextLedgerConfig {
+            root_dir: root.clone(),
+            session_id: "finger-kernel".to_string(),
+            agent_id: "kernel".to_string(),
+            mode: "events".to_string(),
+            role: None,
+            can_read_all: false,
+            readable_agents: Vec::new(),
+            focus_enabled: false,
+            focus_max_chars: 1,
+            max_segment_bytes: None,
+            max_retained_segments: None,
+        };
+        let mut runtime = KernelRuntime::spawn(KernelConfig {
+            event_ledger: Some(ledger_config.clone()),
+            ..KernelConfig::default()
+        });
+        let _ = recv_event(runtime.events_mut()).await;
+
+        runtime
+            .submit(Submission {
+                id: "sub-1".to_string(),
+                op: Op::UserTurn {
+                    items: vec![InputItem::Text {
+                        text: "hello".to_string(),
+                    }],
+                    options: UserTurnOptions::default(),
+                },
+            })
+            .await
+            .expect("submit turn");
+        let _ = recv_event(runtime.events_mut()).await;
+        let completed = recv_event(runtime.events_mut()).await;
+        assert!(matches!(completed.msg, EventMsg::TaskComplete(_)));
+
+        let ledger = ContextLedger::new(ledger_config).expect("open ledger");
+        let response = ledger
+            .query(&finger_kernel_context_ledger::LedgerQueryRequest::default())
+            .expect("query ledger");
+        let event_types: Vec<&str> = response
+            .entries
+            .iter()
+            .map(|entry| entry.event_type.as_str())
+            .collect();
+        assert_eq!(
+            event_types,
+            vec!["session_configured", "task_started", "task_complete"]
+        );
+        assert_eq!(response.entries[1].payload["id"], "sub-1");
+        assert_eq!(
+            response.entries[2].payload["msg"]["last_agent_message"],
+            "hello"
+        );
+
+        runtime
+            .submit(Submission {
+                id: "shutdown".to_string(),
+                op: Op::Shutdown,
+            })
+            .await
+            .expect("submit shutdown");
+        runtime.join().await.expect("join runtime");
+        let _ = std::fs::remove_dir_all(root);
+    }
+
     struct ContinuationHistoryEngine {
         history_counts: Arc<Mutex<Vec<usize>>>,
     }
@@ -563,7 +1167,10 @@ mod tests {
             request: &TurnRequest,
             _progress_tx: Option<UnboundedSender<EventMsg>>,
         ) -> Result<TurnRunResult, String> {
-            let mut guard = self.history_counts.lock().expect("history count lock poisoned");
+            let mut guard = self
+                .history_counts
+                .lock()
+                .expect("history count lock poisoned");
             let call_index = guard.len();
             guard.push(request.options.history_items.len());
             drop(guard);
@@ -590,11 +1197,15 @@ mod tests {
 
             let last = request.items.iter().rev().find_map(|item| match item {
                 InputItem::Text { text } => Some(text.clone()),
-                InputItem::Image { .. } | InputItem::LocalImage { .. } => None,
+                InputItem::Image { .. } | InputItem::LocalImage { .. } | InputItem::File { .. } => {
+                    None
+                }
             });
             Ok(TurnRunResult {
                 last_agent_message: last,
                 metadata_json,
+                metrics: TurnMetrics::default(),
+                finish_reason: None,
             })
         }
     }
@@ -661,7 +1272,11 @@ mod tests {
         runtime.join().await.expect("join runtime");
 
         let counts = history_counts.lock().expect("history count lock poisoned");
-        assert!(counts.len() >= 2, "expected two run_turn calls, got {:?}", *counts);
+        assert!(
+            counts.len() >= 2,
+            "expected two run_turn calls, got {:?}",
+            *counts
+        );
         assert_eq!(counts[0], 0, "first run should keep submitted history");
         assert_eq!(
             counts[1], 2,
@@ -669,38 +1284,267 @@ mod tests {
         );
     }
 
-    struct ProgressTestEngine;
+    struct SlowEngine;
 
     #[async_trait]
-    impl ChatEngine for ProgressTestEngine {
+    impl ChatEngine for SlowEngine {
         async fn run_turn(
             &self,
-            _request: &TurnRequest,
+            request: &TurnRequest,
             progress_tx: Option<UnboundedSender<EventMsg>>,
         ) -> Result<TurnRunResult, String> {
-            if let Some(tx) = progress_tx {
-                let _ = tx.send(EventMsg::ModelRound(ModelRoundEvent {
-                    seq: 1,
-                    round: 1,
-                    function_calls_count: 1,
-                    reasoning_count: 0,
-                    history_items_count: 2,
-                    has_output_text: false,
-                    finish_reason: Some("tool_calls".to_string()),
-                    response_status: Some("completed".to_string()),
-                    response_incomplete_reason: None,
-                    response_id: Some("resp_1".to_string()),
-                    input_tokens: Some(20),
-                    output_tokens: Some(10),
-                    total_tokens: Some(30),
-                    estimated_tokens_in_context_window: Some(30),
-                    estimated_tokens_compactable: Some(26),
-                    context_usage_percent: Some(10),
-                    max_input_tokens: Some(300),
-                    threshold_percent: Some(85),
-                }));
-                let _ = tx.send(EventMsg::ToolCall(ToolCallEvent {
-                    seq: 2,
+            let delay = match request.items.first() {
+                Some(InputItem::Text { text }) if text == "slow" => Duration::from_secs(5),
+                Some(InputItem::Text { text }) if text == "very slow" => Duration::from_secs(120),
+                _ => Duration::ZERO,
+            };
+            tokio::time::sleep(delay).await;
+            EchoChatEngine.run_turn(request, progress_tx).await
+        }
+    }
+
+    #[tokio::test]
+    async fn turn_exceeding_timeout_is_aborted_and_runtime_keeps_serving() {
+        let mut runtime = KernelRuntime::spawn_with_engine(
+            KernelConfig {
+                turn_timeout: Some(Duration::from_millis(50)),
+                ..KernelConfig::default()
+            },
+            Arc::new(SlowEngine),
+        );
+        let _ = recv_event(runtime.events_mut()).await;
+
+        runtime
+            .submit(session_turn("sub-slow", "session-a", "slow"))
+            .await
+            .expect("submit slow turn");
+        let started = recv_event(runtime.events_mut()).await;
+        assert!(matches!(started.msg, EventMsg::TaskStarted(_)));
+        let aborted = recv_event(runtime.events_mut()).await;
+        assert_eq!(aborted.id, "sub-slow");
+        assert!(matches!(
+            aborted.msg,
+            EventMsg::TurnAborted(TurnAbortedEvent {
+                reason: TurnAbortReason::Timeout
+            })
+        ));
+
+        runtime
+            .submit(session_turn("sub-fast", "session-a", "fast"))
+            .await
+            .expect("submit fast turn");
+        let started = recv_event(runtime.events_mut()).await;
+        assert_eq!(started.id, "sub-fast");
+        assert!(matches!(started.msg, EventMsg::TaskStarted(_)));
+        let completed = recv_event(runtime.events_mut()).await;
+        assert!(matches!(
+            completed.msg,
+            EventMsg::TaskComplete(TaskCompleteEvent {
+                last_agent_message: Some(ref message),
+                ..
+            }) if message == "fast"
+        ));
+
+        shutdown(runtime).await;
+    }
+
+    #[tokio::test]
+    async fn turns_queued_behind_a_timed_out_turn_are_reported() {
+        let mut runtime = KernelRuntime::spawn_with_engine(
+            KernelConfig {
+                turn_timeout: Some(Duration::from_millis(50)),
+                ..KernelConfig::default()
+            },
+            Arc::new(SlowEngine),
+        );
+        let _ = recv_event(runtime.events_mut()).await;
+
+        runtime
+            .submit(session_turn("sub-slow", "session-a", "slow"))
+            .await
+            .expect("submit slow turn");
+        let started = recv_event(runtime.events_mut()).await;
+        assert!(matches!(started.msg, EventMsg::TaskStarted(_)));
+        runtime
+            .submit(session_turn("sub-queued", "session-a", "fast"))
+            .await
+            .expect("submit queued turn");
+
+        let aborted = recv_event(runtime.events_mut()).await;
+        assert!(matches!(
+            aborted.msg,
+            EventMsg::TurnAborted(TurnAbortedEvent {
+                reason: TurnAbortReason::Timeout
+            })
+        ));
+        let dropped = recv_event(runtime.events_mut()).await;
+        assert_eq!(dropped.id, "sub-slow");
+        assert!(matches!(
+            dropped.msg,
+            EventMsg::Error(ErrorEvent { ref message }) if message.contains("timed out")
+        ));
+
+        shutdown(runtime).await;
+    }
+
+    /// Completes only once every turn sharing `barrier` is in flight at the
+    /// same time.
+    struct RendezvousEngine {
+        barrier: Arc<tokio::sync::Barrier>,
+    }
+
+    #[async_trait]
+    impl ChatEngine for RendezvousEngine {
+        async fn run_turn(
+            &self,
+            request: &TurnRequest,
+            progress_tx: Option<UnboundedSender<EventMsg>>,
+        ) -> Result<TurnRunResult, String> {
+            self.barrier.wait().await;
+            EchoChatEngine.run_turn(request, progress_tx).await
+        }
+    }
+
+    fn session_turn(id: &str, session_id: &str, text: &str) -> Submission {
+        Submission {
+            id: id.to_string(),
+            op: Op::UserTurn {
+                items: vec![InputItem::Text {
+                    text: text.to_string(),
+                }],
+                options: UserTurnOptions {
+                    session_id: Some(session_id.to_string()),
+                    ..UserTurnOptions::default()
+                },
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn turns_for_distinct_sessions_run_concurrently() {
+        let engine: Arc<dyn ChatEngine> = Arc::new(RendezvousEngine {
+            barrier: Arc::new(tokio::sync::Barrier::new(2)),
+        });
+        let mut runtime = KernelRuntime::spawn_with_engine(KernelConfig::default(), engine);
+        let _ = recv_event(runtime.events_mut()).await;
+
+        runtime
+            .submit(session_turn("sub-a", "session-a", "from a"))
+            .await
+            .expect("submit first session turn");
+        runtime
+            .submit(session_turn("sub-b", "session-b", "from b"))
+            .await
+            .expect("submit second session turn");
+
+        let mut started = Vec::new();
+        let mut completed = HashMap::new();
+        while completed.len() < 2 {
+            let event = recv_event(runtime.events_mut()).await;
+            match event.msg {
+                EventMsg::TaskStarted(_) => started.push(event.id),
+                EventMsg::TaskComplete(TaskCompleteEvent {
+                    last_agent_message, ..
+                }) => {
+                    completed.insert(event.id, last_agent_message);
+                }
+                other => panic!("unexpected event: {other:?}"),
+            }
+        }
+        started.sort();
+        assert_eq!(started, vec!["sub-a".to_string(), "sub-b".to_string()]);
+        assert_eq!(completed["sub-a"].as_deref(), Some("from a"));
+        assert_eq!(completed["sub-b"].as_deref(), Some("from b"));
+
+        shutdown(runtime).await;
+    }
+
+    #[tokio::test]
+    async fn targeted_interrupt_leaves_other_sessions_running() {
+        let mut runtime = KernelRuntime::spawn(KernelConfig {
+            task_idle_timeout: Duration::from_secs(5),
+            ..KernelConfig::default()
+        });
+        let _ = recv_event(runtime.events_mut()).await;
+
+        for (id, session_id) in [("sub-a", "session-a"), ("sub-b", "session-b")] {
+            runtime
+                .submit(session_turn(id, session_id, "hello"))
+                .await
+                .expect("submit session turn");
+            let started = recv_event(runtime.events_mut()).await;
+            assert!(matches!(started.msg, EventMsg::TaskStarted(_)));
+        }
+
+        runtime
+            .submit(Submission {
+                id: "interrupt".to_string(),
+                op: Op::Interrupt {
+                    target: Some("sub-b".to_string()),
+                },
+            })
+            .await
+            .expect("submit interrupt");
+        let aborted = recv_event(runtime.events_mut()).await;
+        assert_eq!(aborted.id, "sub-b");
+        assert!(matches!(
+            aborted.msg,
+            EventMsg::TurnAborted(TurnAbortedEvent {
+                reason: TurnAbortReason::UserInterrupt
+            })
+        ));
+
+        runtime
+            .submit(Submission {
+                id: "shutdown".to_string(),
+                op: Op::Shutdown,
+            })
+            .await
+            .expect("submit shutdown");
+        let aborted = recv_event(runtime.events_mut()).await;
+        assert_eq!(aborted.id, "sub-a");
+        assert!(matches!(
+            aborted.msg,
+            EventMsg::TurnAborted(TurnAbortedEvent {
+                reason: TurnAbortReason::Shutdown
+            })
+        ));
+        runtime.join().await.expect("join runtime");
+    }
+
+    struct ProgressTestEngine;
+
+    #[async_trait]
+    impl ChatEngine for ProgressTestEngine {
+        async fn run_turn(
+            &self,
+            _request: &TurnRequest,
+            progress_tx: Option<UnboundedSender<EventMsg>>,
+        ) -> Result<TurnRunResult, String> {
+            if let Some(tx) = progress_tx {
+                let _ = tx.send(EventMsg::ModelRound(ModelRoundEvent {
+                    seq: 1,
+                    round: 1,
+                    function_calls_count: 1,
+                    reasoning_count: 0,
+                    history_items_count: 2,
+                    has_output_text: false,
+                    finish_reason: Some("tool_calls".to_string()),
+                    response_status: Some("completed".to_string()),
+                    response_incomplete_reason: None,
+                    response_id: Some("resp_1".to_string()),
+                    request_id: None,
+                    input_tokens: Some(20),
+                    output_tokens: Some(10),
+                    total_tokens: Some(30),
+                    estimated_tokens_in_context_window: Some(30),
+                    estimated_tokens_compactable: Some(26),
+                    context_usage_percent: Some(10),
+                    max_input_tokens: Some(300),
+                    threshold_percent: Some(85),
+                }));
+                let _ = tx.send(EventMsg::ToolCall(ToolCallEvent {
+                    seq: 2,
                     call_id: "call_1".to_string(),
                     tool_name: "shell.exec".to_string(),
                     input: serde_json::json!({"command":"pwd"}),
@@ -716,6 +1560,8 @@ mod tests {
             Ok(TurnRunResult {
                 last_agent_message: Some("done".to_string()),
                 metadata_json: None,
+                metrics: TurnMetrics::default(),
+                finish_reason: None,
             })
         }
     }
@@ -772,4 +1618,385 @@ mod tests {
             .expect("submit shutdown");
         runtime.join().await.expect("join runtime");
     }
+
+    #[tokio::test]
+    async fn replay_engine_result_flows_through_to_task_complete() {
+        let engine = Arc::new(ReplayChatEngine::new([TurnRunResult {
+            last_agent_message: Some("recorded answer".to_string()),
+            metadata_json: Some(r#"{"round_trace":[]}"#.to_string()),
+            metrics: TurnMetrics {
+                rounds: 2,
+                input_tokens: 40,
+                output_tokens: 12,
+                ..TurnMetrics::default()
+            },
+            finish_reason: None,
+        }]));
+        let mut runtime = KernelRuntime::spawn_with_engine(KernelConfig::default(), engine.clone());
+        let _ = recv_event(runtime.events_mut()).await;
+
+        runtime
+            .submit(Submission {
+                id: "sub-replay".to_string(),
+                op: Op::UserTurn {
+                    items: vec![InputItem::Text {
+                        text: "anything".to_string(),
+                    }],
+                    options: UserTurnOptions::default(),
+                },
+            })
+            .await
+            .expect("submit turn");
+
+        let started = recv_event(runtime.events_mut()).await;
+        assert!(matches!(started.msg, EventMsg::TaskStarted(_)));
+        for expected_round in 1..=2 {
+            let round_event = recv_event(runtime.events_mut()).await;
+            assert!(matches!(
+                round_event.msg,
+                EventMsg::ModelRound(ModelRoundEvent { round, has_output_text, .. })
+                    if round == expected_round && has_output_text == (expected_round == 2)
+            ));
+        }
+        let usage = recv_event(runtime.events_mut()).await;
+        assert!(matches!(
+            usage.msg,
+            EventMsg::Usage(UsageEvent {
+                total_tokens: 52,
+                rounds: 2,
+                ..
+            })
+        ));
+        let completed = recv_event(runtime.events_mut()).await;
+        assert_eq!(
+            completed.msg,
+            EventMsg::TaskComplete(TaskCompleteEvent {
+                last_agent_message: Some("recorded answer".to_string()),
+                metadata_json: Some(r#"{"round_trace":[]}"#.to_string()),
+            })
+        );
+        assert_eq!(engine.remaining(), 0);
+
+        shutdown(runtime).await;
+    }
+
+    #[tokio::test]
+    async fn every_event_carries_the_next_session_sequence_number() {
+        let engine = Arc::new(ReplayChatEngine::new([TurnRunResult {
+            last_agent_message: Some("recorded answer".to_string()),
+            metadata_json: None,
+            metrics: TurnMetrics {
+                rounds: 1,
+                ..TurnMetrics::default()
+            },
+            finish_reason: None,
+        }]));
+        let mut runtime = KernelRuntime::spawn_with_engine(
+            KernelConfig {
+                task_idle_timeout: Duration::from_millis(20),
+                ..KernelConfig::default()
+            },
+            engine,
+        );
+        runtime
+            .submit(Submission {
+                id: "sub-seq".to_string(),
+                op: Op::UserTurn {
+                    items: vec![InputItem::Text {
+                        text: "anything".to_string(),
+                    }],
+                    options: UserTurnOptions::default(),
+                },
+            })
+            .await
+            .expect("submit turn");
+
+        let mut events = Vec::new();
+        loop {
+            let event = recv_event(runtime.events_mut()).await;
+            let complete = matches!(event.msg, EventMsg::TaskComplete(_));
+            events.push(event);
+            if complete {
+                break;
+            }
+        }
+        runtime
+            .submit(Submission {
+                id: "shutdown".to_string(),
+                op: Op::Shutdown,
+            })
+            .await
+            .expect("submit shutdown");
+        events.push(recv_event(runtime.events_mut()).await);
+        runtime.join().await.expect("join runtime");
+
+        let kinds = events
+            .iter()
+            .map(|event| match event.msg {
+                EventMsg::SessionConfigured(_) => "session_configured",
+                EventMsg::TaskStarted(_) => "task_started",
+                EventMsg::ModelRound(_) => "model_round",
+                EventMsg::Usage(_) => "usage",
+                EventMsg::TaskComplete(_) => "task_complete",
+                EventMsg::ShutdownComplete => "shutdown_complete",
+                _ => "other",
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                "session_configured",
+                "task_started",
+                "model_round",
+                "usage",
+                "task_complete",
+                "shutdown_complete",
+            ]
+        );
+        let seqs = events.iter().map(|event| event.seq).collect::<Vec<_>>();
+        assert_eq!(seqs, (1..=6).collect::<Vec<u64>>());
+    }
+
+    struct ApprovalTestEngine;
+
+    #[async_trait]
+    impl ChatEngine for ApprovalTestEngine {
+        async fn run_turn(
+            &self,
+            _request: &TurnRequest,
+            _progress_tx: Option<UnboundedSender<EventMsg>>,
+        ) -> Result<TurnRunResult, String> {
+            Err("approval broker required".to_string())
+        }
+
+        async fn run_turn_with_approvals(
+            &self,
+            _request: &TurnRequest,
+            progress_tx: Option<UnboundedSender<EventMsg>>,
+            approvals: ApprovalBroker,
+        ) -> Result<TurnRunResult, String> {
+            let decision_rx = approvals.register("call_1", ApprovalKind::Exec, "shell.exec");
+            if let Some(tx) = progress_tx {
+                let _ = tx.send(EventMsg::ExecApprovalRequest(ApprovalRequestEvent {
+                    seq: 1,
+                    call_id: "call_1".to_string(),
+                    tool_name: "shell.exec".to_string(),
+                    input: serde_json::json!({"command":"rm -rf build"}),
+                }));
+            }
+            let message = match decision_rx.await.unwrap_or(ReviewDecision::Abort) {
+                ReviewDecision::Approved | ReviewDecision::ApprovedForSession => "ran",
+                ReviewDecision::Denied => "denied",
+                ReviewDecision::Abort => "aborted",
+            };
+            Ok(TurnRunResult {
+                last_agent_message: Some(message.to_string()),
+                metadata_json: None,
+                metrics: TurnMetrics::default(),
+                finish_reason: None,
+            })
+        }
+    }
+
+    async fn start_approval_turn() -> KernelRuntime {
+        let mut runtime =
+            KernelRuntime::spawn_with_engine(KernelConfig::default(), Arc::new(ApprovalTestEngine));
+        let _ = recv_event(runtime.events_mut()).await;
+        runtime
+            .submit(Submission {
+                id: "sub-approval".to_string(),
+                op: Op::UserTurn {
+                    items: vec![InputItem::Text {
+                        text: "clean the build".to_string(),
+                    }],
+                    options: UserTurnOptions::default(),
+                },
+            })
+            .await
+            .expect("submit turn");
+        let started = recv_event(runtime.events_mut()).await;
+        assert!(matches!(started.msg, EventMsg::TaskStarted(_)));
+        let request = recv_event(runtime.events_mut()).await;
+        assert!(matches!(
+            request.msg,
+            EventMsg::ExecApprovalRequest(ApprovalRequestEvent { ref call_id, .. })
+                if call_id == "call_1"
+        ));
+        runtime
+    }
+
+    #[tokio::test]
+    async fn submit_after_close_submissions_reports_runtime_closed() {
+        let mut runtime = KernelRuntime::spawn(KernelConfig::default());
+        let _ = recv_event(runtime.events_mut()).await;
+        runtime.close_submissions();
+
+        let error = runtime
+            .submit(Submission {
+                id: "late".to_string(),
+                op: Op::Shutdown,
+            })
+            .await
+            .expect_err("closed handle rejects submissions");
+        assert!(matches!(error, KernelError::RuntimeClosed));
+        assert!(matches!(
+            runtime.submission_sender(),
+            Err(KernelError::RuntimeClosed)
+        ));
+
+        let event = recv_event(runtime.events_mut()).await;
+        assert!(matches!(event.msg, EventMsg::ShutdownComplete));
+        runtime.join().await.expect("join runtime");
+    }
+
+    async fn shutdown(runtime: KernelRuntime) {
+        runtime
+            .submit(Submission {
+                id: "shutdown".to_string(),
+                op: Op::Shutdown,
+            })
+            .await
+            .expect("submit shutdown");
+        runtime.join().await.expect("join runtime");
+    }
+
+    #[tokio::test]
+    async fn exec_approval_resumes_or_denies_waiting_tool_call() {
+        for (decision, expected) in [
+            (ReviewDecision::Approved, "ran"),
+            (ReviewDecision::Denied, "denied"),
+        ] {
+            let mut runtime = start_approval_turn().await;
+
+            // A patch approval for the same id does not satisfy an exec request.
+            runtime
+                .submit(Submission {
+                    id: "wrong-kind".to_string(),
+                    op: Op::PatchApproval {
+                        id: "call_1".to_string(),
+                        decision: ReviewDecision::Approved,
+                    },
+                })
+                .await
+                .expect("submit patch approval");
+            let rejected = recv_event(runtime.events_mut()).await;
+            assert_eq!(rejected.id, "wrong-kind");
+            assert!(matches!(rejected.msg, EventMsg::Error(_)));
+
+            runtime
+                .submit(Submission {
+                    id: "approval".to_string(),
+                    op: Op::ExecApproval {
+                        id: "call_1".to_string(),
+                        decision,
+                    },
+                })
+                .await
+                .expect("submit exec approval");
+            let completed = recv_event(runtime.events_mut()).await;
+            assert!(matches!(
+                completed.msg,
+                EventMsg::TaskComplete(TaskCompleteEvent {
+                    last_agent_message: Some(ref message),
+                    ..
+                }) if message == expected
+            ));
+            shutdown(runtime).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn exec_approval_abort_aborts_turn() {
+        let mut runtime = start_approval_turn().await;
+        runtime
+            .submit(Submission {
+                id: "approval".to_string(),
+                op: Op::ExecApproval {
+                    id: "call_1".to_string(),
+                    decision: ReviewDecision::Abort,
+                },
+            })
+            .await
+            .expect("submit exec approval");
+        let aborted = recv_event(runtime.events_mut()).await;
+        assert_eq!(aborted.id, "sub-approval");
+        assert!(matches!(
+            aborted.msg,
+            EventMsg::TurnAborted(TurnAbortedEvent {
+                reason: TurnAbortReason::ApprovalAborted
+            })
+        ));
+        shutdown(runtime).await;
+    }
+
+    #[tokio::test]
+    async fn interrupted_task_leaves_no_pending_approval_behind() {
+        let mut runtime = start_approval_turn().await;
+        runtime
+            .submit(Submission {
+                id: "interrupt".to_string(),
+                op: Op::Interrupt { target: None },
+            })
+            .await
+            .expect("submit interrupt");
+        let aborted = recv_event(runtime.events_mut()).await;
+        assert!(matches!(
+            aborted.msg,
+            EventMsg::TurnAborted(TurnAbortedEvent {
+                reason: TurnAbortReason::UserInterrupt
+            })
+        ));
+
+        runtime
+            .submit(Submission {
+                id: "late-approval".to_string(),
+                op: Op::ExecApproval {
+                    id: "call_1".to_string(),
+                    decision: ReviewDecision::Approved,
+                },
+            })
+            .await
+            .expect("submit late approval");
+        let rejected = recv_event(runtime.events_mut()).await;
+        assert_eq!(rejected.id, "late-approval");
+        assert!(matches!(rejected.msg, EventMsg::Error(_)));
+        shutdown(runtime).await;
+    }
+
+    #[test]
+    fn approved_for_session_is_remembered_per_tool() {
+        let approvals = ApprovalBroker::default();
+        let _decision_rx = approvals.register("call_1", ApprovalKind::Exec, "shell.exec");
+        assert!(!approvals.resolve("call_2", ApprovalKind::Exec, ReviewDecision::Approved));
+        assert!(approvals.resolve(
+            "call_1",
+            ApprovalKind::Exec,
+            ReviewDecision::ApprovedForSession
+        ));
+        assert!(approvals.is_approved_for_session("shell.exec"));
+        assert!(!approvals.is_pending("call_1", ApprovalKind::Exec));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn long_turn_finishes_after_submissions_close_without_turn_timeout() {
+        let mut runtime =
+            KernelRuntime::spawn_with_engine(KernelConfig::default(), Arc::new(SlowEngine));
+        let _ = recv_event(runtime.events_mut()).await;
+
+        runtime
+            .submit(session_turn("sub-long", "session-a", "very slow"))
+            .await
+            .expect("submit long turn");
+        let started = recv_event(runtime.events_mut()).await;
+        assert!(matches!(started.msg, EventMsg::TaskStarted(_)));
+        runtime.close_submissions();
+
+        // Paused time skips ahead through the two-minute turn.
+        let completed = runtime.events_mut().recv().await.expect("turn outcome");
+        assert_eq!(completed.id, "sub-long");
+        assert!(matches!(completed.msg, EventMsg::TaskComplete(_)));
+        let event = runtime.events_mut().recv().await.expect("shutdown event");
+        assert!(matches!(event.msg, EventMsg::ShutdownComplete));
+        runtime.join().await.expect("join runtime");
+    }
 }
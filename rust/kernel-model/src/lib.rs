@@ -1,6 +1,8 @@
-use std::collections::HashSet;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs;
 use std::path::Path;
+use std::sync::Arc;
 use std::time::Instant;
 
 use async_trait::async_trait;
@@ -9,11 +11,17 @@ use finger_kernel_config::LocalModelConfig;
 #[cfg(test)]
 use finger_kernel_context_ledger::LedgerQueryRequest;
 use finger_kernel_context_ledger::{ContextLedger, ContextLedgerConfig};
-use finger_kernel_core::{ChatEngine, TurnRequest, TurnRunResult};
+use finger_kernel_core::{ApprovalBroker, ChatEngine, TurnMetrics, TurnRequest, TurnRunResult};
 use finger_kernel_protocol::{
-    CompactConfig, EventMsg, InputItem, ModelRoundEvent, ResponsesRequestOptions, ToolCallEvent,
-    ToolErrorEvent, ToolExecutionConfig, ToolResultEvent, ToolSpec, TurnContext, UserTurnOptions,
+    ApprovalKind, ApprovalRequestEvent, CompactConfig, CompactionEvent, CompactionTrigger,
+    ContextBlockPosition, ContextBlockRole, EventMsg, InputItem, ModelRoundEvent,
+    OutputTextDeltaEvent, ReasoningEvent, ResponsesRequestOptions, ResponsesTextOptions,
+    ReviewDecision, ToolCallEvent, ToolChoice, ToolErrorEvent, ToolExecutionConfig,
+    ToolResultEvent, ToolSpec, TurnContext, UsageEvent, UserTurnOptions,
 };
+use futures_util::stream::{self, StreamExt};
+use rand::Rng;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use thiserror::Error;
@@ -21,23 +29,61 @@ use time::format_description::well_known::Rfc3339;
 use time::OffsetDateTime;
 use tokio::sync::mpsc::UnboundedSender;
 use tokio::time::{sleep, Duration};
+use tracing::Instrument;
 
 
 use crate::protocol::anthropic::request::build_anthropic_request_payload;
-use crate::protocol::anthropic::transport::{send_anthropic_http, AnthropicResponseBody};
-use crate::protocol::anthropic::response::{parse_anthropic_event_type, parse_anthropic_sse_data, AnthropicEventType};
+use crate::protocol::anthropic::response::{
+    anthropic_event_progress_events, parse_anthropic_wire_response,
+};
 use finger_kernel_config::WireApi;
 mod protocol;
+mod token_estimator;
+mod tool_executor;
+
+pub use token_estimator::{HeuristicTokenEstimator, TiktokenEstimator, TokenEstimator};
+pub use tool_executor::{FnToolExecutor, ToolExecutor};
 
 
-use protocol::request::build_responses_request_payload;
+use protocol::chat::request::build_chat_request_payload;
+use protocol::chat::response::{chat_chunk_progress_events, parse_chat_wire_response};
+use protocol::request::{
+    build_responses_request_payload, merge_instructions, reasoning_requested,
+    stable_prefix_cache_key, REASONING_EFFORTS,
+};
 use protocol::response::parse_wire_response;
-use protocol::transport::send_responses_http;
+use protocol::transport::{
+    classify_http_error, headers_with_request_id, join_endpoint, new_request_id, read_body_capped,
+    send_models_probe, send_responses_http, ApiKeyAuth, ANTHROPIC_MESSAGES_ENDPOINT_PATH,
+    CHAT_COMPLETIONS_ENDPOINT_PATH, RESPONSES_ENDPOINT_PATH, USER_AGENT,
+};
 
 const DEFAULT_AUTO_COMPACT_THRESHOLD_RATIO: f64 = 0.85;
 const DEFAULT_FOCUS_MAX_CHARS: usize = 20_000;
 const MAX_MISSING_STREAM_RETRIES: u8 = 10;
 const INITIAL_MISSING_STREAM_BACKOFF_MS: u64 = 500;
+const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_REQUEST_TIMEOUT_SECS: u64 = 300;
+const DEFAULT_POOL_IDLE_TIMEOUT_SECS: u64 = 90;
+const DEFAULT_MAX_RATE_LIMIT_RETRIES: u8 = 5;
+const DEFAULT_MAX_RESPONSE_BYTES: u64 = 64 * 1024 * 1024;
+const HEALTH_CHECK_TIMEOUT_SECS: u64 = 10;
+const DEFAULT_TOOL_CALL_CONCURRENCY: usize = 4;
+const DEFAULT_TOOL_MAX_RETRIES: u8 = 2;
+const TOOL_EXECUTE_ENDPOINT_PATH: &str = "/api/v1/tools/execute";
+const TOOL_RESULT_CACHE_CAPACITY: usize = 64;
+const MAX_TOOL_LOOP_ROUNDS: usize = 128;
+const TOOL_LOOP_EXCEEDED_FINISH_REASON: &str = "tool_loop_exceeded";
+const INITIAL_TOOL_RETRY_BACKOFF_MS: u64 = 200;
+const MAX_TOOL_RETRY_BACKOFF_MS: u64 = 10_000;
+const DEFAULT_MAX_SCHEMA_RETRIES: u8 = 1;
+const DEFAULT_PRESERVE_RECENT_TOOL_ROUNDS: usize = 2;
+const DEFAULT_MAX_PRESERVED_USER_MESSAGES: usize = 12;
+const DEFAULT_RETRY_MAX_ATTEMPTS: u32 = 5;
+const DEFAULT_RETRY_BASE_DELAY_MS: u64 = 500;
+const DEFAULT_RETRY_MAX_DELAY_SECS: u64 = 30;
+const MAX_RATE_LIMIT_RETRY_AFTER_SECS: u64 = 60;
+const COMPACT_SUMMARY_INSTRUCTIONS: &str = "You compress conversation history for an agent that will continue the work. Summarize the transcript in concise prose: the user's goals, decisions made, tool results that matter, and open threads. Do not address the user.";
 
 #[derive(Debug, Error)]
 pub enum ModelError {
@@ -45,34 +91,328 @@ pub enum ModelError {
     Request(#[from] reqwest::Error),
     #[error("responses api returned non-success status: {status}; body: {body}")]
     HttpStatus { status: u16, body: String },
+    #[error("provider rejected credentials with status {status}: {message}")]
+    AuthFailed { status: u16, message: String },
+    #[error("provider rejected the request: {message}")]
+    InvalidRequest {
+        message: String,
+        param: Option<String>,
+    },
+    #[error("provider does not know the model: {message}")]
+    ModelNotFound { message: String },
+    #[error("request exceeds the model's context length: {message}")]
+    ContextLengthExceeded { message: String },
+    #[error("provider failed with status {status}: {message}")]
+    ServerError { status: u16, message: String },
     #[error("invalid responses payload: {0}")]
     ParsePayload(#[from] serde_json::Error),
     #[error("failed to read local image from {path}: {error}")]
     LocalImageRead { path: String, error: String },
+    #[error("failed to read local file from {path}: {error}")]
+    LocalFileRead { path: String, error: String },
+    #[error("failed to fetch remote image from {url}: {error}")]
+    RemoteImageFetch { url: String, error: String },
     #[error("responses api returned empty output")]
     EmptyOutput,
+    #[error("tool loop reached {rounds} rounds without a final answer")]
+    ToolLoopExceeded { rounds: u64 },
+    #[error("responses api returned an incomplete response: {reason}")]
+    Incomplete { reason: String },
+    #[error("model refused the request: {message}")]
+    Refused { message: String },
     #[error("responses stream did not contain a completed response payload")]
     MissingStreamResponse,
     #[error("responses stream failed: {message}")]
     StreamFailed { message: String },
+    #[error("responses api rate limited with status {status}; body: {body}")]
+    RateLimited {
+        status: u16,
+        retry_after_secs: Option<u64>,
+        body: String,
+    },
+    #[error("responses request timed out: {message}")]
+    Timeout { message: String },
+    #[error("response body exceeded the {limit}-byte limit")]
+    ResponseTooLarge { limit: u64 },
+    #[error("failed to load tokenizer for {model}: {message}")]
+    Tokenizer { model: String, message: String },
     #[error("tool execution failed for {tool_name}: {message}")]
     ToolExecution { tool_name: String, message: String },
+    #[error("tool daemon request failed for {tool_name}: {source}")]
+    ToolTransport {
+        tool_name: String,
+        #[source]
+        source: reqwest::Error,
+    },
+    #[error("structured output does not match output schema: {}", errors.join("; "))]
+    SchemaValidation { errors: Vec<String> },
+    #[error("turn aborted while awaiting approval for {call_id}")]
+    ApprovalAborted { call_id: String },
+    #[error("invalid extra header '{name}'")]
+    InvalidHeader { name: String },
+    #[error("invalid focus injection role '{role}'; expected user, developer or system")]
+    InvalidFocusRole { role: String },
+    #[error("invalid input schema for tool {tool_name}: {message}")]
+    InvalidToolSchema { tool_name: String, message: String },
+    #[error("invalid reasoning effort '{effort}'; expected none, minimal, low, medium or high")]
+    InvalidReasoningEffort { effort: String },
+}
+
+/// How the provider answered [`FingerChatEngine::check_health`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HealthState {
+    Reachable,
+    AuthFailed,
+    /// The server answered but has no models route, which usually means
+    /// `base_url` points at the wrong host or path.
+    Misconfigured,
+    Unreachable,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthStatus {
+    pub state: HealthState,
+    /// HTTP status of the probe; `None` when no response arrived.
+    pub status: Option<u16>,
+    pub latency_ms: u64,
+}
+
+/// History compacted outside a turn by [`FingerChatEngine::compact`]; token
+/// counts are estimates.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompactionOutcome {
+    pub history: Vec<Value>,
+    pub summary: Option<String>,
+    pub before_tokens: u64,
+    pub after_tokens: u64,
+}
+
+/// HTTP client settings used for provider and tool daemon requests.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientOptions {
+    pub connect_timeout: Duration,
+    /// Total time allowed for a request, including reading a streamed body.
+    pub request_timeout: Duration,
+    pub pool_idle_timeout: Option<Duration>,
+    /// Retries for 429 (and 503 with `Retry-After`) responses before giving up.
+    pub max_rate_limit_retries: u8,
+    /// Longest gap allowed between chunks of a streamed response before the
+    /// stream counts as stalled; `None` relies on `request_timeout` alone.
+    pub sse_idle_timeout: Option<Duration>,
+    /// Largest body read from a provider or the tool daemon, streamed
+    /// responses included; reading stops once it is exceeded.
+    pub max_response_bytes: u64,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(DEFAULT_CONNECT_TIMEOUT_SECS),
+            request_timeout: Duration::from_secs(DEFAULT_REQUEST_TIMEOUT_SECS),
+            pool_idle_timeout: Some(Duration::from_secs(DEFAULT_POOL_IDLE_TIMEOUT_SECS)),
+            max_rate_limit_retries: DEFAULT_MAX_RATE_LIMIT_RETRIES,
+            sse_idle_timeout: None,
+            max_response_bytes: DEFAULT_MAX_RESPONSE_BYTES,
+        }
+    }
+}
+
+/// Backoff for transient provider failures: 5xx, connection errors, 429s
+/// and transient auth errors share one attempt budget per request. The
+/// one-shot payload fixes (enabling `store`, stripping reasoning items) are
+/// not counted against it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts per request, the first one included.
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// Decorrelated jitter; without it the delay simply doubles.
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: DEFAULT_RETRY_MAX_ATTEMPTS,
+            base_delay: Duration::from_millis(DEFAULT_RETRY_BASE_DELAY_MS),
+            max_delay: Duration::from_secs(DEFAULT_RETRY_MAX_DELAY_SECS),
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before the next retry given the previous one (`None` before
+    /// the first retry).
+    fn next_delay(&self, previous: Option<Duration>) -> Duration {
+        let base = self.base_delay.min(self.max_delay);
+        let Some(previous) = previous else {
+            return base;
+        };
+        if !self.jitter {
+            return previous.saturating_mul(2).clamp(base, self.max_delay);
+        }
+        let upper = previous.saturating_mul(3).clamp(base, self.max_delay);
+        let span_nanos = (upper - base).as_nanos() as u64;
+        // Spread concurrent retries so they do not hit the provider in lockstep.
+        let jitter_nanos = rand::thread_rng().gen_range(0..=span_nanos);
+        base + Duration::from_nanos(jitter_nanos)
+    }
 }
 
 #[derive(Clone)]
 pub struct FingerChatEngine {
     config: LocalModelConfig,
     client: reqwest::Client,
+    client_options: ClientOptions,
+    retry_policy: RetryPolicy,
+    extra_headers: HeaderMap,
+    token_estimator: Arc<dyn TokenEstimator>,
+    tool_executor: Option<Arc<dyn ToolExecutor>>,
+    remote_image_max_bytes: Option<u64>,
 }
 
 impl FingerChatEngine {
     pub fn new(config: LocalModelConfig) -> Self {
-        Self {
+        Self::with_client_options(config, ClientOptions::default())
+            .expect("default http client options should build")
+    }
+
+    pub fn with_client_options(
+        config: LocalModelConfig,
+        client_options: ClientOptions,
+    ) -> Result<Self, ModelError> {
+        let client = reqwest::Client::builder()
+            .user_agent(USER_AGENT)
+            .connect_timeout(client_options.connect_timeout)
+            .timeout(client_options.request_timeout)
+            .pool_idle_timeout(client_options.pool_idle_timeout)
+            .build()?;
+        let extra_headers = build_extra_header_map(&config.extra_headers)?;
+        Ok(Self {
             config,
-            client: reqwest::Client::new(),
+            client,
+            client_options,
+            retry_policy: RetryPolicy::default(),
+            extra_headers,
+            token_estimator: Arc::new(HeuristicTokenEstimator),
+            tool_executor: None,
+            remote_image_max_bytes: None,
+        })
+    }
+
+    /// Replaces the chars/4 heuristic used for context budgeting and auto-compaction.
+    pub fn with_token_estimator(mut self, token_estimator: Arc<dyn TokenEstimator>) -> Self {
+        self.token_estimator = token_estimator;
+        self
+    }
+
+    /// Runs the tools `tool_executor` handles in-process; every other call
+    /// still goes to the tool daemon.
+    pub fn with_tool_executor(mut self, tool_executor: Arc<dyn ToolExecutor>) -> Self {
+        self.tool_executor = Some(tool_executor);
+        self
+    }
+
+    /// Returns the payload the first request of a turn would send, without
+    /// calling the provider or running tools. Remote image URLs are left as
+    /// they are rather than fetched.
+    pub fn build_request_preview(
+        &self,
+        items: &[InputItem],
+        options: &UserTurnOptions,
+    ) -> Result<Value, ModelError> {
+        let merged_options = self.apply_responses_defaults(options);
+        let options = merged_options.as_ref().unwrap_or(options);
+        let tool_bindings = build_tool_bindings(&options.tools)?;
+        validate_reasoning_effort(options)?;
+        let input = build_initial_input(items, options)?;
+        let tool_payload = build_tool_payload(&tool_bindings);
+        let responses_opts =
+            resolve_responses_tool_choice(options.responses.as_ref(), &tool_bindings);
+        let (payload, _) = self.build_wire_payload(
+            &input,
+            options,
+            tool_payload.as_deref(),
+            responses_opts.as_ref(),
+            None,
+        );
+        Ok(payload)
+    }
+
+    /// Compacts a stored history the way a turn would, without calling the
+    /// provider, so callers can shrink it before the next turn.
+    pub fn compact(&self, history: &[Value], cfg: Option<&CompactConfig>) -> CompactionOutcome {
+        let token_estimator = self.token_estimator.as_ref();
+        let compacted = compact_history(history, cfg, None, token_estimator);
+        CompactionOutcome {
+            before_tokens: estimate_tokens_in_history(history, token_estimator),
+            after_tokens: estimate_tokens_in_history(&compacted.history, token_estimator),
+            history: compacted.history,
+            summary: compacted.summary,
         }
     }
 
+    /// Probes the provider's models list with the configured key, without
+    /// running a turn. Only unexpected statuses (e.g. 5xx) and bodies over
+    /// `max_response_bytes` are errors.
+    pub async fn check_health(&self) -> Result<HealthStatus, ModelError> {
+        let started_at = Instant::now();
+        let probe = send_models_probe(
+            &self.client,
+            &self.config.base_url,
+            &self.config.api_key,
+            &self.extra_headers,
+            ApiKeyAuth::for_config(&self.config),
+            Duration::from_secs(HEALTH_CHECK_TIMEOUT_SECS),
+            self.client_options.max_response_bytes,
+        )
+        .await;
+        let latency_ms = started_at.elapsed().as_millis() as u64;
+        let (status, body) = match probe {
+            Ok(response) => response,
+            Err(ModelError::Request(error)) if error.is_connect() || error.is_timeout() => {
+                return Ok(HealthStatus {
+                    state: HealthState::Unreachable,
+                    status: None,
+                    latency_ms,
+                });
+            }
+            Err(error) => return Err(error),
+        };
+        let state = match status.as_u16() {
+            // The route exists but the gateway does not allow listing models.
+            200..=299 | 405 => HealthState::Reachable,
+            401 | 403 => HealthState::AuthFailed,
+            // Azure deployments and gateways with a custom responses route
+            // need not serve `/v1/models`, so a 404 says nothing about them.
+            404 if self.config.azure.is_some() || self.config.responses_path.is_some() => {
+                HealthState::Reachable
+            }
+            404 => HealthState::Misconfigured,
+            status => return Err(classify_http_error(status, body)),
+        };
+        Ok(HealthStatus {
+            state,
+            status: Some(status.as_u16()),
+            latency_ms,
+        })
+    }
+
+    /// Replaces the backoff used for transient provider failures.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Downloads `http`/`https` image inputs and sends them as `data:` URLs, for
+    /// gateways that do not fetch external images themselves.
+    pub fn with_remote_image_inlining(mut self, max_bytes: u64) -> Self {
+        self.remote_image_max_bytes = Some(max_bytes);
+        self
+    }
+
     pub async fn complete_text(&self, user_text: &str) -> Result<String, ModelError> {
         let completion = self
             .complete_with_options(
@@ -81,39 +421,106 @@ impl FingerChatEngine {
                 }],
                 &UserTurnOptions::default(),
                 None,
+                None,
             )
             .await?;
-        Ok(completion.output_text)
+        completion.into_output_text()
     }
 
     pub async fn complete_items(&self, items: &[InputItem]) -> Result<String, ModelError> {
         let completion = self
-            .complete_with_options(items, &UserTurnOptions::default(), None)
+            .complete_with_options(items, &UserTurnOptions::default(), None, None)
             .await?;
-        Ok(completion.output_text)
+        completion.into_output_text()
+    }
+
+    /// Layers the provider's configured `responses` defaults under the
+    /// per-turn options; `None` when there is nothing to merge.
+    ///
+    /// Providers without encrypted reasoning support never get the include
+    /// and keep reasoning server-side with `store = true` instead.
+    fn apply_responses_defaults(&self, options: &UserTurnOptions) -> Option<UserTurnOptions> {
+        let supports_encrypted_reasoning = self.config.supports_encrypted_reasoning;
+        let defaults = self.config.responses.as_ref();
+        if defaults.is_none() && supports_encrypted_reasoning {
+            return None;
+        }
+        let mut responses = match (options.responses.clone(), defaults) {
+            (Some(turn), Some(defaults)) => turn.with_defaults(defaults),
+            (Some(turn), None) => turn,
+            (None, Some(defaults)) => defaults.clone(),
+            (None, None) => ResponsesRequestOptions::default(),
+        };
+        if !supports_encrypted_reasoning {
+            responses
+                .reasoning
+                .get_or_insert_with(Default::default)
+                .include_encrypted_content = Some(false);
+            responses.store = Some(true);
+        }
+        Some(UserTurnOptions {
+            responses: Some(responses),
+            ..options.clone()
+        })
     }
 
+    #[tracing::instrument(
+        name = "turn",
+        skip_all,
+        fields(
+            session_id = options.session_id.as_deref().unwrap_or_default(),
+            mode = options.mode.as_deref().unwrap_or_default(),
+        ),
+        err
+    )]
     async fn complete_with_options(
         &self,
         items: &[InputItem],
         options: &UserTurnOptions,
         progress_tx: Option<&UnboundedSender<EventMsg>>,
+        approvals: Option<&ApprovalBroker>,
     ) -> Result<TurnCompletion, ModelError> {
-        let tool_bindings = build_tool_bindings(&options.tools);
+        let merged_options = self.apply_responses_defaults(options);
+        let options = merged_options.as_ref().unwrap_or(options);
+        let tool_bindings = build_tool_bindings(&options.tools)?;
+        validate_reasoning_effort(options)?;
+        let focus_role = resolve_focus_injection_role(options)?;
         let context_ledger = build_context_ledger(options);
+        let inlined_items = self.inline_remote_images(items).await?;
+        let items = inlined_items.as_deref().unwrap_or(items);
         let mut rolling_input = build_initial_input(items, options)?;
+        if is_stateless(options.responses.as_ref()) {
+            rolling_input = strip_reasoning_history_items(&rolling_input);
+        }
 
         if let Some(ledger) = context_ledger.as_ref() {
             safe_append_ledger(
                 ledger,
                 "turn_start",
                 json!({
+                    "user_text": items
+                        .iter()
+                        .filter_map(|item| match item {
+                            InputItem::Text { text } if !text.trim().is_empty() => {
+                                Some(text.as_str())
+                            }
+                            _ => None,
+                        })
+                        .collect::<Vec<_>>()
+                        .join("\n"),
                     "items_count": items.len(),
                     "history_items_count": options.history_items.len(),
                     "tool_count": options.tools.len(),
                     "mode": options.mode,
                 }),
             );
+            let renamed_tools = tool_name_map(&tool_bindings)
+                .into_iter()
+                .filter(|entry| entry["renamed"] == true)
+                .collect::<Vec<_>>();
+            if !renamed_tools.is_empty() {
+                safe_append_ledger(ledger, "tool_renamed", json!({ "tools": renamed_tools }));
+            }
             if let Ok(Some(focus_text)) = ledger.read_focus() {
                 let recall_block = format!(
                     "OLD_MEMORY_RECALL_ZONE\nThis block contains recalled old memory extracted from prior history.\nIt is for recall/reference and may not represent the latest state.\n{}\nEND_OLD_MEMORY_RECALL_ZONE",
@@ -123,7 +530,8 @@ impl FingerChatEngine {
                     &mut rolling_input,
                     "context_ledger_focus",
                     Some(recall_block.as_str()),
-                    "user",
+                    focus_role,
+                    options.refresh_context_blocks,
                 );
                 safe_append_ledger(
                     ledger,
@@ -135,14 +543,34 @@ impl FingerChatEngine {
             }
         }
 
-        if let Some(fork_user_message_index) = options.fork_user_message_index {
-            rolling_input = apply_fork_truncate(rolling_input, fork_user_message_index);
-        }
+        let fork = options.fork_user_message_index.map(|user_message_index| {
+            let truncation =
+                apply_fork_truncate(std::mem::take(&mut rolling_input), user_message_index);
+            rolling_input = truncation.history;
+            json!({
+                "user_message_index": user_message_index,
+                "index_out_of_range": truncation.index_out_of_range,
+            })
+        });
 
+        let output_schema_validator = build_output_schema_validator(options)?;
+        let max_schema_retries = options
+            .responses
+            .as_ref()
+            .and_then(|responses| responses.text.as_ref())
+            .and_then(|text_opts| text_opts.max_schema_retries)
+            .unwrap_or(DEFAULT_MAX_SCHEMA_RETRIES);
+        let mut schema_reask_count: u8 = 0;
         let mut tool_trace: Vec<Value> = Vec::new();
         let mut reasoning_trace: Vec<String> = Vec::new();
+        let mut citations: Vec<Value> = Vec::new();
         let mut round_trace: Vec<Value> = Vec::new();
         let mut round: usize = 0;
+        let max_tool_rounds = options
+            .max_tool_rounds
+            .map(|rounds| rounds as usize)
+            .unwrap_or(MAX_TOOL_LOOP_ROUNDS)
+            .max(1);
         let mut progress_seq: u64 = 0;
         let baseline_tokens = options
             .context_window
@@ -161,31 +589,141 @@ impl FingerChatEngine {
         let threshold_percent = Some((threshold_ratio * 100.0).round() as u64);
         let include_reasoning_items = should_replay_reasoning_items(options.responses.as_ref());
         let mut compact_state = CompactExecutionState::default();
-
-        let output_text = loop {
+        let mut turn_usage = ParsedUsage::default();
+        let token_estimator = self.token_estimator.as_ref();
+        let turn_started_at = Instant::now();
+        let mut model_duration_ms: u64 = 0;
+        let mut tool_duration_ms: u64 = 0;
+        let mut tool_result_cache = ToolResultCache::default();
+
+        let mut previous_response: Option<(String, usize)> = None;
+        let mut compact_for_context_length = false;
+        let mut has_compacted_for_context_length = false;
+
+        // `None` output means the model was still calling tools at the round
+        // limit; what the turn did so far is still reported.
+        let (output_text, incomplete_reason) = loop {
+            if round >= max_tool_rounds {
+                break (None, None);
+            }
             round = round.saturating_add(1);
+            let compacted_at_before = compact_state.compressed_at_ms;
             let _ = maybe_apply_compaction(
                 &mut rolling_input,
                 options,
                 context_ledger.as_ref(),
+                token_estimator,
                 baseline_tokens,
                 threshold_ratio,
                 max_input_tokens,
                 &mut compact_state,
+                std::mem::take(&mut compact_for_context_length),
+                progress_tx,
+                &mut progress_seq,
             );
+            if let Some(source_items) = compact_state.pending_model_summary_source.take() {
+                self.apply_model_compact_summary(
+                    &mut rolling_input,
+                    &source_items,
+                    options,
+                    context_ledger.as_ref(),
+                    &mut compact_state,
+                )
+                .await;
+            }
+            // Compaction rewrites history the stored response was built from.
+            if compact_state.compressed_at_ms != compacted_at_before {
+                previous_response = None;
+            }
+            if let Some(max_input_tokens) = max_input_tokens {
+                let estimated_tokens = snapshot_compact_budget(
+                    &rolling_input,
+                    token_estimator,
+                    baseline_tokens,
+                    threshold_ratio,
+                    Some(max_input_tokens),
+                )
+                .estimated_tokens_in_window;
+                // Oversized input would only fail server-side after the
+                // upload: compact once, then give up locally.
+                if estimated_tokens > max_input_tokens {
+                    if !has_compacted_for_context_length {
+                        has_compacted_for_context_length = true;
+                        compact_for_context_length = true;
+                        round = round.saturating_sub(1);
+                        continue;
+                    }
+                    return Err(ModelError::ContextLengthExceeded {
+                        message: format!(
+                            "estimated {estimated_tokens} input tokens exceed max_input_tokens {max_input_tokens}"
+                        ),
+                    });
+                }
+            }
+            let round_span = tracing::info_span!("model_round", round);
+            let mut stream_progress = StreamProgress::new(progress_tx, &mut progress_seq);
+            let model_started_at = Instant::now();
             let response = self
-                .send_protocol_request(&rolling_input, options, &tool_bindings)
-                .await?;
+                .send_protocol_request(
+                    &rolling_input,
+                    previous_response
+                        .as_ref()
+                        .map(|(id, delta_start)| PreviousResponse {
+                            id: id.as_str(),
+                            delta_start: *delta_start,
+                        }),
+                    options,
+                    &tool_bindings,
+                    &mut stream_progress,
+                )
+                .instrument(round_span.clone())
+                .await;
+            model_duration_ms += model_started_at.elapsed().as_millis() as u64;
+            let response = match response {
+                Err(ModelError::ContextLengthExceeded { .. })
+                    if !has_compacted_for_context_length =>
+                {
+                    // Compact once and retry this round; a second overflow fails.
+                    has_compacted_for_context_length = true;
+                    compact_for_context_length = true;
+                    round = round.saturating_sub(1);
+                    continue;
+                }
+                response => response?,
+            };
+            let (response, request_id) = response;
             let parsed = parse_protocol_payload(&response)?;
+            round_span.in_scope(|| {
+                tracing::info!(
+                    request_id = request_id.as_str(),
+                    status = parsed.response_status.as_deref().unwrap_or_default(),
+                    incomplete_reason = parsed
+                        .response_incomplete_reason
+                        .as_deref()
+                        .unwrap_or_default(),
+                    function_calls = parsed.function_calls.len(),
+                    input_tokens = parsed.usage.input_tokens.unwrap_or(0),
+                    output_tokens = parsed.usage.output_tokens.unwrap_or(0),
+                    "model round completed"
+                )
+            });
+            turn_usage.accumulate(&parsed.usage);
+            stream_progress.finish_reasoning(&parsed.reasoning);
+            stream_progress.finish_output_text(parsed.output_text.as_deref());
             let replay_history_items =
                 filter_history_items_for_replay(&parsed.history_items, include_reasoning_items);
             if !replay_history_items.is_empty() {
                 rolling_input.extend(replay_history_items);
             }
+            previous_response = parsed
+                .response_id
+                .clone()
+                .map(|id| (id, rolling_input.len()));
             let estimated_tokens_in_window =
-                estimate_tokens_in_history(&rolling_input).saturating_sub(baseline_tokens);
+                estimate_tokens_in_history(&rolling_input, token_estimator)
+                    .saturating_sub(baseline_tokens);
             let estimated_tokens_compactable =
-                estimate_tokens_excluding_ledger_focus(&rolling_input)
+                estimate_tokens_excluding_ledger_focus(&rolling_input, token_estimator)
                     .saturating_sub(baseline_tokens);
             let context_usage_percent = max_input_tokens.and_then(|max| {
                 if max == 0 {
@@ -214,6 +752,7 @@ impl FingerChatEngine {
                     "model_round",
                     json!({
                         "round": round + 1,
+                        "output_text": parsed.output_text.clone(),
                         "tool_count": tool_bindings.len(),
                         "reasoning_count": parsed.reasoning.len(),
                         "history_items_count": parsed.history_items.len(),
@@ -236,6 +775,7 @@ impl FingerChatEngine {
             if !parsed.reasoning.is_empty() {
                 reasoning_trace.extend(parsed.reasoning.clone());
             }
+            citations.extend(parsed.annotations.iter().cloned());
             let model_round_seq = next_progress_seq(&mut progress_seq);
             round_trace.push(json!({
                 "seq": model_round_seq,
@@ -248,6 +788,7 @@ impl FingerChatEngine {
                 "response_status": parsed.response_status.clone(),
                 "response_incomplete_reason": parsed.response_incomplete_reason.clone(),
                 "response_id": parsed.response_id.clone(),
+                "request_id": request_id.clone(),
                 "input_tokens": parsed.usage.input_tokens,
                 "output_tokens": parsed.usage.output_tokens,
                 "total_tokens": parsed.usage.total_tokens,
@@ -270,6 +811,7 @@ impl FingerChatEngine {
                     response_status: parsed.response_status.clone(),
                     response_incomplete_reason: parsed.response_incomplete_reason.clone(),
                     response_id: parsed.response_id.clone(),
+                    request_id: Some(request_id),
                     input_tokens: parsed.usage.input_tokens,
                     output_tokens: parsed.usage.output_tokens,
                     total_tokens: parsed.usage.total_tokens,
@@ -285,12 +827,49 @@ impl FingerChatEngine {
                 if let Some(text) = parsed.output_text.clone() {
                     let trimmed = text.trim();
                     if !trimmed.is_empty() {
-                        break trimmed.to_string();
+                        let errors = output_schema_validator
+                            .as_ref()
+                            .map(|validator| validate_structured_output(validator, trimmed))
+                            .unwrap_or_default();
+                        if errors.is_empty() {
+                            // Text cut short (e.g. by `max_output_tokens`) is
+                            // still returned, flagged so callers can continue.
+                            break (
+                                Some(trimmed.to_string()),
+                                parsed.response_incomplete_reason.clone(),
+                            );
+                        }
+                        if schema_reask_count >= max_schema_retries {
+                            return Err(ModelError::SchemaValidation { errors });
+                        }
+                        schema_reask_count = schema_reask_count.saturating_add(1);
+                        rolling_input.push(build_schema_reask_item(&errors));
+                        // Re-asks have their own budget, so they do not use up tool rounds.
+                        round = round.saturating_sub(1);
+                        continue;
+                    }
+                }
+                if let Some(message) = parsed.refusal {
+                    if let Some(ledger) = context_ledger.as_ref() {
+                        safe_append_ledger(
+                            ledger,
+                            "model_refusal",
+                            json!({
+                                "round": round,
+                                "response_id": parsed.response_id,
+                                "message": message,
+                            }),
+                        );
                     }
+                    return Err(ModelError::Refused { message });
+                }
+                if let Some(reason) = parsed.response_incomplete_reason {
+                    return Err(ModelError::Incomplete { reason });
                 }
                 return Err(ModelError::EmptyOutput);
             }
 
+            let tools_started_at = Instant::now();
             let function_call_batch = self
                 .execute_function_calls(
                     &parsed.function_calls,
@@ -299,8 +878,11 @@ impl FingerChatEngine {
                     context_ledger.as_ref(),
                     progress_tx,
                     &mut progress_seq,
+                    approvals,
+                    &mut tool_result_cache,
                 )
-                .await;
+                .await?;
+            tool_duration_ms += tools_started_at.elapsed().as_millis() as u64;
             if !function_call_batch.traces.is_empty() {
                 tool_trace.extend(function_call_batch.traces);
             }
@@ -309,8 +891,22 @@ impl FingerChatEngine {
             }
         };
 
+        let usage_seq = next_progress_seq(&mut progress_seq);
+        emit_progress_event(
+            progress_tx,
+            EventMsg::Usage(UsageEvent {
+                seq: usage_seq,
+                input_tokens: turn_usage.input_tokens.unwrap_or(0),
+                output_tokens: turn_usage.output_tokens.unwrap_or(0),
+                total_tokens: turn_usage.total_tokens.unwrap_or(0),
+                reasoning_tokens: turn_usage.reasoning_tokens,
+                rounds: round as u64,
+            }),
+        );
+
         let budget_snapshot = snapshot_compact_budget(
             &rolling_input,
+            token_estimator,
             baseline_tokens,
             threshold_ratio,
             max_input_tokens,
@@ -326,13 +922,28 @@ impl FingerChatEngine {
         let compacted_source_start = compact_state.source_time_start.clone();
         let compacted_source_end = compact_state.source_time_end.clone();
 
+        let finish_reason = output_text
+            .is_none()
+            .then_some(TOOL_LOOP_EXCEEDED_FINISH_REASON);
+        let api_history = if options.strip_reasoning_from_history {
+            Cow::Owned(strip_reasoning_history_items(&rolling_input))
+        } else {
+            Cow::Borrowed(rolling_input.as_slice())
+        };
+        let stripped_reasoning_items = rolling_input.len() - api_history.len();
         let metadata_value = json!({
             "session_id": options.session_id,
             "mode": options.mode,
             "tool_trace": tool_trace,
             "round_trace": round_trace,
             "reasoning_trace": reasoning_trace,
-            "api_history": rolling_input,
+            "citations": citations,
+            "tool_names": tool_name_map(&tool_bindings),
+            "api_history": api_history,
+            "stripped_reasoning_items": stripped_reasoning_items,
+            "incomplete_reason": incomplete_reason,
+            "finish_reason": finish_reason,
+            "fork": fork,
             "context_budget": {
                 "estimated_tokens_in_context_window": estimated_tokens_in_window,
                 "estimated_tokens_compactable": estimated_tokens_compactable,
@@ -360,11 +971,13 @@ impl FingerChatEngine {
                 ledger,
                 "turn_complete",
                 json!({
-                    "reply_chars": output_text.chars().count(),
+                    "reply_chars": output_text.as_deref().map_or(0, |text| text.chars().count()),
                     "tool_trace_count": tool_trace.len(),
                     "reasoning_count": reasoning_trace.len(),
                     "reasoning_trace": reasoning_trace,
                     "compact_applied": compact_applied,
+                    "incomplete_reason": incomplete_reason,
+                    "finish_reason": finish_reason,
                 }),
             );
         }
@@ -374,63 +987,311 @@ impl FingerChatEngine {
         Ok(TurnCompletion {
             output_text,
             metadata_json,
+            metrics: TurnMetrics {
+                rounds: round as u64,
+                tool_calls: tool_trace.len() as u64,
+                tool_errors: tool_trace
+                    .iter()
+                    .filter(|trace| trace["status"] == "error")
+                    .count() as u64,
+                total_duration_ms: turn_started_at.elapsed().as_millis() as u64,
+                model_duration_ms,
+                tool_duration_ms,
+                input_tokens: turn_usage.input_tokens.unwrap_or(0),
+                output_tokens: turn_usage.output_tokens.unwrap_or(0),
+            },
         })
     }
 
+    /// Swaps the task digests of a fresh compaction for a prose summary of
+    /// `source_items` written by the model. Any failure keeps the digests.
+    async fn apply_model_compact_summary(
+        &self,
+        rolling_input: &mut Vec<Value>,
+        source_items: &[Value],
+        options: &UserTurnOptions,
+        context_ledger: Option<&ContextLedger>,
+        compact_state: &mut CompactExecutionState,
+    ) {
+        let transcript = source_items
+            .iter()
+            .filter_map(|item| {
+                let text = extract_text_from_history_item(item)?;
+                let role = item
+                    .get("role")
+                    .and_then(Value::as_str)
+                    .or_else(|| item.get("type").and_then(Value::as_str))
+                    .unwrap_or("item");
+                Some(format!("[{role}] {text}"))
+            })
+            .collect::<Vec<_>>();
+        if transcript.is_empty() {
+            return;
+        }
+
+        let summary_options = UserTurnOptions {
+            system_prompt: Some(COMPACT_SUMMARY_INSTRUCTIONS.to_string()),
+            session_id: options.session_id.clone(),
+            responses: Some(ResponsesRequestOptions {
+                text: Some(ResponsesTextOptions {
+                    verbosity: Some("low".to_string()),
+                    ..ResponsesTextOptions::default()
+                }),
+                store: options
+                    .responses
+                    .as_ref()
+                    .and_then(|responses| responses.store),
+                ..ResponsesRequestOptions::default()
+            }),
+            ..UserTurnOptions::default()
+        };
+        let summary_input = [json!({
+            "role": "user",
+            "content": [{ "type": "input_text", "text": transcript.join("\n") }],
+        })];
+        let mut summary_seq = 0;
+        let mut stream_progress = StreamProgress::new(None, &mut summary_seq);
+        let summary = match self
+            .send_protocol_request(
+                &summary_input,
+                None,
+                &summary_options,
+                &[],
+                &mut stream_progress,
+            )
+            .await
+            .and_then(|(response, _)| parse_protocol_payload(&response))
+        {
+            Ok(parsed) => parsed
+                .output_text
+                .map(|text| text.trim().to_string())
+                .filter(|text| !text.is_empty()),
+            Err(error) => {
+                if let Some(ledger) = context_ledger {
+                    safe_append_ledger(
+                        ledger,
+                        "compact_model_summary_failed",
+                        json!({ "error": error.to_string() }),
+                    );
+                }
+                None
+            }
+        };
+        let Some(summary) = summary else {
+            return;
+        };
+
+        let block = build_model_compact_summary_block(&summary, compact_state);
+        replace_task_digests_with_summary(rolling_input, &block);
+        compact_state.summary = Some(block);
+    }
+
+    /// Builds the request body for the configured wire API, along with the
+    /// endpoint it is posted to.
+    fn build_wire_payload(
+        &self,
+        input: &[Value],
+        options: &UserTurnOptions,
+        tool_payload: Option<&[Value]>,
+        responses_opts: Option<&ResponsesRequestOptions>,
+        previous_response_id: Option<&str>,
+    ) -> (Value, Cow<'_, str>) {
+        let system_prompt = merge_instructions(
+            options.system_prompt.as_deref(),
+            responses_opts.or(options.responses.as_ref()),
+        );
+        let model = self.resolve_model(options);
+        match self.config.wire_api {
+            WireApi::OpenAIChat => {
+                let payload = build_chat_request_payload(
+                    model,
+                    input,
+                    system_prompt.as_deref(),
+                    tool_payload,
+                    responses_opts,
+                );
+                (payload, Cow::Borrowed(CHAT_COMPLETIONS_ENDPOINT_PATH))
+            }
+            WireApi::Anthropic => {
+                let payload = build_anthropic_request_payload(
+                    model,
+                    input,
+                    system_prompt.as_deref(),
+                    tool_payload,
+                    responses_opts,
+                    options.anthropic.as_ref(),
+                );
+                (payload, Cow::Borrowed(ANTHROPIC_MESSAGES_ENDPOINT_PATH))
+            }
+            WireApi::Responses => {
+                let prompt_cache_key = self.resolve_prompt_cache_key(
+                    options,
+                    system_prompt.as_deref(),
+                    tool_payload,
+                    responses_opts.or(options.responses.as_ref()),
+                );
+                let payload = build_responses_request_payload(
+                    model,
+                    input,
+                    system_prompt.as_deref(),
+                    tool_payload,
+                    prompt_cache_key.as_deref(),
+                    responses_opts,
+                    Some(self.config.base_url.as_str()),
+                    previous_response_id,
+                );
+                let path = match &self.config.azure {
+                    Some(azure) => Cow::Owned(azure.responses_path()),
+                    None => Cow::Borrowed(
+                        self.config
+                            .responses_path
+                            .as_deref()
+                            .unwrap_or(RESPONSES_ENDPOINT_PATH),
+                    ),
+                };
+                (payload, path)
+            }
+        }
+    }
+
+    /// The turn's non-empty `model_override`, else the configured model.
+    fn resolve_model<'a>(&'a self, options: &'a UserTurnOptions) -> &'a str {
+        options
+            .model_override
+            .as_deref()
+            .map(str::trim)
+            .filter(|model| !model.is_empty())
+            .unwrap_or(&self.config.model)
+    }
+
+    /// An explicit `responses.prompt_cache_key` wins; otherwise turns sharing
+    /// a stable prefix share a key, falling back to the session id.
+    fn resolve_prompt_cache_key(
+        &self,
+        options: &UserTurnOptions,
+        system_prompt: Option<&str>,
+        tool_payload: Option<&[Value]>,
+        responses_opts: Option<&ResponsesRequestOptions>,
+    ) -> Option<String> {
+        responses_opts
+            .and_then(|opts| opts.prompt_cache_key.as_deref())
+            .map(str::trim)
+            .filter(|key| !key.is_empty())
+            .map(str::to_string)
+            .or_else(|| {
+                stable_prefix_cache_key(
+                    self.resolve_model(options),
+                    system_prompt,
+                    tool_payload,
+                    &[
+                        options.developer_instructions.as_deref(),
+                        options.user_instructions.as_deref(),
+                    ],
+                )
+            })
+            .or_else(|| options.session_id.clone())
+    }
+
     async fn send_protocol_request(
         &self,
         input: &[Value],
+        previous_response: Option<PreviousResponse<'_>>,
         options: &UserTurnOptions,
         tool_bindings: &[ToolBinding],
-    ) -> Result<Value, ModelError> {
-        let tool_payload = if tool_bindings.is_empty() {
-            None
-        } else {
-            Some(
-                tool_bindings
-                    .iter()
-                    .map(build_responses_tool)
-                    .collect::<Vec<_>>(),
-            )
-        };
+        stream_progress: &mut StreamProgress<'_>,
+    ) -> Result<(Value, String), ModelError> {
+        let tool_payload = build_tool_payload(tool_bindings);
+        let base_responses_opts =
+            resolve_responses_tool_choice(options.responses.as_ref(), tool_bindings);
         let mut store_retry_override: Option<ResponsesRequestOptions> = None;
         let mut has_retried_store = false;
         let mut sanitized_input_override: Option<Vec<Value>> = None;
         let mut has_retried_without_reasoning = false;
-        let mut authentication_retry_count: u8 = 0;
+        let mut has_dropped_previous_response = false;
+        let mut transient_attempts: u32 = 1;
+        let mut last_retry_delay: Option<Duration> = None;
         let mut missing_stream_retry_count: u8 = 0;
+        let mut rate_limit_retry_count: u8 = 0;
 
         loop {
             let request_input = sanitized_input_override.as_deref().unwrap_or(input);
-            let responses_opts = store_retry_override.as_ref().or(options.responses.as_ref());
-            let payload = build_responses_request_payload(
-                &self.config.model,
+            let responses_opts = store_retry_override
+                .as_ref()
+                .or(base_responses_opts.as_ref());
+            let wire_api = self.config.wire_api;
+            let chained_response = previous_response.filter(|previous| {
+                wire_api == WireApi::Responses
+                    && !has_dropped_previous_response
+                    && sanitized_input_override.is_none()
+                    && previous.delta_start <= request_input.len()
+                    && responses_opts.is_some_and(|opts| {
+                        opts.use_previous_response_id && opts.store == Some(true) && !opts.stateless
+                    })
+            });
+            let request_input = match chained_response {
+                Some(previous) => &request_input[previous.delta_start..],
+                None => request_input,
+            };
+            let (payload, endpoint_path) = self.build_wire_payload(
                 request_input,
-                options.system_prompt.as_deref(),
+                options,
                 tool_payload.as_deref(),
-                options.session_id.as_deref(),
                 responses_opts,
-                Some(self.config.base_url.as_str()),
+                chained_response.map(|previous| previous.id),
             );
             let expect_sse = payload
                 .get("stream")
                 .and_then(Value::as_bool)
                 .unwrap_or(false);
 
+            stream_progress.reset_attempt();
+            let request_id = new_request_id();
+            let headers = headers_with_request_id(&request_id, &self.extra_headers);
             let wire_body = match send_responses_http(
                 &self.client,
                 &self.config.base_url,
+                &endpoint_path,
+                wire_api,
+                ApiKeyAuth::for_config(&self.config),
                 &self.config.api_key,
+                &headers,
                 &payload,
                 expect_sse,
+                self.client_options.sse_idle_timeout,
+                self.client_options.max_response_bytes,
+                &mut |event_type, event| match wire_api {
+                    WireApi::OpenAIChat => {
+                        for (event_type, event) in chat_chunk_progress_events(event) {
+                            stream_progress.observe_sse_event(event_type, &event);
+                        }
+                    }
+                    WireApi::Anthropic => {
+                        for (event_type, event) in
+                            anthropic_event_progress_events(event_type, event)
+                        {
+                            stream_progress.observe_sse_event(event_type, &event);
+                        }
+                    }
+                    WireApi::Responses => stream_progress.observe_sse_event(event_type, event),
+                },
             )
             .await
             {
                 Ok(body) => body,
+                Err(ModelError::HttpStatus { status, body })
+                    if chained_response.is_some()
+                        && should_retry_without_previous_response(status, body.as_str()) =>
+                {
+                    // The stored response expired or was dropped; resend the
+                    // full history instead.
+                    has_dropped_previous_response = true;
+                    continue;
+                }
                 Err(ModelError::HttpStatus { status, body })
                     if !has_retried_store
                         && should_retry_with_store(status, body.as_str())
-                        && !responses_opts.and_then(|opts| opts.store).unwrap_or(false) =>
+                        && !responses_opts.and_then(|opts| opts.store).unwrap_or(false)
+                        && !is_stateless(responses_opts) =>
                 {
                     has_retried_store = true;
                     store_retry_override = Some(responses_with_store_enabled(responses_opts));
@@ -446,22 +1307,48 @@ impl FingerChatEngine {
                         sanitized_input_override = Some(sanitized);
                         continue;
                     }
-                    return Err(ModelError::HttpStatus { status, body });
+                    return Err(classify_http_error(status, body));
                 }
-                Err(ModelError::HttpStatus { status, body })
-                    if should_retry_authentication_failure(status, body.as_str())
-                        && authentication_retry_count < 2 =>
+                Err(error)
+                    if transient_attempts < self.retry_policy.max_attempts
+                        && is_transient_provider_error(
+                            &error,
+                            rate_limit_retry_count < self.client_options.max_rate_limit_retries,
+                        ) =>
                 {
-                    authentication_retry_count = authentication_retry_count.saturating_add(1);
-                    let backoff_ms = 200_u64.saturating_mul(authentication_retry_count as u64);
-                    sleep(Duration::from_millis(backoff_ms)).await;
+                    transient_attempts = transient_attempts.saturating_add(1);
+                    let backoff = self.retry_policy.next_delay(last_retry_delay);
+                    last_retry_delay = Some(backoff);
+                    tracing::warn!(
+                        attempt = transient_attempts,
+                        error = %error,
+                        "retrying transient provider error"
+                    );
+                    let delay = match error {
+                        ModelError::RateLimited {
+                            retry_after_secs, ..
+                        } => {
+                            rate_limit_retry_count = rate_limit_retry_count.saturating_add(1);
+                            rate_limit_retry_delay(retry_after_secs, backoff)
+                        }
+                        _ => backoff,
+                    };
+                    sleep(delay).await;
                     continue;
                 }
+                Err(ModelError::HttpStatus { status, body }) => {
+                    return Err(classify_http_error(status, body))
+                }
                 Err(error) => return Err(error),
             };
 
-            match parse_wire_response(wire_body) {
-                Ok(parsed) => return Ok(parsed),
+            let parsed_wire = match wire_api {
+                WireApi::OpenAIChat => parse_chat_wire_response(wire_body),
+                WireApi::Anthropic => parse_anthropic_wire_response(wire_body),
+                WireApi::Responses => parse_wire_response(wire_body),
+            };
+            match parsed_wire {
+                Ok(parsed) => return Ok((parsed, request_id)),
                 Err(ModelError::MissingStreamResponse)
                     if missing_stream_retry_count < MAX_MISSING_STREAM_RETRIES =>
                 {
@@ -477,6 +1364,7 @@ impl FingerChatEngine {
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn execute_function_calls(
         &self,
         function_calls: &[FunctionCallItem],
@@ -485,18 +1373,36 @@ impl FingerChatEngine {
         context_ledger: Option<&ContextLedger>,
         progress_tx: Option<&UnboundedSender<EventMsg>>,
         progress_seq: &mut u64,
-    ) -> ToolExecutionBatch {
-        let runtime_config = execution_config.cloned().unwrap_or(ToolExecutionConfig {
+        approvals: Option<&ApprovalBroker>,
+        tool_result_cache: &mut ToolResultCache,
+    ) -> Result<ToolExecutionBatch, ModelError> {
+        let mut runtime_config = execution_config.cloned().unwrap_or(ToolExecutionConfig {
             daemon_url: self.config.tool_daemon_url.clone(),
             agent_id: self.config.tool_agent_id.clone(),
+            max_concurrency: None,
+            tool_timeout_ms: None,
+            auth_token: None,
+            retry_transient_errors: false,
+            tool_max_retries: None,
+            tool_execute_path: None,
+            cache_identical_calls: false,
+            routes: Vec::new(),
         });
+        if runtime_config.auth_token.is_none() {
+            runtime_config.auth_token = self.config.tool_daemon_token.clone();
+        }
+        let max_concurrency = runtime_config
+            .max_concurrency
+            .unwrap_or(DEFAULT_TOOL_CALL_CONCURRENCY)
+            .max(1);
 
-        let mut output_items = Vec::with_capacity(function_calls.len());
-        let mut traces = Vec::with_capacity(function_calls.len());
+        let mut pending_calls = Vec::with_capacity(function_calls.len());
+        let mut repaired_arguments = Vec::with_capacity(function_calls.len());
         for call in function_calls {
             let runtime_tool_name = resolve_runtime_tool_name(&call.name, tool_bindings);
-            let tool_input_snapshot = parse_function_arguments(&call.arguments);
-            let mut view_image_local_path: Option<String> = None;
+            let (tool_input_snapshot, arguments_repaired) =
+                parse_function_arguments_with_repair(&call.arguments);
+            repaired_arguments.push(arguments_repaired);
             let tool_call_seq = next_progress_seq(progress_seq);
             emit_progress_event(
                 progress_tx,
@@ -513,26 +1419,109 @@ impl FingerChatEngine {
                     "tool_call",
                     json!({
                         "call_id": call.call_id,
+                        "name": call.name,
+                        "arguments": call.arguments,
                         "tool_name": runtime_tool_name,
                         "input": tool_input_snapshot,
                     }),
                 );
             }
-            let started_at = Instant::now();
-            let output_payload = match self
-                .execute_single_tool_call(
-                    call,
-                    &runtime_config,
-                    runtime_tool_name.as_str(),
-                    context_ledger,
+            pending_calls.push((call, runtime_tool_name, tool_input_snapshot));
+        }
+
+        // Approvals are asked one at a time, before any call runs, so the user
+        // sees requests in the order the model issued them.
+        let mut denials = Vec::with_capacity(pending_calls.len());
+        for (call, runtime_tool_name, tool_input_snapshot) in &pending_calls {
+            let denial = match resolve_tool_approval_kind(runtime_tool_name, tool_bindings) {
+                Some(kind) => {
+                    await_tool_approval(
+                        approvals,
+                        kind,
+                        call,
+                        runtime_tool_name,
+                        tool_input_snapshot,
+                        progress_tx,
+                        progress_seq,
+                    )
+                    .await?
+                }
+                None => None,
+            };
+            denials.push(denial);
+        }
+
+        // With caching on, a call identical to an earlier one (this turn or
+        // this round) waits until the round's other calls finish, then reuses
+        // the earlier result if it succeeded.
+        let cache_keys = pending_calls
+            .iter()
+            .map(|(_, runtime_tool_name, tool_input_snapshot)| {
+                runtime_config
+                    .cache_identical_calls
+                    .then(|| ToolResultCache::key(runtime_tool_name, tool_input_snapshot))
+            })
+            .collect::<Vec<_>>();
+        let mut deferred = Vec::new();
+        for (index, key) in cache_keys.iter().enumerate() {
+            let Some(key) = key.as_ref().filter(|_| denials[index].is_none()) else {
+                continue;
+            };
+            if tool_result_cache.contains(key) || cache_keys[..index].contains(&Some(key.clone())) {
+                deferred.push(index);
+            }
+        }
+
+        // Calls run concurrently and report progress as they finish; outputs are
+        // reassembled in input order afterwards so replay stays deterministic.
+        let runtime_config = &runtime_config;
+        let mut tool_futures = Vec::with_capacity(pending_calls.len());
+        for (index, (call, runtime_tool_name, _)) in pending_calls.iter().enumerate() {
+            if deferred.contains(&index) {
+                continue;
+            }
+            let denial = denials[index].clone();
+            tool_futures.push(async move {
+                if let Some(message) = denial {
+                    let error = ModelError::ToolExecution {
+                        tool_name: runtime_tool_name.clone(),
+                        message,
+                    };
+                    return (index, Err(error), 0, 0);
+                }
+                let started_at = Instant::now();
+                let (result, retries) = self
+                    .execute_single_tool_call(
+                        call,
+                        runtime_config,
+                        runtime_tool_name.as_str(),
+                        context_ledger,
+                    )
+                    .await;
+                (
+                    index,
+                    result,
+                    started_at.elapsed().as_millis() as u64,
+                    retries,
                 )
-                .await
-            {
+            });
+        }
+        let mut completions = stream::iter(tool_futures).buffer_unordered(max_concurrency);
+
+        let mut completed_calls: Vec<Option<CompletedToolCall>> =
+            (0..pending_calls.len()).map(|_| None).collect();
+        let mut finish_call = |index: usize,
+                               result: Result<Value, ModelError>,
+                               duration_ms: u64,
+                               retries: u8,
+                               cached: bool| {
+            let (call, runtime_tool_name, tool_input_snapshot) = &pending_calls[index];
+            let mut view_image_local_path: Option<String> = None;
+            let (output_payload, mut trace) = match result {
                 Ok(result) => {
                     if runtime_tool_name == "view_image" {
                         view_image_local_path = extract_view_image_local_path(&result);
                     }
-                    let duration_ms = started_at.elapsed().as_millis() as u64;
                     let tool_result_seq = next_progress_seq(progress_seq);
                     emit_progress_event(
                         progress_tx,
@@ -558,7 +1547,7 @@ impl FingerChatEngine {
                             }),
                         );
                     }
-                    traces.push(json!({
+                    let mut trace = json!({
                         "call_id": call.call_id,
                         "tool": runtime_tool_name,
                         "status": "ok",
@@ -566,16 +1555,21 @@ impl FingerChatEngine {
                         "input": tool_input_snapshot.clone(),
                         "output": result.clone(),
                         "duration_ms": duration_ms,
-                    }));
-                    json!({
+                        "retries": retries,
+                    });
+                    if cached {
+                        trace["cached"] = Value::Bool(true);
+                    }
+                    let output_payload = json!({
                         "ok": true,
                         "tool": runtime_tool_name,
                         "result": result,
-                    })
+                    });
+                    (output_payload, trace)
                 }
                 Err(error) => {
-                    let duration_ms = started_at.elapsed().as_millis() as u64;
                     let error_message = error.to_string();
+                    let transport = matches!(error, ModelError::ToolTransport { .. });
                     let tool_error_seq = next_progress_seq(progress_seq);
                     emit_progress_event(
                         progress_tx,
@@ -585,6 +1579,7 @@ impl FingerChatEngine {
                             tool_name: runtime_tool_name.clone(),
                             error: error_message.clone(),
                             duration_ms,
+                            transport,
                         }),
                     );
                     if let Some(ledger) = context_ledger {
@@ -601,7 +1596,7 @@ impl FingerChatEngine {
                             }),
                         );
                     }
-                    traces.push(json!({
+                    let mut trace = json!({
                         "call_id": call.call_id,
                         "tool": runtime_tool_name,
                         "status": "error",
@@ -609,23 +1604,84 @@ impl FingerChatEngine {
                         "input": tool_input_snapshot.clone(),
                         "error": error_message,
                         "duration_ms": duration_ms,
-                    }));
-                    json!({
+                        "retries": retries,
+                    });
+                    if transport {
+                        trace["transport"] = Value::Bool(true);
+                    }
+                    let output_payload = json!({
                         "ok": false,
                         "tool": runtime_tool_name,
                         "error": error_message,
-                    })
+                    });
+                    (output_payload, trace)
                 }
             };
+            if repaired_arguments[index] {
+                trace["arguments_repaired"] = Value::Bool(true);
+            }
+            completed_calls[index] = Some(CompletedToolCall {
+                output_payload,
+                trace,
+                view_image_local_path,
+            });
+        };
+        while let Some((index, result, duration_ms, retries)) = completions.next().await {
+            if let (Ok(result), Some(key)) = (&result, &cache_keys[index]) {
+                tool_result_cache.insert(key.clone(), result.clone());
+            }
+            finish_call(index, result, duration_ms, retries, false);
+        }
+        for index in deferred {
+            let (call, runtime_tool_name, _) = &pending_calls[index];
+            let cached = cache_keys[index]
+                .as_ref()
+                .and_then(|key| tool_result_cache.get(key));
+            match cached {
+                Some(result) => finish_call(index, Ok(result), 0, 0, true),
+                None => {
+                    let started_at = Instant::now();
+                    let (result, retries) = self
+                        .execute_single_tool_call(
+                            call,
+                            runtime_config,
+                            runtime_tool_name.as_str(),
+                            context_ledger,
+                        )
+                        .await;
+                    let duration_ms = started_at.elapsed().as_millis() as u64;
+                    if let (Ok(result), Some(key)) = (&result, &cache_keys[index]) {
+                        tool_result_cache.insert(key.clone(), result.clone());
+                    }
+                    finish_call(index, result, duration_ms, retries, false);
+                }
+            }
+        }
 
+        let mut output_items = Vec::with_capacity(function_calls.len());
+        let mut traces = Vec::with_capacity(function_calls.len());
+        for ((call, runtime_tool_name, _), completed) in pending_calls.iter().zip(completed_calls) {
+            let Some(completed) = completed else {
+                continue;
+            };
+            traces.push(completed.trace);
+            // Image results use the multimodal output shape so the model
+            // sees the image rather than its encoded text.
+            let output = match split_tool_output_image(&completed.output_payload) {
+                Some((text_payload, image_url)) => json!([
+                    { "type": "input_text", "text": text_payload.to_string() },
+                    { "type": "input_image", "image_url": image_url },
+                ]),
+                None => Value::String(completed.output_payload.to_string()),
+            };
             output_items.push(json!({
                 "type": "function_call_output",
                 "call_id": call.call_id,
-                "output": output_payload.to_string(),
+                "output": output,
             }));
 
             if runtime_tool_name == "view_image" {
-                if let Some(local_path) = view_image_local_path.as_deref() {
+                if let Some(local_path) = completed.view_image_local_path.as_deref() {
                     if let Ok(image_url) = to_data_url_from_local_image(local_path) {
                         output_items.push(json!({
                             "role": "user",
@@ -641,23 +1697,128 @@ impl FingerChatEngine {
             }
         }
 
-        ToolExecutionBatch {
+        Ok(ToolExecutionBatch {
             output_items,
             traces,
-        }
+        })
     }
 
-    async fn execute_single_tool_call(
+    async fn inline_remote_images(
         &self,
-        call: &FunctionCallItem,
-        config: &ToolExecutionConfig,
-        runtime_tool_name: &str,
-        context_ledger: Option<&ContextLedger>,
-    ) -> Result<Value, ModelError> {
-        let endpoint = format!(
-            "{}/api/v1/tools/execute",
-            config.daemon_url.trim_end_matches('/')
-        );
+        items: &[InputItem],
+    ) -> Result<Option<Vec<InputItem>>, ModelError> {
+        let Some(max_bytes) = self.remote_image_max_bytes else {
+            return Ok(None);
+        };
+        if !items.iter().any(is_remote_image_item) {
+            return Ok(None);
+        }
+
+        let mut inlined = Vec::with_capacity(items.len());
+        for item in items {
+            match item {
+                InputItem::Image { image_url } if is_remote_image_url(image_url) => {
+                    inlined.push(InputItem::Image {
+                        image_url: self
+                            .fetch_remote_image_data_url(image_url, max_bytes)
+                            .await?,
+                    });
+                }
+                other => inlined.push(other.clone()),
+            }
+        }
+        Ok(Some(inlined))
+    }
+
+    async fn fetch_remote_image_data_url(
+        &self,
+        url: &str,
+        max_bytes: u64,
+    ) -> Result<String, ModelError> {
+        let fetch_error = |error: String| ModelError::RemoteImageFetch {
+            url: url.to_string(),
+            error,
+        };
+        let oversize_error = || fetch_error(format!("image exceeds max size of {max_bytes} bytes"));
+
+        let mut response = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .map_err(|error| fetch_error(error.to_string()))?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(fetch_error(format!("status {}", status.as_u16())));
+        }
+        if response
+            .content_length()
+            .is_some_and(|length| length > max_bytes)
+        {
+            return Err(oversize_error());
+        }
+
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(';').next())
+            .map(|value| value.trim().to_ascii_lowercase())
+            .filter(|value| value.starts_with("image/"));
+
+        let mut bytes = Vec::new();
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .map_err(|error| fetch_error(error.to_string()))?
+        {
+            if (bytes.len() + chunk.len()) as u64 > max_bytes {
+                return Err(oversize_error());
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+
+        let mime = content_type.unwrap_or_else(|| {
+            let path = reqwest::Url::parse(url)
+                .map(|parsed| parsed.path().to_string())
+                .unwrap_or_default();
+            sniff_image_mime_type(&bytes)
+                .unwrap_or_else(|| infer_image_mime_type(&path))
+                .to_string()
+        });
+        let encoded = base64::engine::general_purpose::STANDARD.encode(bytes);
+        Ok(format!("data:{mime};base64,{encoded}"))
+    }
+
+    #[tracing::instrument(
+        name = "tool_call",
+        skip_all,
+        fields(call_id = call.call_id.as_str(), tool = runtime_tool_name)
+    )]
+    async fn execute_single_tool_call(
+        &self,
+        call: &FunctionCallItem,
+        config: &ToolExecutionConfig,
+        runtime_tool_name: &str,
+        context_ledger: Option<&ContextLedger>,
+    ) -> (Result<Value, ModelError>, u8) {
+        let (result, retries) = self
+            .run_single_tool_call(call, config, runtime_tool_name, context_ledger)
+            .await;
+        match &result {
+            Ok(_) => tracing::info!(retries, "tool call completed"),
+            Err(error) => tracing::warn!(retries, error = %error, "tool call failed"),
+        }
+        (result, retries)
+    }
+
+    async fn run_single_tool_call(
+        &self,
+        call: &FunctionCallItem,
+        config: &ToolExecutionConfig,
+        runtime_tool_name: &str,
+        context_ledger: Option<&ContextLedger>,
+    ) -> (Result<Value, ModelError>, u8) {
         let mut parsed_input = parse_function_arguments(&call.arguments);
         if runtime_tool_name == "context_ledger.memory" {
             parsed_input = inject_context_ledger_runtime_context(parsed_input, context_ledger);
@@ -665,36 +1826,123 @@ impl FingerChatEngine {
         if runtime_tool_name == "shell.exec" {
             parsed_input = normalize_shell_exec_input(parsed_input);
         }
+        if let Some(executor) = self
+            .tool_executor
+            .as_ref()
+            .filter(|executor| executor.handles(runtime_tool_name))
+        {
+            let result = executor
+                .execute(runtime_tool_name, parsed_input)
+                .await
+                .map_err(|message| ModelError::ToolExecution {
+                    tool_name: runtime_tool_name.to_string(),
+                    message,
+                });
+            return (result, 0);
+        }
+
+        let (daemon_url, agent_id) = resolve_tool_daemon(config, runtime_tool_name);
+        let endpoint = join_endpoint(
+            daemon_url,
+            config
+                .tool_execute_path
+                .as_deref()
+                .unwrap_or(TOOL_EXECUTE_ENDPOINT_PATH),
+        );
         let request_payload = json!({
-            "agentId": config.agent_id,
+            "agentId": agent_id,
             "toolName": runtime_tool_name,
             "input": parsed_input,
         });
 
-        let response = self
+        let max_retries = if config.retry_transient_errors {
+            config.tool_max_retries.unwrap_or(DEFAULT_TOOL_MAX_RETRIES)
+        } else {
+            0
+        };
+        let mut retries = 0;
+        loop {
+            match self
+                .send_tool_request(&endpoint, &request_payload, config, runtime_tool_name)
+                .await
+            {
+                Err(failure) if failure.transient && retries < max_retries => {
+                    retries += 1;
+                    let backoff_ms = tool_retry_backoff_ms(retries);
+                    tracing::warn!(
+                        retry = retries,
+                        backoff_ms,
+                        error = %failure.error,
+                        "retrying transient tool daemon error"
+                    );
+                    tokio::time::sleep(Duration::from_millis(backoff_ms)).await;
+                }
+                result => return (result.map_err(|failure| failure.error), retries),
+            }
+        }
+    }
+
+    async fn send_tool_request(
+        &self,
+        endpoint: &str,
+        request_payload: &Value,
+        config: &ToolExecutionConfig,
+        runtime_tool_name: &str,
+    ) -> Result<Value, ToolRequestFailure> {
+        let mut request = self
             .client
             .post(endpoint)
             .header(reqwest::header::CONTENT_TYPE, "application/json")
             .header(reqwest::header::ACCEPT, "application/json")
-            .json(&request_payload)
-            .send()
-            .await?;
+            .headers(headers_with_request_id(
+                &new_request_id(),
+                &self.extra_headers,
+            ))
+            .json(request_payload);
+        if let Some(timeout_ms) = config.tool_timeout_ms {
+            request = request.timeout(Duration::from_millis(timeout_ms));
+        }
+        if let Some(token) = config
+            .auth_token
+            .as_deref()
+            .map(str::trim)
+            .filter(|token| !token.is_empty())
+        {
+            request = request.bearer_auth(token);
+        }
+        let map_request_error = |error: reqwest::Error| ToolRequestFailure {
+            transient: is_transient_tool_transport_error(&error),
+            error: map_tool_request_error(error, runtime_tool_name, config.tool_timeout_ms),
+        };
+        let mut response = request.send().await.map_err(map_request_error)?;
 
         let status = response.status();
-        let body = response.bytes().await?;
-        let payload = serde_json::from_slice::<Value>(&body).map_err(ModelError::from)?;
-
+        let body = read_body_capped(
+            &mut response,
+            self.client_options.max_response_bytes,
+            map_request_error,
+        )
+        .await?;
         if !status.is_success() {
-            let message = payload
-                .get("error")
-                .and_then(Value::as_str)
-                .map(str::to_string)
+            // Proxies answer 5xx with HTML, so a non-JSON body is not an error here.
+            let message = serde_json::from_slice::<Value>(&body)
+                .ok()
+                .and_then(|payload| {
+                    payload
+                        .get("error")
+                        .and_then(Value::as_str)
+                        .map(str::to_string)
+                })
                 .unwrap_or_else(|| String::from_utf8_lossy(&body).to_string());
-            return Err(ModelError::ToolExecution {
-                tool_name: runtime_tool_name.to_string(),
-                message,
+            return Err(ToolRequestFailure {
+                transient: status.is_server_error(),
+                error: ModelError::ToolExecution {
+                    tool_name: runtime_tool_name.to_string(),
+                    message,
+                },
             });
         }
+        let payload = serde_json::from_slice::<Value>(&body).map_err(ModelError::from)?;
 
         // Check for success:false in response body (tool execution returned error but HTTP 200)
         if payload.get("success").and_then(Value::as_bool) == Some(false) {
@@ -704,20 +1952,64 @@ impl FingerChatEngine {
             return Err(ModelError::ToolExecution {
                 tool_name: runtime_tool_name.to_string(),
                 message: error_msg,
-            });
+            }
+            .into());
         }
 
         if let Some(error_message) = payload.get("error").and_then(Value::as_str) {
             return Err(ModelError::ToolExecution {
                 tool_name: runtime_tool_name.to_string(),
                 message: error_message.to_string(),
-            });
+            }
+            .into());
         }
 
         Ok(payload.get("result").cloned().unwrap_or(Value::Null))
     }
 }
 
+/// Connection failures, including a reset on a pooled connection or a body
+/// cut off mid-response, may succeed on a fresh connection; timeouts and
+/// undecodable bodies would only fail again.
+fn is_transient_tool_transport_error(error: &reqwest::Error) -> bool {
+    !error.is_timeout()
+        && !error.is_decode()
+        && (error.is_connect() || error.is_request() || error.is_body())
+}
+
+/// A failed tool daemon request; `transient` failures (connection errors
+/// and 5xx responses) may succeed when retried.
+struct ToolRequestFailure {
+    error: ModelError,
+    transient: bool,
+}
+
+impl From<ModelError> for ToolRequestFailure {
+    fn from(error: ModelError) -> Self {
+        Self {
+            error,
+            transient: false,
+        }
+    }
+}
+
+fn map_tool_request_error(
+    error: reqwest::Error,
+    runtime_tool_name: &str,
+    tool_timeout_ms: Option<u64>,
+) -> ModelError {
+    match tool_timeout_ms {
+        Some(timeout_ms) if error.is_timeout() => ModelError::ToolExecution {
+            tool_name: runtime_tool_name.to_string(),
+            message: format!("timed out after {timeout_ms}ms"),
+        },
+        _ => ModelError::ToolTransport {
+            tool_name: runtime_tool_name.to_string(),
+            source: error,
+        },
+    }
+}
+
 fn inject_context_ledger_runtime_context(
     input: Value,
     context_ledger: Option<&ContextLedger>,
@@ -756,11 +2048,33 @@ impl ChatEngine for FingerChatEngine {
         &self,
         request: &TurnRequest,
         progress_tx: Option<UnboundedSender<EventMsg>>,
+    ) -> Result<TurnRunResult, String> {
+        self.run_turn_inner(request, progress_tx, None).await
+    }
+
+    async fn run_turn_with_approvals(
+        &self,
+        request: &TurnRequest,
+        progress_tx: Option<UnboundedSender<EventMsg>>,
+        approvals: ApprovalBroker,
+    ) -> Result<TurnRunResult, String> {
+        self.run_turn_inner(request, progress_tx, Some(&approvals))
+            .await
+    }
+}
+
+impl FingerChatEngine {
+    async fn run_turn_inner(
+        &self,
+        request: &TurnRequest,
+        progress_tx: Option<UnboundedSender<EventMsg>>,
+        approvals: Option<&ApprovalBroker>,
     ) -> Result<TurnRunResult, String> {
         let has_supported_input = request.items.iter().any(|item| match item {
             InputItem::Text { text } => !text.trim().is_empty(),
             InputItem::Image { image_url } => !image_url.trim().is_empty(),
             InputItem::LocalImage { path } => !path.trim().is_empty(),
+            InputItem::File { path, file_id, .. } => has_file_reference(path, file_id),
         });
 
         if !has_supported_input {
@@ -770,7 +2084,12 @@ impl ChatEngine for FingerChatEngine {
         let context_ledger = build_context_ledger(&request.options);
 
         let completion = self
-            .complete_with_options(&request.items, &request.options, progress_tx.as_ref())
+            .complete_with_options(
+                &request.items,
+                &request.options,
+                progress_tx.as_ref(),
+                approvals,
+            )
             .await
             .map_err(|err| {
                 let error_msg = err.to_string();
@@ -786,9 +2105,15 @@ impl ChatEngine for FingerChatEngine {
                 error_msg
             })?;
 
+        let finish_reason = completion
+            .output_text
+            .is_none()
+            .then(|| TOOL_LOOP_EXCEEDED_FINISH_REASON.to_string());
         Ok(TurnRunResult {
-            last_agent_message: Some(completion.output_text),
+            last_agent_message: completion.output_text,
             metadata_json: completion.metadata_json,
+            metrics: completion.metrics,
+            finish_reason,
         })
     }
 }
@@ -796,9 +2121,11 @@ impl ChatEngine for FingerChatEngine {
 #[derive(Debug, Clone)]
 pub(crate) struct ParsedResponse {
     output_text: Option<String>,
+    refusal: Option<String>,
     function_calls: Vec<FunctionCallItem>,
     history_items: Vec<Value>,
     reasoning: Vec<String>,
+    annotations: Vec<Value>,
     finish_reason: Option<String>,
     response_status: Option<String>,
     response_incomplete_reason: Option<String>,
@@ -811,6 +2138,27 @@ pub(crate) struct ParsedUsage {
     input_tokens: Option<u64>,
     output_tokens: Option<u64>,
     total_tokens: Option<u64>,
+    reasoning_tokens: Option<u64>,
+}
+
+impl ParsedUsage {
+    fn accumulate(&mut self, round: &ParsedUsage) {
+        fn add(total: Option<u64>, round: Option<u64>) -> Option<u64> {
+            match (total, round) {
+                (None, None) => None,
+                (total, round) => Some(total.unwrap_or(0).saturating_add(round.unwrap_or(0))),
+            }
+        }
+        self.input_tokens = add(self.input_tokens, round.input_tokens);
+        self.output_tokens = add(self.output_tokens, round.output_tokens);
+        self.total_tokens = add(
+            self.total_tokens,
+            round
+                .total_tokens
+                .or_else(|| add(round.input_tokens, round.output_tokens)),
+        );
+        self.reasoning_tokens = add(self.reasoning_tokens, round.reasoning_tokens);
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -826,12 +2174,31 @@ struct ToolBinding {
     model_name: String,
     description: Option<String>,
     input_schema: Option<Value>,
+    approval: Option<ApprovalKind>,
 }
 
 #[derive(Debug, Clone)]
 struct TurnCompletion {
-    output_text: String,
+    /// `None` when the tool loop hit its round limit.
+    output_text: Option<String>,
     metadata_json: Option<String>,
+    metrics: TurnMetrics,
+}
+
+impl TurnCompletion {
+    fn into_output_text(self) -> Result<String, ModelError> {
+        self.output_text.ok_or(ModelError::ToolLoopExceeded {
+            rounds: self.metrics.rounds,
+        })
+    }
+}
+
+/// A stored response a request continues from; the provider already holds
+/// the first `delta_start` input items, so only the rest are sent.
+#[derive(Debug, Clone, Copy)]
+struct PreviousResponse<'a> {
+    id: &'a str,
+    delta_start: usize,
 }
 
 #[derive(Debug, Clone)]
@@ -840,6 +2207,47 @@ struct ToolExecutionBatch {
     traces: Vec<Value>,
 }
 
+struct CompletedToolCall {
+    output_payload: Value,
+    trace: Value,
+    view_image_local_path: Option<String>,
+}
+
+/// Successful tool results of one turn, keyed by tool and arguments; the
+/// oldest entry is evicted once the cache is full.
+#[derive(Default)]
+struct ToolResultCache {
+    results: HashMap<String, Value>,
+    order: VecDeque<String>,
+}
+
+impl ToolResultCache {
+    fn key(runtime_tool_name: &str, input: &Value) -> String {
+        let input = canonicalize_tool_arguments(input.clone());
+        format!("{runtime_tool_name}\n{input}")
+    }
+
+    fn contains(&self, key: &str) -> bool {
+        self.results.contains_key(key)
+    }
+
+    fn get(&self, key: &str) -> Option<Value> {
+        self.results.get(key).cloned()
+    }
+
+    fn insert(&mut self, key: String, result: Value) {
+        if self.results.insert(key.clone(), result).is_some() {
+            return;
+        }
+        self.order.push_back(key);
+        if self.order.len() > TOOL_RESULT_CACHE_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.results.remove(&oldest);
+            }
+        }
+    }
+}
+
 fn extract_view_image_local_path(result: &Value) -> Option<String> {
     let ok = result.get("ok").and_then(Value::as_bool).unwrap_or(false);
     if !ok {
@@ -858,6 +2266,98 @@ fn emit_progress_event(progress_tx: Option<&UnboundedSender<EventMsg>>, event: E
     }
 }
 
+/// Forwards streamed output text and reasoning deltas for a single model round.
+struct StreamProgress<'a> {
+    progress_tx: Option<&'a UnboundedSender<EventMsg>>,
+    progress_seq: &'a mut u64,
+    streamed_output_text: String,
+    streamed_reasoning: bool,
+}
+
+impl<'a> StreamProgress<'a> {
+    fn new(progress_tx: Option<&'a UnboundedSender<EventMsg>>, progress_seq: &'a mut u64) -> Self {
+        Self {
+            progress_tx,
+            progress_seq,
+            streamed_output_text: String::new(),
+            streamed_reasoning: false,
+        }
+    }
+
+    fn observe_sse_event(&mut self, event_type: &str, event: &Value) {
+        if event_type != "response.output_text.delta"
+            && event_type != "response.reasoning_summary_text.delta"
+        {
+            return;
+        }
+        let Some(delta) = event
+            .get("delta")
+            .and_then(Value::as_str)
+            .filter(|delta| !delta.is_empty())
+        else {
+            return;
+        };
+        if event_type == "response.reasoning_summary_text.delta" {
+            self.streamed_reasoning = true;
+            self.emit_reasoning(delta.to_string());
+            return;
+        }
+        self.streamed_output_text.push_str(delta);
+        self.emit_delta(delta.to_string());
+    }
+
+    fn reset_attempt(&mut self) {
+        self.streamed_output_text.clear();
+        self.streamed_reasoning = false;
+    }
+
+    /// Emits the round's reasoning summaries when none were streamed, e.g. for
+    /// providers that only report reasoning in the completed response.
+    fn finish_reasoning(&mut self, reasoning: &[String]) {
+        if self.streamed_reasoning {
+            return;
+        }
+        for text in reasoning {
+            self.emit_reasoning(text.clone());
+        }
+    }
+
+    /// Emits whatever part of the final output text was not streamed, so the
+    /// concatenated deltas match the completed response. Rounds that streamed
+    /// nothing are left to the completed response alone.
+    fn finish_output_text(&mut self, final_text: Option<&str>) {
+        if self.streamed_output_text.is_empty() {
+            return;
+        }
+        let Some(remainder) =
+            final_text.and_then(|text| text.strip_prefix(self.streamed_output_text.as_str()))
+        else {
+            return;
+        };
+        if !remainder.is_empty() {
+            let remainder = remainder.to_string();
+            self.streamed_output_text.push_str(&remainder);
+            self.emit_delta(remainder);
+        }
+    }
+
+    fn emit_delta(&mut self, delta: String) {
+        let seq = next_progress_seq(self.progress_seq);
+        emit_progress_event(
+            self.progress_tx,
+            EventMsg::OutputTextDelta(OutputTextDeltaEvent { seq, delta }),
+        );
+    }
+
+    fn emit_reasoning(&mut self, text: String) {
+        let seq = next_progress_seq(self.progress_seq);
+        emit_progress_event(
+            self.progress_tx,
+            EventMsg::Reasoning(ReasoningEvent { seq, text }),
+        );
+    }
+}
+
 fn should_retry_with_store(status: u16, body: &str) -> bool {
     if status != 404 && status != 400 {
         return false;
@@ -867,6 +2367,14 @@ fn should_retry_with_store(status: u16, body: &str) -> bool {
         || normalized.contains("items are not persisted when store is set to false")
 }
 
+fn should_retry_without_previous_response(status: u16, body: &str) -> bool {
+    if status != 404 && status != 400 {
+        return false;
+    }
+    let normalized = body.to_ascii_lowercase();
+    normalized.contains("previous_response") || normalized.contains("previous response")
+}
+
 fn should_retry_without_reasoning_items(status: u16, body: &str) -> bool {
     if status != 404 && status != 400 {
         return false;
@@ -886,6 +2394,26 @@ fn should_retry_authentication_failure(status: u16, body: &str) -> bool {
     normalized.contains("authentication failed")
 }
 
+/// 5xx, dropped connections, transient auth failures and (while the
+/// rate-limit budget lasts) 429s are worth another attempt.
+fn is_transient_provider_error(error: &ModelError, allow_rate_limit_retry: bool) -> bool {
+    match error {
+        ModelError::HttpStatus { status, body } => {
+            *status >= 500 || should_retry_authentication_failure(*status, body.as_str())
+        }
+        ModelError::RateLimited { .. } => allow_rate_limit_retry,
+        ModelError::Request(_) => true,
+        _ => false,
+    }
+}
+
+fn rate_limit_retry_delay(retry_after_secs: Option<u64>, backoff: Duration) -> Duration {
+    match retry_after_secs {
+        Some(secs) => Duration::from_secs(secs.min(MAX_RATE_LIMIT_RETRY_AFTER_SECS)),
+        None => backoff,
+    }
+}
+
 fn responses_with_store_enabled(
     current: Option<&ResponsesRequestOptions>,
 ) -> ResponsesRequestOptions {
@@ -895,12 +2423,18 @@ fn responses_with_store_enabled(
 }
 
 fn should_replay_reasoning_items(responses: Option<&ResponsesRequestOptions>) -> bool {
+    if is_stateless(responses) {
+        return false;
+    }
     let Some(reasoning) = responses.and_then(|options| options.reasoning.as_ref()) else {
         return true;
     };
-    let enabled = reasoning.enabled.unwrap_or(true);
     let include_encrypted_content = reasoning.include_encrypted_content.unwrap_or(true);
-    enabled && include_encrypted_content
+    reasoning_requested(Some(reasoning)) && include_encrypted_content
+}
+
+fn is_stateless(responses: Option<&ResponsesRequestOptions>) -> bool {
+    responses.is_some_and(|options| options.stateless)
 }
 
 fn filter_history_items_for_replay(
@@ -926,6 +2460,46 @@ fn next_progress_seq(progress_seq: &mut u64) -> u64 {
     *progress_seq
 }
 
+fn validate_reasoning_effort(options: &UserTurnOptions) -> Result<(), ModelError> {
+    let Some(effort) = options
+        .responses
+        .as_ref()
+        .and_then(|responses| responses.reasoning.as_ref())
+        .and_then(|reasoning| reasoning.effort.as_deref())
+        .map(str::trim)
+        .filter(|effort| !effort.is_empty())
+    else {
+        return Ok(());
+    };
+    if REASONING_EFFORTS.contains(&effort) {
+        Ok(())
+    } else {
+        Err(ModelError::InvalidReasoningEffort {
+            effort: effort.to_string(),
+        })
+    }
+}
+
+fn resolve_focus_injection_role(options: &UserTurnOptions) -> Result<&'static str, ModelError> {
+    let Some(role) = options
+        .context_ledger
+        .as_ref()
+        .and_then(|ledger_opts| ledger_opts.focus_injection_role.as_deref())
+        .map(str::trim)
+        .filter(|role| !role.is_empty())
+    else {
+        return Ok("user");
+    };
+    match role.to_ascii_lowercase().as_str() {
+        "user" => Ok("user"),
+        "developer" => Ok("developer"),
+        "system" => Ok("system"),
+        _ => Err(ModelError::InvalidFocusRole {
+            role: role.to_string(),
+        }),
+    }
+}
+
 fn build_context_ledger(options: &UserTurnOptions) -> Option<ContextLedger> {
     let ledger_opts = options.context_ledger.as_ref()?;
     if !ledger_opts.enabled {
@@ -987,6 +2561,8 @@ fn build_context_ledger(options: &UserTurnOptions) -> Option<ContextLedger> {
             .focus_max_chars
             .unwrap_or(DEFAULT_FOCUS_MAX_CHARS)
             .max(1),
+        max_segment_bytes: ledger_opts.max_segment_bytes,
+        max_retained_segments: ledger_opts.max_retained_segments,
     })
     .ok()
 }
@@ -1025,12 +2601,31 @@ fn execute_context_ledger_query(
             .get("until_ms")
             .and_then(parse_u64)
             .or_else(|| args.get("untilMs").and_then(parse_u64)),
+        since_iso: first_string_field(&args, &["since_iso", "sinceIso"]),
+        until_iso: first_string_field(&args, &["until_iso", "untilIso"]),
         limit,
         contains: first_string_field(&args, &["contains", "query", "keyword"]),
         fuzzy: args.get("fuzzy").and_then(Value::as_bool).unwrap_or(false),
+        regex: first_string_field(&args, &["regex"]),
+        payload_path: first_string_field(&args, &["payload_path", "payloadPath"]),
         event_types: extract_string_array(&args, "event_types")
             .or_else(|| extract_string_array(&args, "eventTypes"))
             .unwrap_or_default(),
+        before_id: first_string_field(&args, &["before_id", "beforeId", "cursor"]),
+        before_ms: args
+            .get("before_ms")
+            .and_then(parse_u64)
+            .or_else(|| args.get("beforeMs").and_then(parse_u64)),
+        preview_max_chars: args
+            .get("preview_max_chars")
+            .and_then(parse_u64)
+            .or_else(|| args.get("previewMaxChars").and_then(parse_u64))
+            .map(|value| value as usize),
+        include_stats: args
+            .get("include_stats")
+            .or_else(|| args.get("includeStats"))
+            .and_then(Value::as_bool)
+            .unwrap_or(false),
     };
 
     let response = ledger
@@ -1046,6 +2641,8 @@ fn execute_context_ledger_query(
         "total": response.total,
         "truncated": response.truncated,
         "source": response.source,
+        "next_cursor": response.next_cursor,
+        "stats": response.stats,
     }))
 }
 
@@ -1127,9 +2724,11 @@ fn parse_protocol_payload(payload: &Value) -> Result<ParsedResponse, ModelError>
 
     // 不使用 API 的 output_text 字段（包含工具调用语法），只从结构化 output 中提取纯文本
     let mut output_text: Option<String> = None;
+    let mut refusal: Option<String> = None;
     let mut function_calls = Vec::new();
     let mut history_items = Vec::new();
     let mut reasoning = Vec::new();
+    let mut annotations = Vec::new();
     let response_status = payload
         .get("status")
         .and_then(Value::as_str)
@@ -1173,10 +2772,17 @@ fn parse_protocol_payload(payload: &Value) -> Result<ParsedResponse, ModelError>
                 "message" => {
                     // 累积所有 message item 中的 output_text（工具调用前后可能有多个 message）
                     if let Some(text) = parse_output_text_from_message(item) {
-                        match output_text {
-                            Some(ref existing) => output_text = Some(format!("{}\n{}", existing, text)),
-                            None => output_text = Some(text),
-                        }
+                        output_text = Some(match output_text.take() {
+                            Some(existing) => format!("{existing}\n{text}"),
+                            None => text,
+                        });
+                    }
+                    annotations.extend(parse_annotations_from_message(item));
+                    if let Some(text) = parse_refusal_from_message(item) {
+                        refusal = Some(match refusal.take() {
+                            Some(existing) => format!("{existing}\n{text}"),
+                            None => text,
+                        });
                     }
                 }
                 "reasoning" => {
@@ -1198,6 +2804,8 @@ fn parse_protocol_payload(payload: &Value) -> Result<ParsedResponse, ModelError>
             .unwrap_or(false)
         {
             Some("stop".to_string())
+        } else if refusal.is_some() {
+            Some("refusal".to_string())
         } else if let Some(reason) = response_incomplete_reason.clone() {
             Some(reason)
         } else {
@@ -1207,9 +2815,11 @@ fn parse_protocol_payload(payload: &Value) -> Result<ParsedResponse, ModelError>
 
     Ok(ParsedResponse {
         output_text,
+        refusal,
         function_calls,
         history_items,
         reasoning,
+        annotations,
         finish_reason,
         response_status,
         response_incomplete_reason,
@@ -1230,6 +2840,11 @@ fn parse_usage(raw: Option<&Value>) -> ParsedUsage {
         input_tokens: parse_json_u64(object.get("input_tokens")),
         output_tokens: parse_json_u64(object.get("output_tokens")),
         total_tokens: parse_json_u64(object.get("total_tokens")),
+        reasoning_tokens: parse_json_u64(
+            object
+                .get("output_tokens_details")
+                .and_then(|details| details.get("reasoning_tokens")),
+        ),
     }
 }
 
@@ -1328,41 +2943,220 @@ fn parse_output_text_from_message(item: &Value) -> Option<String> {
         Some(text_parts.join("\n"))
     }
 }
-fn parse_function_arguments(arguments: &str) -> Value {
-    let trimmed = arguments.trim();
-    if trimmed.is_empty() {
-        return Value::Object(serde_json::Map::new());
+
+/// Refusals arrive as `{"type":"refusal","refusal":"..."}` content parts in
+/// place of `output_text`.
+fn parse_refusal_from_message(item: &Value) -> Option<String> {
+    let refusal_parts = item
+        .get("content")
+        .and_then(Value::as_array)?
+        .iter()
+        .filter(|part| part.get("type").and_then(Value::as_str) == Some("refusal"))
+        .filter_map(|part| {
+            part.get("refusal")
+                .or_else(|| part.get("text"))
+                .and_then(Value::as_str)
+                .map(str::trim)
+                .filter(|text| !text.is_empty())
+        })
+        .collect::<Vec<_>>();
+    if refusal_parts.is_empty() {
+        None
+    } else {
+        Some(refusal_parts.join("\n"))
     }
+}
 
-    serde_json::from_str::<Value>(trimmed).unwrap_or_else(|_| Value::String(trimmed.to_string()))
+/// Citations (`url_citation`, `file_citation`, ...) ride on the text parts
+/// of a message as an `annotations` array; they are kept verbatim.
+fn parse_annotations_from_message(item: &Value) -> Vec<Value> {
+    item.get("content")
+        .and_then(Value::as_array)
+        .map(|parts| {
+            parts
+                .iter()
+                .filter_map(|part| part.get("annotations").and_then(Value::as_array))
+                .flatten()
+                .filter(|annotation| annotation.is_object())
+                .cloned()
+                .collect()
+        })
+        .unwrap_or_default()
 }
 
-fn normalize_shell_exec_input(input: Value) -> Value {
-    let mut map = match input {
-        Value::Object(map) => map,
-        other => return other,
-    };
+fn parse_function_arguments(arguments: &str) -> Value {
+    parse_function_arguments_with_repair(arguments).0
+}
 
-    if map.contains_key("command") {
-        return Value::Object(map);
+/// Also reports whether the arguments only parsed after
+/// [`repair_json_arguments`]; text beyond repair is passed on as a string.
+fn parse_function_arguments_with_repair(arguments: &str) -> (Value, bool) {
+    let trimmed = arguments.trim();
+    if trimmed.is_empty() {
+        return (Value::Object(serde_json::Map::new()), false);
     }
-    if let Some(cmd) = map.get("cmd").cloned() {
-        map.insert("command".to_string(), cmd);
+
+    if let Ok(value) = serde_json::from_str::<Value>(trimmed) {
+        return (value, false);
+    }
+    match repair_json_arguments(trimmed) {
+        Some(value) => (value, true),
+        None => (Value::String(trimmed.to_string()), false),
     }
-    Value::Object(map)
 }
 
-fn build_responses_tool(tool: &ToolBinding) -> Value {
-    let description = tool
-        .description
-        .as_ref()
-        .map(|value| value.trim())
-        .filter(|value| !value.is_empty())
-        .map(|value| value.to_string())
-        .unwrap_or_else(|| format!("Execute tool {}", tool.runtime_name));
-    let parameters = tool
-        .input_schema
-        .clone()
+/// Best-effort fix for the JSON mistakes models commonly make in tool
+/// arguments: trailing commas, bare identifier keys, and output cut off
+/// mid-string or before the closing brackets.
+fn repair_json_arguments(text: &str) -> Option<Value> {
+    if !text.starts_with('{') {
+        return None;
+    }
+    let mut repaired = String::with_capacity(text.len() + 8);
+    let mut closers: Vec<char> = Vec::new();
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut chars = text.chars().peekable();
+    while let Some(ch) = chars.next() {
+        if in_string {
+            repaired.push(ch);
+            if escaped {
+                escaped = false;
+            } else if ch == '\\' {
+                escaped = true;
+            } else if ch == '"' {
+                in_string = false;
+            }
+            continue;
+        }
+        match ch {
+            '"' => {
+                in_string = true;
+                repaired.push(ch);
+            }
+            '{' | '[' => {
+                closers.push(if ch == '{' { '}' } else { ']' });
+                repaired.push(ch);
+            }
+            '}' | ']' => {
+                trim_trailing_comma(&mut repaired);
+                closers.pop();
+                repaired.push(ch);
+            }
+            _ if (ch.is_alphabetic() || ch == '_')
+                && closers.last() == Some(&'}')
+                && repaired.trim_end().ends_with(['{', ',']) =>
+            {
+                repaired.push('"');
+                repaired.push(ch);
+                while let Some(next) = chars.next_if(|next| next.is_alphanumeric() || *next == '_')
+                {
+                    repaired.push(next);
+                }
+                repaired.push('"');
+            }
+            _ => repaired.push(ch),
+        }
+    }
+    if in_string {
+        if escaped {
+            repaired.pop();
+        }
+        repaired.push('"');
+    }
+    while let Some(closer) = closers.pop() {
+        trim_trailing_comma(&mut repaired);
+        repaired.push(closer);
+    }
+    serde_json::from_str(&repaired).ok()
+}
+
+fn trim_trailing_comma(text: &mut String) {
+    let content_len = text.trim_end().len();
+    if text[..content_len].ends_with(',') {
+        text.truncate(content_len - 1);
+    }
+}
+
+/// Form of parsed tool arguments used to tell calls apart: object keys are
+/// sorted at every level, so key order does not make identical calls look
+/// different. Keys and strings stay byte-for-byte, since whitespace in them
+/// (e.g. a trailing newline in file content) is part of the call. Tools
+/// themselves still get the input as parsed.
+fn canonicalize_tool_arguments(arguments: Value) -> Value {
+    match arguments {
+        Value::Object(map) => {
+            let mut entries = map
+                .into_iter()
+                .map(|(key, value)| (key, canonicalize_tool_arguments(value)))
+                .collect::<Vec<_>>();
+            entries.sort_by(|(left, _), (right, _)| left.cmp(right));
+            Value::Object(entries.into_iter().collect())
+        }
+        Value::Array(items) => {
+            Value::Array(items.into_iter().map(canonicalize_tool_arguments).collect())
+        }
+        other => other,
+    }
+}
+
+fn normalize_shell_exec_input(input: Value) -> Value {
+    let mut map = match input {
+        Value::Object(map) => map,
+        other => return other,
+    };
+
+    if map.contains_key("command") {
+        return Value::Object(map);
+    }
+    if let Some(cmd) = map.get("cmd").cloned() {
+        map.insert("command".to_string(), cmd);
+    }
+    Value::Object(map)
+}
+
+/// Picks the daemon URL and agent id for `runtime_tool_name` by longest
+/// matching route prefix, falling back to the config's own daemon.
+fn resolve_tool_daemon<'a>(
+    config: &'a ToolExecutionConfig,
+    runtime_tool_name: &str,
+) -> (&'a str, &'a str) {
+    config
+        .routes
+        .iter()
+        .filter(|route| !route.prefix.is_empty() && runtime_tool_name.starts_with(&route.prefix))
+        .max_by_key(|route| route.prefix.len())
+        .map(|route| {
+            (
+                route.daemon_url.as_str(),
+                route
+                    .agent_id
+                    .as_deref()
+                    .unwrap_or(config.agent_id.as_str()),
+            )
+        })
+        .unwrap_or((config.daemon_url.as_str(), config.agent_id.as_str()))
+}
+
+fn build_tool_payload(tool_bindings: &[ToolBinding]) -> Option<Vec<Value>> {
+    if tool_bindings.is_empty() {
+        None
+    } else {
+        Some(tool_bindings.iter().map(build_responses_tool).collect())
+    }
+}
+
+fn build_responses_tool(tool: &ToolBinding) -> Value {
+    let description = tool
+        .description
+        .as_ref()
+        .map(|value| value.trim())
+        .filter(|value| !value.is_empty())
+        .map(|value| value.to_string())
+        .unwrap_or_else(|| format!("Execute tool {}", tool.runtime_name));
+    let parameters = tool
+        .input_schema
+        .clone()
         .unwrap_or_else(|| json!({ "type": "object", "additionalProperties": true }));
 
     json!({
@@ -1373,7 +3167,30 @@ fn build_responses_tool(tool: &ToolBinding) -> Value {
     })
 }
 
-fn build_tool_bindings(tools: &[ToolSpec]) -> Vec<ToolBinding> {
+/// Doubles from `INITIAL_TOOL_RETRY_BACKOFF_MS` per retry (1-based), capped so
+/// a large `tool_max_retries` cannot overflow or sleep for hours.
+fn tool_retry_backoff_ms(retry: u8) -> u64 {
+    let exponent = u32::from(retry.saturating_sub(1));
+    2_u64
+        .checked_pow(exponent)
+        .map_or(u64::MAX, |factor| {
+            INITIAL_TOOL_RETRY_BACKOFF_MS.saturating_mul(factor)
+        })
+        .min(MAX_TOOL_RETRY_BACKOFF_MS)
+}
+
+fn build_extra_header_map(headers: &HashMap<String, String>) -> Result<HeaderMap, ModelError> {
+    let mut header_map = HeaderMap::with_capacity(headers.len());
+    for (name, value) in headers {
+        let invalid = || ModelError::InvalidHeader { name: name.clone() };
+        let header_name = HeaderName::from_bytes(name.as_bytes()).map_err(|_| invalid())?;
+        let header_value = HeaderValue::from_str(value).map_err(|_| invalid())?;
+        header_map.insert(header_name, header_value);
+    }
+    Ok(header_map)
+}
+
+fn build_tool_bindings(tools: &[ToolSpec]) -> Result<Vec<ToolBinding>, ModelError> {
     let mut used_names = HashSet::new();
     let mut bindings = Vec::with_capacity(tools.len());
 
@@ -1391,11 +3208,118 @@ fn build_tool_bindings(tools: &[ToolSpec]) -> Vec<ToolBinding> {
             runtime_name: tool.name.clone(),
             model_name,
             description: tool.description.clone(),
-            input_schema: tool.input_schema.clone(),
+            input_schema: normalize_tool_input_schema(tool)?,
+            approval: tool.approval,
         });
     }
 
-    bindings
+    Ok(bindings)
+}
+
+/// Checks a tool's input schema is a JSON Schema object before it reaches the
+/// provider. A schema without `type` is assumed to describe an object.
+fn normalize_tool_input_schema(tool: &ToolSpec) -> Result<Option<Value>, ModelError> {
+    let invalid = |message: &str| ModelError::InvalidToolSchema {
+        tool_name: tool.name.clone(),
+        message: message.to_string(),
+    };
+    let Some(schema) = tool.input_schema.as_ref() else {
+        return Ok(None);
+    };
+    let Some(object) = schema.as_object() else {
+        return Err(invalid("schema must be a JSON object"));
+    };
+    let mut object = object.clone();
+    match object.get("type") {
+        None => {
+            object.insert("type".to_string(), json!("object"));
+        }
+        Some(Value::String(kind)) if kind == "object" => {}
+        Some(_) => return Err(invalid("type must be \"object\"")),
+    }
+    if object
+        .get("properties")
+        .is_some_and(|properties| !properties.is_object())
+    {
+        return Err(invalid("properties must be an object"));
+    }
+    if object.get("required").is_some_and(|required| {
+        !required
+            .as_array()
+            .is_some_and(|names| names.iter().all(Value::is_string))
+    }) {
+        return Err(invalid("required must be an array of property names"));
+    }
+    Ok(Some(Value::Object(object)))
+}
+
+/// Which model-facing name each runtime tool was sent as; `renamed` marks
+/// names suffixed to resolve a collision after sanitizing.
+fn tool_name_map(tool_bindings: &[ToolBinding]) -> Vec<Value> {
+    tool_bindings
+        .iter()
+        .map(|binding| {
+            json!({
+                "runtime_name": binding.runtime_name,
+                "model_name": binding.model_name,
+                "renamed": binding.model_name != sanitize_model_tool_name(&binding.runtime_name),
+            })
+        })
+        .collect()
+}
+
+fn resolve_tool_approval_kind(
+    runtime_tool_name: &str,
+    tool_bindings: &[ToolBinding],
+) -> Option<ApprovalKind> {
+    tool_bindings
+        .iter()
+        .find(|binding| binding.runtime_name == runtime_tool_name)
+        .and_then(|binding| binding.approval)
+}
+
+/// Waits for the user's decision on an approval-gated call. Returns the
+/// denial message fed back to the model, or `None` when the call may run.
+async fn await_tool_approval(
+    approvals: Option<&ApprovalBroker>,
+    kind: ApprovalKind,
+    call: &FunctionCallItem,
+    runtime_tool_name: &str,
+    tool_input: &Value,
+    progress_tx: Option<&UnboundedSender<EventMsg>>,
+    progress_seq: &mut u64,
+) -> Result<Option<String>, ModelError> {
+    let Some(approvals) = approvals else {
+        return Ok(Some(
+            "execution denied: no approval channel for this turn".to_string(),
+        ));
+    };
+    if approvals.is_approved_for_session(runtime_tool_name) {
+        return Ok(None);
+    }
+
+    let decision_rx = approvals.register(&call.call_id, kind, runtime_tool_name);
+    let request = ApprovalRequestEvent {
+        seq: next_progress_seq(progress_seq),
+        call_id: call.call_id.clone(),
+        tool_name: runtime_tool_name.to_string(),
+        input: tool_input.clone(),
+    };
+    emit_progress_event(
+        progress_tx,
+        match kind {
+            ApprovalKind::Exec => EventMsg::ExecApprovalRequest(request),
+            ApprovalKind::Patch => EventMsg::PatchApprovalRequest(request),
+        },
+    );
+
+    match decision_rx.await.unwrap_or(ReviewDecision::Abort) {
+        ReviewDecision::Approved | ReviewDecision::ApprovedForSession => Ok(None),
+        ReviewDecision::Denied => Ok(Some("execution denied by user".to_string())),
+        ReviewDecision::Abort => Err(ModelError::ApprovalAborted {
+            call_id: call.call_id.clone(),
+        }),
+    }
 }
 
 fn sanitize_model_tool_name(name: &str) -> String {
@@ -1425,17 +3349,87 @@ fn resolve_runtime_tool_name(model_name: &str, bindings: &[ToolBinding]) -> Stri
     model_name.to_string()
 }
 
+/// Callers name forced tools by runtime name; the request must use the
+/// sanitized name the model sees.
+fn resolve_responses_tool_choice(
+    responses: Option<&ResponsesRequestOptions>,
+    bindings: &[ToolBinding],
+) -> Option<ResponsesRequestOptions> {
+    let mut resolved = responses.cloned()?;
+    if let Some(ToolChoice::Function { name }) = resolved.tool_choice.as_mut() {
+        if let Some(binding) = bindings
+            .iter()
+            .find(|binding| binding.runtime_name == *name || binding.model_name == *name)
+        {
+            *name = binding.model_name.clone();
+        }
+    }
+    Some(resolved)
+}
+
+/// Mirrors the request builder: the schema is only enforced when it is sent.
+fn build_output_schema_validator(
+    options: &UserTurnOptions,
+) -> Result<Option<jsonschema::Validator>, ModelError> {
+    let Some(text_opts) = options
+        .responses
+        .as_ref()
+        .and_then(|responses| responses.text.as_ref())
+        .filter(|text_opts| text_opts.enabled.unwrap_or(true))
+    else {
+        return Ok(None);
+    };
+    let Some(schema) = text_opts.output_schema.as_ref() else {
+        return Ok(None);
+    };
+    jsonschema::validator_for(schema)
+        .map(Some)
+        .map_err(|error| ModelError::SchemaValidation {
+            errors: vec![format!("invalid output schema: {error}")],
+        })
+}
+
+fn validate_structured_output(validator: &jsonschema::Validator, output_text: &str) -> Vec<String> {
+    let instance = match serde_json::from_str::<Value>(output_text) {
+        Ok(instance) => instance,
+        Err(error) => return vec![format!("output is not valid JSON: {error}")],
+    };
+    validator
+        .iter_errors(&instance)
+        .map(|error| {
+            let path = error.instance_path.to_string();
+            let path = if path.is_empty() { "/" } else { path.as_str() };
+            format!("{path}: {error}")
+        })
+        .collect()
+}
+
+fn build_schema_reask_item(errors: &[String]) -> Value {
+    json!({
+        "role": "developer",
+        "content": [{
+            "type": "input_text",
+            "text": format!(
+                "Your previous reply did not match the required JSON schema:\n- {}\nReply again with only JSON that matches the schema.",
+                errors.join("\n- ")
+            ),
+        }],
+    })
+}
+
 fn build_initial_input(
     items: &[InputItem],
     options: &UserTurnOptions,
 ) -> Result<Vec<Value>, ModelError> {
     let mut input = normalize_history_items(&options.history_items);
+    let refresh = options.refresh_context_blocks;
 
     maybe_inject_context_block(
         &mut input,
         "developer_instructions",
         options.developer_instructions.as_deref(),
         "developer",
+        refresh,
     );
     if let Some(turn_context_text) = render_turn_context_block(options.turn_context.as_ref()) {
         maybe_inject_context_block(
@@ -1443,6 +3437,7 @@ fn build_initial_input(
             "turn_context",
             Some(turn_context_text.as_str()),
             "developer",
+            refresh,
         );
     }
 
@@ -1451,18 +3446,50 @@ fn build_initial_input(
         "user_instructions",
         options.user_instructions.as_deref(),
         "user",
+        refresh,
     );
+    inject_extra_context_blocks(&mut input, options, ContextBlockPosition::AfterInstructions);
     maybe_inject_context_block(
         &mut input,
         "environment_context",
         options.environment_context.as_deref(),
         "user",
+        refresh,
     );
+    inject_extra_context_blocks(&mut input, options, ContextBlockPosition::BeforeUserInput);
 
     input.push(build_user_message_input(items)?);
     Ok(input)
 }
 
+fn inject_extra_context_blocks(
+    input: &mut Vec<Value>,
+    options: &UserTurnOptions,
+    position: ContextBlockPosition,
+) {
+    for block in options
+        .extra_context_blocks
+        .iter()
+        .filter(|block| block.position == position)
+    {
+        let name = block.name.trim();
+        if name.is_empty() {
+            continue;
+        }
+        let role = match block.role {
+            ContextBlockRole::Developer => "developer",
+            ContextBlockRole::User => "user",
+        };
+        maybe_inject_context_block(
+            input,
+            name,
+            Some(block.content.as_str()),
+            role,
+            options.refresh_context_blocks,
+        );
+    }
+}
+
 fn normalize_history_items(history_items: &[Value]) -> Vec<Value> {
     history_items
         .iter()
@@ -1471,11 +3498,14 @@ fn normalize_history_items(history_items: &[Value]) -> Vec<Value> {
         .collect()
 }
 
+/// Context blocks are deduplicated by name: history that already carries a
+/// `<block_name>` keeps it, unless `refresh` swaps it for the new content.
 fn maybe_inject_context_block(
     input: &mut Vec<Value>,
     block_name: &str,
     content: Option<&str>,
     role: &str,
+    refresh: bool,
 ) {
     let Some(raw_content) = content else {
         return;
@@ -1485,18 +3515,55 @@ fn maybe_inject_context_block(
         return;
     }
     let block = wrap_context_block(block_name, trimmed);
-    if history_contains_block(input, block_name, &block) {
-        return;
+    if input
+        .iter()
+        .any(|item| history_item_carries_block(item, block_name))
+    {
+        if !refresh {
+            return;
+        }
+        input.retain_mut(|item| !strip_context_block_parts(item, block_name));
     }
     input.push(build_text_message(role, block));
 }
 
-fn history_contains_block(history: &[Value], block_name: &str, full_block_text: &str) -> bool {
-    let open_tag = format!("<{block_name}>");
-    history.iter().any(|item| {
-        extract_text_from_history_item(item)
-            .map(|text| text.contains(&open_tag) || text.contains(full_block_text))
-            .unwrap_or(false)
+/// Checks every text part, since a block need not be the first one.
+fn history_item_carries_block(item: &Value, block_name: &str) -> bool {
+    item.get("content")
+        .and_then(Value::as_array)
+        .is_some_and(|parts| {
+            parts
+                .iter()
+                .any(|part| content_part_carries_block(part, block_name))
+        })
+}
+
+/// Drops the parts of `item` carrying `block_name`; returns `true` when
+/// nothing else is left and the whole item should go.
+fn strip_context_block_parts(item: &mut Value, block_name: &str) -> bool {
+    let Some(parts) = item.get_mut("content").and_then(Value::as_array_mut) else {
+        return false;
+    };
+    let before = parts.len();
+    parts.retain(|part| !content_part_carries_block(part, block_name));
+    parts.is_empty() && before > 0
+}
+
+/// Accepts an open tag with attributes (`<environment_context cwd="...">`).
+fn content_part_carries_block(part: &Value, block_name: &str) -> bool {
+    let open_tag = format!("<{block_name}");
+    let Some(text) = part
+        .get("text")
+        .or_else(|| part.get("input_text"))
+        .and_then(Value::as_str)
+    else {
+        return false;
+    };
+    text.match_indices(&open_tag).any(|(index, _)| {
+        text[index + open_tag.len()..]
+            .chars()
+            .next()
+            .is_some_and(|next| next == '>' || next.is_whitespace())
     })
 }
 
@@ -1578,65 +3645,55 @@ fn extract_text_from_history_item(item: &Value) -> Option<String> {
     None
 }
 
-fn apply_fork_truncate(history: Vec<Value>, user_message_index: usize) -> Vec<Value> {
-    let mut current_user_index = 0_usize;
-    let mut result = Vec::new();
-
-    for item in history {
-        let role = item.get("role").and_then(Value::as_str).unwrap_or_default();
-        if role == "user" {
-            if current_user_index > user_message_index {
-                break;
-            }
-            current_user_index += 1;
-        }
-        result.push(item);
-    }
-
-    result
+/// History cut at a fork point.
+#[derive(Debug)]
+struct ForkTruncation {
+    history: Vec<Value>,
+    /// The fork index named no user message, so nothing was dropped.
+    index_out_of_range: bool,
 }
 
-fn estimate_tokens_in_history(history: &[Value]) -> u64 {
-    let mut chars = 0_usize;
-    for item in history {
-        chars += estimate_chars_in_json(item);
-    }
-    ((chars as f64) / 4.0).ceil() as u64
-}
+/// Keeps history up to and including the reply to user message
+/// `user_message_index`. Initial context blocks are not counted as user
+/// messages and survive wherever they sit, so a fork keeps its instructions.
+fn apply_fork_truncate(history: Vec<Value>, user_message_index: usize) -> ForkTruncation {
+    let mut user_messages_seen = 0_usize;
+    let mut result = Vec::with_capacity(history.len());
 
-fn estimate_tokens_excluding_ledger_focus(history: &[Value]) -> u64 {
-    let mut chars = 0_usize;
     for item in history {
-        if is_ledger_focus_history_item(item) {
+        let is_context_block = extract_text_from_history_item(&item)
+            .is_some_and(|text| is_initial_context_block(&text));
+        if is_context_block {
+            result.push(item);
             continue;
         }
-        chars += estimate_chars_in_json(item);
+        if item.get("role").and_then(Value::as_str) == Some("user") {
+            user_messages_seen += 1;
+        }
+        if user_messages_seen <= user_message_index + 1 {
+            result.push(item);
+        }
+    }
+
+    ForkTruncation {
+        history: result,
+        index_out_of_range: user_messages_seen <= user_message_index,
     }
-    ((chars as f64) / 4.0).ceil() as u64
 }
 
-fn estimate_chars_in_json(value: &Value) -> usize {
-    match value {
-        Value::Null => 0,
-        Value::Bool(_) => 1,
-        Value::Number(_) => 8,
-        Value::String(text) => text.len(),
-        Value::Array(items) => items.iter().map(estimate_chars_in_json).sum(),
-        Value::Object(map) => {
-            // Image attachments should not inflate text-context budget.
-            // They are non-text multimodal payloads and are billed/handled separately by model providers.
-            if matches!(
-                map.get("type").and_then(Value::as_str),
-                Some("input_image") | Some("image") | Some("local_image")
-            ) {
-                return 16;
-            }
+fn estimate_tokens_in_history(history: &[Value], token_estimator: &dyn TokenEstimator) -> u64 {
+    token_estimator.estimate_history_tokens(&history.iter().collect::<Vec<_>>())
+}
 
-            map.iter()
-                .map(|(key, item)| key.len() + estimate_chars_in_json(item))
-                .sum()
-        }
-    }
+fn estimate_tokens_excluding_ledger_focus(
+    history: &[Value],
+    token_estimator: &dyn TokenEstimator,
+) -> u64 {
+    let items = history
+        .iter()
+        .filter(|item| !is_ledger_focus_history_item(item))
+        .collect::<Vec<_>>();
+    token_estimator.estimate_history_tokens(&items)
 }
 
 struct CompactResult {
@@ -1660,6 +3717,9 @@ struct CompactExecutionState {
     compressed_at_iso: Option<String>,
     source_time_start: Option<String>,
     source_time_end: Option<String>,
+    /// Items folded away by the latest compaction, kept only when the model
+    /// is asked to summarize them.
+    pending_model_summary_source: Option<Vec<Value>>,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -1674,9 +3734,20 @@ struct CompactHistoryItem {
     role: String,
     text: String,
     timestamp_iso: Option<String>,
+    position: usize,
     original: Value,
 }
 
+/// A `function_call` and its `function_call_output`, with their positions in
+/// the history being compacted.
+#[derive(Debug, Clone)]
+struct CompactToolRound {
+    call_position: usize,
+    call: Value,
+    output_position: usize,
+    output: Value,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct CompactTaskDigest {
     id: String,
@@ -1714,6 +3785,7 @@ fn compact_history(
     history: &[Value],
     compact_cfg: Option<&CompactConfig>,
     max_input_tokens: Option<u64>,
+    token_estimator: &dyn TokenEstimator,
 ) -> CompactResult {
     let preserve_user_messages = compact_cfg
         .map(|cfg| cfg.preserve_user_messages)
@@ -1723,14 +3795,30 @@ fn compact_history(
         .map(|text| text.trim())
         .filter(|text| !text.is_empty())
         .map(|text| text.to_string());
+    let preserve_recent_tool_rounds = compact_cfg
+        .and_then(|cfg| cfg.preserve_recent_tool_rounds)
+        .unwrap_or(DEFAULT_PRESERVE_RECENT_TOOL_ROUNDS);
+    let summary_token_budget = compact_cfg.and_then(|cfg| cfg.summary_token_budget);
+    let max_preserved_user_messages = compact_cfg
+        .and_then(|cfg| cfg.max_preserved_user_messages)
+        .filter(|_| !preserve_user_messages);
 
     let mut initial_context_blocks: Vec<Value> = Vec::new();
     let mut conversation_items: Vec<CompactHistoryItem> = Vec::new();
+    let mut tool_items: Vec<(usize, Value)> = Vec::new();
     let mut historical_digests: Vec<CompactTaskDigest> = Vec::new();
     let (compressed_at_ms, compressed_at_iso) = now_timestamp_local();
     let (source_time_start, source_time_end) = extract_history_time_bounds(history);
 
-    for item in history {
+    for (position, item) in history.iter().enumerate() {
+        if matches!(
+            item.get("type").and_then(Value::as_str),
+            Some("function_call" | "function_call_output")
+        ) {
+            tool_items.push((position, item.clone()));
+            continue;
+        }
+
         let role = item.get("role").and_then(Value::as_str).unwrap_or_default();
         let text = extract_text_from_history_item(item);
 
@@ -1785,16 +3873,33 @@ fn compact_history(
                 role: role.to_string(),
                 text: content,
                 timestamp_iso: extract_time_label_from_history_item(item),
+                position,
                 original: item.clone(),
             });
         }
     }
 
+    let mut tool_rounds = pair_compact_tool_rounds(&tool_items);
+    let preserved_tool_rounds = tool_rounds.split_off(
+        tool_rounds
+            .len()
+            .saturating_sub(preserve_recent_tool_rounds),
+    );
+
     let grouped_tasks = group_compact_history_tasks(&conversation_items);
     let split_index = grouped_tasks.len().saturating_sub(1);
     for (index, task) in grouped_tasks.iter().take(split_index).enumerate() {
+        // Rounds before the first task belong to it; the last task's rounds
+        // fall to the synthetic digest below, if one is cut.
+        let start = if index == 0 { 0 } else { task[0].position };
+        let end = grouped_tasks[index + 1][0].position;
+        let task_rounds = tool_rounds
+            .iter()
+            .filter(|round| (start..end).contains(&round.call_position))
+            .collect::<Vec<_>>();
         historical_digests.push(build_task_digest_from_items(
             task,
+            &task_rounds,
             index,
             compressed_at_ms,
             preserve_user_messages,
@@ -1810,9 +3915,11 @@ fn compact_history(
     if historical_digests.is_empty() && !recent_items.is_empty() {
         let synthetic = maybe_extract_synthetic_digest_from_recent(
             &mut recent_items,
+            &tool_rounds,
             compressed_at_ms,
             preserve_user_messages,
             target_tokens,
+            token_estimator,
         );
         if let Some(digest) = synthetic {
             historical_digests.push(digest);
@@ -1823,24 +3930,31 @@ fn compact_history(
         &initial_context_blocks,
         &historical_digests,
         &recent_items,
+        &preserved_tool_rounds,
     );
 
     if let Some(target) = target_tokens {
-        while estimate_tokens_in_history(&compacted_history) > target && historical_digests.len() > 1 {
+        while estimate_tokens_in_history(&compacted_history, token_estimator) > target
+            && historical_digests.len() > 1
+        {
             historical_digests.remove(0);
             compacted_history = rebuild_compacted_history(
                 &initial_context_blocks,
                 &historical_digests,
                 &recent_items,
+                &preserved_tool_rounds,
             );
         }
 
-        while estimate_tokens_in_history(&compacted_history) > target && recent_items.len() > 2 {
+        while estimate_tokens_in_history(&compacted_history, token_estimator) > target
+            && recent_items.len() > 2
+        {
             recent_items.remove(0);
             compacted_history = rebuild_compacted_history(
                 &initial_context_blocks,
                 &historical_digests,
                 &recent_items,
+                &preserved_tool_rounds,
             );
         }
     }
@@ -1853,6 +3967,9 @@ fn compact_history(
         compressed_at_iso.as_str(),
         source_time_start.as_deref(),
         source_time_end.as_deref(),
+        summary_token_budget,
+        max_preserved_user_messages,
+        token_estimator,
     );
     let replacement_history = historical_digests
         .iter()
@@ -1873,14 +3990,16 @@ fn compact_history(
 
 fn snapshot_compact_budget(
     history: &[Value],
+    token_estimator: &dyn TokenEstimator,
     baseline_tokens: u64,
     threshold_ratio: f64,
     max_input_tokens: Option<u64>,
 ) -> CompactBudgetSnapshot {
     let estimated_tokens_in_window =
-        estimate_tokens_in_history(history).saturating_sub(baseline_tokens);
+        estimate_tokens_in_history(history, token_estimator).saturating_sub(baseline_tokens);
     let estimated_tokens_compactable =
-        estimate_tokens_excluding_ledger_focus(history).saturating_sub(baseline_tokens);
+        estimate_tokens_excluding_ledger_focus(history, token_estimator)
+            .saturating_sub(baseline_tokens);
     let auto_compact_triggered = max_input_tokens
         .map(|max| (estimated_tokens_in_window as f64) > (max as f64) * threshold_ratio)
         .unwrap_or(false);
@@ -1892,14 +4011,19 @@ fn snapshot_compact_budget(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn maybe_apply_compaction(
     rolling_input: &mut Vec<Value>,
     options: &UserTurnOptions,
     context_ledger: Option<&ContextLedger>,
+    token_estimator: &dyn TokenEstimator,
     baseline_tokens: u64,
     threshold_ratio: f64,
     max_input_tokens: Option<u64>,
     compact_state: &mut CompactExecutionState,
+    context_length_exceeded: bool,
+    progress_tx: Option<&UnboundedSender<EventMsg>>,
+    progress_seq: &mut u64,
 ) -> CompactBudgetSnapshot {
     let manual_compact = options
         .compact
@@ -1908,6 +4032,7 @@ fn maybe_apply_compaction(
         .unwrap_or(false);
     let budget_before = snapshot_compact_budget(
         rolling_input,
+        token_estimator,
         baseline_tokens,
         threshold_ratio,
         max_input_tokens,
@@ -1917,13 +4042,33 @@ fn maybe_apply_compaction(
     compact_state.requested_auto =
         compact_state.requested_auto || budget_before.auto_compact_triggered;
 
-    let compact_required = budget_before.auto_compact_triggered
+    let compact_required = context_length_exceeded
+        || budget_before.auto_compact_triggered
         || (manual_compact && !compact_state.applied);
     if !compact_required {
         return budget_before;
     }
 
-    let compact_result = compact_history(rolling_input, options.compact.as_ref(), max_input_tokens);
+    let compact_result = compact_history(
+        rolling_input,
+        options.compact.as_ref(),
+        max_input_tokens,
+        token_estimator,
+    );
+    if options
+        .compact
+        .as_ref()
+        .map(|cfg| cfg.use_model_summary)
+        .unwrap_or(false)
+    {
+        compact_state.pending_model_summary_source = Some(
+            rolling_input
+                .iter()
+                .filter(|item| !compact_result.history.contains(item))
+                .cloned()
+                .collect(),
+        );
+    }
     *rolling_input = compact_result.history;
     compact_state.applied = true;
     compact_state.summary = compact_result.summary;
@@ -1934,10 +4079,39 @@ fn maybe_apply_compaction(
 
     let budget_after = snapshot_compact_budget(
         rolling_input,
+        token_estimator,
         baseline_tokens,
         threshold_ratio,
         max_input_tokens,
     );
+    let triggered_by = if context_length_exceeded {
+        CompactionTrigger::ContextLengthExceeded
+    } else if budget_before.auto_compact_triggered {
+        CompactionTrigger::Auto
+    } else {
+        CompactionTrigger::Manual
+    };
+    tracing::info!(
+        trigger = ?triggered_by,
+        before_tokens = budget_before.estimated_tokens_in_window,
+        after_tokens = budget_after.estimated_tokens_in_window,
+        "history compacted"
+    );
+    let compaction_seq = next_progress_seq(progress_seq);
+    emit_progress_event(
+        progress_tx,
+        EventMsg::Compaction(CompactionEvent {
+            seq: compaction_seq,
+            triggered_by,
+            before_tokens: budget_before.estimated_tokens_in_window,
+            after_tokens: budget_after.estimated_tokens_in_window,
+            summary_chars: compact_state
+                .summary
+                .as_deref()
+                .map(|summary| summary.chars().count() as u64)
+                .unwrap_or(0),
+        }),
+    );
     if let Some(ledger) = context_ledger {
         safe_append_ledger(
             ledger,
@@ -1945,6 +4119,7 @@ fn maybe_apply_compaction(
             json!({
                 "manual": manual_compact,
                 "auto": budget_before.auto_compact_triggered,
+                "context_length_exceeded": context_length_exceeded,
                 "summary": compact_state.summary,
                 "compressed_at_ms": compact_state.compressed_at_ms,
                 "compressed_at_iso": compact_state.compressed_at_iso,
@@ -1981,6 +4156,28 @@ fn compact_target_tokens(max_input_tokens: Option<u64>) -> Option<u64> {
     }
 }
 
+/// Pairs calls with their outputs by `call_id`, in call order. Calls that
+/// never got an output (and stray outputs) are dropped.
+fn pair_compact_tool_rounds(tool_items: &[(usize, Value)]) -> Vec<CompactToolRound> {
+    tool_items
+        .iter()
+        .filter(|(_, item)| item.get("type").and_then(Value::as_str) == Some("function_call"))
+        .filter_map(|(call_position, call)| {
+            let call_id = call.get("call_id").and_then(Value::as_str)?;
+            let (output_position, output) = tool_items.iter().find(|(_, item)| {
+                item.get("type").and_then(Value::as_str) == Some("function_call_output")
+                    && item.get("call_id").and_then(Value::as_str) == Some(call_id)
+            })?;
+            Some(CompactToolRound {
+                call_position: *call_position,
+                call: call.clone(),
+                output_position: *output_position,
+                output: output.clone(),
+            })
+        })
+        .collect()
+}
+
 fn group_compact_history_tasks(items: &[CompactHistoryItem]) -> Vec<Vec<CompactHistoryItem>> {
     let mut tasks: Vec<Vec<CompactHistoryItem>> = Vec::new();
     let mut current: Vec<CompactHistoryItem> = Vec::new();
@@ -2023,9 +4220,11 @@ fn build_legacy_summary_digest(
 
 fn maybe_extract_synthetic_digest_from_recent(
     recent_items: &mut Vec<CompactHistoryItem>,
+    tool_rounds: &[CompactToolRound],
     compressed_at_ms: u64,
     preserve_user_messages: bool,
     target_tokens: Option<u64>,
+    token_estimator: &dyn TokenEstimator,
 ) -> Option<CompactTaskDigest> {
     let Some(target) = target_tokens else {
         return None;
@@ -2034,7 +4233,7 @@ fn maybe_extract_synthetic_digest_from_recent(
         .iter()
         .map(|item| item.original.clone())
         .collect::<Vec<_>>();
-    if estimate_tokens_in_history(&recent_history) <= target {
+    if estimate_tokens_in_history(&recent_history, token_estimator) <= target {
         return None;
     }
     if recent_items.len() <= 4 {
@@ -2046,9 +4245,14 @@ fn maybe_extract_synthetic_digest_from_recent(
     if prefix.is_empty() {
         return None;
     }
+    let prefix_rounds = tool_rounds
+        .iter()
+        .filter(|round| round.call_position < suffix[0].position)
+        .collect::<Vec<_>>();
     *recent_items = suffix;
     Some(build_task_digest_from_items(
         &prefix,
+        &prefix_rounds,
         0,
         compressed_at_ms,
         preserve_user_messages,
@@ -2057,6 +4261,7 @@ fn maybe_extract_synthetic_digest_from_recent(
 
 fn build_task_digest_from_items(
     items: &[CompactHistoryItem],
+    tool_rounds: &[&CompactToolRound],
     index: usize,
     compressed_at_ms: u64,
     preserve_user_messages: bool,
@@ -2078,6 +4283,34 @@ fn build_task_digest_from_items(
         .map(|item| sanitize_compact_line(item.text.as_str(), 320))
         .or_else(|| items.last().map(|item| sanitize_compact_line(item.text.as_str(), 320)))
         .unwrap_or_else(|| "(task digest)".to_string());
+    let tool_calls = tool_rounds
+        .iter()
+        .map(|round| CompactToolCallDigest {
+            tool: round
+                .call
+                .get("name")
+                .and_then(Value::as_str)
+                .unwrap_or("unknown")
+                .to_string(),
+            input: round
+                .call
+                .get("arguments")
+                .and_then(Value::as_str)
+                .map(|arguments| sanitize_compact_line(arguments, 160)),
+            status: None,
+            output: round
+                .output
+                .get("output")
+                .and_then(Value::as_str)
+                .map(|output| sanitize_compact_line(output, 200)),
+        })
+        .collect::<Vec<_>>();
+    let mut key_tools: Vec<String> = Vec::new();
+    for call in &tool_calls {
+        if !key_tools.contains(&call.tool) {
+            key_tools.push(call.tool.clone());
+        }
+    }
 
     CompactTaskDigest {
         id: format!("task-digest-{compressed_at_ms}-{index}"),
@@ -2086,8 +4319,8 @@ fn build_task_digest_from_items(
         end_time_iso,
         request,
         summary,
-        key_tools: Vec::new(),
-        tool_calls: Vec::new(),
+        key_tools,
+        tool_calls,
         tags: Vec::new(),
         topic: None,
     }
@@ -2101,6 +4334,72 @@ fn sanitize_compact_line(text: &str, max_chars: usize) -> String {
     collapsed.chars().take(max_chars).collect::<String>() + "..."
 }
 
+fn build_model_compact_summary_block(
+    summary: &str,
+    compact_state: &CompactExecutionState,
+) -> String {
+    [
+        summary.to_string(),
+        "algorithm=model_summary".to_string(),
+        format!(
+            "compressed_at_ms={}",
+            compact_state.compressed_at_ms.unwrap_or_default()
+        ),
+        format!(
+            "compressed_at_iso={}",
+            compact_state
+                .compressed_at_iso
+                .as_deref()
+                .unwrap_or("unknown")
+        ),
+        format!(
+            "source_time_start={}",
+            compact_state
+                .source_time_start
+                .as_deref()
+                .unwrap_or("unknown")
+        ),
+        format!(
+            "source_time_end={}",
+            compact_state
+                .source_time_end
+                .as_deref()
+                .unwrap_or("unknown")
+        ),
+        "timeline_order=ascending".to_string(),
+    ]
+    .join("\n")
+}
+
+/// The summary takes the slot of the first task digest; the rest are
+/// dropped since the summary covers them.
+fn replace_task_digests_with_summary(history: &mut Vec<Value>, summary_block: &str) {
+    let is_task_digest = |item: &Value| {
+        extract_text_from_history_item(item)
+            .map(|text| text.contains("<task_digest>"))
+            .unwrap_or(false)
+    };
+    let summary_item = json!({
+        "role": "assistant",
+        "content": [{
+            "type": "output_text",
+            "text": wrap_context_block("history_summary", summary_block),
+        }],
+    });
+    let insert_at = history.iter().position(is_task_digest).unwrap_or_else(|| {
+        history
+            .iter()
+            .take_while(|item| {
+                extract_text_from_history_item(item)
+                    .map(|text| is_initial_context_block(&text))
+                    .unwrap_or(false)
+            })
+            .count()
+    });
+    history.retain(|item| !is_task_digest(item));
+    history.insert(insert_at.min(history.len()), summary_item);
+}
+
 fn build_task_digest_history_item(item: &CompactTaskDigest) -> Value {
     let digest_json = serde_json::to_string(item).unwrap_or_else(|_| "{}".to_string());
     json!({
@@ -2114,20 +4413,33 @@ fn build_task_digest_history_item(item: &CompactTaskDigest) -> Value {
     })
 }
 
+/// Preserved tool rounds keep their original place among the recent items,
+/// so each output still directly follows its call.
 fn rebuild_compacted_history(
     initial_context_blocks: &[Value],
     historical_digests: &[CompactTaskDigest],
     recent_items: &[CompactHistoryItem],
+    preserved_tool_rounds: &[CompactToolRound],
 ) -> Vec<Value> {
     let mut compacted_history = Vec::new();
     compacted_history.extend(initial_context_blocks.iter().cloned());
     for digest in historical_digests {
         compacted_history.push(build_task_digest_history_item(digest));
     }
-    compacted_history.extend(recent_items.iter().map(|item| item.original.clone()));
+    let mut tail = recent_items
+        .iter()
+        .map(|item| (item.position, &item.original))
+        .collect::<Vec<_>>();
+    for round in preserved_tool_rounds {
+        tail.push((round.call_position, &round.call));
+        tail.push((round.output_position, &round.output));
+    }
+    tail.sort_by_key(|(position, _)| *position);
+    compacted_history.extend(tail.into_iter().map(|(_, item)| item.clone()));
     compacted_history
 }
 
+#[allow(clippy::too_many_arguments)]
 fn build_task_digest_compact_summary(
     digests: &[CompactTaskDigest],
     recent_items: &[CompactHistoryItem],
@@ -2136,6 +4448,9 @@ fn build_task_digest_compact_summary(
     compressed_at_iso: &str,
     source_time_start: Option<&str>,
     source_time_end: Option<&str>,
+    summary_token_budget: Option<u64>,
+    max_task_lines: Option<usize>,
+    token_estimator: &dyn TokenEstimator,
 ) -> String {
     let mut pieces: Vec<String> = Vec::new();
     pieces.push("algorithm=task_digest_v2".to_string());
@@ -2159,30 +4474,70 @@ fn build_task_digest_compact_summary(
     if let Some(hint) = summary_hint {
         pieces.push(format!("hint: {hint}"));
     }
-    for digest in digests.iter().rev().take(12).rev() {
-        let request = if digest.request.trim().is_empty() {
-            "(no request)".to_string()
-        } else {
-            digest.request.clone()
-        };
-        let summary = if digest.summary.trim().is_empty() {
-            "(no summary)".to_string()
-        } else {
-            digest.summary.clone()
-        };
-        pieces.push(format!("[task] request={request}"));
-        pieces.push(format!("[task] summary={summary}"));
-    }
-    for item in recent_items.iter().rev().take(4).rev() {
-        pieces.push(format!(
-            "[recent:{}] {}",
-            item.role,
-            sanitize_compact_line(item.text.as_str(), 200)
-        ));
+    let mut task_lines = digests
+        .iter()
+        .map(|digest| {
+            let request = if digest.request.trim().is_empty() {
+                "(no request)".to_string()
+            } else {
+                digest.request.clone()
+            };
+            let summary = if digest.summary.trim().is_empty() {
+                "(no summary)".to_string()
+            } else {
+                digest.summary.clone()
+            };
+            format!("[task] request={request}\n[task] summary={summary}")
+        })
+        .collect::<Vec<_>>();
+    if let Some(limit) = max_task_lines {
+        task_lines.drain(..task_lines.len().saturating_sub(limit));
     }
-    pieces.join("\n")
-}
-
+    let recent_lines = recent_items
+        .iter()
+        .map(|item| {
+            format!(
+                "[recent:{}] {}",
+                item.role,
+                sanitize_compact_line(item.text.as_str(), 200)
+            )
+        })
+        .collect::<Vec<_>>();
+    match summary_token_budget {
+        Some(budget) => pieces.extend(keep_recent_lines_within_budget(
+            task_lines.into_iter().chain(recent_lines).collect(),
+            budget,
+            token_estimator,
+        )),
+        None => {
+            let limit = max_task_lines.unwrap_or(DEFAULT_MAX_PRESERVED_USER_MESSAGES);
+            pieces.extend(task_lines.into_iter().rev().take(limit).rev());
+            pieces.extend(recent_lines.into_iter().rev().take(4).rev());
+        }
+    }
+    pieces.join("\n")
+}
+
+/// The newest `lines` whose estimated tokens add up to at most `budget`, in
+/// their original order.
+fn keep_recent_lines_within_budget(
+    lines: Vec<String>,
+    budget: u64,
+    token_estimator: &dyn TokenEstimator,
+) -> Vec<String> {
+    let mut used_tokens = 0_u64;
+    let mut kept = lines
+        .into_iter()
+        .rev()
+        .take_while(|line| {
+            used_tokens += token_estimator.estimate_item_tokens(&json!(line));
+            used_tokens <= budget
+        })
+        .collect::<Vec<_>>();
+    kept.reverse();
+    kept
+}
+
 fn is_initial_context_block(text: &str) -> bool {
     text.contains("<developer_instructions>")
         || text.contains("<user_instructions>")
@@ -2356,21 +4711,138 @@ fn build_response_input_content(items: &[InputItem]) -> Result<Vec<Value>, Model
                     "image_url": to_data_url_from_local_image(path)?,
                 }));
             }
+            InputItem::File {
+                path,
+                file_id,
+                filename,
+            } => {
+                if let Some(file_id) = non_empty(file_id) {
+                    content.push(json!({
+                        "type": "input_file",
+                        "file_id": file_id,
+                    }));
+                } else if let Some(path) = non_empty(path) {
+                    let filename = non_empty(filename)
+                        .map(str::to_string)
+                        .or_else(|| {
+                            Path::new(path)
+                                .file_name()
+                                .map(|name| name.to_string_lossy().to_string())
+                        })
+                        .unwrap_or_else(|| path.to_string());
+                    content.push(json!({
+                        "type": "input_file",
+                        "filename": filename,
+                        "file_data": to_data_url_from_local_file(path)?,
+                    }));
+                }
+            }
         }
     }
     Ok(content)
 }
 
+fn has_file_reference(path: &Option<String>, file_id: &Option<String>) -> bool {
+    non_empty(path).is_some() || non_empty(file_id).is_some()
+}
+
+fn non_empty(value: &Option<String>) -> Option<&str> {
+    value
+        .as_deref()
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
+
+fn to_data_url_from_local_file(path: &str) -> Result<String, ModelError> {
+    let bytes = fs::read(path).map_err(|error| ModelError::LocalFileRead {
+        path: path.to_string(),
+        error: error.to_string(),
+    })?;
+    let mime = infer_file_mime_type(path);
+    let encoded = base64::engine::general_purpose::STANDARD.encode(bytes);
+    Ok(format!("data:{mime};base64,{encoded}"))
+}
+
 fn to_data_url_from_local_image(path: &str) -> Result<String, ModelError> {
     let bytes = fs::read(path).map_err(|error| ModelError::LocalImageRead {
         path: path.to_string(),
         error: error.to_string(),
     })?;
+    let mime = sniff_image_mime_type(&bytes).unwrap_or_else(|| infer_image_mime_type(path));
     let encoded = base64::engine::general_purpose::STANDARD.encode(bytes);
-    let mime = infer_image_mime_type(path);
     Ok(format!("data:{mime};base64,{encoded}"))
 }
 
+/// Splits an image out of a successful tool output payload whose result
+/// carries `image_url` or `image_base64` (with an optional `mime_type`),
+/// returning the payload without the image fields and the image URL.
+fn split_tool_output_image(output_payload: &Value) -> Option<(Value, String)> {
+    let result = output_payload.get("result")?.as_object()?;
+    let non_empty_field = |field: &str| {
+        result
+            .get(field)
+            .and_then(Value::as_str)
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+    };
+    let image_url = if let Some(image_url) = non_empty_field("image_url") {
+        image_url.to_string()
+    } else {
+        let encoded = non_empty_field("image_base64")?;
+        if encoded.starts_with("data:") {
+            encoded.to_string()
+        } else {
+            let mime = non_empty_field("mime_type")
+                .map(str::to_string)
+                .unwrap_or_else(|| {
+                    base64::engine::general_purpose::STANDARD
+                        .decode(encoded)
+                        .ok()
+                        .and_then(|bytes| sniff_image_mime_type(&bytes))
+                        .unwrap_or("image/png")
+                        .to_string()
+                });
+            format!("data:{mime};base64,{encoded}")
+        }
+    };
+
+    let mut text_payload = output_payload.clone();
+    if let Some(result) = text_payload
+        .get_mut("result")
+        .and_then(Value::as_object_mut)
+    {
+        result.remove("image_url");
+        result.remove("image_base64");
+    }
+    Some((text_payload, image_url))
+}
+
+fn is_remote_image_item(item: &InputItem) -> bool {
+    matches!(item, InputItem::Image { image_url } if is_remote_image_url(image_url))
+}
+
+fn is_remote_image_url(image_url: &str) -> bool {
+    let normalized = image_url.trim().to_ascii_lowercase();
+    normalized.starts_with("http://") || normalized.starts_with("https://")
+}
+
+/// Identifies common image formats from their leading magic bytes.
+fn sniff_image_mime_type(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.starts_with(&[0x89, 0x50, 0x4E, 0x47]) {
+        Some("image/png")
+    } else if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("image/jpeg")
+    } else if bytes.starts_with(b"GIF") {
+        Some("image/gif")
+    } else if bytes.len() >= 12 && bytes.starts_with(b"RIFF") && &bytes[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else if bytes.starts_with(b"BM") {
+        Some("image/bmp")
+    } else {
+        None
+    }
+}
+
 fn infer_image_mime_type(path: &str) -> &'static str {
     let ext = Path::new(path)
         .extension()
@@ -2389,15 +4861,58 @@ fn infer_image_mime_type(path: &str) -> &'static str {
     }
 }
 
+fn infer_file_mime_type(path: &str) -> &'static str {
+    let ext = Path::new(path)
+        .extension()
+        .and_then(|value| value.to_str())
+        .map(|value| value.to_ascii_lowercase());
+
+    match ext.as_deref() {
+        Some("pdf") => "application/pdf",
+        Some("txt") => "text/plain",
+        Some("md") => "text/markdown",
+        Some("csv") => "text/csv",
+        Some("json") => "application/json",
+        Some("html") | Some("htm") => "text/html",
+        Some("docx") => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        _ => infer_image_mime_type(path),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use finger_kernel_protocol::{ContextWindowConfig, ResponsesReasoningOptions};
+    use crate::protocol::transport::REQUEST_ID_HEADER;
+    use finger_kernel_config::AzureDeployment;
+    use finger_kernel_protocol::{
+        ContextBlock, ContextWindowConfig, ResponsesReasoningOptions, ResponsesTextOptions,
+        ToolDaemonRoute,
+    };
     use mockito::{Matcher, Server};
     use std::fs;
     use std::time::{SystemTime, UNIX_EPOCH};
     use tokio::sync::mpsc::UnboundedReceiver;
 
+    fn test_config(server_url: String) -> LocalModelConfig {
+        LocalModelConfig {
+            provider_id: "test".to_string(),
+            provider_name: "test".to_string(),
+            base_url: server_url.clone(),
+            wire_api: WireApi::Responses,
+            env_key: "TEST_KEY".to_string(),
+            api_key: "test-key".to_string(),
+            model: "gpt-test".to_string(),
+            tool_daemon_url: server_url,
+            tool_agent_id: "chat-codex".to_string(),
+            tool_daemon_token: None,
+            extra_headers: HashMap::new(),
+            responses: None,
+            supports_encrypted_reasoning: true,
+            responses_path: None,
+            azure: None,
+        }
+    }
+
     #[test]
     fn parse_payload_reads_message_and_function_calls() {
         let payload = json!({
@@ -2443,6 +4958,113 @@ mod tests {
         assert_eq!(parsed.function_calls.len(), 0);
     }
 
+    #[test]
+    fn tool_input_schemas_are_validated_and_missing_type_is_filled() {
+        let tool = |input_schema: Value| ToolSpec {
+            name: "shell.exec".to_string(),
+            description: None,
+            input_schema: Some(input_schema),
+            approval: None,
+        };
+
+        let valid = json!({
+            "type": "object",
+            "properties": { "cmd": { "type": "string" } },
+            "required": ["cmd"]
+        });
+        let bindings = build_tool_bindings(&[tool(valid.clone())]).expect("valid schema");
+        assert_eq!(bindings[0].input_schema, Some(valid));
+
+        let bindings = build_tool_bindings(&[tool(json!({
+            "properties": { "cmd": { "type": "string" } }
+        }))])
+        .expect("missing type is coerced");
+        assert_eq!(
+            bindings[0].input_schema,
+            Some(json!({
+                "type": "object",
+                "properties": { "cmd": { "type": "string" } }
+            }))
+        );
+
+        for schema in [
+            json!(["cmd"]),
+            json!({ "type": "string" }),
+            json!({ "type": "object", "properties": ["cmd"] }),
+        ] {
+            let error = build_tool_bindings(&[tool(schema.clone())])
+                .expect_err("invalid schema is rejected");
+            assert!(
+                matches!(
+                    &error,
+                    ModelError::InvalidToolSchema { tool_name, .. } if tool_name == "shell.exec"
+                ),
+                "unexpected error for {schema}: {error}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn invalid_tool_schema_fails_turn_before_calling_provider() {
+        let mut server = Server::new_async().await;
+        let mock = server
+            .mock("POST", "/v1/responses")
+            .expect(0)
+            .create_async()
+            .await;
+
+        let error = fast_retry_engine(server.url(), 1)
+            .complete_with_options(
+                &[InputItem::Text {
+                    text: "list files".to_string(),
+                }],
+                &UserTurnOptions {
+                    tools: vec![ToolSpec {
+                        name: "shell.exec".to_string(),
+                        description: None,
+                        input_schema: Some(json!("not a schema")),
+                        approval: None,
+                    }],
+                    ..UserTurnOptions::default()
+                },
+                None,
+                None,
+            )
+            .await
+            .expect_err("schema rejected");
+        assert!(matches!(error, ModelError::InvalidToolSchema { .. }));
+        mock.assert_async().await;
+    }
+
+    #[test]
+    fn forced_tool_choice_uses_model_facing_tool_name() {
+        let bindings = build_tool_bindings(&[ToolSpec {
+            name: "shell.exec".to_string(),
+            description: None,
+            input_schema: None,
+            approval: None,
+        }])
+        .expect("valid tools");
+        let resolved = resolve_responses_tool_choice(
+            Some(&ResponsesRequestOptions {
+                tool_choice: Some(ToolChoice::Function {
+                    name: "shell.exec".to_string(),
+                }),
+                ..ResponsesRequestOptions::default()
+            }),
+            &bindings,
+        )
+        .expect("resolved options");
+
+        assert_eq!(
+            resolved.tool_choice,
+            Some(ToolChoice::Function {
+                name: "shell_exec".to_string(),
+            })
+        );
+        assert!(resolve_responses_tool_choice(None, &bindings).is_none());
+    }
+
     #[test]
     fn should_disable_reasoning_replay_when_encrypted_content_is_disabled() {
         let options = ResponsesRequestOptions {
@@ -2512,6 +5134,28 @@ mod tests {
         assert!(matches!(parsed_err, Err(ModelError::MissingStreamResponse)));
     }
 
+    #[test]
+    fn parse_sse_returns_incomplete_response_with_its_reason() {
+        let incomplete_stream = concat!(
+            "event: response.output_item.done\n",
+            "data: {\"type\":\"response.output_item.done\",\"item\":{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"partial\"}]}}\n\n",
+            "event: response.incomplete\n",
+            "data: {\"type\":\"response.incomplete\",\"response\":{\"id\":\"resp_cut\",\"status\":\"incomplete\",\"incomplete_details\":{\"reason\":\"max_output_tokens\"},\"output\":[]}}\n\n",
+            "data: [DONE]\n\n",
+        );
+        let parsed =
+            super::protocol::response::parse_sse_response(incomplete_stream).expect("parse sse");
+        assert_eq!(parsed["status"], "incomplete");
+        assert_eq!(parsed["incomplete_details"]["reason"], "max_output_tokens");
+
+        let payload = parse_protocol_payload(&parsed).expect("parse payload from sse");
+        assert_eq!(payload.output_text.as_deref(), Some("partial"));
+        assert_eq!(
+            payload.response_incomplete_reason.as_deref(),
+            Some("max_output_tokens")
+        );
+    }
+
     #[test]
     fn build_response_input_content_maps_text_and_image() {
         let content = build_response_input_content(&[
@@ -2551,6 +5195,200 @@ mod tests {
         assert!(image_url.starts_with("data:image/png;base64,"));
     }
 
+    #[test]
+    fn build_response_input_content_inlines_local_file_as_input_file() {
+        let unique = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("duration since epoch")
+            .as_nanos();
+        let temp_path = std::env::temp_dir().join(format!("finger-kernel-model-{unique}.pdf"));
+        fs::write(&temp_path, b"%PDF-1.7").expect("write pdf header");
+
+        let result = build_response_input_content(&[
+            InputItem::File {
+                path: Some(temp_path.to_string_lossy().to_string()),
+                file_id: None,
+                filename: None,
+            },
+            InputItem::File {
+                path: Some("  ".to_string()),
+                file_id: None,
+                filename: Some("ignored.pdf".to_string()),
+            },
+        ])
+        .expect("content");
+
+        let _ = fs::remove_file(&temp_path);
+
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0]["type"], "input_file");
+        assert_eq!(
+            result[0]["filename"],
+            format!("finger-kernel-model-{unique}.pdf")
+        );
+        let file_data = result[0]["file_data"].as_str().expect("file_data");
+        assert!(file_data.starts_with("data:application/pdf;base64,"));
+    }
+
+    #[test]
+    fn build_response_input_content_passes_file_id_through() {
+        let result = build_response_input_content(&[InputItem::File {
+            path: None,
+            file_id: Some("file-abc123".to_string()),
+            filename: Some("report.pdf".to_string()),
+        }])
+        .expect("content");
+
+        assert_eq!(
+            result,
+            vec![json!({ "type": "input_file", "file_id": "file-abc123" })]
+        );
+        assert!(!has_file_reference(&None, &Some(" ".to_string())));
+    }
+
+    #[test]
+    fn to_data_url_from_local_image_prefers_magic_bytes_over_extension() {
+        let unique = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("duration since epoch")
+            .as_nanos();
+        let cases: [(&str, &[u8], &str); 5] = [
+            ("jpg", &[0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A], "image/png"),
+            ("png", &[0xFF, 0xD8, 0xFF, 0xE0], "image/jpeg"),
+            ("png", b"GIF89a", "image/gif"),
+            ("jpg", b"RIFF\x24\x00\x00\x00WEBPVP8 ", "image/webp"),
+            ("gif", b"BM\x36\x00", "image/bmp"),
+        ];
+        for (index, (extension, header, expected_mime)) in cases.iter().enumerate() {
+            let temp_path = std::env::temp_dir().join(format!(
+                "finger-kernel-model-sniff-{unique}-{index}.{extension}"
+            ));
+            fs::write(&temp_path, header).expect("write image header");
+            let data_url =
+                to_data_url_from_local_image(&temp_path.to_string_lossy()).expect("data url");
+            let _ = fs::remove_file(&temp_path);
+            assert!(
+                data_url.starts_with(&format!("data:{expected_mime};base64,")),
+                "{extension} file with {expected_mime} bytes produced {data_url}"
+            );
+        }
+    }
+
+    #[test]
+    fn to_data_url_from_local_image_falls_back_to_extension_when_bytes_unknown() {
+        let unique = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("duration since epoch")
+            .as_nanos();
+        let temp_path =
+            std::env::temp_dir().join(format!("finger-kernel-model-sniff-{unique}.tiff"));
+        fs::write(&temp_path, [0x49_u8, 0x49, 0x2A, 0x00]).expect("write tiff header");
+        let data_url =
+            to_data_url_from_local_image(&temp_path.to_string_lossy()).expect("data url");
+        let _ = fs::remove_file(&temp_path);
+        assert!(data_url.starts_with("data:image/tiff;base64,"));
+    }
+
+    #[tokio::test]
+    async fn inline_remote_images_converts_http_image_to_data_url() {
+        let mut server = Server::new_async().await;
+        let image_mock = server
+            .mock("GET", "/images/photo")
+            .with_status(200)
+            .with_header("content-type", "image/jpeg")
+            .with_body([0xFF_u8, 0xD8, 0xFF, 0xE0])
+            .expect(1)
+            .create_async()
+            .await;
+
+        let engine = remote_image_test_engine(&server).with_remote_image_inlining(1024);
+        let items = engine
+            .inline_remote_images(&[
+                InputItem::Text {
+                    text: "describe".to_string(),
+                },
+                InputItem::Image {
+                    image_url: format!("{}/images/photo", server.url()),
+                },
+            ])
+            .await
+            .expect("inline remote image")
+            .expect("items should be rewritten");
+
+        assert_eq!(
+            items[0],
+            InputItem::Text {
+                text: "describe".to_string()
+            }
+        );
+        let InputItem::Image { image_url } = &items[1] else {
+            panic!("expected image item");
+        };
+        assert_eq!(image_url, "data:image/jpeg;base64,/9j/4A==");
+        image_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn inline_remote_images_rejects_oversize_download() {
+        let mut server = Server::new_async().await;
+        let image_mock = server
+            .mock("GET", "/images/huge.png")
+            .with_status(200)
+            .with_header("content-type", "image/png")
+            .with_body(vec![0_u8; 2048])
+            .expect(1)
+            .create_async()
+            .await;
+
+        let engine = remote_image_test_engine(&server).with_remote_image_inlining(1024);
+        let error = engine
+            .inline_remote_images(&[InputItem::Image {
+                image_url: format!("{}/images/huge.png", server.url()),
+            }])
+            .await
+            .expect_err("oversize image should fail");
+
+        match error {
+            ModelError::RemoteImageFetch { error, .. } => {
+                assert!(error.contains("exceeds max size of 1024 bytes"));
+            }
+            other => panic!("unexpected error: {other}"),
+        }
+        image_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn inline_remote_images_reports_http_status_failure() {
+        let mut server = Server::new_async().await;
+        let image_mock = server
+            .mock("GET", "/images/missing.png")
+            .with_status(404)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let engine = remote_image_test_engine(&server).with_remote_image_inlining(1024);
+        let error = engine
+            .inline_remote_images(&[InputItem::Image {
+                image_url: format!("{}/images/missing.png", server.url()),
+            }])
+            .await
+            .expect_err("missing image should fail");
+
+        match error {
+            ModelError::RemoteImageFetch { url, error } => {
+                assert!(url.ends_with("/images/missing.png"));
+                assert_eq!(error, "status 404");
+            }
+            other => panic!("unexpected error: {other}"),
+        }
+        image_mock.assert_async().await;
+    }
+
+    fn remote_image_test_engine(server: &mockito::ServerGuard) -> FingerChatEngine {
+        FingerChatEngine::new(test_config(server.url()))
+    }
+
     #[test]
     fn build_initial_input_partitions_context_into_developer_and_user_blocks() {
         let options = UserTurnOptions {
@@ -2605,48 +5443,486 @@ mod tests {
     }
 
     #[test]
-    fn initial_context_block_detection_includes_developer_instructions() {
-        assert!(is_initial_context_block(
-            "<developer_instructions>\npolicy\n</developer_instructions>"
-        ));
-    }
-
-    #[test]
-    fn context_ledger_insert_and_query_roundtrip() {
-        let ts = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("duration since epoch")
-            .as_millis();
-        let root = std::env::temp_dir().join(format!("finger-kernel-model-ledger-{ts}"));
+    fn instructions_wrap_the_system_prompt_with_prefix_and_suffix() {
+        let engine = FingerChatEngine::new(LocalModelConfig {
+            responses: Some(ResponsesRequestOptions {
+                instructions_prefix: Some("Follow the safety policy.".to_string()),
+                ..ResponsesRequestOptions::default()
+            }),
+            ..test_config("http://127.0.0.1:9".to_string())
+        });
+        let items = [InputItem::Text {
+            text: "hello".to_string(),
+        }];
+        let instructions = |options: &UserTurnOptions| {
+            engine
+                .build_request_preview(&items, options)
+                .expect("build preview")["instructions"]
+                .clone()
+        };
 
-        let options = UserTurnOptions {
-            session_id: Some("session-test".to_string()),
-            mode: Some("main".to_string()),
-            context_ledger: Some(finger_kernel_protocol::ContextLedgerOptions {
-                enabled: true,
-                root_dir: Some(root.to_string_lossy().to_string()),
-                agent_id: Some("chat-codex".to_string()),
-                role: Some("coding".to_string()),
-                mode: Some("main".to_string()),
-                can_read_all: true,
-                readable_agents: vec![],
-                focus_enabled: true,
-                focus_max_chars: Some(20_000),
+        let wrapped = UserTurnOptions {
+            system_prompt: Some("be brief".to_string()),
+            responses: Some(ResponsesRequestOptions {
+                instructions_suffix: Some("Answer in JSON.".to_string()),
+                ..ResponsesRequestOptions::default()
             }),
             ..UserTurnOptions::default()
         };
-        let ledger = build_context_ledger(&options).expect("ledger");
+        assert_eq!(
+            instructions(&wrapped),
+            "Follow the safety policy.\nbe brief\nAnswer in JSON."
+        );
 
-        let insert_call = FunctionCallItem {
-            call_id: "call_insert".to_string(),
-            name: "context_ledger.insert".to_string(),
-            arguments: "{\"text\":\"important context\",\"append\":false}".to_string(),
+        let blank_suffix = UserTurnOptions {
+            responses: Some(ResponsesRequestOptions {
+                instructions_suffix: Some("  ".to_string()),
+                ..ResponsesRequestOptions::default()
+            }),
+            ..UserTurnOptions::default()
         };
-        let insert_result =
-            execute_context_ledger_insert(&insert_call, Some(&ledger)).expect("insert result");
-        assert_eq!(insert_result["ok"], true);
+        assert_eq!(instructions(&blank_suffix), "Follow the safety policy.");
+    }
 
-        let query_call = FunctionCallItem {
+    #[test]
+    fn request_preview_shows_context_blocks_and_tools_without_sending() {
+        let engine = FingerChatEngine::new(test_config("http://127.0.0.1:9".to_string()));
+        let options = UserTurnOptions {
+            system_prompt: Some("be brief".to_string()),
+            developer_instructions: Some("permissions=sandboxed".to_string()),
+            user_instructions: Some("# AGENTS.md instructions for /repo".to_string()),
+            environment_context: Some("cwd=/repo".to_string()),
+            tools: vec![ToolSpec {
+                name: "shell.exec".to_string(),
+                description: Some("Execute shell command".to_string()),
+                input_schema: None,
+                approval: None,
+            }],
+            ..UserTurnOptions::default()
+        };
+
+        let preview = engine
+            .build_request_preview(
+                &[InputItem::Text {
+                    text: "hello".to_string(),
+                }],
+                &options,
+            )
+            .expect("build preview");
+
+        assert_eq!(preview["model"], "gpt-test");
+        assert_eq!(preview["instructions"], "be brief");
+        let input_text = serde_json::to_string(&preview["input"]).expect("serialize input");
+        assert!(input_text.contains("<developer_instructions>"));
+        assert!(input_text.contains("<user_instructions>"));
+        assert!(input_text.contains("<environment_context>"));
+        assert!(input_text.contains("hello"));
+        let tools = preview["tools"].as_array().expect("tools array");
+        assert_eq!(tools.len(), 1);
+        assert_eq!(tools[0]["type"], "function");
+        assert_eq!(tools[0]["description"], "Execute shell command");
+    }
+
+    #[test]
+    fn unknown_reasoning_effort_is_rejected_before_building_request() {
+        let engine = fast_retry_engine("http://127.0.0.1:9".to_string(), 1);
+        let with_effort = |effort: &str| UserTurnOptions {
+            responses: Some(ResponsesRequestOptions {
+                reasoning: Some(ResponsesReasoningOptions {
+                    effort: Some(effort.to_string()),
+                    ..ResponsesReasoningOptions::default()
+                }),
+                ..ResponsesRequestOptions::default()
+            }),
+            ..UserTurnOptions::default()
+        };
+        let items = [InputItem::Text {
+            text: "hello".to_string(),
+        }];
+
+        let preview = engine
+            .build_request_preview(&items, &with_effort("none"))
+            .expect("none is a known effort");
+        assert!(preview.get("reasoning").is_none());
+
+        let error = engine
+            .build_request_preview(&items, &with_effort("extreme"))
+            .expect_err("unknown effort");
+        assert!(
+            matches!(&error, ModelError::InvalidReasoningEffort { effort } if effort == "extreme"),
+            "unexpected error: {error}"
+        );
+    }
+
+    #[test]
+    fn prompt_cache_key_prefers_override_then_stable_prefix_then_session() {
+        let engine = fast_retry_engine("http://127.0.0.1:9".to_string(), 1);
+        let items = [InputItem::Text {
+            text: "hello".to_string(),
+        }];
+        let turn = |session_id: &str, prompt_cache_key: Option<&str>| UserTurnOptions {
+            system_prompt: Some("be brief".to_string()),
+            session_id: Some(session_id.to_string()),
+            responses: Some(ResponsesRequestOptions {
+                prompt_cache_key: prompt_cache_key.map(str::to_string),
+                ..ResponsesRequestOptions::default()
+            }),
+            ..UserTurnOptions::default()
+        };
+        let cache_key = |options: &UserTurnOptions| {
+            engine
+                .build_request_preview(&items, options)
+                .expect("build preview")["prompt_cache_key"]
+                .clone()
+        };
+
+        assert_eq!(cache_key(&turn("s1", Some("team-cache"))), "team-cache");
+        let shared = cache_key(&turn("s1", None));
+        assert!(shared
+            .as_str()
+            .is_some_and(|key| key.starts_with("prefix-")));
+        assert_eq!(cache_key(&turn("s2", None)), shared);
+
+        let bare = UserTurnOptions {
+            session_id: Some("s3".to_string()),
+            ..UserTurnOptions::default()
+        };
+        assert_eq!(cache_key(&bare), "s3");
+    }
+
+    fn stale_environment_history() -> Vec<Value> {
+        vec![json!({
+            "role": "user",
+            "content": [
+                { "type": "input_text", "text": "earlier question" },
+                { "type": "input_text", "text": "<environment_context shell=\"zsh\">\ncwd=/old\n</environment_context>" }
+            ]
+        })]
+    }
+
+    fn count_blocks(input: &[Value], block_name: &str) -> usize {
+        input
+            .iter()
+            .filter(|item| history_item_carries_block(item, block_name))
+            .count()
+    }
+
+    #[test]
+    fn build_initial_input_skips_context_block_already_in_history() {
+        let options = UserTurnOptions {
+            history_items: stale_environment_history(),
+            environment_context: Some("cwd=/repo".to_string()),
+            ..UserTurnOptions::default()
+        };
+
+        let input = build_initial_input(
+            &[InputItem::Text {
+                text: "hello".to_string(),
+            }],
+            &options,
+        )
+        .expect("build initial input");
+
+        assert_eq!(input.len(), 2);
+        assert_eq!(count_blocks(&input, "environment_context"), 1);
+        assert!(!serde_json::to_string(&input)
+            .expect("serialize input")
+            .contains("cwd=/repo"));
+    }
+
+    #[test]
+    fn build_initial_input_refreshes_stale_context_block_when_requested() {
+        let options = UserTurnOptions {
+            history_items: stale_environment_history(),
+            environment_context: Some("cwd=/repo".to_string()),
+            refresh_context_blocks: true,
+            ..UserTurnOptions::default()
+        };
+
+        let input = build_initial_input(
+            &[InputItem::Text {
+                text: "hello".to_string(),
+            }],
+            &options,
+        )
+        .expect("build initial input");
+
+        assert_eq!(input.len(), 3);
+        assert_eq!(count_blocks(&input, "environment_context"), 1);
+        assert_eq!(input[0]["content"].as_array().map(Vec::len), Some(1));
+        assert_eq!(input[0]["content"][0]["text"], "earlier question");
+        assert_eq!(
+            input[1]["content"][0]["text"],
+            "<environment_context>\ncwd=/repo\n</environment_context>"
+        );
+        assert_eq!(input[2]["content"][0]["text"], "hello");
+    }
+
+    fn custom_block(name: &str, content: &str, position: ContextBlockPosition) -> ContextBlock {
+        ContextBlock {
+            name: name.to_string(),
+            content: content.to_string(),
+            role: ContextBlockRole::Developer,
+            position,
+        }
+    }
+
+    #[test]
+    fn build_initial_input_places_custom_blocks_by_position() {
+        let options = UserTurnOptions::builder()
+            .developer_instructions("be brief")
+            .user_instructions("# AGENTS.md")
+            .environment_context("cwd=/repo")
+            .context_block(custom_block(
+                "style_guide",
+                "use tabs",
+                ContextBlockPosition::BeforeUserInput,
+            ))
+            .context_block(custom_block(
+                "repo_map",
+                "src/lib.rs",
+                ContextBlockPosition::AfterInstructions,
+            ))
+            .context_block(ContextBlock {
+                role: ContextBlockRole::User,
+                ..custom_block(
+                    "open_files",
+                    "src/main.rs",
+                    ContextBlockPosition::AfterInstructions,
+                )
+            })
+            .build();
+
+        let input = build_initial_input(
+            &[InputItem::Text {
+                text: "hello".to_string(),
+            }],
+            &options,
+        )
+        .expect("build initial input");
+
+        let texts = input
+            .iter()
+            .map(|item| item["content"][0]["text"].as_str().unwrap_or_default())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            texts,
+            vec![
+                "<developer_instructions>\nbe brief\n</developer_instructions>",
+                "<user_instructions>\n# AGENTS.md\n</user_instructions>",
+                "<repo_map>\nsrc/lib.rs\n</repo_map>",
+                "<open_files>\nsrc/main.rs\n</open_files>",
+                "<environment_context>\ncwd=/repo\n</environment_context>",
+                "<style_guide>\nuse tabs\n</style_guide>",
+                "hello",
+            ]
+        );
+        assert_eq!(input[2]["role"], "developer");
+        assert_eq!(input[3]["role"], "user");
+    }
+
+    #[test]
+    fn build_initial_input_dedups_custom_blocks_by_name() {
+        let history_items = vec![
+            build_text_message("developer", wrap_context_block("repo_map", "old map")),
+            build_text_message("user", "earlier question".to_string()),
+        ];
+        let options = UserTurnOptions::builder()
+            .history_items(history_items.clone())
+            .context_block(custom_block(
+                "repo_map",
+                "new map",
+                ContextBlockPosition::AfterInstructions,
+            ))
+            .context_block(custom_block(
+                "style_guide",
+                "use tabs",
+                ContextBlockPosition::BeforeUserInput,
+            ))
+            .context_block(custom_block(
+                "style_guide",
+                "use spaces",
+                ContextBlockPosition::BeforeUserInput,
+            ))
+            .build();
+        let items = [InputItem::Text {
+            text: "hello".to_string(),
+        }];
+
+        let input = build_initial_input(&items, &options).expect("build initial input");
+
+        assert_eq!(input.len(), 4);
+        assert_eq!(count_blocks(&input, "repo_map"), 1);
+        assert_eq!(count_blocks(&input, "style_guide"), 1);
+        assert_eq!(
+            input[0]["content"][0]["text"],
+            "<repo_map>\nold map\n</repo_map>"
+        );
+        assert_eq!(
+            input[2]["content"][0]["text"],
+            "<style_guide>\nuse tabs\n</style_guide>"
+        );
+
+        let refreshed = build_initial_input(
+            &items,
+            &UserTurnOptions {
+                refresh_context_blocks: true,
+                ..options
+            },
+        )
+        .expect("build initial input");
+        assert_eq!(count_blocks(&refreshed, "repo_map"), 1);
+        assert_eq!(
+            refreshed[1]["content"][0]["text"],
+            "<repo_map>\nnew map\n</repo_map>"
+        );
+    }
+
+    #[test]
+    fn initial_context_block_detection_includes_developer_instructions() {
+        assert!(is_initial_context_block(
+            "<developer_instructions>\npolicy\n</developer_instructions>"
+        ));
+    }
+
+    fn fork_history() -> Vec<Value> {
+        vec![
+            build_text_message(
+                "developer",
+                wrap_context_block("developer_instructions", "be brief"),
+            ),
+            build_text_message("user", "first question".to_string()),
+            build_text_message("assistant", "first answer".to_string()),
+            build_text_message("user", "second question".to_string()),
+            build_text_message("assistant", "second answer".to_string()),
+            build_text_message(
+                "user",
+                wrap_context_block("environment_context", "cwd=/repo"),
+            ),
+            build_text_message("user", "third question".to_string()),
+        ]
+    }
+
+    #[test]
+    fn malformed_tool_arguments_are_repaired_before_falling_back_to_a_string() {
+        assert_eq!(
+            parse_function_arguments_with_repair(r#"{"cmd": "ls", "paths": ["src", "docs",],}"#),
+            (json!({ "cmd": "ls", "paths": ["src", "docs"] }), true)
+        );
+        assert_eq!(
+            parse_function_arguments_with_repair(r#"{cmd: "cat \"a b\"", max_lines: 5"#),
+            (json!({ "cmd": "cat \"a b\"", "max_lines": 5 }), true)
+        );
+        assert_eq!(
+            parse_function_arguments_with_repair(r#"{"cmd": "echo hi"#),
+            (json!({ "cmd": "echo hi" }), true)
+        );
+        assert_eq!(
+            parse_function_arguments_with_repair(r#"{"cmd": "ls"}"#),
+            (json!({ "cmd": "ls" }), false)
+        );
+
+        let blob = r#"{"cmd" "ls" :: ]"#;
+        assert_eq!(
+            parse_function_arguments_with_repair(blob),
+            (Value::String(blob.to_string()), false)
+        );
+    }
+
+    #[test]
+    fn equivalent_tool_arguments_canonicalize_identically() {
+        let left = parse_function_arguments(
+            r#"{"cmd":"ls -la","opts":{"depth":2,"follow":true},"paths":["src","docs"]}"#,
+        );
+        let right = parse_function_arguments(
+            r#" { "paths": ["src", "docs"], "opts": {"follow": true, "depth": 2}, "cmd": "ls -la" } "#,
+        );
+        assert_eq!(
+            canonicalize_tool_arguments(left.clone()),
+            canonicalize_tool_arguments(right.clone())
+        );
+        assert_eq!(
+            canonicalize_tool_arguments(left.clone()).to_string(),
+            r#"{"cmd":"ls -la","opts":{"depth":2,"follow":true},"paths":["src","docs"]}"#
+        );
+        assert_eq!(
+            ToolResultCache::key("shell.exec", &left),
+            ToolResultCache::key("shell.exec", &right)
+        );
+        assert_ne!(
+            canonicalize_tool_arguments(left),
+            canonicalize_tool_arguments(json!({ "cmd": "ls" }))
+        );
+    }
+
+    #[test]
+    fn fork_truncate_past_the_end_keeps_everything_and_reports_it() {
+        let truncation = apply_fork_truncate(fork_history(), 3);
+
+        assert!(truncation.index_out_of_range);
+        assert_eq!(truncation.history, fork_history());
+    }
+
+    #[test]
+    fn fork_truncate_mid_history_keeps_context_blocks() {
+        let truncation = apply_fork_truncate(fork_history(), 0);
+
+        assert!(!truncation.index_out_of_range);
+        let texts = truncation
+            .history
+            .iter()
+            .filter_map(extract_text_from_history_item)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            texts,
+            vec![
+                "<developer_instructions>\nbe brief\n</developer_instructions>",
+                "first question",
+                "first answer",
+                "<environment_context>\ncwd=/repo\n</environment_context>",
+            ]
+        );
+    }
+
+    #[test]
+    fn context_ledger_insert_and_query_roundtrip() {
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("duration since epoch")
+            .as_millis();
+        let root = std::env::temp_dir().join(format!("finger-kernel-model-ledger-{ts}"));
+
+        let options = UserTurnOptions {
+            session_id: Some("session-test".to_string()),
+            mode: Some("main".to_string()),
+            context_ledger: Some(finger_kernel_protocol::ContextLedgerOptions {
+                enabled: true,
+                root_dir: Some(root.to_string_lossy().to_string()),
+                agent_id: Some("chat-codex".to_string()),
+                role: Some("coding".to_string()),
+                mode: Some("main".to_string()),
+                can_read_all: true,
+                readable_agents: vec![],
+                focus_enabled: true,
+                focus_max_chars: Some(20_000),
+                max_segment_bytes: None,
+                max_retained_segments: None,
+                focus_injection_role: None,
+            }),
+            ..UserTurnOptions::default()
+        };
+        let ledger = build_context_ledger(&options).expect("ledger");
+
+        let insert_call = FunctionCallItem {
+            call_id: "call_insert".to_string(),
+            name: "context_ledger.insert".to_string(),
+            arguments: "{\"text\":\"important context\",\"append\":false}".to_string(),
+        };
+        let insert_result =
+            execute_context_ledger_insert(&insert_call, Some(&ledger)).expect("insert result");
+        assert_eq!(insert_result["ok"], true);
+
+        let query_call = FunctionCallItem {
             call_id: "call_query".to_string(),
             name: "context_ledger.query".to_string(),
             arguments: "{\"event_types\":[\"focus_insert\"],\"limit\":5}".to_string(),
@@ -2656,6 +5932,104 @@ mod tests {
         assert!(query_result["total"].as_u64().unwrap_or(0) >= 1);
     }
 
+    #[test]
+    fn context_ledger_replays_turn_events_as_history_items() {
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("duration since epoch")
+            .as_millis();
+        let root = std::env::temp_dir().join(format!("finger-kernel-model-replay-{ts}"));
+
+        let options = UserTurnOptions {
+            session_id: Some("session-replay".to_string()),
+            mode: Some("main".to_string()),
+            context_ledger: Some(finger_kernel_protocol::ContextLedgerOptions {
+                enabled: true,
+                root_dir: Some(root.to_string_lossy().to_string()),
+                agent_id: Some("chat-codex".to_string()),
+                role: None,
+                mode: Some("main".to_string()),
+                can_read_all: false,
+                readable_agents: vec![],
+                focus_enabled: false,
+                focus_max_chars: None,
+                max_segment_bytes: None,
+                max_retained_segments: None,
+                focus_injection_role: None,
+            }),
+            ..UserTurnOptions::default()
+        };
+        let ledger = build_context_ledger(&options).expect("ledger");
+        let events = [
+            (
+                "turn_start",
+                json!({ "user_text": "where am i", "items_count": 1 }),
+            ),
+            ("model_round", json!({ "round": 1, "output_text": null })),
+            (
+                "tool_call",
+                json!({
+                    "call_id": "call_pwd",
+                    "name": "shell_exec",
+                    "arguments": "{\"cmd\":\"pwd\"}",
+                    "tool_name": "shell.exec",
+                    "input": { "cmd": "pwd" },
+                }),
+            ),
+            (
+                "tool_result",
+                json!({
+                    "call_id": "call_pwd",
+                    "tool_name": "shell.exec",
+                    "ok": true,
+                    "output": { "stdout": "/tmp" },
+                }),
+            ),
+            (
+                "model_round",
+                json!({ "round": 2, "output_text": "cwd is /tmp" }),
+            ),
+            ("turn_complete", json!({ "reply_chars": 11 })),
+        ];
+        for (event_type, payload) in events {
+            ledger.append_event(event_type, payload).expect("append");
+        }
+
+        let history = ledger.replay_history().expect("replay history");
+        let _ = fs::remove_dir_all(&root);
+
+        assert_eq!(normalize_history_items(&history), history);
+        assert_eq!(
+            history,
+            vec![
+                json!({
+                    "role": "user",
+                    "content": [{ "type": "input_text", "text": "where am i" }],
+                }),
+                json!({
+                    "type": "function_call",
+                    "call_id": "call_pwd",
+                    "name": "shell_exec",
+                    "arguments": "{\"cmd\":\"pwd\"}",
+                }),
+                json!({
+                    "type": "function_call_output",
+                    "call_id": "call_pwd",
+                    "output": json!({
+                        "ok": true,
+                        "tool": "shell.exec",
+                        "result": { "stdout": "/tmp" },
+                    })
+                    .to_string(),
+                }),
+                json!({
+                    "role": "assistant",
+                    "content": [{ "type": "output_text", "text": "cwd is /tmp" }],
+                }),
+            ]
+        );
+    }
+
     #[test]
     fn compact_history_filters_prompt_blocks_and_preserves_timeline_order() {
         let history = vec![
@@ -2691,7 +6065,7 @@ mod tests {
             }),
         ];
 
-        let result = compact_history(&history, None, Some(512));
+        let result = compact_history(&history, None, Some(512), &HeuristicTokenEstimator);
         let summary = result.summary.unwrap_or_default();
 
         assert!(summary.contains("timeline_order=ascending"));
@@ -2725,8 +6099,9 @@ mod tests {
             }),
         ];
 
-        let total_tokens = estimate_tokens_in_history(&history);
-        let compactable_tokens = estimate_tokens_excluding_ledger_focus(&history);
+        let total_tokens = estimate_tokens_in_history(&history, &HeuristicTokenEstimator);
+        let compactable_tokens =
+            estimate_tokens_excluding_ledger_focus(&history, &HeuristicTokenEstimator);
         assert!(total_tokens > compactable_tokens);
         let threshold_tokens = compactable_tokens + 1;
         let trigger_by_total = total_tokens > threshold_tokens;
@@ -2741,7 +6116,7 @@ mod tests {
             "role": "user",
             "content": [{ "type": "input_text", "text": "hello" }]
         })];
-        let plain_tokens = estimate_tokens_in_history(&plain_history);
+        let plain_tokens = estimate_tokens_in_history(&plain_history, &HeuristicTokenEstimator);
 
         let huge_data_url = format!("data:image/png;base64,{}", "A".repeat(800_000));
         let with_image_history = vec![
@@ -2754,7 +6129,8 @@ mod tests {
                 "content": [{ "type": "input_image", "image_url": huge_data_url }]
             }),
         ];
-        let with_image_tokens = estimate_tokens_in_history(&with_image_history);
+        let with_image_tokens =
+            estimate_tokens_in_history(&with_image_history, &HeuristicTokenEstimator);
 
         // Non-text image payloads are tracked as a tiny fixed placeholder, not by base64 size.
         assert!(with_image_tokens.saturating_sub(plain_tokens) < 64);
@@ -2775,7 +6151,7 @@ mod tests {
             }),
         ];
 
-        let result = compact_history(&history, None, Some(512));
+        let result = compact_history(&history, None, Some(512), &HeuristicTokenEstimator);
         let summary = result.summary.unwrap_or_default();
         assert!(summary.contains("legacy_history_summary"));
         assert!(summary.contains("old summary line"));
@@ -2799,9 +6175,9 @@ mod tests {
             }));
         }
 
-        let result = compact_history(&history, None, Some(1200));
+        let result = compact_history(&history, None, Some(1200), &HeuristicTokenEstimator);
         let compacted = result.history;
-        let compacted_tokens = estimate_tokens_in_history(&compacted);
+        let compacted_tokens = estimate_tokens_in_history(&compacted, &HeuristicTokenEstimator);
 
         assert!(compacted_tokens <= 1200);
         let last_text = extract_text_from_history_item(compacted.last().expect("last item")).unwrap_or_default();
@@ -2810,9 +6186,178 @@ mod tests {
         assert!(result.replacement_history.len() < 12);
     }
 
-    #[tokio::test]
-    async fn run_turn_executes_function_call_loop_and_returns_final_message() {
-        let mut server = Server::new_async().await;
+    #[test]
+    fn compact_summary_fills_token_budget_from_newest_lines() {
+        let recent_items = ["short one", &"x".repeat(180), "short two", "short three"]
+            .iter()
+            .enumerate()
+            .map(|(position, text)| CompactHistoryItem {
+                role: "user".to_string(),
+                text: text.to_string(),
+                timestamp_iso: None,
+                position,
+                original: json!({}),
+            })
+            .collect::<Vec<_>>();
+        let summary = |budget: Option<u64>| {
+            build_task_digest_compact_summary(
+                &[],
+                &recent_items,
+                None,
+                1,
+                "1970-01-01T00:00:00Z",
+                None,
+                None,
+                budget,
+                None,
+                &HeuristicTokenEstimator,
+            )
+        };
+
+        // Short lines estimate to 6 or 7 tokens and the long one to 49.
+        let budgeted = summary(Some(20));
+        assert!(budgeted.starts_with("algorithm=task_digest_v2\ncompressed_at_ms=1"));
+        let kept = budgeted
+            .lines()
+            .filter(|line| line.starts_with("[recent:"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kept,
+            vec!["[recent:user] short two", "[recent:user] short three"]
+        );
+        let kept_tokens = kept
+            .iter()
+            .map(|line| HeuristicTokenEstimator.estimate_item_tokens(&json!(line)))
+            .sum::<u64>();
+        assert!(kept_tokens <= 20);
+
+        let roomy = summary(Some(200));
+        assert_eq!(
+            roomy
+                .lines()
+                .filter(|line| line.starts_with("[recent:"))
+                .count(),
+            4
+        );
+    }
+
+    #[test]
+    fn compact_summary_keeps_the_configured_number_of_user_requests() {
+        let mut history = Vec::new();
+        for index in 0..6 {
+            history.push(json!({
+                "role": "user",
+                "content": [{ "type": "input_text", "text": format!("user request {index}") }]
+            }));
+            history.push(json!({
+                "role": "assistant",
+                "content": [{ "type": "output_text", "text": format!("assistant result {index}") }]
+            }));
+        }
+        let summary_requests = |max_preserved_user_messages: Option<usize>| {
+            let compact_cfg = CompactConfig {
+                preserve_user_messages: false,
+                max_preserved_user_messages,
+                ..CompactConfig::default()
+            };
+            let result =
+                compact_history(&history, Some(&compact_cfg), None, &HeuristicTokenEstimator);
+            assert_eq!(result.replacement_history.len(), 5);
+            result
+                .summary
+                .unwrap_or_default()
+                .lines()
+                .filter_map(|line| line.strip_prefix("[task] request="))
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            summary_requests(Some(3)),
+            vec!["user request 2", "user request 3", "user request 4"]
+        );
+        assert!(summary_requests(Some(0)).is_empty());
+        assert_eq!(summary_requests(None).len(), 5);
+    }
+
+    #[test]
+    fn compact_history_preserves_recent_tool_rounds_and_digests_older_ones() {
+        let mut history = Vec::new();
+        for index in 0..4 {
+            history.push(json!({
+                "role": "user",
+                "content": [{ "type": "input_text", "text": format!("user request {index}") }]
+            }));
+            history.push(json!({
+                "type": "function_call",
+                "call_id": format!("call_{index}"),
+                "name": "shell_exec",
+                "arguments": format!("{{\"cmd\":\"step {index}\"}}"),
+            }));
+            history.push(json!({
+                "type": "function_call_output",
+                "call_id": format!("call_{index}"),
+                "output": format!("{{\"stdout\":\"output {index}\"}}"),
+            }));
+            history.push(json!({
+                "role": "assistant",
+                "content": [{ "type": "output_text", "text": format!("assistant result {index}") }]
+            }));
+        }
+        let compact_cfg = CompactConfig {
+            preserve_recent_tool_rounds: Some(2),
+            ..CompactConfig::default()
+        };
+
+        let result = compact_history(&history, Some(&compact_cfg), None, &HeuristicTokenEstimator);
+        let compacted = result.history;
+
+        let kept_call_ids = compacted
+            .iter()
+            .filter(|item| item["type"] == "function_call")
+            .map(|item| item["call_id"].as_str().unwrap_or_default())
+            .collect::<Vec<_>>();
+        assert_eq!(kept_call_ids, vec!["call_2", "call_3"]);
+        for call_id in ["call_2", "call_3"] {
+            let call_index = compacted
+                .iter()
+                .position(|item| item["type"] == "function_call" && item["call_id"] == call_id)
+                .expect("preserved call");
+            let output = &compacted[call_index + 1];
+            assert_eq!(output["type"], "function_call_output");
+            assert_eq!(output["call_id"], call_id);
+        }
+        let call_3_index = compacted
+            .iter()
+            .position(|item| item["call_id"] == "call_3")
+            .expect("latest call");
+        let request_3_index = compacted
+            .iter()
+            .position(|item| {
+                extract_text_from_history_item(item).as_deref() == Some("user request 3")
+            })
+            .expect("latest request");
+        assert!(request_3_index < call_3_index);
+
+        let digested_inputs = result
+            .replacement_history
+            .iter()
+            .flat_map(|digest| digest["tool_calls"].as_array().cloned().unwrap_or_default())
+            .map(|call| call["input"].as_str().unwrap_or_default().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            digested_inputs,
+            vec![r#"{"cmd":"step 0"}"#, r#"{"cmd":"step 1"}"#]
+        );
+        assert_eq!(
+            result.replacement_history[0]["key_tools"],
+            json!(["shell_exec"])
+        );
+    }
+
+    #[tokio::test]
+    async fn run_turn_executes_function_call_loop_and_returns_final_message() {
+        let mut server = Server::new_async().await;
 
         let first_response_mock = server
             .mock("POST", "/v1/responses")
@@ -2871,17 +6416,7 @@ mod tests {
             .create_async()
             .await;
 
-        let engine = FingerChatEngine::new(LocalModelConfig {
-            provider_id: "test".to_string(),
-            provider_name: "test".to_string(),
-            base_url: server.url(),
-            wire_api: WireApi::Responses,
-            env_key: "TEST_KEY".to_string(),
-            api_key: "test-key".to_string(),
-            model: "gpt-test".to_string(),
-            tool_daemon_url: server.url(),
-            tool_agent_id: "chat-codex".to_string(),
-        });
+        let engine = FingerChatEngine::new(test_config(server.url()));
 
         let result = engine
             .run_turn(
@@ -2901,10 +6436,19 @@ mod tests {
                                 },
                                 "required": ["cmd"]
                             })),
+                            approval: None,
                         }],
                         tool_execution: Some(ToolExecutionConfig {
                             daemon_url: server.url(),
                             agent_id: "chat-codex".to_string(),
+                            max_concurrency: None,
+                            tool_timeout_ms: None,
+                            auth_token: None,
+                            retry_transient_errors: false,
+                            tool_max_retries: None,
+                            tool_execute_path: None,
+                            cache_identical_calls: false,
+                            routes: Vec::new(),
                         }),
                         ..UserTurnOptions::default()
                     },
@@ -2958,17 +6502,7 @@ mod tests {
             .create_async()
             .await;
 
-        let engine = FingerChatEngine::new(LocalModelConfig {
-            provider_id: "test".to_string(),
-            provider_name: "test".to_string(),
-            base_url: server.url(),
-            wire_api: WireApi::Responses,
-            env_key: "TEST_KEY".to_string(),
-            api_key: "test-key".to_string(),
-            model: "gpt-test".to_string(),
-            tool_daemon_url: server.url(),
-            tool_agent_id: "chat-codex".to_string(),
-        });
+        let engine = FingerChatEngine::new(test_config(server.url()));
 
         let output = engine
             .complete_text("hello")
@@ -3008,17 +6542,7 @@ mod tests {
             .create_async()
             .await;
 
-        let engine = FingerChatEngine::new(LocalModelConfig {
-            provider_id: "test".to_string(),
-            provider_name: "test".to_string(),
-            base_url: server.url(),
-            wire_api: WireApi::Responses,
-            env_key: "TEST_KEY".to_string(),
-            api_key: "test-key".to_string(),
-            model: "gpt-test".to_string(),
-            tool_daemon_url: server.url(),
-            tool_agent_id: "chat-codex".to_string(),
-        });
+        let engine = FingerChatEngine::new(test_config(server.url()));
 
         let output = engine
             .complete_text("hello")
@@ -3030,6 +6554,210 @@ mod tests {
         second_response_mock.assert_async().await;
     }
 
+    fn fast_retry_engine(base_url: String, max_attempts: u32) -> FingerChatEngine {
+        FingerChatEngine::new(test_config(base_url)).with_retry_policy(RetryPolicy {
+            max_attempts,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(5),
+            jitter: true,
+        })
+    }
+
+    #[tokio::test]
+    async fn server_errors_retry_until_the_attempt_budget_is_spent() {
+        let mut server = Server::new_async().await;
+        let failing_mock = server
+            .mock("POST", "/v1/responses")
+            .with_status(503)
+            .with_body("upstream unavailable")
+            .expect(3)
+            .create_async()
+            .await;
+        let success_mock = server
+            .mock("POST", "/v1/responses")
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_retry\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"recovered\"}]}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        let output = fast_retry_engine(server.url(), 4)
+            .complete_text("hello")
+            .await
+            .expect("fourth attempt succeeds");
+        assert_eq!(output, "recovered");
+        failing_mock.assert_async().await;
+        success_mock.assert_async().await;
+        failing_mock.remove_async().await;
+        success_mock.remove_async().await;
+
+        let exhausted_mock = server
+            .mock("POST", "/v1/responses")
+            .with_status(500)
+            .with_body("still broken")
+            .expect(3)
+            .create_async()
+            .await;
+        let error = fast_retry_engine(server.url(), 3)
+            .complete_text("hello")
+            .await
+            .expect_err("budget runs out");
+        assert!(
+            matches!(error, ModelError::HttpStatus { status: 500, .. }),
+            "unexpected error: {error}"
+        );
+        exhausted_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_not_retried() {
+        let mut server = Server::new_async().await;
+        let rejected_mock = server
+            .mock("POST", "/v1/responses")
+            .with_status(400)
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{"error":{"type":"invalid_request_error","message":"bad temperature","param":"temperature"}}"#,
+            )
+            .expect(1)
+            .create_async()
+            .await;
+
+        let error = fast_retry_engine(server.url(), 5)
+            .complete_text("hello")
+            .await
+            .expect_err("400 is final");
+        assert!(
+            matches!(error, ModelError::InvalidRequest { .. }),
+            "unexpected error: {error}"
+        );
+        rejected_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn retries_after_rate_limit_honoring_retry_after_header() {
+        let mut server = Server::new_async().await;
+
+        let rate_limited_mock = server
+            .mock("POST", "/v1/responses")
+            .match_header("authorization", "Bearer test-key")
+            .with_status(429)
+            .with_header("content-type", "application/json")
+            .with_header("retry-after", "1")
+            .with_body(r#"{"error":{"message":"Rate limit reached"}}"#)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let success_mock = server
+            .mock("POST", "/v1/responses")
+            .match_header("authorization", "Bearer test-key")
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_rate_limit_retry\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"rate limit retry ok\"}]}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        let engine = FingerChatEngine::new(test_config(server.url()));
+
+        let started_at = Instant::now();
+        let output = engine
+            .complete_text("hello")
+            .await
+            .expect("complete text should retry after rate limit");
+        assert_eq!(output, "rate limit retry ok");
+        assert!(started_at.elapsed() >= Duration::from_secs(1));
+
+        rate_limited_mock.assert_async().await;
+        success_mock.assert_async().await;
+    }
+
+    #[test]
+    fn rate_limit_retry_delay_prefers_retry_after_and_caps_it() {
+        let backoff = Duration::from_millis(750);
+        assert_eq!(
+            rate_limit_retry_delay(Some(2), backoff),
+            Duration::from_secs(2)
+        );
+        assert_eq!(
+            rate_limit_retry_delay(Some(3_600), backoff),
+            Duration::from_secs(MAX_RATE_LIMIT_RETRY_AFTER_SECS)
+        );
+        assert_eq!(rate_limit_retry_delay(None, backoff), backoff);
+    }
+
+    #[test]
+    fn retry_policy_backoff_stays_within_bounds() {
+        let doubling = RetryPolicy {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(350),
+            jitter: false,
+        };
+        let mut previous = None;
+        let delays = (0..4)
+            .map(|_| {
+                let delay = doubling.next_delay(previous);
+                previous = Some(delay);
+                delay.as_millis()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(delays, vec![100, 200, 350, 350]);
+
+        let jittered = RetryPolicy {
+            jitter: true,
+            ..doubling
+        };
+        let mut previous = None;
+        for _ in 0..20 {
+            let delay = jittered.next_delay(previous);
+            let upper = previous
+                .map(|previous: Duration| previous * 3)
+                .unwrap_or(jittered.base_delay)
+                .min(jittered.max_delay);
+            assert!(delay >= jittered.base_delay && delay <= upper);
+            previous = Some(delay);
+        }
+    }
+
+    #[test]
+    fn tool_retry_backoff_is_capped_for_large_retry_counts() {
+        assert_eq!(tool_retry_backoff_ms(1), INITIAL_TOOL_RETRY_BACKOFF_MS);
+        assert_eq!(tool_retry_backoff_ms(2), INITIAL_TOOL_RETRY_BACKOFF_MS * 2);
+        for retry in [7, 64, 65, u8::MAX] {
+            assert_eq!(tool_retry_backoff_ms(retry), MAX_TOOL_RETRY_BACKOFF_MS);
+        }
+    }
+
+    #[test]
+    fn jittered_backoff_grows_past_one_second() {
+        let policy = RetryPolicy {
+            max_attempts: 40,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            jitter: true,
+        };
+        let mut previous = None;
+        let mut longest = Duration::ZERO;
+        for _ in 0..40 {
+            let delay = policy.next_delay(previous);
+            assert!(delay >= policy.base_delay && delay <= policy.max_delay);
+            longest = longest.max(delay);
+            previous = Some(delay);
+        }
+        assert!(longest > Duration::from_secs(1), "longest delay {longest:?}");
+    }
+
     #[tokio::test]
     async fn retries_when_sse_stream_missing_completed_payload() {
         let mut server = Server::new_async().await;
@@ -3062,17 +6790,7 @@ mod tests {
             .create_async()
             .await;
 
-        let engine = FingerChatEngine::new(LocalModelConfig {
-            provider_id: "test".to_string(),
-            provider_name: "test".to_string(),
-            base_url: server.url(),
-            wire_api: WireApi::Responses,
-            env_key: "TEST_KEY".to_string(),
-            api_key: "test-key".to_string(),
-            model: "gpt-test".to_string(),
-            tool_daemon_url: server.url(),
-            tool_agent_id: "chat-codex".to_string(),
-        });
+        let engine = FingerChatEngine::new(test_config(server.url()));
 
         let output = engine
             .complete_text("hello")
@@ -3084,6 +6802,52 @@ mod tests {
         second_response_mock.assert_async().await;
     }
 
+    #[tokio::test]
+    async fn returns_timeout_error_when_stream_stalls_past_request_timeout() {
+        let mut server = Server::new_async().await;
+
+        let response_mock = server
+            .mock("POST", "/v1/responses")
+            .match_header("authorization", "Bearer test-key")
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_chunked_body(|writer| {
+                writer.write_all(
+                    concat!(
+                        "event: response.created\n",
+                        "data: {\"type\":\"response.created\",\"response\":{\"id\":\"resp_stall\"}}\n\n",
+                    )
+                    .as_bytes(),
+                )?;
+                writer.flush()?;
+                std::thread::sleep(std::time::Duration::from_millis(800));
+                Ok(())
+            })
+            .expect(1)
+            .create_async()
+            .await;
+
+        let engine = FingerChatEngine::with_client_options(
+            test_config(server.url()),
+            ClientOptions {
+                request_timeout: Duration::from_millis(200),
+                ..ClientOptions::default()
+            },
+        )
+        .expect("build engine");
+
+        let error = engine
+            .complete_text("hello")
+            .await
+            .expect_err("stalled stream should time out");
+        assert!(
+            matches!(error, ModelError::Timeout { .. }),
+            "unexpected error: {error}"
+        );
+
+        response_mock.assert_async().await;
+    }
+
     #[tokio::test]
     async fn retries_without_reasoning_items_when_provider_rejects_rs_references() {
         let mut server = Server::new_async().await;
@@ -3120,17 +6884,7 @@ mod tests {
             .create_async()
             .await;
 
-        let engine = FingerChatEngine::new(LocalModelConfig {
-            provider_id: "test".to_string(),
-            provider_name: "test".to_string(),
-            base_url: server.url(),
-            wire_api: WireApi::Responses,
-            env_key: "TEST_KEY".to_string(),
-            api_key: "test-key".to_string(),
-            model: "gpt-test".to_string(),
-            tool_daemon_url: server.url(),
-            tool_agent_id: "chat-codex".to_string(),
-        });
+        let engine = FingerChatEngine::new(test_config(server.url()));
 
         let result = engine
             .run_turn(
@@ -3224,17 +6978,7 @@ mod tests {
             .create_async()
             .await;
 
-        let engine = FingerChatEngine::new(LocalModelConfig {
-            provider_id: "test".to_string(),
-            provider_name: "test".to_string(),
-            base_url: server.url(),
-            wire_api: WireApi::Responses,
-            env_key: "TEST_KEY".to_string(),
-            api_key: "test-key".to_string(),
-            model: "gpt-test".to_string(),
-            tool_daemon_url: server.url(),
-            tool_agent_id: "chat-codex".to_string(),
-        });
+        let engine = FingerChatEngine::new(test_config(server.url()));
 
         let (progress_tx, mut progress_rx) = tokio::sync::mpsc::unbounded_channel::<EventMsg>();
         let result = engine
@@ -3252,10 +6996,19 @@ mod tests {
                                 "properties": { "cmd": { "type": "string" } },
                                 "required": ["cmd"],
                             })),
+                            approval: None,
                         }],
                         tool_execution: Some(ToolExecutionConfig {
                             daemon_url: server.url(),
                             agent_id: "chat-codex".to_string(),
+                            max_concurrency: None,
+                            tool_timeout_ms: None,
+                            auth_token: None,
+                            retry_transient_errors: false,
+                            tool_max_retries: None,
+                            tool_execute_path: None,
+                            cache_identical_calls: false,
+                            routes: Vec::new(),
                         }),
                         ..UserTurnOptions::default()
                     },
@@ -3267,19 +7020,20 @@ mod tests {
         assert_eq!(result.last_agent_message.as_deref(), Some("all done"));
 
         let progress_events = drain_progress_events(&mut progress_rx);
-        assert_eq!(progress_events.len(), 6);
+        assert_eq!(progress_events.len(), 7);
         assert!(matches!(progress_events[0], EventMsg::ModelRound(_)));
         assert!(matches!(progress_events[1], EventMsg::ToolCall(_)));
-        assert!(matches!(progress_events[2], EventMsg::ToolResult(_)));
-        assert!(matches!(progress_events[3], EventMsg::ToolCall(_)));
+        assert!(matches!(progress_events[2], EventMsg::ToolCall(_)));
+        assert!(matches!(progress_events[3], EventMsg::ToolResult(_)));
         assert!(matches!(progress_events[4], EventMsg::ToolResult(_)));
         assert!(matches!(progress_events[5], EventMsg::ModelRound(_)));
+        assert!(matches!(progress_events[6], EventMsg::Usage(_)));
 
         let seqs = progress_events
             .iter()
             .filter_map(extract_progress_seq)
             .collect::<Vec<_>>();
-        assert_eq!(seqs, vec![1, 2, 3, 4, 5, 6]);
+        assert_eq!(seqs, vec![1, 2, 3, 4, 5, 6, 7]);
 
         first_response_mock.assert_async().await;
         tool_execute_pwd_mock.assert_async().await;
@@ -3288,371 +7042,3741 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn run_turn_auto_compact_writes_task_digest_metadata_and_compact_memory() {
+    async fn in_process_tool_executor_runs_tools_without_the_daemon() {
         let mut server = Server::new_async().await;
 
-        let response_mock = server
+        let first_response_mock = server
+            .mock("POST", "/v1/responses")
+            .match_body(Matcher::Regex(r#""text":"add numbers""#.to_string()))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_1\",\"output\":[{\"type\":\"function_call\",\"call_id\":\"call_1\",\"name\":\"math_add\",\"arguments\":\"{\\\"a\\\":2,\\\"b\\\":3}\"}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+        let second_response_mock = server
+            .mock("POST", "/v1/responses")
+            .match_body(Matcher::Regex(
+                r#"\\"result\\":\{\\"sum\\":5\}"#.to_string(),
+            ))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_2\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"5\"}]}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        let executor = FnToolExecutor::new().register("math.add", |input: Value| {
+            let a = input["a"].as_i64().ok_or("missing a")?;
+            let b = input["b"].as_i64().ok_or("missing b")?;
+            Ok(json!({ "sum": a + b }))
+        });
+        let engine = FingerChatEngine::new(LocalModelConfig {
+            // Nothing listens here; a daemon call would fail the turn.
+            tool_daemon_url: "http://127.0.0.1:9".to_string(),
+            ..test_config(server.url())
+        })
+        .with_tool_executor(Arc::new(executor));
+
+        let result = engine
+            .run_turn(
+                &TurnRequest {
+                    items: vec![InputItem::Text {
+                        text: "add numbers".to_string(),
+                    }],
+                    options: UserTurnOptions {
+                        tools: vec![ToolSpec {
+                            name: "math.add".to_string(),
+                            description: Some("Add two integers".to_string()),
+                            input_schema: None,
+                            approval: None,
+                        }],
+                        ..UserTurnOptions::default()
+                    },
+                },
+                None,
+            )
+            .await
+            .expect("run turn");
+
+        assert_eq!(result.last_agent_message.as_deref(), Some("5"));
+        first_response_mock.assert_async().await;
+        second_response_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    #[tracing_test::traced_test]
+    async fn turn_emits_turn_round_and_tool_call_spans() {
+        let mut server = Server::new_async().await;
+        let _first_response_mock = server
+            .mock("POST", "/v1/responses")
+            .match_body(Matcher::Regex(r#""text":"add numbers""#.to_string()))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_1\",\"output\":[{\"type\":\"function_call\",\"call_id\":\"call_1\",\"name\":\"math_add\",\"arguments\":\"{}\"}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .create_async()
+            .await;
+        let _second_response_mock = server
+            .mock("POST", "/v1/responses")
+            .match_body(Matcher::Regex(r#""type":"function_call_output""#.to_string()))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_2\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"5\"}]}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .create_async()
+            .await;
+
+        let executor = FnToolExecutor::new().register("math.add", |_| Ok(json!({ "sum": 5 })));
+        let engine = fast_retry_engine(server.url(), 1).with_tool_executor(Arc::new(executor));
+        engine
+            .run_turn(
+                &TurnRequest {
+                    items: vec![InputItem::Text {
+                        text: "add numbers".to_string(),
+                    }],
+                    options: UserTurnOptions {
+                        session_id: Some("session-traced".to_string()),
+                        tools: vec![ToolSpec {
+                            name: "math.add".to_string(),
+                            description: None,
+                            input_schema: None,
+                            approval: None,
+                        }],
+                        ..UserTurnOptions::default()
+                    },
+                },
+                None,
+            )
+            .await
+            .expect("run turn");
+
+        assert!(logs_contain("turn{session_id=\"session-traced\""));
+        assert!(logs_contain("model_round{round=1}"));
+        assert!(logs_contain("model_round{round=2}"));
+        assert!(logs_contain("model round completed"));
+        assert!(logs_contain(
+            "tool_call{call_id=\"call_1\" tool=\"math.add\"}"
+        ));
+        assert!(logs_contain("tool call completed"));
+    }
+
+    #[tokio::test]
+    async fn stripped_api_history_omits_reasoning_the_turn_still_replayed() {
+        let mut server = Server::new_async().await;
+        let first_response_mock = server
+            .mock("POST", "/v1/responses")
+            .match_body(Matcher::Regex(r#""text":"add numbers""#.to_string()))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_1\",\"output\":[{\"type\":\"reasoning\",\"id\":\"rs_1\",\"summary\":[],\"encrypted_content\":\"ENCRYPTED_BLOB\"},{\"type\":\"function_call\",\"call_id\":\"call_1\",\"name\":\"math_add\",\"arguments\":\"{}\"}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+        let second_response_mock = server
+            .mock("POST", "/v1/responses")
+            .match_body(Matcher::AllOf(vec![
+                Matcher::Regex(r#""type":"function_call_output""#.to_string()),
+                Matcher::Regex("ENCRYPTED_BLOB".to_string()),
+            ]))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_2\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"5\"}]}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        let executor = FnToolExecutor::new().register("math.add", |_| Ok(json!({ "sum": 5 })));
+        let engine = fast_retry_engine(server.url(), 1).with_tool_executor(Arc::new(executor));
+        let result = engine
+            .run_turn(
+                &TurnRequest {
+                    items: vec![InputItem::Text {
+                        text: "add numbers".to_string(),
+                    }],
+                    options: UserTurnOptions {
+                        tools: vec![ToolSpec {
+                            name: "math.add".to_string(),
+                            description: None,
+                            input_schema: None,
+                            approval: None,
+                        }],
+                        strip_reasoning_from_history: true,
+                        ..UserTurnOptions::default()
+                    },
+                },
+                None,
+            )
+            .await
+            .expect("run turn");
+
+        assert_eq!(result.last_agent_message.as_deref(), Some("5"));
+        let metadata: Value =
+            serde_json::from_str(result.metadata_json.as_deref().expect("metadata json"))
+                .expect("parse metadata json");
+        assert_eq!(metadata["stripped_reasoning_items"], 1);
+        let api_history = metadata["api_history"].as_array().expect("api history");
+        assert!(api_history.iter().all(|item| item["type"] != "reasoning"));
+        assert!(api_history
+            .iter()
+            .any(|item| item["type"] == "function_call_output"));
+        assert!(!metadata["api_history"]
+            .to_string()
+            .contains("ENCRYPTED_BLOB"));
+        first_response_mock.assert_async().await;
+        second_response_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn stateless_mode_sends_no_reasoning_and_store_false_from_the_first_request() {
+        let mut server = Server::new_async().await;
+        let response_mock = server
+            .mock("POST", "/v1/responses")
+            .match_request(|request| {
+                let body: Value =
+                    serde_json::from_str(&request.utf8_lossy_body().unwrap_or_default())
+                        .unwrap_or_default();
+                body["store"] == false
+                    && body["include"] == json!([])
+                    && body["input"]
+                        .as_array()
+                        .is_some_and(|input| input.iter().all(|item| item["type"] != "reasoning"))
+            })
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_1\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"done\"}]}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        let engine = FingerChatEngine::new(test_config(server.url()));
+        let result = engine
+            .run_turn(
+                &TurnRequest {
+                    items: vec![InputItem::Text {
+                        text: "continue".to_string(),
+                    }],
+                    options: UserTurnOptions {
+                        history_items: vec![
+                            json!({ "role": "user", "content": [{ "type": "input_text", "text": "earlier" }] }),
+                            json!({ "type": "reasoning", "id": "rs_prev", "summary": [], "encrypted_content": "ENCRYPTED_BLOB" }),
+                            json!({ "role": "assistant", "content": [{ "type": "output_text", "text": "earlier answer" }] }),
+                        ],
+                        responses: Some(ResponsesRequestOptions {
+                            store: Some(true),
+                            include: vec!["reasoning.encrypted_content".to_string()],
+                            stateless: true,
+                            ..ResponsesRequestOptions::default()
+                        }),
+                        ..UserTurnOptions::default()
+                    },
+                },
+                None,
+            )
+            .await
+            .expect("run turn");
+
+        assert_eq!(result.last_agent_message.as_deref(), Some("done"));
+        response_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn image_tool_results_are_sent_back_as_input_images() {
+        let mut server = Server::new_async().await;
+
+        let first_response_mock = server
+            .mock("POST", "/v1/responses")
+            .match_body(Matcher::Regex(r#""text":"take a screenshot""#.to_string()))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_1\",\"output\":[{\"type\":\"function_call\",\"call_id\":\"call_1\",\"name\":\"screen_capture\",\"arguments\":\"{}\"}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+        let second_response_mock = server
+            .mock("POST", "/v1/responses")
+            .match_body(Matcher::AllOf(vec![
+                Matcher::Regex(
+                    r#""image_url":"data:image/png;base64,iVBORw0KGgo=","type":"input_image""#
+                        .to_string(),
+                ),
+                Matcher::Regex(r#"\\"result\\":\{\\"width\\":1\}"#.to_string()),
+            ]))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_2\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"a blank screen\"}]}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        let executor = FnToolExecutor::new().register("screen.capture", |_| {
+            Ok(json!({ "image_base64": "iVBORw0KGgo=", "width": 1 }))
+        });
+        let engine = FingerChatEngine::new(LocalModelConfig {
+            tool_daemon_url: "http://127.0.0.1:9".to_string(),
+            ..test_config(server.url())
+        })
+        .with_tool_executor(Arc::new(executor));
+
+        let result = engine
+            .run_turn(
+                &TurnRequest {
+                    items: vec![InputItem::Text {
+                        text: "take a screenshot".to_string(),
+                    }],
+                    options: UserTurnOptions {
+                        tools: vec![ToolSpec {
+                            name: "screen.capture".to_string(),
+                            description: Some("Capture the screen".to_string()),
+                            input_schema: None,
+                            approval: None,
+                        }],
+                        ..UserTurnOptions::default()
+                    },
+                },
+                None,
+            )
+            .await
+            .expect("run turn");
+
+        assert_eq!(result.last_agent_message.as_deref(), Some("a blank screen"));
+        first_response_mock.assert_async().await;
+        second_response_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn custom_endpoint_paths_join_cleanly_with_trailing_slash_base_urls() {
+        let mut server = Server::new_async().await;
+        let first_response_mock = server
+            .mock("POST", "/openai/responses")
+            .match_header("openai-beta", "responses=experimental")
+            .match_body(Matcher::Regex(r#""text":"where am i""#.to_string()))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_1\",\"output\":[{\"type\":\"function_call\",\"call_id\":\"call_1\",\"name\":\"shell_exec\",\"arguments\":\"{\\\"cmd\\\":\\\"pwd\\\"}\"}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+        let tool_execute_mock = server
+            .mock("POST", "/tools/run")
+            .match_body(Matcher::Regex(r#""toolName":"shell.exec""#.to_string()))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(json!({ "success": true, "result": { "stdout": "/tmp" } }).to_string())
+            .expect(1)
+            .create_async()
+            .await;
+        let second_response_mock = server
+            .mock("POST", "/openai/responses")
+            .match_body(Matcher::Regex("function_call_output".to_string()))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_2\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"/tmp\"}]}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        let base_url = format!("{}/", server.url());
+        let engine = FingerChatEngine::new(LocalModelConfig {
+            responses_path: Some("/openai/responses".to_string()),
+            ..test_config(base_url.clone())
+        });
+
+        let result = engine
+            .run_turn(
+                &TurnRequest {
+                    items: vec![InputItem::Text {
+                        text: "where am i".to_string(),
+                    }],
+                    options: UserTurnOptions {
+                        tools: vec![ToolSpec {
+                            name: "shell.exec".to_string(),
+                            description: Some("Run a shell command".to_string()),
+                            input_schema: None,
+                            approval: None,
+                        }],
+                        tool_execution: Some(ToolExecutionConfig {
+                            daemon_url: base_url,
+                            agent_id: "chat-codex".to_string(),
+                            max_concurrency: None,
+                            tool_timeout_ms: None,
+                            auth_token: None,
+                            retry_transient_errors: false,
+                            tool_max_retries: None,
+                            tool_execute_path: Some("tools/run".to_string()),
+                            cache_identical_calls: false,
+                            routes: Vec::new(),
+                        }),
+                        ..UserTurnOptions::default()
+                    },
+                },
+                None,
+            )
+            .await
+            .expect("run turn");
+
+        assert_eq!(result.last_agent_message.as_deref(), Some("/tmp"));
+        first_response_mock.assert_async().await;
+        tool_execute_mock.assert_async().await;
+        second_response_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn azure_deployment_uses_deployment_path_api_version_and_api_key_header() {
+        let mut server = Server::new_async().await;
+        let azure_mock = server
+            .mock("POST", "/openai/deployments/gpt4o-prod/responses")
+            .match_query(Matcher::UrlEncoded(
+                "api-version".to_string(),
+                "2025-04-01-preview".to_string(),
+            ))
+            .match_header("api-key", "test-key")
+            .match_header("authorization", Matcher::Missing)
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_azure\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"hi from azure\"}]}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        let engine = FingerChatEngine::new(LocalModelConfig {
+            provider_id: "azure".to_string(),
+            provider_name: "azure".to_string(),
+            env_key: "AZURE_OPENAI_KEY".to_string(),
+            model: "gpt-4o".to_string(),
+            tool_daemon_url: server.url(),
+            azure: Some(AzureDeployment {
+                deployment: "gpt4o-prod".to_string(),
+                api_version: "2025-04-01-preview".to_string(),
+            }),
+            ..test_config(format!("{}/", server.url()))
+        });
+
+        let result = engine
+            .run_turn(
+                &TurnRequest {
+                    items: vec![InputItem::Text {
+                        text: "hello".to_string(),
+                    }],
+                    options: UserTurnOptions::default(),
+                },
+                None,
+            )
+            .await
+            .expect("run turn");
+
+        assert_eq!(result.last_agent_message.as_deref(), Some("hi from azure"));
+        azure_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn requests_carry_user_agent_and_the_reported_request_id() {
+        let mut server = Server::new_async().await;
+        let sent_request_ids = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded_ids = Arc::clone(&sent_request_ids);
+        let response_mock = server
+            .mock("POST", "/v1/responses")
+            .match_header("user-agent", USER_AGENT)
+            .match_header(
+                REQUEST_ID_HEADER,
+                Matcher::Regex("^[0-9a-f]{8}-[0-9a-f]{4}-4[0-9a-f]{3}-".to_string()),
+            )
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body_from_request(move |request| {
+                let request_id = request.header(REQUEST_ID_HEADER)[0]
+                    .to_str()
+                    .expect("ascii request id")
+                    .to_string();
+                recorded_ids.lock().expect("ids lock").push(request_id);
+                concat!(
+                    "event: response.completed\n",
+                    "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_1\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"traced\"}]}]}}\n\n",
+                    "data: [DONE]\n\n"
+                )
+                .into()
+            })
+            .expect(1)
+            .create_async()
+            .await;
+
+        let (progress_tx, mut progress_rx) = tokio::sync::mpsc::unbounded_channel::<EventMsg>();
+        let result = fast_retry_engine(server.url(), 1)
+            .run_turn(
+                &TurnRequest {
+                    items: vec![InputItem::Text {
+                        text: "hello".to_string(),
+                    }],
+                    options: UserTurnOptions::default(),
+                },
+                Some(progress_tx),
+            )
+            .await
+            .expect("run turn");
+        response_mock.assert_async().await;
+
+        let sent_request_id = sent_request_ids.lock().expect("ids lock")[0].clone();
+        let metadata: Value =
+            serde_json::from_str(result.metadata_json.as_deref().expect("metadata json"))
+                .expect("metadata is json");
+        assert_eq!(
+            metadata["round_trace"][0]["request_id"].as_str(),
+            Some(sent_request_id.as_str())
+        );
+        let round_request_ids = drain_progress_events(&mut progress_rx)
+            .into_iter()
+            .filter_map(|event| match event {
+                EventMsg::ModelRound(round) => round.request_id,
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(round_request_ids, vec![sent_request_id]);
+    }
+
+    #[tokio::test]
+    async fn tool_daemon_requests_carry_user_agent_and_request_id() {
+        let mut server = Server::new_async().await;
+        let first_response_mock = server
+            .mock("POST", "/v1/responses")
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_1\",\"output\":[{\"type\":\"function_call\",\"call_id\":\"call_1\",\"name\":\"shell_exec\",\"arguments\":\"{\\\"cmd\\\":\\\"pwd\\\"}\"}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+        let tool_execute_mock = server
+            .mock("POST", "/api/v1/tools/execute")
+            .match_header("user-agent", USER_AGENT)
+            .match_header(
+                REQUEST_ID_HEADER,
+                Matcher::Regex("^[0-9a-f-]{36}$".to_string()),
+            )
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(json!({ "success": true, "result": { "stdout": "/tmp" } }).to_string())
+            .expect(1)
+            .create_async()
+            .await;
+        let second_response_mock = server
+            .mock("POST", "/v1/responses")
+            .match_body(Matcher::Regex(r#""type":"function_call_output""#.to_string()))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_2\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"/tmp\"}]}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        fast_retry_engine(server.url(), 1)
+            .run_turn(
+                &TurnRequest {
+                    items: vec![InputItem::Text {
+                        text: "where am i".to_string(),
+                    }],
+                    options: UserTurnOptions {
+                        tools: vec![ToolSpec {
+                            name: "shell.exec".to_string(),
+                            description: None,
+                            input_schema: None,
+                            approval: None,
+                        }],
+                        ..UserTurnOptions::default()
+                    },
+                },
+                None,
+            )
+            .await
+            .expect("run turn");
+
+        first_response_mock.assert_async().await;
+        tool_execute_mock.assert_async().await;
+        second_response_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn identical_tool_calls_hit_the_daemon_once_when_caching() {
+        let mut server = Server::new_async().await;
+        let first_response_mock = server
+            .mock("POST", "/v1/responses")
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_1\",\"output\":[{\"type\":\"function_call\",\"call_id\":\"call_1\",\"name\":\"shell_exec\",\"arguments\":\"{\\\"cmd\\\":\\\"ls\\\",\\\"cwd\\\":\\\"/tmp\\\"}\"},{\"type\":\"function_call\",\"call_id\":\"call_2\",\"name\":\"shell_exec\",\"arguments\":\"{\\\"cwd\\\":\\\"/tmp\\\",\\\"cmd\\\":\\\"ls\\\"}\"}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+        let tool_execute_mock = server
+            .mock("POST", "/api/v1/tools/execute")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(json!({ "success": true, "result": { "stdout": "a.txt" } }).to_string())
+            .expect(1)
+            .create_async()
+            .await;
+        let second_response_mock = server
+            .mock("POST", "/v1/responses")
+            .match_body(Matcher::Regex(r#""call_id":"call_2""#.to_string()))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_2\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"a.txt\"}]}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        let result = fast_retry_engine(server.url(), 1)
+            .run_turn(
+                &TurnRequest {
+                    items: vec![InputItem::Text {
+                        text: "list twice".to_string(),
+                    }],
+                    options: UserTurnOptions {
+                        tools: vec![ToolSpec {
+                            name: "shell.exec".to_string(),
+                            description: None,
+                            input_schema: None,
+                            approval: None,
+                        }],
+                        tool_execution: Some(ToolExecutionConfig {
+                            daemon_url: server.url(),
+                            agent_id: "chat-codex".to_string(),
+                            max_concurrency: None,
+                            tool_timeout_ms: None,
+                            auth_token: None,
+                            retry_transient_errors: false,
+                            tool_max_retries: None,
+                            tool_execute_path: None,
+                            cache_identical_calls: true,
+                            routes: Vec::new(),
+                        }),
+                        ..UserTurnOptions::default()
+                    },
+                },
+                None,
+            )
+            .await
+            .expect("run turn");
+
+        first_response_mock.assert_async().await;
+        tool_execute_mock.assert_async().await;
+        second_response_mock.assert_async().await;
+        let metadata: Value =
+            serde_json::from_str(result.metadata_json.as_deref().expect("metadata json"))
+                .expect("metadata is json");
+        let cached = metadata["tool_trace"]
+            .as_array()
+            .expect("tool trace")
+            .iter()
+            .map(|trace| (trace["call_id"].clone(), trace["cached"].clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            cached,
+            vec![
+                (json!("call_1"), Value::Null),
+                (json!("call_2"), json!(true)),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn tool_loop_limit_returns_partial_metadata_without_a_message() {
+        let mut server = Server::new_async().await;
+        let looping_mock = server
+            .mock("POST", "/v1/responses")
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_loop\",\"output\":[{\"type\":\"reasoning\",\"summary\":[{\"type\":\"summary_text\",\"text\":\"try again\"}]},{\"type\":\"function_call\",\"call_id\":\"call_1\",\"name\":\"math_add\",\"arguments\":\"{\\\"a\\\":1,\\\"b\\\":2}\"}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(3)
+            .create_async()
+            .await;
+
+        let executor = FnToolExecutor::new().register("math.add", |_| Ok(json!({ "sum": 3 })));
+        let result = fast_retry_engine(server.url(), 1)
+            .with_tool_executor(Arc::new(executor))
+            .run_turn(
+                &TurnRequest {
+                    items: vec![InputItem::Text {
+                        text: "add forever".to_string(),
+                    }],
+                    options: UserTurnOptions {
+                        tools: vec![ToolSpec {
+                            name: "math.add".to_string(),
+                            description: None,
+                            input_schema: None,
+                            approval: None,
+                        }],
+                        max_tool_rounds: Some(3),
+                        ..UserTurnOptions::default()
+                    },
+                },
+                None,
+            )
+            .await
+            .expect("loop limit is not an error");
+        looping_mock.assert_async().await;
+
+        assert_eq!(result.last_agent_message, None);
+        assert_eq!(result.finish_reason.as_deref(), Some("tool_loop_exceeded"));
+        assert_eq!(result.metrics.rounds, 3);
+        let metadata: Value =
+            serde_json::from_str(result.metadata_json.as_deref().expect("metadata json"))
+                .expect("metadata is json");
+        assert_eq!(metadata["finish_reason"], "tool_loop_exceeded");
+        assert_eq!(metadata["round_trace"].as_array().map(Vec::len), Some(3));
+        assert_eq!(metadata["tool_trace"].as_array().map(Vec::len), Some(3));
+        assert_eq!(
+            metadata["reasoning_trace"].as_array().map(Vec::len),
+            Some(3)
+        );
+    }
+
+    #[tokio::test]
+    async fn run_turn_reports_metrics_for_two_round_tool_loop() {
+        let mut server = Server::new_async().await;
+        let first_response_mock = server
+            .mock("POST", "/v1/responses")
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_1\",\"output\":[{\"type\":\"function_call\",\"call_id\":\"call_1\",\"name\":\"math_add\",\"arguments\":\"{\\\"a\\\":1,\\\"b\\\":2}\"},{\"type\":\"function_call\",\"call_id\":\"call_2\",\"name\":\"math_add\",\"arguments\":\"{\\\"a\\\":1}\"}],\"usage\":{\"input_tokens\":100,\"output_tokens\":20,\"total_tokens\":120}}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+        let second_response_mock = server
+            .mock("POST", "/v1/responses")
+            .match_body(Matcher::Regex(r#""type":"function_call_output""#.to_string()))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_2\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"3\"}]}],\"usage\":{\"input_tokens\":150,\"output_tokens\":5,\"total_tokens\":155}}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        let executor = FnToolExecutor::new().register("math.add", |input: Value| {
+            let a = input["a"].as_i64().ok_or("missing a")?;
+            let b = input["b"].as_i64().ok_or("missing b")?;
+            Ok(json!({ "sum": a + b }))
+        });
+        let result = fast_retry_engine(server.url(), 1)
+            .with_tool_executor(Arc::new(executor))
+            .run_turn(
+                &TurnRequest {
+                    items: vec![InputItem::Text {
+                        text: "add numbers".to_string(),
+                    }],
+                    options: UserTurnOptions {
+                        tools: vec![ToolSpec {
+                            name: "math.add".to_string(),
+                            description: None,
+                            input_schema: None,
+                            approval: None,
+                        }],
+                        ..UserTurnOptions::default()
+                    },
+                },
+                None,
+            )
+            .await
+            .expect("run turn");
+
+        let metrics = result.metrics;
+        assert_eq!(metrics.rounds, 2);
+        assert_eq!(metrics.tool_calls, 2);
+        assert_eq!(metrics.tool_errors, 1);
+        assert_eq!(metrics.input_tokens, 250);
+        assert_eq!(metrics.output_tokens, 25);
+        assert!(
+            metrics.model_duration_ms + metrics.tool_duration_ms <= metrics.total_duration_ms,
+            "durations do not add up: {metrics:?}"
+        );
+        first_response_mock.assert_async().await;
+        second_response_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn run_turn_emits_usage_summed_across_tool_loop_rounds() {
+        let mut server = Server::new_async().await;
+
+        let first_response_mock = server
+            .mock("POST", "/v1/responses")
+            .match_body(Matcher::Regex(r#""text":"run pwd""#.to_string()))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_1\",\"output\":[{\"type\":\"function_call\",\"call_id\":\"call_1\",\"name\":\"shell_exec\",\"arguments\":\"{\\\"cmd\\\":\\\"pwd\\\"}\"}],\"usage\":{\"input_tokens\":100,\"output_tokens\":20,\"total_tokens\":120,\"output_tokens_details\":{\"reasoning_tokens\":8}}}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        let tool_execute_mock = server
+            .mock("POST", "/api/v1/tools/execute")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(json!({ "success": true, "result": { "stdout": "/tmp" } }).to_string())
+            .expect(1)
+            .create_async()
+            .await;
+
+        let second_response_mock = server
+            .mock("POST", "/v1/responses")
+            .match_body(Matcher::Regex(r#""type":"function_call_output""#.to_string()))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_2\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"/tmp\"}]}],\"usage\":{\"input_tokens\":150,\"output_tokens\":5,\"total_tokens\":155,\"output_tokens_details\":{\"reasoning_tokens\":2}}}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        let engine = FingerChatEngine::new(test_config(server.url()));
+
+        let (progress_tx, mut progress_rx) = tokio::sync::mpsc::unbounded_channel::<EventMsg>();
+        engine
+            .run_turn(
+                &TurnRequest {
+                    items: vec![InputItem::Text {
+                        text: "run pwd".to_string(),
+                    }],
+                    options: UserTurnOptions {
+                        tools: vec![ToolSpec {
+                            name: "shell.exec".to_string(),
+                            description: Some("Execute shell command".to_string()),
+                            input_schema: Some(json!({
+                                "type": "object",
+                                "properties": { "cmd": { "type": "string" } },
+                                "required": ["cmd"],
+                            })),
+                            approval: None,
+                        }],
+                        tool_execution: Some(ToolExecutionConfig {
+                            daemon_url: server.url(),
+                            agent_id: "chat-codex".to_string(),
+                            max_concurrency: None,
+                            tool_timeout_ms: None,
+                            auth_token: None,
+                            retry_transient_errors: false,
+                            tool_max_retries: None,
+                            tool_execute_path: None,
+                            cache_identical_calls: false,
+                            routes: Vec::new(),
+                        }),
+                        ..UserTurnOptions::default()
+                    },
+                },
+                Some(progress_tx),
+            )
+            .await
+            .expect("run turn");
+
+        let progress_events = drain_progress_events(&mut progress_rx);
+        let usage_events = progress_events
+            .iter()
+            .filter_map(|event| match event {
+                EventMsg::Usage(usage) => Some(usage),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(usage_events.len(), 1);
+        assert!(matches!(progress_events.last(), Some(EventMsg::Usage(_))));
+        let usage = usage_events[0];
+        assert_eq!(usage.input_tokens, 250);
+        assert_eq!(usage.output_tokens, 25);
+        assert_eq!(usage.total_tokens, 275);
+        assert_eq!(usage.reasoning_tokens, Some(10));
+        assert_eq!(usage.rounds, 2);
+
+        first_response_mock.assert_async().await;
+        tool_execute_mock.assert_async().await;
+        second_response_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn run_turn_streams_output_text_deltas_before_model_round() {
+        let mut server = Server::new_async().await;
+
+        let response_mock = server
+            .mock("POST", "/v1/responses")
+            .match_header("authorization", "Bearer test-key")
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_chunked_body(|writer| {
+                writer.write_all(concat!(
+                    "event: response.output_text.delta\n",
+                    "data: {\"type\":\"response.output_text.delta\",\"delta\":\"Hel\"}\n\n",
+                    "event: response.output_text.delta\n",
+                    "data: {\"type\":\"response.output_",
+                ).as_bytes())?;
+                writer.flush()?;
+                std::thread::sleep(std::time::Duration::from_millis(20));
+                writer.write_all(concat!(
+                    "text.delta\",\"delta\":\"lo\"}\n\n",
+                    "event: response.completed\n",
+                    "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_stream\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"Hello world\"}]}]}}\n\n",
+                    "data: [DONE]\n\n"
+                ).as_bytes())
+            })
+            .expect(1)
+            .create_async()
+            .await;
+
+        let engine = FingerChatEngine::new(test_config(server.url()));
+
+        let (progress_tx, mut progress_rx) = tokio::sync::mpsc::unbounded_channel::<EventMsg>();
+        let result = engine
+            .run_turn(
+                &TurnRequest {
+                    items: vec![InputItem::Text {
+                        text: "say hello".to_string(),
+                    }],
+                    options: UserTurnOptions::default(),
+                },
+                Some(progress_tx),
+            )
+            .await
+            .expect("run turn");
+        assert_eq!(result.last_agent_message.as_deref(), Some("Hello world"));
+
+        let progress_events = drain_progress_events(&mut progress_rx);
+        let deltas = progress_events
+            .iter()
+            .filter_map(|event| match event {
+                EventMsg::OutputTextDelta(delta) => Some(delta.delta.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(deltas, vec!["Hel", "lo", " world"]);
+        assert_eq!(progress_events.len(), 5);
+        assert!(matches!(progress_events[3], EventMsg::ModelRound(_)));
+        assert!(matches!(progress_events[4], EventMsg::Usage(_)));
+
+        let seqs = progress_events
+            .iter()
+            .filter_map(extract_progress_seq)
+            .collect::<Vec<_>>();
+        assert_eq!(seqs, vec![1, 2, 3, 4, 5]);
+
+        response_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn run_turn_streams_reasoning_deltas_before_output_and_model_round() {
+        let mut server = Server::new_async().await;
+
+        let response_mock = server
+            .mock("POST", "/v1/responses")
+            .match_header("authorization", "Bearer test-key")
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_chunked_body(|writer| {
+                writer.write_all(concat!(
+                    "event: response.reasoning_summary_text.delta\n",
+                    "data: {\"type\":\"response.reasoning_summary_text.delta\",\"delta\":\"Checking \"}\n\n",
+                ).as_bytes())?;
+                writer.flush()?;
+                std::thread::sleep(std::time::Duration::from_millis(20));
+                writer.write_all(concat!(
+                    "event: response.reasoning_summary_text.delta\n",
+                    "data: {\"type\":\"response.reasoning_summary_text.delta\",\"delta\":\"the input\"}\n\n",
+                    "event: response.output_text.delta\n",
+                    "data: {\"type\":\"response.output_text.delta\",\"delta\":\"Done\"}\n\n",
+                    "event: response.completed\n",
+                    "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_reasoning\",\"output\":[{\"type\":\"reasoning\",\"summary\":[{\"type\":\"summary_text\",\"text\":\"Checking the input\"}]},{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"Done\"}]}]}}\n\n",
+                    "data: [DONE]\n\n"
+                ).as_bytes())
+            })
+            .expect(1)
+            .create_async()
+            .await;
+
+        let engine = FingerChatEngine::new(test_config(server.url()));
+
+        let (progress_tx, mut progress_rx) = tokio::sync::mpsc::unbounded_channel::<EventMsg>();
+        let result = engine
+            .run_turn(
+                &TurnRequest {
+                    items: vec![InputItem::Text {
+                        text: "think first".to_string(),
+                    }],
+                    options: UserTurnOptions::default(),
+                },
+                Some(progress_tx),
+            )
+            .await
+            .expect("run turn");
+        assert_eq!(result.last_agent_message.as_deref(), Some("Done"));
+        assert_eq!(
+            result.metadata_json.as_deref().map(|raw| {
+                serde_json::from_str::<Value>(raw).expect("metadata json")["reasoning_trace"]
+                    .clone()
+            }),
+            Some(json!(["Checking the input"]))
+        );
+
+        let progress_events = drain_progress_events(&mut progress_rx);
+        assert_eq!(progress_events.len(), 5);
+        assert!(matches!(
+            &progress_events[0],
+            EventMsg::Reasoning(event) if event.text == "Checking "
+        ));
+        assert!(matches!(
+            &progress_events[1],
+            EventMsg::Reasoning(event) if event.text == "the input"
+        ));
+        assert!(matches!(
+            &progress_events[2],
+            EventMsg::OutputTextDelta(event) if event.delta == "Done"
+        ));
+        assert!(matches!(progress_events[3], EventMsg::ModelRound(_)));
+        assert!(matches!(progress_events[4], EventMsg::Usage(_)));
+        let seqs = progress_events
+            .iter()
+            .filter_map(extract_progress_seq)
+            .collect::<Vec<_>>();
+        assert_eq!(seqs, vec![1, 2, 3, 4, 5]);
+
+        response_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn run_turn_executes_tool_calls_concurrently_and_preserves_order() {
+        let mut server = Server::new_async().await;
+
+        let first_response_mock = server
+            .mock("POST", "/v1/responses")
+            .match_header("authorization", "Bearer test-key")
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_1\",\"output\":[",
+                "{\"type\":\"function_call\",\"call_id\":\"call_1\",\"name\":\"shell_exec\",\"arguments\":\"{\\\"cmd\\\":\\\"sleep-a\\\"}\"},",
+                "{\"type\":\"function_call\",\"call_id\":\"call_2\",\"name\":\"shell_exec\",\"arguments\":\"{\\\"cmd\\\":\\\"sleep-b\\\"}\"},",
+                "{\"type\":\"function_call\",\"call_id\":\"call_3\",\"name\":\"shell_exec\",\"arguments\":\"{\\\"cmd\\\":\\\"sleep-c\\\"}\"}",
+                "]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        let tool_execute_mock = server
+            .mock("POST", "/api/v1/tools/execute")
+            .match_body(Matcher::Regex(r#""toolName":"shell.exec""#.to_string()))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_chunked_body(|writer| {
+                std::thread::sleep(std::time::Duration::from_millis(400));
+                writer.write_all(br#"{"success":true,"result":{"stdout":"done"}}"#)
+            })
+            .expect(3)
+            .create_async()
+            .await;
+
+        let second_response_mock = server
+            .mock("POST", "/v1/responses")
+            .match_header("authorization", "Bearer test-key")
+            .match_body(Matcher::Regex(r#""type":"function_call_output""#.to_string()))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_2\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"all done\"}]}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        let engine = FingerChatEngine::new(test_config(server.url()));
+
+        let (progress_tx, mut progress_rx) = tokio::sync::mpsc::unbounded_channel::<EventMsg>();
+        let started_at = Instant::now();
+        let result = engine
+            .run_turn(
+                &TurnRequest {
+                    items: vec![InputItem::Text {
+                        text: "run three slow tools".to_string(),
+                    }],
+                    options: UserTurnOptions {
+                        tools: vec![ToolSpec {
+                            name: "shell.exec".to_string(),
+                            description: Some("Execute shell command".to_string()),
+                            input_schema: None,
+                            approval: None,
+                        }],
+                        tool_execution: Some(ToolExecutionConfig {
+                            daemon_url: server.url(),
+                            agent_id: "chat-codex".to_string(),
+                            max_concurrency: Some(3),
+                            tool_timeout_ms: None,
+                            auth_token: None,
+                            retry_transient_errors: false,
+                            tool_max_retries: None,
+                            tool_execute_path: None,
+                            cache_identical_calls: false,
+                            routes: Vec::new(),
+                        }),
+                        ..UserTurnOptions::default()
+                    },
+                },
+                Some(progress_tx),
+            )
+            .await
+            .expect("run turn");
+        let elapsed = started_at.elapsed();
+        assert_eq!(result.last_agent_message.as_deref(), Some("all done"));
+        assert!(
+            elapsed < Duration::from_millis(1_000),
+            "three 400ms tools should overlap, took {elapsed:?}"
+        );
+
+        let progress_events = drain_progress_events(&mut progress_rx);
+        let seqs = progress_events
+            .iter()
+            .filter_map(extract_progress_seq)
+            .collect::<Vec<_>>();
+        assert_eq!(seqs, (1..=9).collect::<Vec<u64>>());
+
+        let metadata: Value = serde_json::from_str(
+            result
+                .metadata_json
+                .as_deref()
+                .expect("metadata json should exist"),
+        )
+        .expect("metadata should be valid json");
+        let traced_call_ids = metadata["tool_trace"]
+            .as_array()
+            .expect("tool trace")
+            .iter()
+            .filter_map(|trace| trace["call_id"].as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(traced_call_ids, vec!["call_1", "call_2", "call_3"]);
+        let output_call_ids = metadata["api_history"]
+            .as_array()
+            .expect("api history")
+            .iter()
+            .filter(|item| item["type"] == "function_call_output")
+            .filter_map(|item| item["call_id"].as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(output_call_ids, vec!["call_1", "call_2", "call_3"]);
+
+        first_response_mock.assert_async().await;
+        tool_execute_mock.assert_async().await;
+        second_response_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn run_turn_runs_tool_loop_over_chat_completions_wire() {
+        let mut server = Server::new_async().await;
+
+        let first_response_mock = server
+            .mock("POST", "/v1/chat/completions")
+            .match_header("authorization", "Bearer test-key")
+            .match_body(Matcher::Regex(r#""function":\{"description""#.to_string()))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "data: {\"id\":\"chatcmpl-1\",\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"tool_calls\":[{\"index\":0,\"id\":\"call_1\",\"type\":\"function\",\"function\":{\"name\":\"shell_exec\",\"arguments\":\"{\\\"cmd\\\":\"}}]}}]}\n\n",
+                "data: {\"id\":\"chatcmpl-1\",\"choices\":[{\"index\":0,\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"\\\"pwd\\\"}\"}}]},\"finish_reason\":\"tool_calls\"}]}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        let tool_execute_mock = server
+            .mock("POST", "/api/v1/tools/execute")
+            .match_body(Matcher::Regex(r#""toolName":"shell.exec""#.to_string()))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"success":true,"result":{"stdout":"/tmp"}}"#)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let second_response_mock = server
+            .mock("POST", "/v1/chat/completions")
+            .match_header("authorization", "Bearer test-key")
+            .match_body(Matcher::Regex(
+                r#""role":"tool","tool_call_id":"call_1""#.to_string(),
+            ))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "data: {\"id\":\"chatcmpl-2\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"in \"}}]}\n\n",
+                "data: {\"id\":\"chatcmpl-2\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"/tmp\"},\"finish_reason\":\"stop\"}]}\n\n",
+                "data: {\"id\":\"chatcmpl-2\",\"choices\":[],\"usage\":{\"prompt_tokens\":30,\"completion_tokens\":2,\"total_tokens\":32}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        let engine = FingerChatEngine::new(LocalModelConfig {
+            wire_api: WireApi::OpenAIChat,
+            model: "llama-local".to_string(),
+            ..test_config(server.url())
+        });
+
+        let (progress_tx, mut progress_rx) = tokio::sync::mpsc::unbounded_channel::<EventMsg>();
+        let result = engine
+            .run_turn(
+                &TurnRequest {
+                    items: vec![InputItem::Text {
+                        text: "where am i".to_string(),
+                    }],
+                    options: UserTurnOptions {
+                        tools: vec![ToolSpec {
+                            name: "shell.exec".to_string(),
+                            description: Some("Execute shell command".to_string()),
+                            input_schema: None,
+                            approval: None,
+                        }],
+                        ..UserTurnOptions::default()
+                    },
+                },
+                Some(progress_tx),
+            )
+            .await
+            .expect("run turn");
+        assert_eq!(result.last_agent_message.as_deref(), Some("in /tmp"));
+
+        let progress_events = drain_progress_events(&mut progress_rx);
+        let kinds = progress_events
+            .iter()
+            .map(|event| match event {
+                EventMsg::ModelRound(_) => "model_round",
+                EventMsg::ToolCall(_) => "tool_call",
+                EventMsg::ToolResult(_) => "tool_result",
+                EventMsg::OutputTextDelta(_) => "output_text_delta",
+                EventMsg::Usage(_) => "usage",
+                _ => "other",
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                "model_round",
+                "tool_call",
+                "tool_result",
+                "output_text_delta",
+                "output_text_delta",
+                "model_round",
+                "usage",
+            ]
+        );
+        let EventMsg::ModelRound(final_round) = &progress_events[5] else {
+            panic!("expected final model round");
+        };
+        assert_eq!(final_round.total_tokens, Some(32));
+        assert_eq!(final_round.finish_reason.as_deref(), Some("stop"));
+
+        first_response_mock.assert_async().await;
+        tool_execute_mock.assert_async().await;
+        second_response_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn run_turn_runs_tool_loop_over_anthropic_messages_wire() {
+        let mut server = Server::new_async().await;
+
+        let first_response_mock = server
+            .mock("POST", "/v1/messages")
+            .match_header("x-api-key", "test-key")
+            .match_header("anthropic-version", "2023-06-01")
+            .match_header("authorization", Matcher::Missing)
+            .match_body(Matcher::Regex(r#""input_schema""#.to_string()))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: message_start\n",
+                "data: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_1\",\"content\":[],\"usage\":{\"input_tokens\":20,\"output_tokens\":1}}}\n\n",
+                "event: content_block_start\n",
+                "data: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"tool_use\",\"id\":\"toolu_1\",\"name\":\"shell_exec\",\"input\":{}}}\n\n",
+                "event: content_block_delta\n",
+                "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"input_json_delta\",\"partial_json\":\"{\\\"cmd\\\":\\\"pwd\\\"}\"}}\n\n",
+                "event: message_delta\n",
+                "data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"tool_use\"},\"usage\":{\"output_tokens\":12}}\n\n",
+                "event: message_stop\n",
+                "data: {\"type\":\"message_stop\"}\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        let tool_execute_mock = server
+            .mock("POST", "/api/v1/tools/execute")
+            .match_body(Matcher::Regex(r#""toolName":"shell.exec""#.to_string()))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"success":true,"result":{"stdout":"/tmp"}}"#)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let second_response_mock = server
+            .mock("POST", "/v1/messages")
+            .match_body(Matcher::Regex(r#""tool_use_id":"toolu_1""#.to_string()))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: message_start\n",
+                "data: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_2\",\"content\":[],\"usage\":{\"input_tokens\":40,\"output_tokens\":1}}}\n\n",
+                "event: content_block_start\n",
+                "data: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n\n",
+                "event: content_block_delta\n",
+                "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"in /tmp\"}}\n\n",
+                "event: message_delta\n",
+                "data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"end_turn\"},\"usage\":{\"output_tokens\":3}}\n\n",
+                "event: message_stop\n",
+                "data: {\"type\":\"message_stop\"}\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        let engine = FingerChatEngine::new(LocalModelConfig {
+            wire_api: WireApi::Anthropic,
+            model: "claude-test".to_string(),
+            ..test_config(server.url())
+        });
+
+        let (progress_tx, mut progress_rx) = tokio::sync::mpsc::unbounded_channel::<EventMsg>();
+        let result = engine
+            .run_turn(
+                &TurnRequest {
+                    items: vec![InputItem::Text {
+                        text: "where am i".to_string(),
+                    }],
+                    options: UserTurnOptions {
+                        tools: vec![ToolSpec {
+                            name: "shell.exec".to_string(),
+                            description: Some("Execute shell command".to_string()),
+                            input_schema: None,
+                            approval: None,
+                        }],
+                        ..UserTurnOptions::default()
+                    },
+                },
+                Some(progress_tx),
+            )
+            .await
+            .expect("run turn");
+        assert_eq!(result.last_agent_message.as_deref(), Some("in /tmp"));
+
+        let progress_events = drain_progress_events(&mut progress_rx);
+        assert!(progress_events.iter().any(|event| matches!(
+            event,
+            EventMsg::OutputTextDelta(delta) if delta.delta == "in /tmp"
+        )));
+        let final_round = progress_events
+            .iter()
+            .rev()
+            .find_map(|event| match event {
+                EventMsg::ModelRound(round) => Some(round),
+                _ => None,
+            })
+            .expect("final model round");
+        assert_eq!(final_round.total_tokens, Some(43));
+
+        first_response_mock.assert_async().await;
+        tool_execute_mock.assert_async().await;
+        second_response_mock.assert_async().await;
+    }
+
+    fn structured_output_options() -> UserTurnOptions {
+        UserTurnOptions {
+            responses: Some(ResponsesRequestOptions {
+                text: Some(ResponsesTextOptions {
+                    enabled: Some(true),
+                    verbosity: None,
+                    output_schema: Some(json!({
+                        "type": "object",
+                        "properties": { "label": { "type": "string" } },
+                        "required": ["label"],
+                    })),
+                    max_schema_retries: None,
+                }),
+                ..ResponsesRequestOptions::default()
+            }),
+            ..UserTurnOptions::default()
+        }
+    }
+
+    fn structured_output_engine(server: &mockito::ServerGuard) -> FingerChatEngine {
+        FingerChatEngine::new(test_config(server.url()))
+    }
+
+    #[tokio::test]
+    async fn structured_output_matching_schema_is_returned() {
+        let mut server = Server::new_async().await;
+        let response_mock = server
+            .mock("POST", "/v1/responses")
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_schema_ok\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"{\\\"label\\\":\\\"bug\\\"}\"}]}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        let completion = structured_output_engine(&server)
+            .complete_with_options(
+                &[InputItem::Text {
+                    text: "classify".to_string(),
+                }],
+                &structured_output_options(),
+                None,
+                None,
+            )
+            .await
+            .expect("valid structured output");
+        assert_eq!(
+            completion.output_text.as_deref(),
+            Some(r#"{"label":"bug"}"#)
+        );
+
+        response_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn structured_output_mismatch_reasks_once_then_fails() {
+        let mut server = Server::new_async().await;
+        let first_response_mock = server
+            .mock("POST", "/v1/responses")
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_schema_bad\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"{\\\"label\\\":5}\"}]}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+        let reask_response_mock = server
+            .mock("POST", "/v1/responses")
+            .match_body(Matcher::Regex(
+                "did not match the required JSON schema".to_string(),
+            ))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_schema_prose\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"It is a bug.\"}]}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        let error = match structured_output_engine(&server)
+            .complete_with_options(
+                &[InputItem::Text {
+                    text: "classify".to_string(),
+                }],
+                &structured_output_options(),
+                None,
+                None,
+            )
+            .await
+        {
+            Ok(completion) => panic!("expected schema error, got {:?}", completion.output_text),
+            Err(error) => error,
+        };
+        let ModelError::SchemaValidation { errors } = error else {
+            panic!("expected schema validation error, got {error}");
+        };
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("output is not valid JSON"));
+
+        first_response_mock.assert_async().await;
+        reask_response_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn structured_output_retry_succeeds_after_invalid_json() {
+        let mut server = Server::new_async().await;
+        let invalid_response_mock = server
+            .mock("POST", "/v1/responses")
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_schema_invalid\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"label: bug\"}]}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+        let retry_response_mock = server
+            .mock("POST", "/v1/responses")
+            .match_body(Matcher::Regex(
+                "did not match the required JSON schema".to_string(),
+            ))
+            .match_body(Matcher::Regex(r#""role":"developer""#.to_string()))
+            .match_body(Matcher::Regex(r#""text":"label: bug""#.to_string()))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_schema_fixed\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"{\\\"label\\\":\\\"bug\\\"}\"}]}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        let mut options = structured_output_options();
+        if let Some(text_opts) = options
+            .responses
+            .as_mut()
+            .and_then(|responses| responses.text.as_mut())
+        {
+            text_opts.max_schema_retries = Some(3);
+        }
+        let completion = structured_output_engine(&server)
+            .complete_with_options(
+                &[InputItem::Text {
+                    text: "classify".to_string(),
+                }],
+                &options,
+                None,
+                None,
+            )
+            .await
+            .expect("structured output after one retry");
+        assert_eq!(
+            completion.output_text.as_deref(),
+            Some(r#"{"label":"bug"}"#)
+        );
+
+        invalid_response_mock.assert_async().await;
+        retry_response_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn structured_output_reask_does_not_spend_tool_rounds() {
+        let mut server = Server::new_async().await;
+        let response_mock = server
+            .mock("POST", "/v1/responses")
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_schema_bad\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"{\\\"label\\\":5}\"}]}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(2)
+            .create_async()
+            .await;
+
+        let options = UserTurnOptions {
+            max_tool_rounds: Some(1),
+            ..structured_output_options()
+        };
+        let error = match structured_output_engine(&server)
+            .complete_with_options(
+                &[InputItem::Text {
+                    text: "classify".to_string(),
+                }],
+                &options,
+                None,
+                None,
+            )
+            .await
+        {
+            Ok(completion) => panic!("expected schema error, got {:?}", completion.output_text),
+            Err(error) => error,
+        };
+        assert!(matches!(error, ModelError::SchemaValidation { .. }));
+
+        response_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn refusal_only_message_surfaces_refused_error() {
+        let mut server = Server::new_async().await;
+        let response_mock = server
+            .mock("POST", "/v1/responses")
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_refusal\",\"output\":[{\"type\":\"message\",\"role\":\"assistant\",\"content\":[{\"type\":\"refusal\",\"refusal\":\"I can't help with that.\"}]}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        let error = match FingerChatEngine::new(test_config(server.url()))
+            .complete_with_options(
+                &[InputItem::Text {
+                    text: "do something unsafe".to_string(),
+                }],
+                &UserTurnOptions::default(),
+                None,
+                None,
+            )
+            .await
+        {
+            Ok(completion) => panic!("expected refusal, got {:?}", completion.output_text),
+            Err(error) => error,
+        };
+        let ModelError::Refused { message } = error else {
+            panic!("expected refused error, got {error}");
+        };
+        assert_eq!(message, "I can't help with that.");
+
+        response_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn run_turn_auto_compact_writes_task_digest_metadata_and_compact_memory() {
+        let mut server = Server::new_async().await;
+
+        let response_mock = server
+            .mock("POST", "/v1/responses")
+            .match_header("authorization", "Bearer test-key")
+            .match_body(Matcher::Regex(r#"<task_digest>"#.to_string()))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_compact\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"final after compact\"}]}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        let engine = FingerChatEngine::new(test_config(server.url()));
+
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("duration since epoch")
+            .as_millis();
+        let root = std::env::temp_dir().join(format!("finger-kernel-model-compact-{ts}"));
+        let session_id = "session-compact-regression";
+        let agent_id = "chat-codex";
+        let mode = "main";
+
+        let mut history_items = Vec::new();
+        for index in 0..10 {
+            history_items.push(json!({
+                "role": "user",
+                "timestamp_iso": format!("2026-02-01T10:{index:02}:00Z"),
+                "content": [{
+                    "type": "input_text",
+                    "text": format!("user request {index}: {}", "X".repeat(240))
+                }],
+            }));
+            history_items.push(json!({
+                "role": "assistant",
+                "timestamp_iso": format!("2026-02-01T10:{index:02}:30Z"),
+                "content": [{
+                    "type": "output_text",
+                    "text": format!("assistant result {index}: {}", "Y".repeat(260))
+                }],
+            }));
+        }
+
+        let result = engine
+            .run_turn(
+                &TurnRequest {
+                    items: vec![InputItem::Text {
+                        text: "continue with the latest task".to_string(),
+                    }],
+                    options: UserTurnOptions {
+                        session_id: Some(session_id.to_string()),
+                        mode: Some(mode.to_string()),
+                        history_items,
+                        context_window: Some(ContextWindowConfig {
+                            max_input_tokens: Some(600),
+                            baseline_tokens: Some(0),
+                            auto_compact_threshold_ratio: Some(0.2),
+                        }),
+                        context_ledger: Some(finger_kernel_protocol::ContextLedgerOptions {
+                            enabled: true,
+                            root_dir: Some(root.to_string_lossy().to_string()),
+                            agent_id: Some(agent_id.to_string()),
+                            role: Some("coding".to_string()),
+                            mode: Some(mode.to_string()),
+                            can_read_all: true,
+                            readable_agents: vec![],
+                            focus_enabled: true,
+                            focus_max_chars: Some(20_000),
+                            max_segment_bytes: None,
+                            max_retained_segments: None,
+                            focus_injection_role: None,
+                        }),
+                        ..UserTurnOptions::default()
+                    },
+                },
+                None,
+            )
+            .await
+            .expect("run turn should auto compact");
+
+        assert_eq!(
+            result.last_agent_message.as_deref(),
+            Some("final after compact")
+        );
+
+        let metadata_json = result.metadata_json.expect("metadata json");
+        let metadata: Value = serde_json::from_str(&metadata_json).expect("parse metadata json");
+        assert_eq!(metadata["compact"]["requested_auto"], true);
+        assert_eq!(metadata["compact"]["applied"], true);
+
+        let compact_summary = metadata["compact"]["summary"]
+            .as_str()
+            .expect("compact summary");
+        assert!(compact_summary.contains("algorithm=task_digest_v2"));
+
+        let api_history = metadata["api_history"].as_array().expect("api history");
+        let api_history_text = api_history
+            .iter()
+            .filter_map(extract_text_from_history_item)
+            .collect::<Vec<_>>()
+            .join("\n");
+        assert!(api_history_text.contains("<task_digest>"));
+        assert!(!api_history_text.contains("<history_summary>"));
+
+        let compact_memory_path = root
+            .join(session_id)
+            .join(agent_id)
+            .join(mode)
+            .join("compact-memory.jsonl");
+        let compact_memory_raw =
+            fs::read_to_string(&compact_memory_path).expect("read compact-memory.jsonl");
+        let latest_line = compact_memory_raw
+            .lines()
+            .rev()
+            .find(|line| !line.trim().is_empty())
+            .expect("latest compact memory line");
+        let compact_entry: Value =
+            serde_json::from_str(latest_line).expect("parse compact memory entry");
+        assert_eq!(compact_entry["payload"]["algorithm"], "task_digest_v2");
+        let replacement_history = compact_entry["payload"]["replacement_history"]
+            .as_array()
+            .expect("replacement history");
+        assert!(!replacement_history.is_empty());
+        assert!(replacement_history.iter().all(|item| item.get("task_id").is_some()));
+        assert!(replacement_history.iter().all(|item| item.get("request").is_some()));
+        assert!(replacement_history.iter().all(|item| item.get("summary").is_some()));
+
+        response_mock.assert_async().await;
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn model_summary_compaction_replaces_task_digests_with_model_prose() {
+        let mut server = Server::new_async().await;
+
+        let summary_mock = server
+            .mock("POST", "/v1/responses")
+            .match_body(Matcher::Regex("You compress conversation history".to_string()))
+            .match_body(Matcher::Regex("user request 0".to_string()))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_summary\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"The user filed ten requests; all were resolved.\"}]}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+        let response_mock = server
+            .mock("POST", "/v1/responses")
+            .match_body(Matcher::Regex("<history_summary>".to_string()))
+            .match_body(Matcher::Regex(
+                "The user filed ten requests; all were resolved.".to_string(),
+            ))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_compact\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"final after summary\"}]}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        let mut history_items = Vec::new();
+        for index in 0..10 {
+            history_items.push(json!({
+                "role": "user",
+                "timestamp_iso": format!("2026-02-01T10:{index:02}:00Z"),
+                "content": [{
+                    "type": "input_text",
+                    "text": format!("user request {index}: {}", "X".repeat(240))
+                }],
+            }));
+            history_items.push(json!({
+                "role": "assistant",
+                "timestamp_iso": format!("2026-02-01T10:{index:02}:30Z"),
+                "content": [{
+                    "type": "output_text",
+                    "text": format!("assistant result {index}: {}", "Y".repeat(260))
+                }],
+            }));
+        }
+
+        let completion = FingerChatEngine::new(test_config(server.url()))
+            .complete_with_options(
+                &[InputItem::Text {
+                    text: "continue with the latest task".to_string(),
+                }],
+                &UserTurnOptions {
+                    history_items,
+                    context_window: Some(ContextWindowConfig {
+                        max_input_tokens: Some(600),
+                        baseline_tokens: Some(0),
+                        auto_compact_threshold_ratio: Some(0.2),
+                    }),
+                    compact: Some(CompactConfig {
+                        use_model_summary: true,
+                        ..CompactConfig::default()
+                    }),
+                    ..UserTurnOptions::default()
+                },
+                None,
+                None,
+            )
+            .await
+            .expect("turn with model summary compaction");
+        assert_eq!(
+            completion.output_text.as_deref(),
+            Some("final after summary")
+        );
+
+        let metadata: Value =
+            serde_json::from_str(completion.metadata_json.as_deref().expect("metadata json"))
+                .expect("parse metadata json");
+        let compact_summary = metadata["compact"]["summary"]
+            .as_str()
+            .expect("compact summary");
+        assert!(compact_summary.starts_with("The user filed ten requests; all were resolved."));
+        assert!(compact_summary.contains("compressed_at_ms="));
+        assert!(compact_summary.contains("source_time_start=2026-02-01T10:00:00Z"));
+        assert!(compact_summary.contains("timeline_order=ascending"));
+        let api_history_text = metadata["api_history"]
+            .as_array()
+            .expect("api history")
+            .iter()
+            .filter_map(extract_text_from_history_item)
+            .collect::<Vec<_>>()
+            .join("\n");
+        assert!(api_history_text.contains("<history_summary>"));
+        assert!(!api_history_text.contains("<task_digest>"));
+
+        summary_mock.assert_async().await;
+        response_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn model_override_applies_to_its_turn_only() {
+        let mut server = Server::new_async().await;
+        let override_mock = server
+            .mock("POST", "/v1/responses")
+            .match_body(Matcher::PartialJson(json!({ "model": "gpt-mini" })))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_mini\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"classified\"}]}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+        let default_mock = server
+            .mock("POST", "/v1/responses")
+            .match_body(Matcher::PartialJson(json!({ "model": "gpt-test" })))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_default\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"generated\"}]}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        let engine = FingerChatEngine::new(test_config(server.url()));
+        let items = [InputItem::Text {
+            text: "hello".to_string(),
+        }];
+        let overridden = engine
+            .complete_with_options(
+                &items,
+                &UserTurnOptions {
+                    model_override: Some(" gpt-mini ".to_string()),
+                    ..UserTurnOptions::default()
+                },
+                None,
+                None,
+            )
+            .await
+            .expect("overridden turn");
+        assert_eq!(overridden.output_text.as_deref(), Some("classified"));
+
+        let blank_override = UserTurnOptions {
+            model_override: Some("  ".to_string()),
+            ..UserTurnOptions::default()
+        };
+        let defaulted = engine
+            .complete_with_options(&items, &blank_override, None, None)
+            .await
+            .expect("default turn");
+        assert_eq!(defaulted.output_text.as_deref(), Some("generated"));
+
+        override_mock.assert_async().await;
+        default_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn oversized_input_fails_locally_before_any_request() {
+        let mut server = Server::new_async().await;
+        let response_mock = server
+            .mock("POST", "/v1/responses")
+            .expect(0)
+            .create_async()
+            .await;
+
+        let history_items = vec![json!({
+            "role": "user",
+            "content": [{ "type": "input_text", "text": "Z".repeat(4_000) }],
+        })];
+        let error = FingerChatEngine::new(test_config(server.url()))
+            .complete_with_options(
+                &[InputItem::Text {
+                    text: format!("summarize this: {}", "W".repeat(4_000)),
+                }],
+                &UserTurnOptions {
+                    history_items,
+                    context_window: Some(ContextWindowConfig {
+                        max_input_tokens: Some(200),
+                        baseline_tokens: Some(0),
+                        auto_compact_threshold_ratio: None,
+                    }),
+                    ..UserTurnOptions::default()
+                },
+                None,
+                None,
+            )
+            .await
+            .expect_err("oversized input must fail locally");
+
+        let ModelError::ContextLengthExceeded { message } = error else {
+            panic!("expected ContextLengthExceeded, got {error:?}");
+        };
+        assert!(message.contains("exceed max_input_tokens 200"));
+        response_mock.assert_async().await;
+    }
+
+    #[test]
+    fn compact_shrinks_stored_history_without_a_request() {
+        let server = Server::new();
+        let mut history = Vec::new();
+        for index in 0..10 {
+            history.push(json!({
+                "role": "user",
+                "timestamp_iso": format!("2026-02-01T10:{index:02}:00Z"),
+                "content": [{
+                    "type": "input_text",
+                    "text": format!("user request {index}: {}", "X".repeat(240))
+                }],
+            }));
+            history.push(json!({
+                "role": "assistant",
+                "timestamp_iso": format!("2026-02-01T10:{index:02}:30Z"),
+                "content": [{
+                    "type": "output_text",
+                    "text": format!("assistant result {index}: {}", "Y".repeat(2_600))
+                }],
+            }));
+        }
+
+        let outcome = FingerChatEngine::new(test_config(server.url())).compact(&history, None);
+
+        assert!(outcome
+            .summary
+            .as_deref()
+            .expect("compact summary")
+            .starts_with("algorithm=task_digest_v2"));
+        assert!(outcome.after_tokens < outcome.before_tokens);
+        let texts = outcome
+            .history
+            .iter()
+            .filter_map(extract_text_from_history_item)
+            .collect::<Vec<_>>();
+        assert!(texts[0].starts_with("<task_digest>"));
+        assert!(texts.join("\n").contains("user request 0"));
+        assert!(texts.iter().any(|text| text.starts_with("user request 9:")));
+        assert!(outcome.history.len() < history.len());
+    }
+
+    #[tokio::test]
+    async fn manual_compaction_emits_compaction_event() {
+        let mut server = Server::new_async().await;
+        let response_mock = server
+            .mock("POST", "/v1/responses")
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_compact\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"compacted\"}]}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        let mut history_items = Vec::new();
+        for index in 0..6 {
+            history_items.push(json!({
+                "role": "user",
+                "content": [{
+                    "type": "input_text",
+                    "text": format!("user request {index}: {}", "X".repeat(240))
+                }],
+            }));
+            history_items.push(json!({
+                "role": "assistant",
+                "content": [{
+                    "type": "output_text",
+                    "text": format!("assistant result {index}: {}", "Y".repeat(260))
+                }],
+            }));
+        }
+
+        let (progress_tx, mut progress_rx) = tokio::sync::mpsc::unbounded_channel::<EventMsg>();
+        FingerChatEngine::new(test_config(server.url()))
+            .complete_with_options(
+                &[InputItem::Text {
+                    text: "continue".to_string(),
+                }],
+                &UserTurnOptions {
+                    history_items,
+                    compact: Some(CompactConfig {
+                        manual: true,
+                        ..CompactConfig::default()
+                    }),
+                    ..UserTurnOptions::default()
+                },
+                Some(&progress_tx),
+                None,
+            )
+            .await
+            .expect("turn with manual compaction");
+
+        let compactions = drain_progress_events(&mut progress_rx)
+            .into_iter()
+            .filter_map(|event| match event {
+                EventMsg::Compaction(compaction) => Some(compaction),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(compactions.len(), 1);
+        let compaction = &compactions[0];
+        assert_eq!(compaction.triggered_by, CompactionTrigger::Manual);
+        assert!(compaction.before_tokens > 0);
+        assert!(
+            compaction.after_tokens < compaction.before_tokens,
+            "compaction did not shrink the window: {compaction:?}"
+        );
+        assert!(compaction.summary_chars > 0);
+        response_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn run_turn_handles_tool_success_false_response() {
+        // When tools.ts returns HTTP 200 + {success:false, error:"..."},
+        // the kernel must parse it as a tool execution error (not crash),
+        // emit ToolError event, and let the model continue reasoning.
+        let mut server = Server::new_async().await;
+
+        let first_response_mock = server
+            .mock("POST", "/v1/responses")
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_1\",\"output\":[{\"type\":\"function_call\",\"call_id\":\"call_sf\",\"name\":\"shell_exec\",\"arguments\":\"{\\\"cmd\\\":\\\"rm\\\"}\"}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        // tools.ts returns HTTP 200 but success:false (the real behavior)
+        let tool_execute_mock = server
+            .mock("POST", "/api/v1/tools/execute")
+            .match_body(Matcher::Regex(r#""toolName":"shell.exec""#.to_string()))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                json!({
+                    "success": false,
+                    "error": "Tool execution denied: permission denied for action",
+                    "toolName": "shell.exec",
+                    "agentId": "chat-codex"
+                })
+                .to_string(),
+            )
+            .expect(1)
+            .create_async()
+            .await;
+
+        let second_response_mock = server
+            .mock("POST", "/v1/responses")
+            .match_body(Matcher::Regex(r#""type":"function_call_output""#.to_string()))
+            .match_body(Matcher::Regex(r#""call_id":"call_sf""#.to_string()))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_2\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"I cannot remove that file.\"}]}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        let engine = FingerChatEngine::new(test_config(server.url()));
+
+        let (progress_tx, mut progress_rx) = tokio::sync::mpsc::unbounded_channel::<EventMsg>();
+        let result = engine
+            .run_turn(
+                &TurnRequest {
+                    items: vec![InputItem::Text {
+                        text: "run failing tool".to_string(),
+                    }],
+                    options: UserTurnOptions {
+                        tools: vec![ToolSpec {
+                            name: "shell.exec".to_string(),
+                            description: Some("Execute shell command".to_string()),
+                            input_schema: Some(json!({
+                                "type": "object",
+                                "properties": { "cmd": { "type": "string" } },
+                                "required": ["cmd"],
+                            })),
+                            approval: None,
+                        }],
+                        tool_execution: Some(ToolExecutionConfig {
+                            daemon_url: server.url(),
+                            agent_id: "chat-codex".to_string(),
+                            max_concurrency: None,
+                            tool_timeout_ms: None,
+                            auth_token: None,
+                            retry_transient_errors: false,
+                            tool_max_retries: None,
+                            tool_execute_path: None,
+                            cache_identical_calls: false,
+                            routes: Vec::new(),
+                        }),
+                        ..UserTurnOptions::default()
+                    },
+                },
+                Some(progress_tx),
+            )
+            .await
+            .expect("run turn should succeed even when tool returns success:false");
+        assert_eq!(
+            result.last_agent_message.as_deref(),
+            Some("I cannot remove that file.")
+        );
+
+        let progress_events = drain_progress_events(&mut progress_rx);
+        let tool_error = progress_events.iter().find_map(|event| match event {
+            EventMsg::ToolError(error_event) => Some(error_event),
+            _ => None,
+        });
+        assert!(tool_error.is_some(), "should emit ToolError event for success:false");
+        let tool_error = tool_error.expect("tool error event");
+        assert_eq!(tool_error.tool_name, "shell.exec");
+        assert!(tool_error.error.contains("permission denied"));
+
+        first_response_mock.assert_async().await;
+        tool_execute_mock.assert_async().await;
+        second_response_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn extra_headers_are_sent_to_provider_and_tool_daemon() {
+        let mut server = Server::new_async().await;
+        let first_response_mock = server
+            .mock("POST", "/v1/responses")
+            .match_header("x-api-key", "gateway-secret")
+            .match_header("cf-aig-authorization", "Bearer gateway-token")
+            .match_header("authorization", "Bearer test-key")
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_1\",\"output\":[{\"type\":\"function_call\",\"call_id\":\"call_1\",\"name\":\"shell_exec\",\"arguments\":\"{\\\"cmd\\\":\\\"pwd\\\"}\"}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+        let tool_execute_mock = server
+            .mock("POST", "/api/v1/tools/execute")
+            .match_header("x-api-key", "gateway-secret")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(json!({"success": true, "result": {"stdout": "/tmp"}}).to_string())
+            .expect(1)
+            .create_async()
+            .await;
+        let second_response_mock = server
+            .mock("POST", "/v1/responses")
+            .match_header("x-api-key", "gateway-secret")
+            .match_body(Matcher::Regex(r#""call_id":"call_1""#.to_string()))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_2\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"/tmp\"}]}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        let engine = FingerChatEngine::new(LocalModelConfig {
+            provider_id: "gateway".to_string(),
+            provider_name: "gateway".to_string(),
+            extra_headers: HashMap::from([
+                ("x-api-key".to_string(), "gateway-secret".to_string()),
+                (
+                    "cf-aig-authorization".to_string(),
+                    "Bearer gateway-token".to_string(),
+                ),
+            ]),
+            ..test_config(server.url())
+        });
+        let mut request = approval_gated_turn_request(server.url());
+        request.options.tools[0].approval = None;
+        let result = engine.run_turn(&request, None).await.expect("run turn");
+        assert_eq!(result.last_agent_message.as_deref(), Some("/tmp"));
+
+        first_response_mock.assert_async().await;
+        tool_execute_mock.assert_async().await;
+        second_response_mock.assert_async().await;
+    }
+
+    async fn run_tool_turn_with_daemon_token(tool_daemon_token: Option<&str>) {
+        let mut server = Server::new_async().await;
+        let first_response_mock = server
+            .mock("POST", "/v1/responses")
+            .match_body(Matcher::Regex(r#""text":"remove build dir""#.to_string()))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_1\",\"output\":[{\"type\":\"function_call\",\"call_id\":\"call_1\",\"name\":\"shell_exec\",\"arguments\":\"{\\\"cmd\\\":\\\"pwd\\\"}\"}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+        let authorization = match tool_daemon_token {
+            Some(token) => Matcher::Exact(format!("Bearer {token}")),
+            None => Matcher::Missing,
+        };
+        let tool_execute_mock = server
+            .mock("POST", "/api/v1/tools/execute")
+            .match_header("authorization", authorization)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(json!({"success": true, "result": {"stdout": "/tmp"}}).to_string())
+            .expect(1)
+            .create_async()
+            .await;
+        let second_response_mock = server
+            .mock("POST", "/v1/responses")
+            .match_body(Matcher::Regex(r#""call_id":"call_1""#.to_string()))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_2\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"/tmp\"}]}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        let engine = FingerChatEngine::new(LocalModelConfig {
+            provider_id: "crsa".to_string(),
+            provider_name: "crsa".to_string(),
+            tool_daemon_token: tool_daemon_token.map(str::to_string),
+            ..test_config(server.url())
+        });
+        let mut request = approval_gated_turn_request(server.url());
+        request.options.tools[0].approval = None;
+        let result = engine.run_turn(&request, None).await.expect("run turn");
+        assert_eq!(result.last_agent_message.as_deref(), Some("/tmp"));
+
+        first_response_mock.assert_async().await;
+        tool_execute_mock.assert_async().await;
+        second_response_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn tool_daemon_gets_bearer_token_only_when_configured() {
+        run_tool_turn_with_daemon_token(Some("daemon-secret")).await;
+        run_tool_turn_with_daemon_token(None).await;
+    }
+
+    #[test]
+    fn extra_headers_with_control_characters_are_rejected() {
+        let error = build_extra_header_map(&HashMap::from([(
+            "x-route".to_string(),
+            "a\r\nx-injected: 1".to_string(),
+        )]))
+        .expect_err("header value with CRLF must be rejected");
+        assert!(matches!(error, ModelError::InvalidHeader { ref name } if name == "x-route"));
+    }
+
+    fn approval_gated_turn_request(daemon_url: String) -> TurnRequest {
+        TurnRequest {
+            items: vec![InputItem::Text {
+                text: "remove build dir".to_string(),
+            }],
+            options: UserTurnOptions {
+                tools: vec![ToolSpec {
+                    name: "shell.exec".to_string(),
+                    description: Some("Execute shell command".to_string()),
+                    input_schema: None,
+                    approval: Some(ApprovalKind::Exec),
+                }],
+                tool_execution: Some(ToolExecutionConfig {
+                    daemon_url,
+                    agent_id: "chat-codex".to_string(),
+                    max_concurrency: None,
+                    tool_timeout_ms: None,
+                    auth_token: None,
+                    retry_transient_errors: false,
+                    tool_max_retries: None,
+                    tool_execute_path: None,
+                    cache_identical_calls: false,
+                    routes: Vec::new(),
+                }),
+                ..UserTurnOptions::default()
+            },
+        }
+    }
+
+    async fn run_approval_gated_turn(
+        server_url: String,
+        decision: ReviewDecision,
+    ) -> (Result<TurnRunResult, String>, Vec<EventMsg>) {
+        let engine = FingerChatEngine::new(test_config(server_url.clone()));
+        let request = approval_gated_turn_request(server_url);
+        let approvals = ApprovalBroker::default();
+        let (progress_tx, mut progress_rx) = tokio::sync::mpsc::unbounded_channel::<EventMsg>();
+
+        let decide = async {
+            let mut seen = Vec::new();
+            while let Some(event) = progress_rx.recv().await {
+                let pending_call_id = match &event {
+                    EventMsg::ExecApprovalRequest(request) => Some(request.call_id.clone()),
+                    _ => None,
+                };
+                seen.push(event);
+                if let Some(call_id) = pending_call_id {
+                    assert!(approvals.resolve(&call_id, ApprovalKind::Exec, decision));
+                    break;
+                }
+            }
+            (seen, progress_rx)
+        };
+        let (result, (mut events, mut progress_rx)) = tokio::join!(
+            engine.run_turn_with_approvals(&request, Some(progress_tx), approvals.clone()),
+            decide
+        );
+        events.extend(drain_progress_events(&mut progress_rx));
+        (result, events)
+    }
+
+    #[tokio::test]
+    async fn approval_gated_tool_runs_only_after_approval() {
+        let mut server = Server::new_async().await;
+        let first_response_mock = server
+            .mock("POST", "/v1/responses")
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_1\",\"output\":[{\"type\":\"function_call\",\"call_id\":\"call_rm\",\"name\":\"shell_exec\",\"arguments\":\"{\\\"cmd\\\":\\\"rm -rf build\\\"}\"}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+        let tool_execute_mock = server
+            .mock("POST", "/api/v1/tools/execute")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(json!({"success": true, "result": {"exitCode": 0}}).to_string())
+            .expect(1)
+            .create_async()
+            .await;
+        let second_response_mock = server
+            .mock("POST", "/v1/responses")
+            .match_body(Matcher::Regex(r#""call_id":"call_rm""#.to_string()))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_2\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"Removed.\"}]}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        let (result, events) =
+            run_approval_gated_turn(server.url(), ReviewDecision::Approved).await;
+        let result = result.expect("approved turn should succeed");
+        assert_eq!(result.last_agent_message.as_deref(), Some("Removed."));
+
+        let request_index = events
+            .iter()
+            .position(|event| matches!(event, EventMsg::ExecApprovalRequest(_)))
+            .expect("exec approval request event");
+        let result_index = events
+            .iter()
+            .position(|event| matches!(event, EventMsg::ToolResult(_)))
+            .expect("tool result event");
+        assert!(request_index < result_index);
+        let EventMsg::ExecApprovalRequest(request) = &events[request_index] else {
+            unreachable!();
+        };
+        assert_eq!(request.tool_name, "shell.exec");
+        assert_eq!(request.input, json!({"cmd": "rm -rf build"}));
+
+        first_response_mock.assert_async().await;
+        tool_execute_mock.assert_async().await;
+        second_response_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn denied_approval_skips_tool_and_reports_denial_to_model() {
+        let mut server = Server::new_async().await;
+        let first_response_mock = server
+            .mock("POST", "/v1/responses")
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_1\",\"output\":[{\"type\":\"function_call\",\"call_id\":\"call_rm\",\"name\":\"shell_exec\",\"arguments\":\"{\\\"cmd\\\":\\\"rm -rf build\\\"}\"}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+        let tool_execute_mock = server
+            .mock("POST", "/api/v1/tools/execute")
+            .with_status(200)
+            .expect(0)
+            .create_async()
+            .await;
+        let second_response_mock = server
+            .mock("POST", "/v1/responses")
+            .match_body(Matcher::Regex(r#"execution denied by user"#.to_string()))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_2\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"Skipped.\"}]}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        let (result, events) = run_approval_gated_turn(server.url(), ReviewDecision::Denied).await;
+        let result = result.expect("denied turn should still finish");
+        assert_eq!(result.last_agent_message.as_deref(), Some("Skipped."));
+        let tool_error = events
+            .iter()
+            .find_map(|event| match event {
+                EventMsg::ToolError(error) => Some(error),
+                _ => None,
+            })
+            .expect("tool error event for denied call");
+        assert_eq!(tool_error.call_id, "call_rm");
+        assert!(tool_error.error.contains("denied"));
+
+        first_response_mock.assert_async().await;
+        tool_execute_mock.assert_async().await;
+        second_response_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn run_turn_emits_tool_error_progress_when_tool_execution_fails() {
+        let mut server = Server::new_async().await;
+
+        let first_response_mock = server
+            .mock("POST", "/v1/responses")
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_1\",\"output\":[{\"type\":\"function_call\",\"call_id\":\"call_fail\",\"name\":\"shell_exec\",\"arguments\":\"{\\\"cmd\\\":\\\"rm\\\"}\"}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        let tool_execute_mock = server
+            .mock("POST", "/api/v1/tools/execute")
+            .match_body(Matcher::Regex(r#""toolName":"shell.exec""#.to_string()))
+            .match_body(Matcher::Regex(r#""command":"rm""#.to_string()))
+            .with_status(500)
+            .with_header("content-type", "application/json")
+            .with_body(json!({ "error": "permission denied" }).to_string())
+            .expect(1)
+            .create_async()
+            .await;
+
+        let second_response_mock = server
+            .mock("POST", "/v1/responses")
+            .match_body(Matcher::Regex(r#""type":"function_call_output""#.to_string()))
+            .match_body(Matcher::Regex(r#""call_id":"call_fail""#.to_string()))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_2\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"handled failure\"}]}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        let engine = FingerChatEngine::new(test_config(server.url()));
+
+        let (progress_tx, mut progress_rx) = tokio::sync::mpsc::unbounded_channel::<EventMsg>();
+        let result = engine
+            .run_turn(
+                &TurnRequest {
+                    items: vec![InputItem::Text {
+                        text: "run failing tool".to_string(),
+                    }],
+                    options: UserTurnOptions {
+                        tools: vec![ToolSpec {
+                            name: "shell.exec".to_string(),
+                            description: Some("Execute shell command".to_string()),
+                            input_schema: Some(json!({
+                                "type": "object",
+                                "properties": { "cmd": { "type": "string" } },
+                                "required": ["cmd"],
+                            })),
+                            approval: None,
+                        }],
+                        tool_execution: Some(ToolExecutionConfig {
+                            daemon_url: server.url(),
+                            agent_id: "chat-codex".to_string(),
+                            max_concurrency: None,
+                            tool_timeout_ms: None,
+                            auth_token: None,
+                            retry_transient_errors: false,
+                            tool_max_retries: None,
+                            tool_execute_path: None,
+                            cache_identical_calls: false,
+                            routes: Vec::new(),
+                        }),
+                        ..UserTurnOptions::default()
+                    },
+                },
+                Some(progress_tx),
+            )
+            .await
+            .expect("run turn");
+        assert_eq!(
+            result.last_agent_message.as_deref(),
+            Some("handled failure")
+        );
+
+        let progress_events = drain_progress_events(&mut progress_rx);
+        let tool_error = progress_events.iter().find_map(|event| match event {
+            EventMsg::ToolError(error_event) => Some(error_event),
+            _ => None,
+        });
+        assert!(tool_error.is_some());
+        let tool_error = tool_error.expect("tool error event");
+        assert_eq!(tool_error.tool_name, "shell.exec");
+        assert!(tool_error.error.contains("permission denied"));
+
+        let seqs = progress_events
+            .iter()
+            .filter_map(extract_progress_seq)
+            .collect::<Vec<_>>();
+        assert_eq!(seqs, vec![1, 2, 3, 4, 5]);
+
+        first_response_mock.assert_async().await;
+        tool_execute_mock.assert_async().await;
+        second_response_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn run_turn_reports_tool_timeout_as_tool_error_and_continues() {
+        let mut server = Server::new_async().await;
+
+        let first_response_mock = server
+            .mock("POST", "/v1/responses")
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_1\",\"output\":[{\"type\":\"function_call\",\"call_id\":\"call_slow\",\"name\":\"shell_exec\",\"arguments\":\"{\\\"cmd\\\":\\\"yes\\\"}\"}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        let tool_execute_mock = server
+            .mock("POST", "/api/v1/tools/execute")
+            .match_body(Matcher::Regex(r#""toolName":"shell.exec""#.to_string()))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_chunked_body(|writer| {
+                std::thread::sleep(std::time::Duration::from_millis(600));
+                writer.write_all(br#"{"success":true,"result":{"stdout":"late"}}"#)
+            })
+            .expect(1)
+            .create_async()
+            .await;
+
+        let second_response_mock = server
+            .mock("POST", "/v1/responses")
+            .match_body(Matcher::Regex(r#""call_id":"call_slow""#.to_string()))
+            .match_body(Matcher::Regex("timed out after 100ms".to_string()))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_2\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"tool was too slow\"}]}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        let engine = FingerChatEngine::new(test_config(server.url()));
+
+        let (progress_tx, mut progress_rx) = tokio::sync::mpsc::unbounded_channel::<EventMsg>();
+        let result = engine
+            .run_turn(
+                &TurnRequest {
+                    items: vec![InputItem::Text {
+                        text: "run slow tool".to_string(),
+                    }],
+                    options: UserTurnOptions {
+                        tools: vec![ToolSpec {
+                            name: "shell.exec".to_string(),
+                            description: Some("Execute shell command".to_string()),
+                            input_schema: None,
+                            approval: None,
+                        }],
+                        tool_execution: Some(ToolExecutionConfig {
+                            daemon_url: server.url(),
+                            agent_id: "chat-codex".to_string(),
+                            max_concurrency: None,
+                            tool_timeout_ms: Some(100),
+                            auth_token: None,
+                            retry_transient_errors: false,
+                            tool_max_retries: None,
+                            tool_execute_path: None,
+                            cache_identical_calls: false,
+                            routes: Vec::new(),
+                        }),
+                        ..UserTurnOptions::default()
+                    },
+                },
+                Some(progress_tx),
+            )
+            .await
+            .expect("run turn");
+        assert_eq!(
+            result.last_agent_message.as_deref(),
+            Some("tool was too slow")
+        );
+
+        let progress_events = drain_progress_events(&mut progress_rx);
+        let tool_error = progress_events
+            .iter()
+            .find_map(|event| match event {
+                EventMsg::ToolError(error_event) => Some(error_event),
+                _ => None,
+            })
+            .expect("tool error event");
+        assert_eq!(tool_error.call_id, "call_slow");
+        assert!(tool_error.error.contains("timed out after 100ms"));
+        assert!(tool_error.duration_ms >= 100);
+
+        first_response_mock.assert_async().await;
+        tool_execute_mock.assert_async().await;
+        second_response_mock.assert_async().await;
+    }
+
+    fn daemon_tool_config(daemon_url: String) -> ToolExecutionConfig {
+        ToolExecutionConfig {
+            daemon_url,
+            agent_id: "chat-codex".to_string(),
+            max_concurrency: None,
+            tool_timeout_ms: None,
+            auth_token: None,
+            retry_transient_errors: false,
+            tool_max_retries: None,
+            tool_execute_path: None,
+            cache_identical_calls: false,
+            routes: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn unreachable_tool_daemon_is_a_transport_error() {
+        let engine = fast_retry_engine("http://127.0.0.1:9".to_string(), 1);
+        let call = FunctionCallItem {
+            call_id: "call_down".to_string(),
+            name: "shell_exec".to_string(),
+            arguments: "{\"cmd\":\"pwd\"}".to_string(),
+        };
+
+        let (result, retries) = engine
+            .execute_single_tool_call(
+                &call,
+                &daemon_tool_config("http://127.0.0.1:9".to_string()),
+                "shell.exec",
+                None,
+            )
+            .await;
+
+        assert_eq!(retries, 0);
+        match result {
+            Err(ModelError::ToolTransport { tool_name, source }) => {
+                assert_eq!(tool_name, "shell.exec");
+                assert!(source.is_connect());
+            }
+            other => panic!("expected a transport error, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn oversized_response_bodies_fail_instead_of_being_buffered() {
+        let mut server = Server::new_async().await;
+        let padding = "x".repeat(4096);
+        let response_mock = server
+            .mock("POST", "/v1/responses")
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(format!(
+                "event: response.output_text.delta\ndata: {{\"type\":\"response.output_text.delta\",\"delta\":\"{padding}\"}}\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+        let tool_execute_mock = server
+            .mock("POST", "/api/v1/tools/execute")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(json!({ "result": { "stdout": padding } }).to_string())
+            .expect(1)
+            .create_async()
+            .await;
+        let engine = FingerChatEngine::with_client_options(
+            test_config(server.url()),
+            ClientOptions {
+                max_response_bytes: 1024,
+                ..ClientOptions::default()
+            },
+        )
+        .expect("build engine");
+
+        let error = engine
+            .complete_text("hello")
+            .await
+            .expect_err("oversized stream should fail");
+        assert!(
+            matches!(error, ModelError::ResponseTooLarge { limit: 1024 }),
+            "unexpected error: {error}"
+        );
+
+        let call = FunctionCallItem {
+            call_id: "call_big".to_string(),
+            name: "shell_exec".to_string(),
+            arguments: "{\"cmd\":\"cat big.log\"}".to_string(),
+        };
+        let (result, _) = engine
+            .execute_single_tool_call(&call, &daemon_tool_config(server.url()), "shell.exec", None)
+            .await;
+        assert!(matches!(
+            result,
+            Err(ModelError::ResponseTooLarge { limit: 1024 })
+        ));
+        response_mock.assert_async().await;
+        tool_execute_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn tool_reported_error_with_ok_status_is_an_execution_error() {
+        let mut server = Server::new_async().await;
+        let tool_execute_mock = server
+            .mock("POST", "/api/v1/tools/execute")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(json!({ "error": "no such file" }).to_string())
+            .expect(1)
+            .create_async()
+            .await;
+        let engine = fast_retry_engine(server.url(), 1);
+        let call = FunctionCallItem {
+            call_id: "call_cat".to_string(),
+            name: "shell_exec".to_string(),
+            arguments: "{\"cmd\":\"cat missing\"}".to_string(),
+        };
+
+        let (result, _) = engine
+            .execute_single_tool_call(&call, &daemon_tool_config(server.url()), "shell.exec", None)
+            .await;
+
+        match result {
+            Err(ModelError::ToolExecution { tool_name, message }) => {
+                assert_eq!(tool_name, "shell.exec");
+                assert_eq!(message, "no such file");
+            }
+            other => panic!("expected a tool execution error, got {other:?}"),
+        }
+        tool_execute_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn run_turn_retries_transient_tool_daemon_failure_when_enabled() {
+        let mut server = Server::new_async().await;
+
+        let first_response_mock = server
+            .mock("POST", "/v1/responses")
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_1\",\"output\":[{\"type\":\"function_call\",\"call_id\":\"call_flaky\",\"name\":\"shell_exec\",\"arguments\":\"{\\\"cmd\\\":\\\"pwd\\\"}\"}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        // mockito prefers mocks that still expect hits, so the 502 is served
+        // once before the daemon starts succeeding.
+        let failing_tool_mock = server
+            .mock("POST", "/api/v1/tools/execute")
+            .with_status(502)
+            .with_body("bad gateway")
+            .expect(1)
+            .create_async()
+            .await;
+        let tool_execute_mock = server
+            .mock("POST", "/api/v1/tools/execute")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"success":true,"result":{"stdout":"/tmp"}}"#)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let second_response_mock = server
+            .mock("POST", "/v1/responses")
+            .match_body(Matcher::Regex(r#""call_id":"call_flaky""#.to_string()))
+            .match_body(Matcher::Regex("/tmp".to_string()))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_2\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"cwd is /tmp\"}]}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        let engine = FingerChatEngine::new(test_config(server.url()));
+
+        let result = engine
+            .run_turn(
+                &TurnRequest {
+                    items: vec![InputItem::Text {
+                        text: "where am i".to_string(),
+                    }],
+                    options: UserTurnOptions {
+                        tools: vec![ToolSpec {
+                            name: "shell.exec".to_string(),
+                            description: Some("Execute shell command".to_string()),
+                            input_schema: None,
+                            approval: None,
+                        }],
+                        tool_execution: Some(ToolExecutionConfig {
+                            daemon_url: server.url(),
+                            agent_id: "chat-codex".to_string(),
+                            max_concurrency: None,
+                            tool_timeout_ms: None,
+                            auth_token: None,
+                            retry_transient_errors: true,
+                            tool_max_retries: Some(2),
+                            tool_execute_path: None,
+                            cache_identical_calls: false,
+                            routes: Vec::new(),
+                        }),
+                        ..UserTurnOptions::default()
+                    },
+                },
+                None,
+            )
+            .await
+            .expect("run turn");
+        assert_eq!(result.last_agent_message.as_deref(), Some("cwd is /tmp"));
+
+        let metadata: Value = serde_json::from_str(
+            result
+                .metadata_json
+                .as_deref()
+                .expect("metadata json should exist"),
+        )
+        .expect("metadata should be valid json");
+        let trace = &metadata["tool_trace"][0];
+        assert_eq!(trace["call_id"], "call_flaky");
+        assert_eq!(trace["retries"], 1);
+
+        first_response_mock.assert_async().await;
+        failing_tool_mock.assert_async().await;
+        tool_execute_mock.assert_async().await;
+        second_response_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn run_turn_routes_tools_to_daemons_by_name_prefix() {
+        let mut server = Server::new_async().await;
+        let mut shell_daemon = Server::new_async().await;
+        let mut browser_daemon = Server::new_async().await;
+
+        let first_response_mock = server
+            .mock("POST", "/v1/responses")
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_1\",\"output\":[{\"type\":\"function_call\",\"call_id\":\"call_shell\",\"name\":\"shell_exec\",\"arguments\":\"{\\\"cmd\\\":\\\"pwd\\\"}\"},{\"type\":\"function_call\",\"call_id\":\"call_browser\",\"name\":\"browser_open\",\"arguments\":\"{\\\"url\\\":\\\"https://example.com\\\"}\"}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+        let default_daemon_mock = server
+            .mock("POST", "/api/v1/tools/execute")
+            .expect(0)
+            .create_async()
+            .await;
+        let shell_mock = shell_daemon
+            .mock("POST", "/api/v1/tools/execute")
+            .match_body(Matcher::PartialJson(
+                json!({"agentId": "chat-codex", "toolName": "shell.exec"}),
+            ))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"success":true,"result":{"stdout":"/tmp"}}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        let browser_mock = browser_daemon
+            .mock("POST", "/api/v1/tools/execute")
+            .match_body(Matcher::PartialJson(
+                json!({"agentId": "browser-agent", "toolName": "browser.open"}),
+            ))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"success":true,"result":{"title":"Example Domain"}}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        let second_response_mock = server
+            .mock("POST", "/v1/responses")
+            .match_body(Matcher::AllOf(vec![
+                Matcher::Regex("/tmp".to_string()),
+                Matcher::Regex("Example Domain".to_string()),
+            ]))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_2\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"done\"}]}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        let engine = FingerChatEngine::new(test_config(server.url()));
+        let tool = |name: &str| ToolSpec {
+            name: name.to_string(),
+            description: None,
+            input_schema: None,
+            approval: None,
+        };
+
+        let result = engine
+            .run_turn(
+                &TurnRequest {
+                    items: vec![InputItem::Text {
+                        text: "check the page".to_string(),
+                    }],
+                    options: UserTurnOptions {
+                        tools: vec![tool("shell.exec"), tool("browser.open")],
+                        tool_execution: Some(ToolExecutionConfig {
+                            daemon_url: server.url(),
+                            agent_id: "chat-codex".to_string(),
+                            max_concurrency: None,
+                            tool_timeout_ms: None,
+                            auth_token: None,
+                            retry_transient_errors: false,
+                            tool_max_retries: None,
+                            tool_execute_path: None,
+                            cache_identical_calls: false,
+                            routes: vec![
+                                ToolDaemonRoute {
+                                    prefix: "shell.".to_string(),
+                                    daemon_url: shell_daemon.url(),
+                                    agent_id: None,
+                                },
+                                ToolDaemonRoute {
+                                    prefix: "browser.".to_string(),
+                                    daemon_url: browser_daemon.url(),
+                                    agent_id: Some("browser-agent".to_string()),
+                                },
+                            ],
+                        }),
+                        ..UserTurnOptions::default()
+                    },
+                },
+                None,
+            )
+            .await
+            .expect("run turn");
+        assert_eq!(result.last_agent_message.as_deref(), Some("done"));
+
+        first_response_mock.assert_async().await;
+        default_daemon_mock.assert_async().await;
+        shell_mock.assert_async().await;
+        browser_mock.assert_async().await;
+        second_response_mock.assert_async().await;
+    }
+
+    #[test]
+    fn tool_daemon_routing_prefers_longest_prefix() {
+        let config = ToolExecutionConfig {
+            daemon_url: "http://default".to_string(),
+            agent_id: "default-agent".to_string(),
+            max_concurrency: None,
+            tool_timeout_ms: None,
+            auth_token: None,
+            retry_transient_errors: false,
+            tool_max_retries: None,
+            tool_execute_path: None,
+            cache_identical_calls: false,
+            routes: vec![
+                ToolDaemonRoute {
+                    prefix: "browser.".to_string(),
+                    daemon_url: "http://browser".to_string(),
+                    agent_id: None,
+                },
+                ToolDaemonRoute {
+                    prefix: "browser.devtools.".to_string(),
+                    daemon_url: "http://devtools".to_string(),
+                    agent_id: Some("devtools-agent".to_string()),
+                },
+            ],
+        };
+
+        assert_eq!(
+            resolve_tool_daemon(&config, "browser.open"),
+            ("http://browser", "default-agent")
+        );
+        assert_eq!(
+            resolve_tool_daemon(&config, "browser.devtools.eval"),
+            ("http://devtools", "devtools-agent")
+        );
+        assert_eq!(
+            resolve_tool_daemon(&config, "shell.exec"),
+            ("http://default", "default-agent")
+        );
+    }
+
+    #[tokio::test]
+    async fn provider_without_encrypted_reasoning_omits_include_and_forces_store() {
+        let mut server = Server::new_async().await;
+        let mock = server
+            .mock("POST", "/v1/responses")
+            .match_body(Matcher::AllOf(vec![
+                Matcher::Regex(r#""include":\[\]"#.to_string()),
+                Matcher::Regex(r#""store":true"#.to_string()),
+            ]))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_plain\",\"status\":\"completed\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"ok\"}]}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        let engine = FingerChatEngine::new(LocalModelConfig {
+            supports_encrypted_reasoning: false,
+            ..test_config(server.url())
+        });
+        let result = engine
+            .run_turn(
+                &TurnRequest {
+                    items: vec![InputItem::Text {
+                        text: "hello".to_string(),
+                    }],
+                    options: UserTurnOptions {
+                        responses: Some(ResponsesRequestOptions {
+                            store: Some(false),
+                            reasoning: Some(ResponsesReasoningOptions {
+                                include_encrypted_content: Some(true),
+                                ..ResponsesReasoningOptions::default()
+                            }),
+                            ..ResponsesRequestOptions::default()
+                        }),
+                        ..UserTurnOptions::default()
+                    },
+                },
+                None,
+            )
+            .await
+            .expect("run turn");
+
+        assert_eq!(result.last_agent_message.as_deref(), Some("ok"));
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn run_turn_injects_ledger_focus_with_configured_role() {
+        let mut server = Server::new_async().await;
+        let _mock = server
             .mock("POST", "/v1/responses")
-            .match_header("authorization", "Bearer test-key")
-            .match_body(Matcher::Regex(r#"<task_digest>"#.to_string()))
+            .match_body(Matcher::Regex("OLD_MEMORY_RECALL_ZONE".to_string()))
             .with_status(200)
             .with_header("content-type", "text/event-stream")
             .with_body(concat!(
                 "event: response.completed\n",
-                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_compact\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"final after compact\"}]}]}}\n\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_focus\",\"status\":\"completed\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"noted\"}]}]}}\n\n",
                 "data: [DONE]\n\n"
             ))
             .expect(1)
             .create_async()
             .await;
 
-        let engine = FingerChatEngine::new(LocalModelConfig {
-            provider_id: "test".to_string(),
-            provider_name: "test".to_string(),
-            base_url: server.url(),
-            wire_api: WireApi::Responses,
-            env_key: "TEST_KEY".to_string(),
-            api_key: "test-key".to_string(),
-            model: "gpt-test".to_string(),
-            tool_daemon_url: server.url(),
-            tool_agent_id: "chat-codex".to_string(),
-        });
-
         let ts = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .expect("duration since epoch")
-            .as_millis();
-        let root = std::env::temp_dir().join(format!("finger-kernel-model-compact-{ts}"));
-        let session_id = "session-compact-regression";
-        let agent_id = "chat-codex";
-        let mode = "main";
-
-        let mut history_items = Vec::new();
-        for index in 0..10 {
-            history_items.push(json!({
-                "role": "user",
-                "timestamp_iso": format!("2026-02-01T10:{index:02}:00Z"),
-                "content": [{
-                    "type": "input_text",
-                    "text": format!("user request {index}: {}", "X".repeat(240))
-                }],
-            }));
-            history_items.push(json!({
-                "role": "assistant",
-                "timestamp_iso": format!("2026-02-01T10:{index:02}:30Z"),
-                "content": [{
-                    "type": "output_text",
-                    "text": format!("assistant result {index}: {}", "Y".repeat(260))
-                }],
-            }));
-        }
+            .as_nanos();
+        let root = std::env::temp_dir().join(format!("finger-kernel-model-focus-role-{ts}"));
+        let ledger_options = |role: &str| finger_kernel_protocol::ContextLedgerOptions {
+            enabled: true,
+            root_dir: Some(root.to_string_lossy().to_string()),
+            agent_id: Some("chat-codex".to_string()),
+            role: None,
+            mode: Some("main".to_string()),
+            can_read_all: false,
+            readable_agents: vec![],
+            focus_enabled: true,
+            focus_max_chars: Some(1_000),
+            max_segment_bytes: None,
+            max_retained_segments: None,
+            focus_injection_role: Some(role.to_string()),
+        };
+        let turn = |role: &str| TurnRequest {
+            items: vec![InputItem::Text {
+                text: "what did we decide?".to_string(),
+            }],
+            options: UserTurnOptions {
+                session_id: Some("session-focus-role".to_string()),
+                mode: Some("main".to_string()),
+                context_ledger: Some(ledger_options(role)),
+                ..UserTurnOptions::default()
+            },
+        };
+        build_context_ledger(&turn("developer").options)
+            .expect("ledger")
+            .insert_focus("use postgres for storage", false)
+            .expect("insert focus");
 
+        let engine = FingerChatEngine::new(test_config(server.url()));
         let result = engine
+            .run_turn(&turn("developer"), None)
+            .await
+            .expect("run turn");
+
+        let metadata: Value = serde_json::from_str(
+            result
+                .metadata_json
+                .as_deref()
+                .expect("metadata json should exist"),
+        )
+        .expect("metadata should be valid json");
+        let focus_items = metadata["api_history"]
+            .as_array()
+            .expect("api history")
+            .iter()
+            .filter(|item| is_ledger_focus_history_item(item))
+            .collect::<Vec<_>>();
+        assert_eq!(focus_items.len(), 1);
+        assert_eq!(focus_items[0]["role"], "developer");
+
+        let error = engine
+            .run_turn(&turn("assistant"), None)
+            .await
+            .expect_err("unknown focus role should be rejected");
+        assert!(error.contains("invalid focus injection role 'assistant'"));
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn run_turn_reports_model_names_of_colliding_tools() {
+        let mut server = Server::new_async().await;
+        let _mock = server
+            .mock("POST", "/v1/responses")
+            .match_body(Matcher::AllOf(vec![
+                Matcher::Regex(r#""name":"shell_exec""#.to_string()),
+                Matcher::Regex(r#""name":"shell_exec_2""#.to_string()),
+            ]))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_names\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"ok\"}]}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        let tool = |name: &str| ToolSpec {
+            name: name.to_string(),
+            description: None,
+            input_schema: None,
+            approval: None,
+        };
+        let result = fast_retry_engine(server.url(), 1)
             .run_turn(
                 &TurnRequest {
                     items: vec![InputItem::Text {
-                        text: "continue with the latest task".to_string(),
+                        text: "hello".to_string(),
                     }],
                     options: UserTurnOptions {
-                        session_id: Some(session_id.to_string()),
-                        mode: Some(mode.to_string()),
-                        history_items,
-                        context_window: Some(ContextWindowConfig {
-                            max_input_tokens: Some(600),
-                            baseline_tokens: Some(0),
-                            auto_compact_threshold_ratio: Some(0.2),
-                        }),
-                        context_ledger: Some(finger_kernel_protocol::ContextLedgerOptions {
-                            enabled: true,
-                            root_dir: Some(root.to_string_lossy().to_string()),
-                            agent_id: Some(agent_id.to_string()),
-                            role: Some("coding".to_string()),
-                            mode: Some(mode.to_string()),
-                            can_read_all: true,
-                            readable_agents: vec![],
-                            focus_enabled: true,
-                            focus_max_chars: Some(20_000),
-                        }),
+                        tools: vec![tool("shell.exec"), tool("shell/exec")],
                         ..UserTurnOptions::default()
                     },
                 },
                 None,
             )
             .await
-            .expect("run turn should auto compact");
+            .expect("run turn");
 
+        let metadata: Value =
+            serde_json::from_str(result.metadata_json.as_deref().expect("metadata json"))
+                .expect("parse metadata");
         assert_eq!(
-            result.last_agent_message.as_deref(),
-            Some("final after compact")
+            metadata["tool_names"],
+            json!([
+                { "runtime_name": "shell.exec", "model_name": "shell_exec", "renamed": false },
+                { "runtime_name": "shell/exec", "model_name": "shell_exec_2", "renamed": true },
+            ])
         );
-
-        let metadata_json = result.metadata_json.expect("metadata json");
-        let metadata: Value = serde_json::from_str(&metadata_json).expect("parse metadata json");
-        assert_eq!(metadata["compact"]["requested_auto"], true);
-        assert_eq!(metadata["compact"]["applied"], true);
-
-        let compact_summary = metadata["compact"]["summary"]
-            .as_str()
-            .expect("compact summary");
-        assert!(compact_summary.contains("algorithm=task_digest_v2"));
-
-        let api_history = metadata["api_history"].as_array().expect("api history");
-        let api_history_text = api_history
-            .iter()
-            .filter_map(extract_text_from_history_item)
-            .collect::<Vec<_>>()
-            .join("\n");
-        assert!(api_history_text.contains("<task_digest>"));
-        assert!(!api_history_text.contains("<history_summary>"));
-
-        let compact_memory_path = root
-            .join(session_id)
-            .join(agent_id)
-            .join(mode)
-            .join("compact-memory.jsonl");
-        let compact_memory_raw =
-            fs::read_to_string(&compact_memory_path).expect("read compact-memory.jsonl");
-        let latest_line = compact_memory_raw
-            .lines()
-            .rev()
-            .find(|line| !line.trim().is_empty())
-            .expect("latest compact memory line");
-        let compact_entry: Value =
-            serde_json::from_str(latest_line).expect("parse compact memory entry");
-        assert_eq!(compact_entry["payload"]["algorithm"], "task_digest_v2");
-        let replacement_history = compact_entry["payload"]["replacement_history"]
-            .as_array()
-            .expect("replacement history");
-        assert!(!replacement_history.is_empty());
-        assert!(replacement_history.iter().all(|item| item.get("task_id").is_some()));
-        assert!(replacement_history.iter().all(|item| item.get("request").is_some()));
-        assert!(replacement_history.iter().all(|item| item.get("summary").is_some()));
-
-        response_mock.assert_async().await;
-        let _ = fs::remove_dir_all(root);
     }
 
     #[tokio::test]
-    async fn run_turn_handles_tool_success_false_response() {
-        // When tools.ts returns HTTP 200 + {success:false, error:"..."},
-        // the kernel must parse it as a tool execution error (not crash),
-        // emit ToolError event, and let the model continue reasoning.
+    async fn run_turn_collects_url_citations_into_metadata() {
         let mut server = Server::new_async().await;
-
-        let first_response_mock = server
+        let _mock = server
             .mock("POST", "/v1/responses")
             .with_status(200)
             .with_header("content-type", "text/event-stream")
             .with_body(concat!(
                 "event: response.completed\n",
-                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_1\",\"output\":[{\"type\":\"function_call\",\"call_id\":\"call_sf\",\"name\":\"shell_exec\",\"arguments\":\"{\\\"cmd\\\":\\\"rm\\\"}\"}]}}\n\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_cite\",\"status\":\"completed\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"Rust 1.0 shipped in 2015.\",\"annotations\":[{\"type\":\"url_citation\",\"url\":\"https://blog.rust-lang.org/2015/05/15/Rust-1.0.html\",\"title\":\"Announcing Rust 1.0\",\"start_index\":0,\"end_index\":25}]}]}]}}\n\n",
                 "data: [DONE]\n\n"
             ))
             .expect(1)
             .create_async()
             .await;
 
-        // tools.ts returns HTTP 200 but success:false (the real behavior)
-        let tool_execute_mock = server
-            .mock("POST", "/api/v1/tools/execute")
-            .match_body(Matcher::Regex(r#""toolName":"shell.exec""#.to_string()))
-            .with_status(200)
-            .with_header("content-type", "application/json")
-            .with_body(
-                json!({
-                    "success": false,
-                    "error": "Tool execution denied: permission denied for action",
-                    "toolName": "shell.exec",
-                    "agentId": "chat-codex"
-                })
-                .to_string(),
+        let engine = FingerChatEngine::new(test_config(server.url()));
+        let result = engine
+            .run_turn(
+                &TurnRequest {
+                    items: vec![InputItem::Text {
+                        text: "when did rust 1.0 ship?".to_string(),
+                    }],
+                    options: UserTurnOptions::default(),
+                },
+                None,
             )
+            .await
+            .expect("run turn");
+
+        assert_eq!(
+            result.last_agent_message.as_deref(),
+            Some("Rust 1.0 shipped in 2015.")
+        );
+        let metadata: Value = serde_json::from_str(
+            result
+                .metadata_json
+                .as_deref()
+                .expect("metadata json should exist"),
+        )
+        .expect("metadata should be valid json");
+        let citations = metadata["citations"].as_array().expect("citations array");
+        assert_eq!(citations.len(), 1);
+        assert_eq!(citations[0]["type"], "url_citation");
+        assert_eq!(
+            citations[0]["url"],
+            "https://blog.rust-lang.org/2015/05/15/Rust-1.0.html"
+        );
+        assert_eq!(citations[0]["title"], "Announcing Rust 1.0");
+    }
+
+    #[tokio::test]
+    async fn run_turn_flags_incomplete_responses() {
+        let mut server = Server::new_async().await;
+        let truncated_mock = server
+            .mock("POST", "/v1/responses")
+            .match_body(Matcher::Regex("write an essay".to_string()))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_1\",\"status\":\"incomplete\",\"incomplete_details\":{\"reason\":\"max_output_tokens\"},\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"Once upon a\"}]}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
             .expect(1)
             .create_async()
             .await;
-
-        let second_response_mock = server
+        let empty_mock = server
             .mock("POST", "/v1/responses")
-            .match_body(Matcher::Regex(r#""type":"function_call_output""#.to_string()))
-            .match_body(Matcher::Regex(r#""call_id":"call_sf""#.to_string()))
+            .match_body(Matcher::Regex("think hard".to_string()))
             .with_status(200)
             .with_header("content-type", "text/event-stream")
             .with_body(concat!(
                 "event: response.completed\n",
-                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_2\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"I cannot remove that file.\"}]}]}}\n\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_2\",\"status\":\"incomplete\",\"incomplete_details\":{\"reason\":\"max_output_tokens\"},\"output\":[]}}\n\n",
                 "data: [DONE]\n\n"
             ))
             .expect(1)
             .create_async()
             .await;
 
-        let engine = FingerChatEngine::new(LocalModelConfig {
-            provider_id: "test".to_string(),
-            provider_name: "test".to_string(),
-            base_url: server.url(),
-            wire_api: WireApi::Responses,
-            env_key: "TEST_KEY".to_string(),
-            api_key: "test-key".to_string(),
-            model: "gpt-test".to_string(),
-            tool_daemon_url: server.url(),
-            tool_agent_id: "chat-codex".to_string(),
-        });
+        let engine = FingerChatEngine::new(test_config(server.url()));
+        let turn = |text: &str| TurnRequest {
+            items: vec![InputItem::Text {
+                text: text.to_string(),
+            }],
+            options: UserTurnOptions::default(),
+        };
 
-        let (progress_tx, mut progress_rx) = tokio::sync::mpsc::unbounded_channel::<EventMsg>();
         let result = engine
+            .run_turn(&turn("write an essay"), None)
+            .await
+            .expect("truncated text is still returned");
+        assert_eq!(result.last_agent_message.as_deref(), Some("Once upon a"));
+        let metadata: Value = serde_json::from_str(
+            result
+                .metadata_json
+                .as_deref()
+                .expect("metadata json should exist"),
+        )
+        .expect("metadata should be valid json");
+        assert_eq!(metadata["incomplete_reason"], "max_output_tokens");
+
+        let error = engine
+            .run_turn(&turn("think hard"), None)
+            .await
+            .expect_err("empty incomplete response is an error");
+        assert_eq!(
+            error,
+            ModelError::Incomplete {
+                reason: "max_output_tokens".to_string()
+            }
+            .to_string()
+        );
+
+        truncated_mock.assert_async().await;
+        empty_mock.assert_async().await;
+    }
+
+    async fn run_chained_tool_turn(server: &mut mockito::ServerGuard) -> String {
+        server
+            .mock("POST", "/api/v1/tools/execute")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"success":true,"result":{"stdout":"/tmp"}}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        let engine = FingerChatEngine::new(test_config(server.url()));
+        engine
             .run_turn(
                 &TurnRequest {
                     items: vec![InputItem::Text {
-                        text: "run failing tool".to_string(),
+                        text: "where am i".to_string(),
                     }],
                     options: UserTurnOptions {
                         tools: vec![ToolSpec {
                             name: "shell.exec".to_string(),
                             description: Some("Execute shell command".to_string()),
-                            input_schema: Some(json!({
-                                "type": "object",
-                                "properties": { "cmd": { "type": "string" } },
-                                "required": ["cmd"],
-                            })),
+                            input_schema: None,
+                            approval: None,
                         }],
-                        tool_execution: Some(ToolExecutionConfig {
-                            daemon_url: server.url(),
-                            agent_id: "chat-codex".to_string(),
+                        responses: Some(ResponsesRequestOptions {
+                            store: Some(true),
+                            use_previous_response_id: true,
+                            ..ResponsesRequestOptions::default()
                         }),
                         ..UserTurnOptions::default()
                     },
                 },
-                Some(progress_tx),
+                None,
             )
             .await
-            .expect("run turn should succeed even when tool returns success:false");
-        assert_eq!(
-            result.last_agent_message.as_deref(),
-            Some("I cannot remove that file.")
-        );
+            .expect("run turn")
+            .last_agent_message
+            .unwrap_or_default()
+    }
+
+    async fn mock_first_chained_round(server: &mut mockito::ServerGuard) -> mockito::Mock {
+        server
+            .mock("POST", "/v1/responses")
+            .match_body(Matcher::Regex("where am i".to_string()))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_1\",\"output\":[{\"type\":\"function_call\",\"call_id\":\"call_1\",\"name\":\"shell_exec\",\"arguments\":\"{\\\"cmd\\\":\\\"pwd\\\"}\"}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await
+    }
 
-        let progress_events = drain_progress_events(&mut progress_rx);
-        let tool_error = progress_events.iter().find_map(|event| match event {
-            EventMsg::ToolError(error_event) => Some(error_event),
-            _ => None,
-        });
-        assert!(tool_error.is_some(), "should emit ToolError event for success:false");
-        let tool_error = tool_error.expect("tool error event");
-        assert_eq!(tool_error.tool_name, "shell.exec");
-        assert!(tool_error.error.contains("permission denied"));
+    #[tokio::test]
+    async fn run_turn_continues_from_previous_response_with_only_new_items() {
+        let mut server = Server::new_async().await;
+        let first_response_mock = mock_first_chained_round(&mut server).await;
+        // The follow-up round carries only the tool output, not the user turn.
+        let second_response_mock = server
+            .mock("POST", "/v1/responses")
+            .match_body(Matcher::Regex(r#""previous_response_id":"resp_1""#.to_string()))
+            .match_body(Matcher::Regex(
+                r#""input":\[\{"call_id":"call_1","output":"(?:[^"\\]|\\.)*","type":"function_call_output"\}\]"#
+                    .to_string(),
+            ))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_2\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"cwd is /tmp\"}]}]}}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        assert_eq!(run_chained_tool_turn(&mut server).await, "cwd is /tmp");
 
         first_response_mock.assert_async().await;
-        tool_execute_mock.assert_async().await;
         second_response_mock.assert_async().await;
     }
 
     #[tokio::test]
-    async fn run_turn_emits_tool_error_progress_when_tool_execution_fails() {
+    async fn run_turn_resends_full_history_when_previous_response_is_rejected() {
         let mut server = Server::new_async().await;
-
-        let first_response_mock = server
+        let first_response_mock = mock_first_chained_round(&mut server).await;
+        let stale_mock = server
+            .mock("POST", "/v1/responses")
+            .match_body(Matcher::Regex(
+                r#""previous_response_id":"resp_1""#.to_string(),
+            ))
+            .with_status(400)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"error":{"message":"Previous response with id 'resp_1' not found."}}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        let full_history_mock = server
             .mock("POST", "/v1/responses")
+            .match_body(Matcher::Regex("where am i".to_string()))
+            .match_body(Matcher::Regex(r#""call_id":"call_1""#.to_string()))
             .with_status(200)
             .with_header("content-type", "text/event-stream")
             .with_body(concat!(
                 "event: response.completed\n",
-                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_1\",\"output\":[{\"type\":\"function_call\",\"call_id\":\"call_fail\",\"name\":\"shell_exec\",\"arguments\":\"{\\\"cmd\\\":\\\"rm\\\"}\"}]}}\n\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_2\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"cwd is /tmp\"}]}]}}\n\n",
                 "data: [DONE]\n\n"
             ))
             .expect(1)
             .create_async()
             .await;
 
-        let tool_execute_mock = server
-            .mock("POST", "/api/v1/tools/execute")
-            .match_body(Matcher::Regex(r#""toolName":"shell.exec""#.to_string()))
-            .match_body(Matcher::Regex(r#""command":"rm""#.to_string()))
-            .with_status(500)
+        assert_eq!(run_chained_tool_turn(&mut server).await, "cwd is /tmp");
+
+        first_response_mock.assert_async().await;
+        stale_mock.assert_async().await;
+        full_history_mock.assert_async().await;
+    }
+
+    fn health_check_engine(base_url: String) -> FingerChatEngine {
+        FingerChatEngine::new(test_config(base_url))
+    }
+
+    #[tokio::test]
+    async fn check_health_classifies_provider_responses() {
+        let mut server = Server::new_async().await;
+        let healthy_mock = server
+            .mock("GET", "/v1/models")
+            .match_header("authorization", "Bearer test-key")
+            .with_status(200)
             .with_header("content-type", "application/json")
-            .with_body(json!({ "error": "permission denied" }).to_string())
+            .with_body(r#"{"object":"list","data":[]}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        let health = health_check_engine(server.url())
+            .check_health()
+            .await
+            .expect("healthy provider");
+        assert_eq!(health.state, HealthState::Reachable);
+        assert_eq!(health.status, Some(200));
+        healthy_mock.assert_async().await;
+        healthy_mock.remove_async().await;
+
+        let unauthorized_mock = server
+            .mock("GET", "/v1/models")
+            .with_status(401)
+            .with_body(r#"{"error":{"message":"invalid api key"}}"#)
             .expect(1)
             .create_async()
             .await;
+        let health = health_check_engine(server.url())
+            .check_health()
+            .await
+            .expect("auth failure is a status, not an error");
+        assert_eq!(health.state, HealthState::AuthFailed);
+        assert_eq!(health.status, Some(401));
+        unauthorized_mock.assert_async().await;
+
+        // Nothing listens on port 1, so the connection is refused.
+        let health = health_check_engine("http://127.0.0.1:1".to_string())
+            .check_health()
+            .await
+            .expect("unreachable provider is a status, not an error");
+        assert_eq!(health.state, HealthState::Unreachable);
+        assert_eq!(health.status, None);
+    }
 
-        let second_response_mock = server
+    #[tokio::test]
+    async fn run_turn_compacts_and_retries_once_on_context_length_error() {
+        let mut server = Server::new_async().await;
+        let overflow_mock = server
             .mock("POST", "/v1/responses")
-            .match_body(Matcher::Regex(r#""type":"function_call_output""#.to_string()))
-            .match_body(Matcher::Regex(r#""call_id":"call_fail""#.to_string()))
+            .with_status(400)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"error":{"type":"invalid_request_error","code":"context_length_exceeded","message":"Your input exceeds the context window of this model.","param":"input"}}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        // Created second so the first request hits the overflow mock.
+        let compacted_mock = server
+            .mock("POST", "/v1/responses")
+            .match_body(Matcher::Regex("task_digest".to_string()))
             .with_status(200)
             .with_header("content-type", "text/event-stream")
             .with_body(concat!(
                 "event: response.completed\n",
-                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_2\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"handled failure\"}]}]}}\n\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_1\",\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"fits now\"}]}]}}\n\n",
                 "data: [DONE]\n\n"
             ))
             .expect(1)
             .create_async()
             .await;
 
-        let engine = FingerChatEngine::new(LocalModelConfig {
-            provider_id: "test".to_string(),
-            provider_name: "test".to_string(),
-            base_url: server.url(),
-            wire_api: WireApi::Responses,
-            env_key: "TEST_KEY".to_string(),
-            api_key: "test-key".to_string(),
-            model: "gpt-test".to_string(),
-            tool_daemon_url: server.url(),
-            tool_agent_id: "chat-codex".to_string(),
-        });
+        let engine = FingerChatEngine::new(test_config(server.url()));
+
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("duration since epoch")
+            .as_millis();
+        let root = std::env::temp_dir().join(format!("finger-kernel-model-overflow-{ts}"));
+        let mut history_items = Vec::new();
+        for index in 0..10 {
+            history_items.push(json!({
+                "role": "user",
+                "content": [{
+                    "type": "input_text",
+                    "text": format!("user request {index}: {}", "X".repeat(240))
+                }],
+            }));
+            history_items.push(json!({
+                "role": "assistant",
+                "content": [{
+                    "type": "output_text",
+                    "text": format!("assistant result {index}: {}", "Y".repeat(260))
+                }],
+            }));
+        }
+        let options = UserTurnOptions {
+            session_id: Some("session-overflow".to_string()),
+            history_items,
+            context_ledger: Some(finger_kernel_protocol::ContextLedgerOptions {
+                enabled: true,
+                root_dir: Some(root.to_string_lossy().to_string()),
+                agent_id: Some("chat-codex".to_string()),
+                role: None,
+                mode: Some("main".to_string()),
+                can_read_all: false,
+                readable_agents: vec![],
+                focus_enabled: false,
+                focus_max_chars: None,
+                max_segment_bytes: None,
+                max_retained_segments: None,
+                focus_injection_role: None,
+            }),
+            ..UserTurnOptions::default()
+        };
 
-        let (progress_tx, mut progress_rx) = tokio::sync::mpsc::unbounded_channel::<EventMsg>();
         let result = engine
             .run_turn(
                 &TurnRequest {
                     items: vec![InputItem::Text {
-                        text: "run failing tool".to_string(),
+                        text: "continue".to_string(),
                     }],
-                    options: UserTurnOptions {
-                        tools: vec![ToolSpec {
-                            name: "shell.exec".to_string(),
-                            description: Some("Execute shell command".to_string()),
-                            input_schema: Some(json!({
-                                "type": "object",
-                                "properties": { "cmd": { "type": "string" } },
-                                "required": ["cmd"],
-                            })),
-                        }],
-                        tool_execution: Some(ToolExecutionConfig {
-                            daemon_url: server.url(),
-                            agent_id: "chat-codex".to_string(),
-                        }),
-                        ..UserTurnOptions::default()
-                    },
+                    options: options.clone(),
                 },
-                Some(progress_tx),
+                None,
             )
             .await
-            .expect("run turn");
-        assert_eq!(
-            result.last_agent_message.as_deref(),
-            Some("handled failure")
-        );
-
-        let progress_events = drain_progress_events(&mut progress_rx);
-        let tool_error = progress_events.iter().find_map(|event| match event {
-            EventMsg::ToolError(error_event) => Some(error_event),
-            _ => None,
-        });
-        assert!(tool_error.is_some());
-        let tool_error = tool_error.expect("tool error event");
-        assert_eq!(tool_error.tool_name, "shell.exec");
-        assert!(tool_error.error.contains("permission denied"));
-
-        let seqs = progress_events
-            .iter()
-            .filter_map(extract_progress_seq)
-            .collect::<Vec<_>>();
-        assert_eq!(seqs, vec![1, 2, 3, 4]);
+            .expect("compacted retry succeeds");
+        assert_eq!(result.last_agent_message.as_deref(), Some("fits now"));
 
-        first_response_mock.assert_async().await;
-        tool_execute_mock.assert_async().await;
-        second_response_mock.assert_async().await;
+        let ledger = build_context_ledger(&options).expect("ledger");
+        let compact_events = ledger
+            .query(&LedgerQueryRequest {
+                event_types: vec!["context_compact".to_string()],
+                ..LedgerQueryRequest::default()
+            })
+            .expect("query compact events")
+            .entries;
+        let _ = fs::remove_dir_all(&root);
+        assert_eq!(compact_events.len(), 1);
+        assert_eq!(compact_events[0].payload["context_length_exceeded"], true);
+
+        overflow_mock.assert_async().await;
+        compacted_mock.assert_async().await;
     }
 
     fn drain_progress_events(progress_rx: &mut UnboundedReceiver<EventMsg>) -> Vec<EventMsg> {
@@ -3666,9 +10790,15 @@ mod tests {
     fn extract_progress_seq(event: &EventMsg) -> Option<u64> {
         match event {
             EventMsg::ModelRound(model_round) => Some(model_round.seq),
+            EventMsg::OutputTextDelta(delta) => Some(delta.seq),
+            EventMsg::Reasoning(reasoning) => Some(reasoning.seq),
             EventMsg::ToolCall(tool_call) => Some(tool_call.seq),
             EventMsg::ToolResult(tool_result) => Some(tool_result.seq),
             EventMsg::ToolError(tool_error) => Some(tool_error.seq),
+            EventMsg::Usage(usage) => Some(usage.seq),
+            EventMsg::ExecApprovalRequest(request) | EventMsg::PatchApprovalRequest(request) => {
+                Some(request.seq)
+            }
             _ => None,
         }
     }
@@ -3742,6 +10872,39 @@ mod tests {
         assert_eq!(parsed.output_text, Some("开始执行。\n执行完成。".to_string()));
     }
     
+    #[test]
+    fn parse_output_text_joins_split_answer_and_keeps_calls_and_reasoning_separate() {
+        let payload = json!({
+            "id": "resp_split",
+            "output": [
+                {
+                    "type": "reasoning",
+                    "summary": [{ "type": "summary_text", "text": "plan the answer" }]
+                },
+                {
+                    "type": "message",
+                    "content": [{ "type": "output_text", "text": "part one" }]
+                },
+                {
+                    "type": "function_call",
+                    "call_id": "call_split",
+                    "name": "exec_command",
+                    "arguments": "{\"cmd\":\"pwd\"}"
+                },
+                {
+                    "type": "message",
+                    "content": [{ "type": "output_text", "text": "part two" }]
+                }
+            ]
+        });
+
+        let parsed = parse_protocol_payload(&payload).expect("parse payload");
+        assert_eq!(parsed.output_text.as_deref(), Some("part one\npart two"));
+        assert_eq!(parsed.function_calls.len(), 1);
+        assert_eq!(parsed.function_calls[0].call_id, "call_split");
+        assert_eq!(parsed.reasoning, vec!["plan the answer".to_string()]);
+    }
+
     #[test]
     fn parse_output_text_filters_other_content_types() {
         // 测试：message 中的其他 content type（如 refusal）不应混入 output_text
@@ -3804,4 +10967,199 @@ mod tests {
         let parsed = parse_protocol_payload(&payload).expect("parse payload");
         assert_eq!(parsed.output_text, Some("有效文本".to_string()));
     }
+
+    #[tokio::test]
+    async fn check_health_reports_missing_models_route_as_misconfigured() {
+        let mut server = Server::new_async().await;
+        let not_found_mock = server
+            .mock("GET", "/v1/models")
+            .with_status(404)
+            .expect(1)
+            .create_async()
+            .await;
+        let health = health_check_engine(server.url())
+            .check_health()
+            .await
+            .expect("missing route is a status, not an error");
+        assert_eq!(health.state, HealthState::Misconfigured);
+        assert_eq!(health.status, Some(404));
+        not_found_mock.assert_async().await;
+        not_found_mock.remove_async().await;
+
+        let not_allowed_mock = server
+            .mock("GET", "/v1/models")
+            .with_status(405)
+            .expect(1)
+            .create_async()
+            .await;
+        let health = health_check_engine(server.url())
+            .check_health()
+            .await
+            .expect("disallowed method is a status, not an error");
+        assert_eq!(health.state, HealthState::Reachable);
+        assert_eq!(health.status, Some(405));
+        not_allowed_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn check_health_caps_the_models_list_body() {
+        let mut server = Server::new_async().await;
+        let models_mock = server
+            .mock("GET", "/v1/models")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(json!({ "object": "list", "data": ["x".repeat(4096)] }).to_string())
+            .expect(1)
+            .create_async()
+            .await;
+        let engine = FingerChatEngine::with_client_options(
+            test_config(server.url()),
+            ClientOptions {
+                max_response_bytes: 1024,
+                ..ClientOptions::default()
+            },
+        )
+        .expect("build engine");
+
+        let error = engine
+            .check_health()
+            .await
+            .expect_err("oversized models list should fail");
+        assert!(
+            matches!(error, ModelError::ResponseTooLarge { limit: 1024 }),
+            "unexpected error: {error}"
+        );
+        models_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn check_health_ignores_missing_models_route_for_custom_endpoints() {
+        let mut server = Server::new_async().await;
+        let not_found_mock = server
+            .mock("GET", "/v1/models")
+            .with_status(404)
+            .expect(2)
+            .create_async()
+            .await;
+
+        let azure_engine = FingerChatEngine::new(LocalModelConfig {
+            azure: Some(AzureDeployment {
+                deployment: "gpt4o-prod".to_string(),
+                api_version: "2025-04-01-preview".to_string(),
+            }),
+            ..test_config(server.url())
+        });
+        let health = azure_engine
+            .check_health()
+            .await
+            .expect("azure deployment health");
+        assert_eq!(health.state, HealthState::Reachable);
+        assert_eq!(health.status, Some(404));
+
+        let gateway_engine = FingerChatEngine::new(LocalModelConfig {
+            responses_path: Some("/openai/responses".to_string()),
+            ..test_config(server.url())
+        });
+        let health = gateway_engine
+            .check_health()
+            .await
+            .expect("custom gateway health");
+        assert_eq!(health.state, HealthState::Reachable);
+        assert_eq!(health.status, Some(404));
+        not_found_mock.assert_async().await;
+    }
+
+    #[test]
+    fn tool_argument_whitespace_inside_keys_and_strings_is_significant() {
+        let with_newline = json!({ "path": "a.txt", "content": "a\n" });
+        let without_newline = json!({ "path": "a.txt", "content": "a" });
+        assert_ne!(
+            canonicalize_tool_arguments(with_newline.clone()),
+            canonicalize_tool_arguments(without_newline.clone())
+        );
+        assert_ne!(
+            ToolResultCache::key("file.write", &with_newline),
+            ToolResultCache::key("file.write", &without_newline)
+        );
+        assert_ne!(
+            canonicalize_tool_arguments(json!({ " path": "a.txt" })),
+            canonicalize_tool_arguments(json!({ "path": "a.txt" }))
+        );
+    }
+
+    /// Reads one request's headers and `content-length` body off `socket`.
+    async fn read_http_request(socket: &mut tokio::net::TcpStream) {
+        use tokio::io::AsyncReadExt;
+
+        let mut request = Vec::new();
+        let mut chunk = [0_u8; 4096];
+        loop {
+            let read = socket.read(&mut chunk).await.expect("read request");
+            assert!(read > 0, "client closed before sending a full request");
+            request.extend_from_slice(&chunk[..read]);
+            let text = String::from_utf8_lossy(&request);
+            let Some(header_end) = text.find("\r\n\r\n") else {
+                continue;
+            };
+            let content_length = text[..header_end]
+                .lines()
+                .find_map(|line| {
+                    let (name, value) = line.split_once(':')?;
+                    name.eq_ignore_ascii_case("content-length")
+                        .then(|| value.trim().parse::<usize>().ok())
+                        .flatten()
+                })
+                .unwrap_or(0);
+            if request.len() >= header_end + 4 + content_length {
+                return;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn tool_daemon_connection_dropped_mid_request_is_retried() {
+        use tokio::io::AsyncWriteExt;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind daemon");
+        let daemon_url = format!("http://{}", listener.local_addr().expect("daemon addr"));
+        let daemon = tokio::spawn(async move {
+            // The connection is accepted, then dropped without a response,
+            // like a keep-alive socket the daemon reset.
+            let (mut socket, _) = listener.accept().await.expect("accept first");
+            read_http_request(&mut socket).await;
+            drop(socket);
+
+            let (mut socket, _) = listener.accept().await.expect("accept retry");
+            read_http_request(&mut socket).await;
+            let body = r#"{"success":true,"result":{"stdout":"/tmp"}}"#;
+            let response = format!(
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            socket
+                .write_all(response.as_bytes())
+                .await
+                .expect("write response");
+        });
+
+        let engine = FingerChatEngine::new(test_config(daemon_url.clone()));
+        let call = FunctionCallItem {
+            call_id: "call_reset".to_string(),
+            name: "shell_exec".to_string(),
+            arguments: "{\"cmd\":\"pwd\"}".to_string(),
+        };
+        let config = ToolExecutionConfig {
+            retry_transient_errors: true,
+            tool_max_retries: Some(1),
+            ..daemon_tool_config(daemon_url)
+        };
+        let (result, retries) = engine
+            .execute_single_tool_call(&call, &config, "shell.exec", None)
+            .await;
+        assert_eq!(result.expect("retried tool call")["stdout"], json!("/tmp"));
+        assert_eq!(retries, 1);
+        daemon.await.expect("daemon task");
+    }
 }
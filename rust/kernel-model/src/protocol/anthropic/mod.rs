@@ -1,6 +1,8 @@
 //! Anthropic Messages API protocol adapter.
 //!
-//! Provides request builder, response parser, and HTTP transport for Anthropic Claude models.
+//! Converts the engine's Responses-shaped history into Messages API requests
+//! and normalizes Messages responses back into the Responses payload shape,
+//! so the tool loop and compaction are shared with the other wire APIs.
 
 pub(crate) mod request;
 pub(crate) mod response;
@@ -1,147 +1,389 @@
 //! Anthropic Messages API request builder.
 //!
-//! Transforms Finger kernel request format to Anthropic Messages API format.
+//! Converts the engine's Responses-shaped history into Messages API
+//! `messages`, so the tool loop and compaction stay wire-agnostic.
 
-/// Stream mode for Anthropic API.
-const STREAM_MODE: bool = true;
-
-use finger_kernel_protocol::{AnthropicRequestOptions, InputItem, ToolSpec};
-use serde_json::{json, Value};
+use finger_kernel_protocol::{AnthropicRequestOptions, ResponsesRequestOptions, ToolChoice};
+use serde_json::{json, Map, Value};
 
 /// Default max tokens for Anthropic API (required field).
 const DEFAULT_MAX_TOKENS: u64 = 4096;
 
 /// Build Anthropic Messages API request payload.
-pub fn build_anthropic_request_payload(
+pub(crate) fn build_anthropic_request_payload(
     model: &str,
-    items: &[InputItem],
+    input: &[Value],
     system_prompt: Option<&str>,
-    tools: Option<&[ToolSpec]>,
+    tools: Option<&[Value]>,
+    responses: Option<&ResponsesRequestOptions>,
     anthropic: Option<&AnthropicRequestOptions>,
 ) -> Value {
     let max_tokens = anthropic
         .and_then(|opts| opts.max_tokens)
+        .or_else(|| responses.and_then(|opts| opts.max_output_tokens))
         .unwrap_or(DEFAULT_MAX_TOKENS);
 
-    let mut payload = json!({
-        "stream": STREAM_MODE,
+    let (system_messages, messages) = convert_history_to_anthropic_messages(input);
+    let system = system_prompt
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(ToString::to_string)
+        .into_iter()
+        .chain(system_messages)
+        .collect::<Vec<_>>()
+        .join("\n\n");
 
+    let mut payload = json!({
         "model": model,
         "max_tokens": max_tokens,
+        "stream": true,
+        "messages": messages,
     });
+    // Anthropic takes the system prompt as a top-level field, not a message.
+    if !system.is_empty() {
+        payload["system"] = Value::String(system);
+    }
 
-    // System prompt (Anthropic uses "system" field, not "instructions")
-    if let Some(system) = system_prompt
-        .map(str::trim)
-        .filter(|value| !value.is_empty())
+    if let Some(temperature) = anthropic
+        .and_then(|opts| opts.temperature)
+        .or_else(|| responses.and_then(|opts| opts.temperature))
     {
-        payload["system"] = Value::String(system.to_string());
+        payload["temperature"] = json!(temperature);
+    }
+    if let Some(stop_sequences) = anthropic
+        .and_then(|opts| opts.stop_sequences.as_ref())
+        .filter(|sequences| !sequences.is_empty())
+    {
+        payload["stop_sequences"] = json!(stop_sequences);
+    }
+    if let Some(budget_tokens) = anthropic
+        .and_then(|opts| opts.thinking.as_ref())
+        .and_then(|thinking| thinking.budget_tokens)
+    {
+        payload["thinking"] = json!({
+            "type": "enabled",
+            "budget_tokens": budget_tokens,
+        });
     }
 
-    // Convert InputItem to Anthropic messages format (simple string format)
-    // Use single user message with string content (not array)
-    let user_content = items
-        .iter()
-        .filter_map(|item| match item {
-            InputItem::Text { text } => Some(text.clone()),
-            _ => None,
-        })
-        .collect::<Vec<_>>()
-        .join("\n");
+    if let Some(tool_defs) = tools.filter(|defs| !defs.is_empty()) {
+        payload["tools"] = Value::Array(tool_defs.iter().map(convert_tool_to_anthropic).collect());
+        let mut tool_choice = match responses.and_then(|opts| opts.tool_choice.as_ref()) {
+            None | Some(ToolChoice::Auto) => json!({ "type": "auto" }),
+            Some(ToolChoice::None) => json!({ "type": "none" }),
+            Some(ToolChoice::Required) => json!({ "type": "any" }),
+            Some(ToolChoice::Function { name }) => json!({ "type": "tool", "name": name }),
+        };
+        // Anthropic has the inverse flag, and nests it under `tool_choice`.
+        let disable_parallel_tool_use = anthropic
+            .and_then(|opts| opts.disable_parallel_tool_use)
+            .or_else(|| {
+                responses
+                    .and_then(|opts| opts.parallel_tool_calls)
+                    .map(|parallel| !parallel)
+            });
+        if let Some(disable) = disable_parallel_tool_use {
+            tool_choice["disable_parallel_tool_use"] = Value::Bool(disable);
+        }
+        payload["tool_choice"] = tool_choice;
+    }
 
-    payload["messages"] = json!([
-        {"role": "user", "content": user_content}
-    ]);
+    payload
+}
 
-    // Tools
-    if let Some(tool_defs) = tools.filter(|defs| !defs.is_empty()) {
-        payload["tools"] = Value::Array(
-            tool_defs
-                .iter()
-                .map(|tool| json!({
-                    "name": tool.name,
-                    "description": tool.description.clone().unwrap_or_default(),
-                    "input_schema": tool.input_schema.clone().unwrap_or(json!({})),
-                }))
-                .collect(),
-        );
+/// Responses tools carry their schema as `parameters`; Anthropic calls it
+/// `input_schema` and requires an object schema.
+fn convert_tool_to_anthropic(tool: &Value) -> Value {
+    json!({
+        "name": tool.get("name").cloned().unwrap_or(Value::Null),
+        "description": tool.get("description").and_then(Value::as_str).unwrap_or_default(),
+        "input_schema": tool
+            .get("parameters")
+            .filter(|schema| schema.is_object())
+            .cloned()
+            .unwrap_or_else(|| json!({ "type": "object", "properties": {} })),
+    })
+}
 
-        // Tool choice
-        payload["tool_choice"] = json!({ "type": "auto" });
+/// Returns the system/developer texts found in history alongside the
+/// converted messages. Consecutive items of the same role are merged, since
+/// tool results must sit in the user turn right after their `tool_use`.
+pub(crate) fn convert_history_to_anthropic_messages(
+    history: &[Value],
+) -> (Vec<String>, Vec<Value>) {
+    let mut system_texts = Vec::new();
+    let mut messages: Vec<Value> = Vec::with_capacity(history.len());
+    for item in history {
+        let (role, blocks) = match item.get("type").and_then(Value::as_str).unwrap_or_default() {
+            "function_call" => ("assistant", vec![convert_function_call_to_tool_use(item)]),
+            "function_call_output" => ("user", vec![convert_function_output_to_tool_result(item)]),
+            // Replaying thinking needs Anthropic's signatures, which Responses
+            // reasoning items do not carry.
+            "reasoning" => continue,
+            _ => match item.get("role").and_then(Value::as_str) {
+                Some("system") | Some("developer") => {
+                    let text = collect_text(item.get("content"));
+                    if !text.trim().is_empty() {
+                        system_texts.push(text);
+                    }
+                    continue;
+                }
+                Some("assistant") => (
+                    "assistant",
+                    convert_content_blocks(item.get("content"), false),
+                ),
+                Some("user") => ("user", convert_content_blocks(item.get("content"), true)),
+                _ => continue,
+            },
+        };
+        if blocks.is_empty() {
+            continue;
+        }
+        push_blocks(&mut messages, role, blocks);
     }
+    (system_texts, messages)
+}
 
-    // Parallel tool calls (Anthropic uses inverted semantics)
-    if let Some(disable_parallel) = anthropic.and_then(|opts| opts.disable_parallel_tool_use) {
-        payload["disable_parallel_tool_use"] = Value::Bool(disable_parallel);
+fn push_blocks(messages: &mut Vec<Value>, role: &str, blocks: Vec<Value>) {
+    if let Some(content) = messages
+        .last_mut()
+        .filter(|message| message.get("role").and_then(Value::as_str) == Some(role))
+        .and_then(|message| message.get_mut("content"))
+        .and_then(Value::as_array_mut)
+    {
+        content.extend(blocks);
+        return;
     }
+    messages.push(json!({
+        "role": role,
+        "content": blocks,
+    }));
+}
 
-    // Thinking options (extended thinking for Claude models)
-    if let Some(thinking) = anthropic.and_then(|opts| opts.thinking.as_ref()) {
-        if thinking.budget_tokens.is_some() {
-            payload["thinking"] = json!({
-                "type": "enabled",
-                "budget_tokens": thinking.budget_tokens.unwrap_or(10000),
-            });
-        }
-    }
+fn convert_function_call_to_tool_use(item: &Value) -> Value {
+    let arguments = item
+        .get("arguments")
+        .and_then(Value::as_str)
+        .unwrap_or("{}");
+    let input = serde_json::from_str::<Value>(arguments)
+        .ok()
+        .filter(Value::is_object)
+        .unwrap_or_else(|| Value::Object(Map::new()));
+    json!({
+        "type": "tool_use",
+        "id": item
+            .get("call_id")
+            .or_else(|| item.get("id"))
+            .cloned()
+            .unwrap_or(Value::Null),
+        "name": item.get("name").cloned().unwrap_or(Value::Null),
+        "input": input,
+    })
+}
 
-    payload
+fn convert_function_output_to_tool_result(item: &Value) -> Value {
+    let output = match item.get("output") {
+        Some(Value::String(text)) => Value::String(text.clone()),
+        Some(parts @ Value::Array(_)) => Value::Array(convert_content_blocks(Some(parts), true)),
+        Some(other) => Value::String(other.to_string()),
+        None => Value::String(String::new()),
+    };
+    json!({
+        "type": "tool_result",
+        "tool_use_id": item.get("call_id").cloned().unwrap_or(Value::Null),
+        "content": output,
+    })
 }
 
-/// Build Anthropic messages format (array of content blocks).
-pub fn build_anthropic_messages(items: &[InputItem]) -> Vec<Value> {
-    items
+/// Empty text blocks are rejected by the API, so they are dropped; images
+/// and documents are only kept on user messages.
+fn convert_content_blocks(content: Option<&Value>, allow_media: bool) -> Vec<Value> {
+    let parts = match content {
+        Some(Value::String(text)) => return text_block(text).into_iter().collect(),
+        Some(Value::Array(parts)) => parts,
+        _ => return Vec::new(),
+    };
+    parts
         .iter()
-        .filter_map(|item| match item {
-            InputItem::Text { text } => Some(json!({
-                "type": "text",
-                "text": text,
-            })),
-            InputItem::Image { image_url } => Some(json!({
-                "type": "image",
-                "source": {
-                    "type": "url",
-                    "url": image_url,
-                },
-            })),
-            _ => None,
-        })
+        .filter_map(
+            |part| match part.get("type").and_then(Value::as_str).unwrap_or_default() {
+                "input_text" | "output_text" | "text" => part
+                    .get("text")
+                    .and_then(Value::as_str)
+                    .and_then(text_block),
+                "input_image" | "image" if allow_media => part
+                    .get("image_url")
+                    .and_then(Value::as_str)
+                    .map(convert_image_url_to_source)
+                    .map(|source| json!({ "type": "image", "source": source })),
+                "input_file" if allow_media => convert_input_file_to_document(part),
+                _ => None,
+            },
+        )
         .collect()
 }
 
-/// Convert history items to Anthropic messages format.
-pub fn convert_history_to_anthropic_messages(history: &[Value]) -> Vec<Value> {
-    history.iter().map(convert_history_item_to_anthropic).collect()
+/// Inline `file_data` becomes a base64 document; a `file_id` refers to a
+/// file uploaded through the Files API.
+fn convert_input_file_to_document(part: &Value) -> Option<Value> {
+    let source = if let Some(file_id) = part.get("file_id").and_then(Value::as_str) {
+        json!({ "type": "file", "file_id": file_id })
+    } else {
+        convert_image_url_to_source(part.get("file_data").and_then(Value::as_str)?)
+    };
+    let mut document = json!({ "type": "document", "source": source });
+    if let Some(filename) = part.get("filename").and_then(Value::as_str) {
+        document["title"] = Value::String(filename.to_string());
+    }
+    Some(document)
 }
 
-fn convert_history_item_to_anthropic(item: &Value) -> Value {
-    let role = item.get("role").and_then(|r| r.as_str()).unwrap_or("user");
-    let content = item.get("content").cloned().unwrap_or(json!(""));
+fn text_block(text: &str) -> Option<Value> {
+    if text.trim().is_empty() {
+        return None;
+    }
+    Some(json!({ "type": "text", "text": text }))
+}
 
-    json!({
-        "role": role,
-        "content": content,
-    })
+fn convert_image_url_to_source(image_url: &str) -> Value {
+    if let Some((media_type, data)) = image_url
+        .strip_prefix("data:")
+        .and_then(|rest| rest.split_once(";base64,"))
+    {
+        return json!({
+            "type": "base64",
+            "media_type": media_type,
+            "data": data,
+        });
+    }
+    json!({ "type": "url", "url": image_url })
 }
 
-fn convert_content_block_to_anthropic(block: &Value) -> Value {
-    let block_type = block.get("type").and_then(|t| t.as_str()).unwrap_or("text");
+fn collect_text(content: Option<&Value>) -> String {
+    convert_content_blocks(content, false)
+        .iter()
+        .filter_map(|block| block.get("text").and_then(Value::as_str))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
 
-    match block_type {
-        "text" => {
-            let text = block.get("text").and_then(|t| t.as_str()).unwrap_or("");
-            json!({"type": "text", "text": text})
-        }
-        "image" => {
-            let url = block
-                .get("image_url")
-                .and_then(|u| u.as_str())
-                .unwrap_or("");
-            json!({
-                "type": "image",
-                "source": {"type": "url", "url": url},
-            })
-        }
-        _ => block.clone(),
+#[cfg(test)]
+mod tests {
+    use super::build_anthropic_request_payload;
+    use finger_kernel_protocol::{AnthropicRequestOptions, ResponsesRequestOptions, ToolChoice};
+    use serde_json::json;
+
+    #[test]
+    fn payload_converts_history_tools_and_system_prompt() {
+        let history = vec![
+            json!({"role":"developer","content":[{"type":"input_text","text":"prefer rg"}]}),
+            json!({"role":"user","content":[{"type":"input_text","text":"list files"},{"type":"input_image","image_url":"data:image/png;base64,AAAA"}]}),
+            json!({"type":"reasoning","summary":[{"type":"summary_text","text":"thinking"}]}),
+            json!({"type":"message","role":"assistant","content":[{"type":"output_text","text":"Checking."}]}),
+            json!({"type":"function_call","call_id":"toolu_1","name":"shell_exec","arguments":"{\"cmd\":\"ls\"}"}),
+            json!({"type":"function_call_output","call_id":"toolu_1","output":"{\"stdout\":\"a.txt\"}"}),
+        ];
+        let tools = [json!({
+            "type": "function",
+            "name": "shell_exec",
+            "description": "Execute shell command",
+            "parameters": {"type": "object", "properties": {"cmd": {"type": "string"}}},
+        })];
+
+        let payload = build_anthropic_request_payload(
+            "claude-test",
+            &history,
+            Some("be brief"),
+            Some(&tools),
+            Some(&ResponsesRequestOptions {
+                tool_choice: Some(ToolChoice::Required),
+                parallel_tool_calls: Some(false),
+                ..ResponsesRequestOptions::default()
+            }),
+            Some(&AnthropicRequestOptions {
+                max_tokens: Some(1024),
+                ..AnthropicRequestOptions::default()
+            }),
+        );
+
+        assert_eq!(payload["max_tokens"], json!(1024));
+        assert_eq!(payload["system"], json!("be brief\n\nprefer rg"));
+        assert_eq!(
+            payload["tool_choice"],
+            json!({"type": "any", "disable_parallel_tool_use": true})
+        );
+        assert_eq!(
+            payload["tools"],
+            json!([{
+                "name": "shell_exec",
+                "description": "Execute shell command",
+                "input_schema": {"type": "object", "properties": {"cmd": {"type": "string"}}},
+            }])
+        );
+        assert_eq!(
+            payload["messages"],
+            json!([
+                {
+                    "role": "user",
+                    "content": [
+                        {"type": "text", "text": "list files"},
+                        {"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": "AAAA"}},
+                    ],
+                },
+                {
+                    "role": "assistant",
+                    "content": [
+                        {"type": "text", "text": "Checking."},
+                        {"type": "tool_use", "id": "toolu_1", "name": "shell_exec", "input": {"cmd": "ls"}},
+                    ],
+                },
+                {
+                    "role": "user",
+                    "content": [
+                        {"type": "tool_result", "tool_use_id": "toolu_1", "content": "{\"stdout\":\"a.txt\"}"},
+                    ],
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn payload_defaults_max_tokens_and_omits_tools_when_absent() {
+        let history = vec![json!({"role":"user","content":"hi"})];
+        let payload =
+            build_anthropic_request_payload("claude-test", &history, None, None, None, None);
+
+        assert_eq!(payload["max_tokens"], json!(4096));
+        assert!(payload.get("system").is_none());
+        assert!(payload.get("tools").is_none());
+        assert!(payload.get("tool_choice").is_none());
+        assert_eq!(
+            payload["messages"],
+            json!([{"role": "user", "content": [{"type": "text", "text": "hi"}]}])
+        );
+    }
+
+    #[test]
+    fn payload_converts_user_files_to_documents() {
+        let history = vec![json!({"role":"user","content":[
+            {"type":"input_text","text":"summarize"},
+            {"type":"input_file","filename":"report.pdf","file_data":"data:application/pdf;base64,JVBERi0="},
+            {"type":"input_file","file_id":"file_abc123"},
+        ]})];
+        let payload =
+            build_anthropic_request_payload("claude-test", &history, None, None, None, None);
+
+        assert_eq!(
+            payload["messages"],
+            json!([{"role": "user", "content": [
+                {"type": "text", "text": "summarize"},
+                {
+                    "type": "document",
+                    "source": {"type": "base64", "media_type": "application/pdf", "data": "JVBERi0="},
+                    "title": "report.pdf",
+                },
+                {"type": "document", "source": {"type": "file", "file_id": "file_abc123"}},
+            ]}])
+        );
     }
 }
@@ -2,7 +2,12 @@
 //!
 //! Parses Anthropic SSE events and converts to kernel Event format.
 
-use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+use serde_json::{json, Map, Value};
+
+use crate::protocol::response::{split_sse_block, WireResponseBody};
+use crate::ModelError;
 
 /// Anthropic SSE event types.
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -41,6 +46,282 @@ pub fn parse_anthropic_sse_data(data: &str) -> Option<Value> {
     serde_json::from_str::<Value>(data).ok()
 }
 
+/// Parses a Messages API body (streamed or not) into the Responses payload
+/// shape consumed by `parse_protocol_payload`.
+pub(crate) fn parse_anthropic_wire_response(body: WireResponseBody) -> Result<Value, ModelError> {
+    match body {
+        WireResponseBody::Json(bytes) => {
+            let message = serde_json::from_slice::<Value>(&bytes)?;
+            if let Some(error) = extract_error_message(&message) {
+                return Err(ModelError::StreamFailed { message: error });
+            }
+            Ok(normalize_anthropic_message(&message))
+        }
+        WireResponseBody::Sse(raw) => parse_anthropic_sse_response(&raw),
+    }
+}
+
+/// Maps a streamed Messages event to the Responses delta events that drive
+/// live progress, so streaming looks the same regardless of wire API.
+pub(crate) fn anthropic_event_progress_events(
+    event_type: &str,
+    event: &Value,
+) -> Vec<(&'static str, Value)> {
+    if parse_anthropic_event_type(event_type) != AnthropicEventType::ContentBlockDelta {
+        return Vec::new();
+    }
+    let Some(delta) = event.get("delta") else {
+        return Vec::new();
+    };
+    match delta.get("type").and_then(Value::as_str) {
+        Some("text_delta") => delta
+            .get("text")
+            .and_then(Value::as_str)
+            .map(|text| vec![("response.output_text.delta", json!({ "delta": text }))])
+            .unwrap_or_default(),
+        Some("thinking_delta") => delta
+            .get("thinking")
+            .and_then(Value::as_str)
+            .map(|text| {
+                vec![(
+                    "response.reasoning_summary_text.delta",
+                    json!({ "delta": text }),
+                )]
+            })
+            .unwrap_or_default(),
+        _ => Vec::new(),
+    }
+}
+
+/// Rebuilds the final message from `message_start`, the content block events
+/// and `message_delta`, then normalizes it like a non-streamed response.
+fn parse_anthropic_sse_response(raw: &str) -> Result<Value, ModelError> {
+    let normalized = raw.replace("\r\n", "\n");
+    let mut message: Option<Value> = None;
+    let mut blocks: BTreeMap<u64, Value> = BTreeMap::new();
+    let mut tool_inputs: BTreeMap<u64, String> = BTreeMap::new();
+    let mut stop_reason: Option<Value> = None;
+    let mut output_tokens: Option<Value> = None;
+
+    for block in normalized.split("\n\n") {
+        let Some((event_name, data)) = split_sse_block(block) else {
+            continue;
+        };
+        let Some(event) = parse_anthropic_sse_data(&data) else {
+            continue;
+        };
+        let event_type = event
+            .get("type")
+            .and_then(Value::as_str)
+            .or(event_name.as_deref())
+            .unwrap_or_default();
+        let index = event.get("index").and_then(Value::as_u64).unwrap_or(0);
+        match parse_anthropic_event_type(event_type) {
+            AnthropicEventType::MessageStart => {
+                message = Some(event.get("message").cloned().unwrap_or_else(|| json!({})));
+            }
+            AnthropicEventType::ContentBlockStart => {
+                if let Some(content_block) = event.get("content_block") {
+                    blocks.insert(index, content_block.clone());
+                }
+            }
+            AnthropicEventType::ContentBlockDelta => {
+                let Some(delta) = event.get("delta") else {
+                    continue;
+                };
+                let entry = blocks.entry(index).or_insert_with(|| json!({}));
+                match delta
+                    .get("type")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+                {
+                    "text_delta" => append_block_text(entry, "text", delta.get("text")),
+                    "thinking_delta" => append_block_text(entry, "thinking", delta.get("thinking")),
+                    "input_json_delta" => {
+                        if let Some(partial) = delta.get("partial_json").and_then(Value::as_str) {
+                            tool_inputs.entry(index).or_default().push_str(partial);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            AnthropicEventType::MessageDelta => {
+                if let Some(reason) = event
+                    .get("delta")
+                    .and_then(|delta| delta.get("stop_reason"))
+                {
+                    stop_reason = Some(reason.clone());
+                }
+                if let Some(tokens) = event
+                    .get("usage")
+                    .and_then(|usage| usage.get("output_tokens"))
+                {
+                    output_tokens = Some(tokens.clone());
+                }
+            }
+            AnthropicEventType::Error => {
+                if let Some(error) = extract_error_message(&event) {
+                    return Err(ModelError::StreamFailed { message: error });
+                }
+            }
+            AnthropicEventType::ContentBlockStop
+            | AnthropicEventType::MessageStop
+            | AnthropicEventType::Ping => {}
+        }
+    }
+
+    let Some(mut message) = message else {
+        return Err(ModelError::MissingStreamResponse);
+    };
+    for (index, partial_json) in tool_inputs {
+        if let Some(block) = blocks.get_mut(&index) {
+            // Streamed tool input arrives as JSON fragments; the start event
+            // only carries an empty placeholder object.
+            block["input"] =
+                serde_json::from_str::<Value>(&partial_json).unwrap_or_else(|_| json!({}));
+        }
+    }
+    message["content"] = Value::Array(blocks.into_values().collect());
+    if let Some(reason) = stop_reason {
+        message["stop_reason"] = reason;
+    }
+    if let Some(tokens) = output_tokens {
+        message["usage"]["output_tokens"] = tokens;
+    }
+    Ok(normalize_anthropic_message(&message))
+}
+
+fn append_block_text(block: &mut Value, key: &str, text: Option<&Value>) {
+    let Some(text) = text.and_then(Value::as_str) else {
+        return;
+    };
+    let mut combined = block
+        .get(key)
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string();
+    combined.push_str(text);
+    block[key] = Value::String(combined);
+}
+
+fn extract_error_message(value: &Value) -> Option<String> {
+    if value.get("type").and_then(Value::as_str) != Some("error") {
+        return None;
+    }
+    let error = value.get("error");
+    let message = error
+        .and_then(|error| error.get("message"))
+        .and_then(Value::as_str)
+        .unwrap_or("Unknown error");
+    let error_type = error
+        .and_then(|error| error.get("type"))
+        .and_then(Value::as_str)
+        .unwrap_or("error");
+    Some(format!("{error_type}: {message}"))
+}
+
+/// Maps `text`, `thinking` and `tool_use` content blocks onto Responses
+/// `message`, `reasoning` and `function_call` output items.
+fn normalize_anthropic_message(message: &Value) -> Value {
+    let mut reasoning = String::new();
+    let mut text = String::new();
+    let mut function_calls = Vec::new();
+    for block in message
+        .get("content")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+    {
+        match block
+            .get("type")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+        {
+            "text" => text.push_str(
+                block
+                    .get("text")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default(),
+            ),
+            "thinking" => reasoning.push_str(
+                block
+                    .get("thinking")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default(),
+            ),
+            "tool_use" => function_calls.push(json!({
+                "type": "function_call",
+                "call_id": block.get("id").cloned().unwrap_or(Value::Null),
+                "name": block.get("name").cloned().unwrap_or(Value::Null),
+                "arguments": block
+                    .get("input")
+                    .filter(|input| input.is_object())
+                    .map(Value::to_string)
+                    .unwrap_or_else(|| "{}".to_string()),
+            })),
+            _ => {}
+        }
+    }
+
+    let mut output = Vec::with_capacity(function_calls.len() + 2);
+    if !reasoning.trim().is_empty() {
+        output.push(json!({
+            "type": "reasoning",
+            "summary": [{ "type": "summary_text", "text": reasoning }],
+        }));
+    }
+    if !text.is_empty() {
+        output.push(json!({
+            "type": "message",
+            "role": "assistant",
+            "content": [{ "type": "output_text", "text": text }],
+        }));
+    }
+    output.extend(function_calls);
+
+    let mut payload = Map::new();
+    if let Some(id) = message.get("id").filter(|id| id.is_string()) {
+        payload.insert("id".to_string(), id.clone());
+    }
+    let stop_reason = message
+        .get("stop_reason")
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    if stop_reason == "max_tokens" {
+        payload.insert("status".to_string(), json!("incomplete"));
+        payload.insert(
+            "incomplete_details".to_string(),
+            json!({ "reason": "max_output_tokens" }),
+        );
+    } else {
+        payload.insert("status".to_string(), json!("completed"));
+    }
+    if !stop_reason.is_empty() {
+        payload.insert(
+            "finish_reason".to_string(),
+            json!(map_anthropic_stop_reason(stop_reason)),
+        );
+    }
+    payload.insert("output".to_string(), Value::Array(output));
+    if let Some(usage) = message.get("usage").and_then(Value::as_object) {
+        let input_tokens = usage.get("input_tokens").and_then(Value::as_u64);
+        let output_tokens = usage.get("output_tokens").and_then(Value::as_u64);
+        let total_tokens = match (input_tokens, output_tokens) {
+            (Some(input), Some(output)) => Some(input.saturating_add(output)),
+            _ => None,
+        };
+        payload.insert(
+            "usage".to_string(),
+            json!({
+                "input_tokens": input_tokens,
+                "output_tokens": output_tokens,
+                "total_tokens": total_tokens,
+            }),
+        );
+    }
+    Value::Object(payload)
+}
+
 /// Convert Anthropic message_start event to kernel event.
 ///
 /// Anthropic format:
@@ -266,6 +547,104 @@ fn map_anthropic_stop_reason(stop_reason: &str) -> &str {
 mod tests {
     use super::*;
 
+    #[test]
+    fn parses_recorded_streamed_tool_use_response() {
+        let raw = concat!(
+            "event: message_start\n",
+            "data: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_01\",\"type\":\"message\",\"role\":\"assistant\",\"model\":\"claude-sonnet-4\",\"content\":[],\"stop_reason\":null,\"usage\":{\"input_tokens\":25,\"output_tokens\":1}}}\n\n",
+            "event: content_block_start\n",
+            "data: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n\n",
+            "event: ping\n",
+            "data: {\"type\":\"ping\"}\n\n",
+            "event: content_block_delta\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"Listing \"}}\n\n",
+            "event: content_block_delta\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"files.\"}}\n\n",
+            "event: content_block_stop\n",
+            "data: {\"type\":\"content_block_stop\",\"index\":0}\n\n",
+            "event: content_block_start\n",
+            "data: {\"type\":\"content_block_start\",\"index\":1,\"content_block\":{\"type\":\"tool_use\",\"id\":\"toolu_01\",\"name\":\"shell_exec\",\"input\":{}}}\n\n",
+            "event: content_block_delta\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":1,\"delta\":{\"type\":\"input_json_delta\",\"partial_json\":\"{\\\"cmd\\\": \"}}\n\n",
+            "event: content_block_delta\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":1,\"delta\":{\"type\":\"input_json_delta\",\"partial_json\":\"\\\"ls\\\"}\"}}\n\n",
+            "event: content_block_stop\n",
+            "data: {\"type\":\"content_block_stop\",\"index\":1}\n\n",
+            "event: message_delta\n",
+            "data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"tool_use\",\"stop_sequence\":null},\"usage\":{\"output_tokens\":40}}\n\n",
+            "event: message_stop\n",
+            "data: {\"type\":\"message_stop\"}\n\n"
+        );
+
+        let payload = parse_anthropic_wire_response(WireResponseBody::Sse(raw.to_string()))
+            .expect("parse anthropic stream");
+
+        assert_eq!(payload["id"], json!("msg_01"));
+        assert_eq!(payload["finish_reason"], json!("tool_use"));
+        assert_eq!(
+            payload["output"],
+            json!([
+                {"type": "message", "role": "assistant", "content": [{"type": "output_text", "text": "Listing files."}]},
+                {"type": "function_call", "call_id": "toolu_01", "name": "shell_exec", "arguments": "{\"cmd\":\"ls\"}"},
+            ])
+        );
+        assert_eq!(
+            payload["usage"],
+            json!({"input_tokens": 25, "output_tokens": 40, "total_tokens": 65})
+        );
+    }
+
+    #[test]
+    fn parses_recorded_json_tool_use_response() {
+        let body = json!({
+            "id": "msg_02",
+            "type": "message",
+            "role": "assistant",
+            "model": "claude-sonnet-4",
+            "content": [
+                {"type": "thinking", "thinking": "Need the file list.", "signature": "sig"},
+                {"type": "tool_use", "id": "toolu_02", "name": "shell_exec", "input": {"cmd": "ls"}}
+            ],
+            "stop_reason": "max_tokens",
+            "usage": {"input_tokens": 10, "output_tokens": 4096}
+        });
+
+        let payload = parse_anthropic_wire_response(WireResponseBody::Json(
+            serde_json::to_vec(&body).expect("serialize"),
+        ))
+        .expect("parse anthropic json");
+
+        assert_eq!(payload["status"], json!("incomplete"));
+        assert_eq!(
+            payload["incomplete_details"]["reason"],
+            json!("max_output_tokens")
+        );
+        assert_eq!(payload["output"][0]["type"], json!("reasoning"));
+        assert_eq!(
+            payload["output"][0]["summary"][0]["text"],
+            json!("Need the file list.")
+        );
+        assert_eq!(payload["output"][1]["call_id"], json!("toolu_02"));
+        assert_eq!(payload["output"][1]["arguments"], json!("{\"cmd\":\"ls\"}"));
+    }
+
+    #[test]
+    fn stream_error_event_fails_the_request() {
+        let raw = concat!(
+            "event: message_start\n",
+            "data: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_03\",\"content\":[]}}\n\n",
+            "event: error\n",
+            "data: {\"type\":\"error\",\"error\":{\"type\":\"overloaded_error\",\"message\":\"Overloaded\"}}\n\n"
+        );
+
+        match parse_anthropic_wire_response(WireResponseBody::Sse(raw.to_string())) {
+            Err(ModelError::StreamFailed { message }) => {
+                assert_eq!(message, "overloaded_error: Overloaded");
+            }
+            other => panic!("expected stream failure, got {other:?}"),
+        }
+    }
+
     #[test]
     fn parses_event_types() {
         assert_eq!(parse_anthropic_event_type("message_start"), AnthropicEventType::MessageStart);
@@ -15,11 +15,11 @@ use header::{ACCEPT, CONTENT_TYPE};
 const MAX_RETRIES: usize = 2;
 
 /// Auth header: `x-api-key` (not `Authorization: Bearer`)
-const X_API_KEY_HEADER: &str = "x-api-key";
+pub(crate) const X_API_KEY_HEADER: &str = "x-api-key";
 
 /// Anthropic API version header.
-const ANTHROPIC_VERSION_HEADER: &str = "anthropic-version";
-const ANTHROPIC_VERSION_VALUE: &str = "2023-06-01";
+pub(crate) const ANTHROPIC_VERSION_HEADER: &str = "anthropic-version";
+pub(crate) const ANTHROPIC_VERSION_VALUE: &str = "2023-06-01";
 
 /// Anthropic response body (either SSE stream or JSON).
 #[derive(Debug)]
@@ -0,0 +1,8 @@
+//! OpenAI chat/completions protocol adapter.
+//!
+//! Converts the engine's Responses-shaped history into chat messages and
+//! normalizes chat completions back into the Responses payload shape, so the
+//! tool loop and compaction are shared with the Responses wire API.
+
+pub(crate) mod request;
+pub(crate) mod response;
@@ -0,0 +1,329 @@
+use crate::protocol::request::{MAX_TEMPERATURE, MAX_TOP_P};
+use finger_kernel_protocol::{ResponsesRequestOptions, ToolChoice};
+use serde_json::{json, Map, Value};
+
+pub(crate) fn build_chat_request_payload(
+    model: &str,
+    input: &[Value],
+    system_prompt: Option<&str>,
+    tools: Option<&[Value]>,
+    responses: Option<&ResponsesRequestOptions>,
+) -> Value {
+    let mut messages = Vec::with_capacity(input.len() + 1);
+    if let Some(instructions) = system_prompt
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+    {
+        messages.push(json!({
+            "role": "system",
+            "content": instructions,
+        }));
+    }
+    messages.extend(convert_history_to_chat_messages(input));
+
+    let mut payload = json!({
+        "model": model,
+        "stream": true,
+        "stream_options": { "include_usage": true },
+        "messages": messages,
+    });
+
+    if let Some(tool_defs) = tools.filter(|defs| !defs.is_empty()) {
+        payload["tools"] = Value::Array(tool_defs.iter().map(convert_tool_to_chat).collect());
+        payload["tool_choice"] = match responses.and_then(|opts| opts.tool_choice.as_ref()) {
+            None | Some(ToolChoice::Auto) => json!("auto"),
+            Some(ToolChoice::None) => json!("none"),
+            Some(ToolChoice::Required) => json!("required"),
+            Some(ToolChoice::Function { name }) => json!({
+                "type": "function",
+                "function": { "name": name },
+            }),
+        };
+        // Many local servers reject the field, so it is only sent when requested.
+        if let Some(parallel_tool_calls) = responses.and_then(|opts| opts.parallel_tool_calls) {
+            payload["parallel_tool_calls"] = Value::Bool(parallel_tool_calls);
+        }
+    }
+
+    if let Some(temperature) = responses
+        .and_then(|opts| opts.temperature)
+        .filter(|value| (0.0..=MAX_TEMPERATURE).contains(value))
+    {
+        payload["temperature"] = json!(temperature);
+    }
+    if let Some(top_p) = responses
+        .and_then(|opts| opts.top_p)
+        .filter(|value| (0.0..=MAX_TOP_P).contains(value))
+    {
+        payload["top_p"] = json!(top_p);
+    }
+    // Chat completions name the output cap `max_tokens`.
+    if let Some(max_tokens) = responses
+        .and_then(|opts| opts.max_output_tokens)
+        .filter(|value| *value > 0)
+    {
+        payload["max_tokens"] = json!(max_tokens);
+    }
+
+    payload
+}
+
+/// Responses tools are flat (`name`, `parameters`, ...); chat nests them under `function`.
+fn convert_tool_to_chat(tool: &Value) -> Value {
+    let mut function = Map::new();
+    for key in ["name", "description", "parameters"] {
+        if let Some(value) = tool.get(key) {
+            function.insert(key.to_string(), value.clone());
+        }
+    }
+    json!({
+        "type": "function",
+        "function": Value::Object(function),
+    })
+}
+
+pub(crate) fn convert_history_to_chat_messages(history: &[Value]) -> Vec<Value> {
+    let mut messages: Vec<Value> = Vec::with_capacity(history.len());
+    for item in history {
+        match item.get("type").and_then(Value::as_str).unwrap_or_default() {
+            "function_call" => push_tool_call(&mut messages, item),
+            "function_call_output" => {
+                let output = match item.get("output") {
+                    Some(Value::String(text)) => text.clone(),
+                    // Tool messages are text-only, so multimodal outputs keep
+                    // just their text parts.
+                    Some(Value::Array(parts)) => parts
+                        .iter()
+                        .filter_map(|part| part.get("text").and_then(Value::as_str))
+                        .collect::<Vec<_>>()
+                        .join("\n"),
+                    Some(other) => other.to_string(),
+                    None => String::new(),
+                };
+                messages.push(json!({
+                    "role": "tool",
+                    "tool_call_id": item.get("call_id").cloned().unwrap_or(Value::Null),
+                    "content": output,
+                }));
+            }
+            // Chat completions has no slot for replayed reasoning items.
+            "reasoning" => {}
+            _ => {
+                if let Some(message) = convert_message_to_chat(item) {
+                    messages.push(message);
+                }
+            }
+        }
+    }
+    messages
+}
+
+/// Consecutive function calls belong to a single assistant turn in chat format.
+fn push_tool_call(messages: &mut Vec<Value>, item: &Value) {
+    let tool_call = json!({
+        "id": item
+            .get("call_id")
+            .or_else(|| item.get("id"))
+            .cloned()
+            .unwrap_or(Value::Null),
+        "type": "function",
+        "function": {
+            "name": item.get("name").cloned().unwrap_or(Value::Null),
+            "arguments": item
+                .get("arguments")
+                .and_then(Value::as_str)
+                .unwrap_or("{}"),
+        },
+    });
+
+    if let Some(last) = messages
+        .last_mut()
+        .filter(|message| message.get("role").and_then(Value::as_str) == Some("assistant"))
+    {
+        match last.get_mut("tool_calls").and_then(Value::as_array_mut) {
+            Some(tool_calls) => tool_calls.push(tool_call),
+            None => last["tool_calls"] = Value::Array(vec![tool_call]),
+        }
+        return;
+    }
+
+    messages.push(json!({
+        "role": "assistant",
+        "content": Value::Null,
+        "tool_calls": [tool_call],
+    }));
+}
+
+fn convert_message_to_chat(item: &Value) -> Option<Value> {
+    let role = match item.get("role").and_then(Value::as_str)? {
+        "developer" => "system",
+        role => role,
+    };
+    let content = match item.get("content")? {
+        Value::String(text) => Value::String(text.clone()),
+        Value::Array(parts) => convert_content_parts(parts, role == "user"),
+        _ => return None,
+    };
+    Some(json!({
+        "role": role,
+        "content": content,
+    }))
+}
+
+/// Text-only content collapses to a plain string, which every chat server
+/// accepts; images are only kept on user messages.
+fn convert_content_parts(parts: &[Value], allow_images: bool) -> Value {
+    let mut texts = Vec::new();
+    let mut chat_parts = Vec::new();
+    let mut has_image = false;
+    for part in parts {
+        match part.get("type").and_then(Value::as_str).unwrap_or_default() {
+            "input_text" | "output_text" | "text" => {
+                if let Some(text) = part.get("text").and_then(Value::as_str) {
+                    texts.push(text.to_string());
+                    chat_parts.push(json!({ "type": "text", "text": text }));
+                }
+            }
+            "input_image" | "image" if allow_images => {
+                if let Some(url) = part.get("image_url").and_then(Value::as_str) {
+                    has_image = true;
+                    chat_parts.push(json!({ "type": "image_url", "image_url": { "url": url } }));
+                }
+            }
+            _ => {}
+        }
+    }
+    if has_image {
+        Value::Array(chat_parts)
+    } else {
+        Value::String(texts.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::build_chat_request_payload;
+    use finger_kernel_protocol::{ResponsesRequestOptions, ToolChoice};
+    use serde_json::json;
+
+    #[test]
+    fn payload_converts_history_tools_and_system_prompt() {
+        let history = vec![
+            json!({"role":"user","content":[{"type":"input_text","text":"list files"}]}),
+            json!({"type":"reasoning","summary":[{"type":"summary_text","text":"thinking"}]}),
+            json!({"type":"message","role":"assistant","content":[{"type":"output_text","text":"Checking."}]}),
+            json!({"type":"function_call","call_id":"call_1","name":"shell_exec","arguments":"{\"cmd\":\"ls\"}"}),
+            json!({"type":"function_call","call_id":"call_2","name":"shell_exec","arguments":"{\"cmd\":\"pwd\"}"}),
+            json!({"type":"function_call_output","call_id":"call_1","output":"{\"stdout\":\"a.txt\"}"}),
+            json!({"type":"function_call_output","call_id":"call_2","output":"{\"stdout\":\"/tmp\"}"}),
+            json!({"role":"user","content":[{"type":"input_image","image_url":"data:image/png;base64,AAAA"}]}),
+        ];
+        let tools = [json!({
+            "type": "function",
+            "name": "shell_exec",
+            "description": "Execute shell command",
+            "parameters": {"type": "object"},
+        })];
+
+        let payload = build_chat_request_payload(
+            "llama-local",
+            &history,
+            Some("be brief"),
+            Some(&tools),
+            Some(&ResponsesRequestOptions {
+                tool_choice: Some(ToolChoice::Function {
+                    name: "shell_exec".to_string(),
+                }),
+                ..Default::default()
+            }),
+        );
+
+        assert_eq!(payload["stream"], json!(true));
+        assert!(payload.get("parallel_tool_calls").is_none());
+        assert!(payload.get("temperature").is_none());
+        assert!(payload.get("max_tokens").is_none());
+        assert_eq!(
+            payload["tool_choice"],
+            json!({"type": "function", "function": {"name": "shell_exec"}})
+        );
+        assert_eq!(
+            payload["tools"],
+            json!([{
+                "type": "function",
+                "function": {
+                    "name": "shell_exec",
+                    "description": "Execute shell command",
+                    "parameters": {"type": "object"},
+                },
+            }])
+        );
+        assert_eq!(
+            payload["messages"],
+            json!([
+                {"role": "system", "content": "be brief"},
+                {"role": "user", "content": "list files"},
+                {
+                    "role": "assistant",
+                    "content": "Checking.",
+                    "tool_calls": [
+                        {"id": "call_1", "type": "function", "function": {"name": "shell_exec", "arguments": "{\"cmd\":\"ls\"}"}},
+                        {"id": "call_2", "type": "function", "function": {"name": "shell_exec", "arguments": "{\"cmd\":\"pwd\"}"}},
+                    ],
+                },
+                {"role": "tool", "tool_call_id": "call_1", "content": "{\"stdout\":\"a.txt\"}"},
+                {"role": "tool", "tool_call_id": "call_2", "content": "{\"stdout\":\"/tmp\"}"},
+                {
+                    "role": "user",
+                    "content": [{"type": "image_url", "image_url": {"url": "data:image/png;base64,AAAA"}}],
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn payload_starts_a_tool_call_message_after_tool_results() {
+        let history = vec![
+            json!({"type":"function_call","call_id":"call_1","name":"a","arguments":"{}"}),
+            json!({"type":"function_call_output","call_id":"call_1","output":"ok"}),
+            json!({"type":"function_call","call_id":"call_2","name":"b","arguments":"{}"}),
+        ];
+        let responses = ResponsesRequestOptions {
+            parallel_tool_calls: Some(true),
+            ..Default::default()
+        };
+        let payload = build_chat_request_payload("m", &history, None, None, Some(&responses));
+
+        let messages = payload["messages"].as_array().expect("messages");
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[0]["content"], json!(null));
+        assert_eq!(messages[2]["role"], json!("assistant"));
+        assert_eq!(messages[2]["tool_calls"][0]["id"], json!("call_2"));
+        assert!(payload.get("tools").is_none());
+    }
+
+    #[test]
+    fn payload_sends_sampling_options_and_drops_out_of_range_values() {
+        let responses = ResponsesRequestOptions {
+            temperature: Some(0.2),
+            top_p: Some(0.9),
+            max_output_tokens: Some(512),
+            ..Default::default()
+        };
+        let payload = build_chat_request_payload("m", &[], None, None, Some(&responses));
+        assert_eq!(payload["temperature"], json!(0.2));
+        assert_eq!(payload["top_p"], json!(0.9));
+        assert_eq!(payload["max_tokens"], json!(512));
+        assert!(payload.get("max_output_tokens").is_none());
+
+        let out_of_range = ResponsesRequestOptions {
+            temperature: Some(2.5),
+            top_p: Some(1.5),
+            max_output_tokens: Some(0),
+            ..Default::default()
+        };
+        let payload = build_chat_request_payload("m", &[], None, None, Some(&out_of_range));
+        assert!(payload.get("temperature").is_none());
+        assert!(payload.get("top_p").is_none());
+        assert!(payload.get("max_tokens").is_none());
+    }
+}
@@ -0,0 +1,386 @@
+use std::collections::BTreeMap;
+
+use serde_json::{json, Map, Value};
+
+use crate::protocol::response::{split_sse_block, WireResponseBody};
+use crate::ModelError;
+
+/// Parses a chat/completions body (streamed or not) into the Responses payload
+/// shape consumed by `parse_protocol_payload`.
+pub(crate) fn parse_chat_wire_response(body: WireResponseBody) -> Result<Value, ModelError> {
+    match body {
+        WireResponseBody::Json(bytes) => {
+            let completion = serde_json::from_slice::<Value>(&bytes)?;
+            Ok(normalize_chat_completion(&completion))
+        }
+        WireResponseBody::Sse(raw) => parse_chat_sse_response(&raw),
+    }
+}
+
+/// Maps a streamed chat chunk to the Responses delta events that drive live
+/// progress, so streaming looks the same regardless of wire API.
+pub(crate) fn chat_chunk_progress_events(chunk: &Value) -> Vec<(&'static str, Value)> {
+    let mut events = Vec::new();
+    let Some(choices) = chunk.get("choices").and_then(Value::as_array) else {
+        return events;
+    };
+    for delta in choices.iter().filter_map(|choice| choice.get("delta")) {
+        if let Some(reasoning) = reasoning_delta(delta) {
+            events.push((
+                "response.reasoning_summary_text.delta",
+                json!({ "delta": reasoning }),
+            ));
+        }
+        if let Some(content) = delta.get("content").and_then(Value::as_str) {
+            events.push(("response.output_text.delta", json!({ "delta": content })));
+        }
+    }
+    events
+}
+
+#[derive(Default)]
+struct ChatToolCallAccumulator {
+    id: Option<String>,
+    name: String,
+    arguments: String,
+}
+
+fn parse_chat_sse_response(raw: &str) -> Result<Value, ModelError> {
+    let normalized = raw.replace("\r\n", "\n");
+    let mut saw_chunk = false;
+    let mut id: Option<String> = None;
+    let mut content = String::new();
+    let mut reasoning = String::new();
+    let mut tool_calls: BTreeMap<u64, ChatToolCallAccumulator> = BTreeMap::new();
+    let mut finish_reason: Option<String> = None;
+    let mut usage: Option<Value> = None;
+
+    for block in normalized.split("\n\n") {
+        let Some((_, data)) = split_sse_block(block) else {
+            continue;
+        };
+        let chunk = serde_json::from_str::<Value>(&data)?;
+        if let Some(message) = chunk
+            .get("error")
+            .and_then(|error| error.get("message"))
+            .and_then(Value::as_str)
+        {
+            return Err(ModelError::StreamFailed {
+                message: message.to_string(),
+            });
+        }
+        saw_chunk = true;
+        if id.is_none() {
+            id = chunk
+                .get("id")
+                .and_then(Value::as_str)
+                .map(ToString::to_string);
+        }
+        if let Some(chunk_usage) = chunk.get("usage").filter(|value| value.is_object()) {
+            usage = Some(chunk_usage.clone());
+        }
+        let Some(choices) = chunk.get("choices").and_then(Value::as_array) else {
+            continue;
+        };
+        for choice in choices {
+            if let Some(reason) = choice.get("finish_reason").and_then(Value::as_str) {
+                finish_reason = Some(reason.to_string());
+            }
+            let Some(delta) = choice.get("delta") else {
+                continue;
+            };
+            if let Some(text) = delta.get("content").and_then(Value::as_str) {
+                content.push_str(text);
+            }
+            if let Some(text) = reasoning_delta(delta) {
+                reasoning.push_str(text);
+            }
+            for (position, call) in delta
+                .get("tool_calls")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .enumerate()
+            {
+                let index = call
+                    .get("index")
+                    .and_then(Value::as_u64)
+                    .unwrap_or(position as u64);
+                let entry = tool_calls.entry(index).or_default();
+                if let Some(call_id) = call.get("id").and_then(Value::as_str) {
+                    entry.id = Some(call_id.to_string());
+                }
+                let function = call.get("function");
+                if let Some(name) = function
+                    .and_then(|function| function.get("name"))
+                    .and_then(Value::as_str)
+                {
+                    entry.name.push_str(name);
+                }
+                if let Some(arguments) = function
+                    .and_then(|function| function.get("arguments"))
+                    .and_then(Value::as_str)
+                {
+                    entry.arguments.push_str(arguments);
+                }
+            }
+        }
+    }
+
+    if !saw_chunk {
+        return Err(ModelError::MissingStreamResponse);
+    }
+
+    let tool_calls = tool_calls
+        .into_iter()
+        .map(|(index, call)| build_function_call_item(call.id, &call.name, &call.arguments, index))
+        .collect::<Vec<_>>();
+    Ok(build_responses_payload(
+        id,
+        &reasoning,
+        &content,
+        tool_calls,
+        finish_reason,
+        usage.as_ref(),
+    ))
+}
+
+fn normalize_chat_completion(completion: &Value) -> Value {
+    let choice = completion
+        .get("choices")
+        .and_then(Value::as_array)
+        .and_then(|choices| choices.first());
+    let message = choice.and_then(|choice| choice.get("message"));
+    let content = message
+        .and_then(|message| message.get("content"))
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    let reasoning = message.and_then(reasoning_delta).unwrap_or_default();
+    let tool_calls = message
+        .and_then(|message| message.get("tool_calls"))
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .enumerate()
+        .map(|(index, call)| {
+            let function = call.get("function");
+            build_function_call_item(
+                call.get("id")
+                    .and_then(Value::as_str)
+                    .map(ToString::to_string),
+                function
+                    .and_then(|function| function.get("name"))
+                    .and_then(Value::as_str)
+                    .unwrap_or_default(),
+                function
+                    .and_then(|function| function.get("arguments"))
+                    .and_then(Value::as_str)
+                    .unwrap_or_default(),
+                index as u64,
+            )
+        })
+        .collect::<Vec<_>>();
+    let finish_reason = choice
+        .and_then(|choice| choice.get("finish_reason"))
+        .and_then(Value::as_str)
+        .map(ToString::to_string);
+
+    build_responses_payload(
+        completion
+            .get("id")
+            .and_then(Value::as_str)
+            .map(ToString::to_string),
+        reasoning,
+        content,
+        tool_calls,
+        finish_reason,
+        completion.get("usage"),
+    )
+}
+
+/// llama.cpp, vLLM and DeepSeek-style servers report reasoning under one of
+/// these non-standard keys.
+fn reasoning_delta(delta: &Value) -> Option<&str> {
+    ["reasoning_content", "reasoning"]
+        .iter()
+        .find_map(|key| delta.get(*key).and_then(Value::as_str))
+        .filter(|text| !text.is_empty())
+}
+
+fn build_function_call_item(id: Option<String>, name: &str, arguments: &str, index: u64) -> Value {
+    // Some local servers omit tool call ids; the tool loop needs one to pair outputs.
+    let call_id = id
+        .filter(|value| !value.trim().is_empty())
+        .unwrap_or_else(|| format!("call_{index}"));
+    let arguments = if arguments.trim().is_empty() {
+        "{}"
+    } else {
+        arguments
+    };
+    json!({
+        "type": "function_call",
+        "call_id": call_id,
+        "name": name,
+        "arguments": arguments,
+    })
+}
+
+fn build_responses_payload(
+    id: Option<String>,
+    reasoning: &str,
+    content: &str,
+    tool_calls: Vec<Value>,
+    finish_reason: Option<String>,
+    usage: Option<&Value>,
+) -> Value {
+    let mut output = Vec::with_capacity(tool_calls.len() + 2);
+    if !reasoning.trim().is_empty() {
+        output.push(json!({
+            "type": "reasoning",
+            "summary": [{ "type": "summary_text", "text": reasoning }],
+        }));
+    }
+    if !content.is_empty() {
+        output.push(json!({
+            "type": "message",
+            "role": "assistant",
+            "content": [{ "type": "output_text", "text": content }],
+        }));
+    }
+    output.extend(tool_calls);
+
+    let mut payload = Map::new();
+    if let Some(id) = id {
+        payload.insert("id".to_string(), Value::String(id));
+    }
+    if finish_reason.as_deref() == Some("length") {
+        payload.insert("status".to_string(), json!("incomplete"));
+        payload.insert(
+            "incomplete_details".to_string(),
+            json!({ "reason": "max_output_tokens" }),
+        );
+    } else {
+        payload.insert("status".to_string(), json!("completed"));
+    }
+    if let Some(reason) = finish_reason {
+        payload.insert("finish_reason".to_string(), Value::String(reason));
+    }
+    payload.insert("output".to_string(), Value::Array(output));
+    if let Some(usage) = usage.and_then(Value::as_object) {
+        payload.insert(
+            "usage".to_string(),
+            json!({
+                "input_tokens": usage.get("prompt_tokens"),
+                "output_tokens": usage.get("completion_tokens"),
+                "total_tokens": usage.get("total_tokens"),
+            }),
+        );
+    }
+    Value::Object(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{chat_chunk_progress_events, parse_chat_wire_response};
+    use crate::protocol::response::WireResponseBody;
+    use serde_json::json;
+
+    #[test]
+    fn parses_streamed_content_and_tool_call_deltas() {
+        let raw = concat!(
+            "data: {\"id\":\"chatcmpl-1\",\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"reasoning_content\":\"Need ls\"}}]}\n\n",
+            "data: {\"id\":\"chatcmpl-1\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Checking\"}}]}\n\n",
+            "data: {\"id\":\"chatcmpl-1\",\"choices\":[{\"index\":0,\"delta\":{\"tool_calls\":[{\"index\":0,\"id\":\"call_1\",\"type\":\"function\",\"function\":{\"name\":\"shell_exec\",\"arguments\":\"{\\\"cmd\\\":\"}}]}}]}\n\n",
+            "data: {\"id\":\"chatcmpl-1\",\"choices\":[{\"index\":0,\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"\\\"ls\\\"}\"}}]}}]}\n\n",
+            "data: {\"id\":\"chatcmpl-1\",\"choices\":[{\"index\":0,\"delta\":{},\"finish_reason\":\"tool_calls\"}]}\n\n",
+            "data: {\"id\":\"chatcmpl-1\",\"choices\":[],\"usage\":{\"prompt_tokens\":12,\"completion_tokens\":5,\"total_tokens\":17}}\n\n",
+            "data: [DONE]\n\n"
+        );
+
+        let payload = parse_chat_wire_response(WireResponseBody::Sse(raw.to_string()))
+            .expect("parse chat stream");
+
+        assert_eq!(payload["id"], json!("chatcmpl-1"));
+        assert_eq!(payload["finish_reason"], json!("tool_calls"));
+        assert_eq!(
+            payload["output"],
+            json!([
+                {"type": "reasoning", "summary": [{"type": "summary_text", "text": "Need ls"}]},
+                {"type": "message", "role": "assistant", "content": [{"type": "output_text", "text": "Checking"}]},
+                {"type": "function_call", "call_id": "call_1", "name": "shell_exec", "arguments": "{\"cmd\":\"ls\"}"},
+            ])
+        );
+        assert_eq!(
+            payload["usage"],
+            json!({"input_tokens": 12, "output_tokens": 5, "total_tokens": 17})
+        );
+    }
+
+    #[test]
+    fn parses_non_streamed_completion_and_fills_missing_call_ids() {
+        let body = json!({
+            "id": "chatcmpl-2",
+            "choices": [{
+                "index": 0,
+                "message": {
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [{
+                        "type": "function",
+                        "function": {"name": "read_file", "arguments": ""},
+                    }],
+                },
+                "finish_reason": "length",
+            }],
+        });
+
+        let payload = parse_chat_wire_response(WireResponseBody::Json(
+            serde_json::to_vec(&body).expect("encode"),
+        ))
+        .expect("parse chat completion");
+
+        assert_eq!(payload["status"], json!("incomplete"));
+        assert_eq!(
+            payload["incomplete_details"]["reason"],
+            json!("max_output_tokens")
+        );
+        assert_eq!(
+            payload["output"],
+            json!([{"type": "function_call", "call_id": "call_0", "name": "read_file", "arguments": "{}"}])
+        );
+    }
+
+    #[test]
+    fn surfaces_stream_errors_and_empty_streams() {
+        let failed = parse_chat_wire_response(WireResponseBody::Sse(
+            "data: {\"error\":{\"message\":\"model not loaded\"}}\n\n".to_string(),
+        ));
+        assert!(matches!(
+            failed,
+            Err(crate::ModelError::StreamFailed { ref message }) if message == "model not loaded"
+        ));
+
+        let empty = parse_chat_wire_response(WireResponseBody::Sse("data: [DONE]\n\n".to_string()));
+        assert!(matches!(
+            empty,
+            Err(crate::ModelError::MissingStreamResponse)
+        ));
+    }
+
+    #[test]
+    fn maps_chunks_to_responses_progress_events() {
+        let events = chat_chunk_progress_events(&json!({
+            "choices": [{"delta": {"reasoning_content": "hmm", "content": "Hi"}}],
+        }));
+        assert_eq!(
+            events,
+            vec![
+                (
+                    "response.reasoning_summary_text.delta",
+                    json!({"delta": "hmm"})
+                ),
+                ("response.output_text.delta", json!({"delta": "Hi"})),
+            ]
+        );
+    }
+}
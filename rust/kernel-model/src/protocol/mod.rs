@@ -3,3 +3,4 @@ pub(crate) mod response;
 pub(crate) mod transport;
 
 pub(crate) mod anthropic;
+pub(crate) mod chat;
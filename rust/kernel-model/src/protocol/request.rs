@@ -1,13 +1,18 @@
 use std::collections::HashSet;
 
-use finger_kernel_protocol::ResponsesRequestOptions;
+use finger_kernel_protocol::{ResponsesReasoningOptions, ResponsesRequestOptions, ToolChoice};
 use serde_json::{json, Map, Value};
 
 const DEFAULT_REASONING_EFFORT: &str = "medium";
+/// Effort values the Responses API accepts; `none` drops the reasoning block.
+pub(crate) const REASONING_EFFORTS: &[&str] = &["none", "minimal", "low", "medium", "high"];
 const DEFAULT_REASONING_SUMMARY: &str = "detailed";
 const DEFAULT_TEXT_VERBOSITY: &str = "medium";
 const REASONING_ENCRYPTED_CONTENT_INCLUDE: &str = "reasoning.encrypted_content";
+pub(crate) const MAX_TEMPERATURE: f64 = 2.0;
+pub(crate) const MAX_TOP_P: f64 = 1.0;
 
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn build_responses_request_payload(
     model: &str,
     input: &[Value],
@@ -16,28 +21,33 @@ pub(crate) fn build_responses_request_payload(
     prompt_cache_key: Option<&str>,
     responses: Option<&ResponsesRequestOptions>,
     base_url: Option<&str>,
+    previous_response_id: Option<&str>,
 ) -> Value {
     let mut include = sanitize_include_list(responses.map(|options| options.include.as_slice()));
+    let stateless = responses.is_some_and(|options| options.stateless);
     let reasoning_opts = responses.and_then(|options| options.reasoning.as_ref());
-    let reasoning_enabled = reasoning_opts.and_then(|opts| opts.enabled).unwrap_or(true);
+    let reasoning_enabled = reasoning_requested(reasoning_opts);
     let include_reasoning_encrypted = reasoning_opts
         .and_then(|opts| opts.include_encrypted_content)
         .unwrap_or(true);
-    if reasoning_enabled && include_reasoning_encrypted {
+    if stateless {
+        include.retain(|entry| entry != REASONING_ENCRYPTED_CONTENT_INCLUDE);
+    } else if reasoning_enabled && include_reasoning_encrypted {
         push_unique_include(
             &mut include,
             REASONING_ENCRYPTED_CONTENT_INCLUDE.to_string(),
         );
     }
-    let store = responses
-        .and_then(|options| options.store)
-        .unwrap_or_else(|| {
-            if reasoning_enabled && !include_reasoning_encrypted {
-                true
-            } else {
-                is_azure_responses_endpoint(base_url)
-            }
-        });
+    let store = !stateless
+        && responses
+            .and_then(|options| options.store)
+            .unwrap_or_else(|| {
+                if reasoning_enabled && !include_reasoning_encrypted {
+                    true
+                } else {
+                    is_azure_responses_endpoint(base_url)
+                }
+            });
 
     let mut payload = json!({
         "model": model,
@@ -59,7 +69,8 @@ pub(crate) fn build_responses_request_payload(
             .and_then(|options| options.parallel_tool_calls)
             .unwrap_or(true);
         payload["tools"] = Value::Array(tool_defs.to_vec());
-        payload["tool_choice"] = Value::String("auto".to_string());
+        payload["tool_choice"] =
+            build_tool_choice(responses.and_then(|options| options.tool_choice.as_ref()));
         payload["parallel_tool_calls"] = Value::Bool(parallel_tool_calls);
     }
 
@@ -98,6 +109,25 @@ pub(crate) fn build_responses_request_payload(
         payload["text"] = Value::Object(text);
     }
 
+    if let Some(temperature) = responses
+        .and_then(|options| options.temperature)
+        .filter(|value| (0.0..=MAX_TEMPERATURE).contains(value))
+    {
+        payload["temperature"] = json!(temperature);
+    }
+    if let Some(top_p) = responses
+        .and_then(|options| options.top_p)
+        .filter(|value| (0.0..=MAX_TOP_P).contains(value))
+    {
+        payload["top_p"] = json!(top_p);
+    }
+    if let Some(max_output_tokens) = responses
+        .and_then(|options| options.max_output_tokens)
+        .filter(|value| *value > 0)
+    {
+        payload["max_output_tokens"] = json!(max_output_tokens);
+    }
+
     if let Some(cache_key) = prompt_cache_key
         .map(str::trim)
         .filter(|value| !value.is_empty())
@@ -105,9 +135,97 @@ pub(crate) fn build_responses_request_payload(
         payload["prompt_cache_key"] = Value::String(cache_key.to_string());
     }
 
+    if let Some(metadata) = responses
+        .and_then(|options| options.metadata.as_ref())
+        .filter(|metadata| !metadata.is_empty())
+    {
+        payload["metadata"] = Value::Object(metadata.clone());
+    }
+
+    if let Some(previous_response_id) = previous_response_id {
+        payload["previous_response_id"] = Value::String(previous_response_id.to_string());
+    }
+
     payload
 }
 
+/// The system prompt with the configured `instructions_prefix` and
+/// `instructions_suffix` joined around it; blank parts are skipped.
+pub(crate) fn merge_instructions(
+    system_prompt: Option<&str>,
+    responses: Option<&ResponsesRequestOptions>,
+) -> Option<String> {
+    let parts = [
+        responses.and_then(|options| options.instructions_prefix.as_deref()),
+        system_prompt,
+        responses.and_then(|options| options.instructions_suffix.as_deref()),
+    ];
+    let merged = parts
+        .into_iter()
+        .flatten()
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>();
+    (!merged.is_empty()).then(|| merged.join("\n"))
+}
+
+/// Cache key shared by every turn with the same model, system prompt, tools
+/// and instructions; `None` when there is no prefix to share. The digest is
+/// FNV-1a so it stays the same across builds and processes.
+pub(crate) fn stable_prefix_cache_key(
+    model: &str,
+    system_prompt: Option<&str>,
+    tools: Option<&[Value]>,
+    instructions: &[Option<&str>],
+) -> Option<String> {
+    let system_prompt = system_prompt
+        .map(str::trim)
+        .filter(|value| !value.is_empty());
+    let tools = tools.filter(|defs| !defs.is_empty());
+    let instructions = instructions
+        .iter()
+        .map(|item| item.map(str::trim).filter(|value| !value.is_empty()))
+        .collect::<Vec<_>>();
+    if system_prompt.is_none() && tools.is_none() && instructions.iter().all(Option::is_none) {
+        return None;
+    }
+    let prefix = json!({
+        "model": model,
+        "system_prompt": system_prompt,
+        "tools": tools,
+        "instructions": instructions,
+    })
+    .to_string();
+    let hash = prefix
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+    Some(format!("prefix-{hash:016x}"))
+}
+
+/// Whether the request carries a reasoning block: reasoning is not switched
+/// off and its effort is not `none`.
+pub(crate) fn reasoning_requested(reasoning: Option<&ResponsesReasoningOptions>) -> bool {
+    let Some(reasoning) = reasoning else {
+        return true;
+    };
+    reasoning.enabled.unwrap_or(true)
+        && normalized_option(reasoning.effort.as_ref()).as_deref() != Some("none")
+}
+
+fn build_tool_choice(choice: Option<&ToolChoice>) -> Value {
+    match choice {
+        None | Some(ToolChoice::Auto) => Value::String("auto".to_string()),
+        Some(ToolChoice::None) => Value::String("none".to_string()),
+        Some(ToolChoice::Required) => Value::String("required".to_string()),
+        Some(ToolChoice::Function { name }) => json!({
+            "type": "function",
+            "name": name,
+        }),
+    }
+}
+
 fn sanitize_include_list(raw: Option<&[String]>) -> Vec<String> {
     let Some(items) = raw else {
         return Vec::new();
@@ -149,10 +267,10 @@ fn is_azure_responses_endpoint(base_url: Option<&str>) -> bool {
 #[cfg(test)]
 mod tests {
     use finger_kernel_protocol::{
-        ResponsesReasoningOptions, ResponsesRequestOptions, ResponsesTextOptions,
+        ResponsesReasoningOptions, ResponsesRequestOptions, ResponsesTextOptions, ToolChoice,
     };
 
-    use super::build_responses_request_payload;
+    use super::{build_responses_request_payload, stable_prefix_cache_key};
     use serde_json::json;
 
     #[test]
@@ -165,6 +283,7 @@ mod tests {
             Some("session-1"),
             None,
             Some("https://api.openai.com/v1"),
+            None,
         );
 
         assert_eq!(payload.get("stream").and_then(|v| v.as_bool()), Some(true));
@@ -215,12 +334,24 @@ mod tests {
                     enabled: Some(true),
                     verbosity: Some("high".to_string()),
                     output_schema: Some(json!({"type":"object"})),
+                    max_schema_retries: None,
                 }),
                 include: vec!["response.output_text.logprobs".to_string()],
                 store: Some(true),
                 parallel_tool_calls: Some(false),
+                temperature: None,
+                top_p: None,
+                max_output_tokens: None,
+                tool_choice: None,
+                use_previous_response_id: false,
+                metadata: None,
+                prompt_cache_key: None,
+                instructions_prefix: None,
+                instructions_suffix: None,
+                stateless: false,
             }),
             Some("https://resource.openai.azure.com/openai"),
+            None,
         );
 
         assert_eq!(payload.get("store").and_then(|v| v.as_bool()), Some(true));
@@ -271,7 +402,174 @@ mod tests {
                 ..ResponsesRequestOptions::default()
             }),
             Some("https://api.openai.com/v1"),
+            None,
         );
         assert_eq!(payload.get("store").and_then(|v| v.as_bool()), Some(true));
     }
+
+    #[test]
+    fn payload_keeps_minimal_effort_and_drops_reasoning_for_none() {
+        let input = [json!({"role":"user","content":[{"type":"input_text","text":"hello"}]})];
+        let with_effort = |effort: &str| ResponsesRequestOptions {
+            reasoning: Some(ResponsesReasoningOptions {
+                effort: Some(effort.to_string()),
+                ..ResponsesReasoningOptions::default()
+            }),
+            ..ResponsesRequestOptions::default()
+        };
+
+        let minimal = build_responses_request_payload(
+            "gpt-test",
+            &input,
+            None,
+            None,
+            None,
+            Some(&with_effort("minimal")),
+            Some("https://api.openai.com/v1"),
+            None,
+        );
+        assert_eq!(minimal["reasoning"]["effort"], "minimal");
+        assert_eq!(minimal["include"], json!(["reasoning.encrypted_content"]));
+
+        let none = build_responses_request_payload(
+            "gpt-test",
+            &input,
+            None,
+            None,
+            None,
+            Some(&with_effort("none")),
+            Some("https://api.openai.com/v1"),
+            None,
+        );
+        assert!(none.get("reasoning").is_none());
+        assert_eq!(none["include"], json!([]));
+        assert_eq!(none["store"], false);
+    }
+
+    #[test]
+    fn stable_prefix_cache_key_depends_only_on_the_prefix() {
+        let tools = [json!({"type":"function","name":"shell_exec"})];
+        let key = |system: &str, developer: Option<&str>| {
+            stable_prefix_cache_key("gpt-test", Some(system), Some(&tools), &[developer, None])
+        };
+
+        let first = key("be brief", Some("sandboxed")).expect("prefix key");
+        assert!(first.starts_with("prefix-"));
+        assert_eq!(key("be brief", Some("sandboxed")), Some(first.clone()));
+        assert_ne!(key("be brief", Some("read-only")), Some(first.clone()));
+        assert_ne!(key("be verbose", Some("sandboxed")), Some(first));
+        assert_eq!(
+            stable_prefix_cache_key("gpt-test", Some("  "), None, &[None, None]),
+            None
+        );
+    }
+
+    #[test]
+    fn payload_passes_metadata_through_only_when_set() {
+        let input = [json!({"role":"user","content":[{"type":"input_text","text":"hello"}]})];
+        let unset =
+            build_responses_request_payload("gpt-test", &input, None, None, None, None, None, None);
+        assert!(unset.get("metadata").is_none());
+
+        let metadata = json!({"user_id": "u-1", "trace_id": "t-42"});
+        let set = build_responses_request_payload(
+            "gpt-test",
+            &input,
+            None,
+            None,
+            None,
+            Some(&ResponsesRequestOptions {
+                metadata: metadata.as_object().cloned(),
+                ..ResponsesRequestOptions::default()
+            }),
+            None,
+            None,
+        );
+        assert_eq!(set["metadata"], metadata);
+    }
+
+    #[test]
+    fn payload_includes_sampling_parameters_only_when_set() {
+        let input = [json!({"role":"user","content":[{"type":"input_text","text":"hello"}]})];
+        let unset =
+            build_responses_request_payload("gpt-test", &input, None, None, None, None, None, None);
+        assert!(unset.get("temperature").is_none());
+        assert!(unset.get("top_p").is_none());
+        assert!(unset.get("max_output_tokens").is_none());
+
+        let set = build_responses_request_payload(
+            "gpt-test",
+            &input,
+            None,
+            None,
+            None,
+            Some(&ResponsesRequestOptions {
+                temperature: Some(0.0),
+                top_p: Some(0.9),
+                max_output_tokens: Some(256),
+                ..ResponsesRequestOptions::default()
+            }),
+            None,
+            None,
+        );
+        assert_eq!(set.get("temperature").and_then(|v| v.as_f64()), Some(0.0));
+        assert_eq!(set.get("top_p").and_then(|v| v.as_f64()), Some(0.9));
+        assert_eq!(
+            set.get("max_output_tokens").and_then(|v| v.as_u64()),
+            Some(256)
+        );
+
+        let invalid = build_responses_request_payload(
+            "gpt-test",
+            &input,
+            None,
+            None,
+            None,
+            Some(&ResponsesRequestOptions {
+                temperature: Some(2.5),
+                top_p: Some(f64::NAN),
+                max_output_tokens: Some(0),
+                ..ResponsesRequestOptions::default()
+            }),
+            None,
+            None,
+        );
+        assert!(invalid.get("temperature").is_none());
+        assert!(invalid.get("top_p").is_none());
+        assert!(invalid.get("max_output_tokens").is_none());
+    }
+
+    #[test]
+    fn payload_serializes_each_tool_choice_variant() {
+        let input = [json!({"role":"user","content":[{"type":"input_text","text":"hello"}]})];
+        let tools = [json!({"type":"function","name":"shell_exec"})];
+        let cases = [
+            (None, json!("auto")),
+            (Some(ToolChoice::Auto), json!("auto")),
+            (Some(ToolChoice::None), json!("none")),
+            (Some(ToolChoice::Required), json!("required")),
+            (
+                Some(ToolChoice::Function {
+                    name: "shell_exec".to_string(),
+                }),
+                json!({"type": "function", "name": "shell_exec"}),
+            ),
+        ];
+        for (tool_choice, expected) in cases {
+            let payload = build_responses_request_payload(
+                "gpt-test",
+                &input,
+                None,
+                Some(&tools),
+                None,
+                Some(&ResponsesRequestOptions {
+                    tool_choice,
+                    ..ResponsesRequestOptions::default()
+                }),
+                None,
+                None,
+            );
+            assert_eq!(payload.get("tool_choice"), Some(&expected));
+        }
+    }
 }
@@ -22,35 +22,12 @@ pub(crate) fn parse_sse_response(raw: &str) -> Result<Value, ModelError> {
     let mut output_items: Vec<Value> = Vec::new();
 
     for chunk in normalized.split("\n\n") {
-        let mut data_lines: Vec<&str> = Vec::new();
-        let mut event_name: Option<String> = None;
-        for line in chunk.lines() {
-            if let Some(rest) = line.strip_prefix("event:") {
-                let normalized_event = rest.trim();
-                if !normalized_event.is_empty() {
-                    event_name = Some(normalized_event.to_string());
-                }
-                continue;
-            }
-            if let Some(rest) = line.strip_prefix("data:") {
-                data_lines.push(rest.trim_start());
-            }
-        }
-        if data_lines.is_empty() {
+        let Some((event_name, data)) = split_sse_block(chunk) else {
             continue;
-        }
-
-        let data = data_lines.join("\n");
-        if data.trim() == "[DONE]" {
-            continue;
-        }
+        };
 
         let event_value = serde_json::from_str::<Value>(&data).map_err(ModelError::from)?;
-        let event_type = event_value
-            .get("type")
-            .and_then(Value::as_str)
-            .or(event_name.as_deref())
-            .unwrap_or_default();
+        let event_type = resolve_sse_event_type(&event_value, event_name.as_deref());
 
         match event_type {
             "response.output_item.done" => {
@@ -62,7 +39,9 @@ pub(crate) fn parse_sse_response(raw: &str) -> Result<Value, ModelError> {
                 let message = extract_response_failed_message(&event_value);
                 return Err(ModelError::StreamFailed { message });
             }
-            "response.completed" => {
+            // An incomplete response is terminal too; keeping its payload
+            // preserves `status` and `incomplete_details` for the caller.
+            "response.completed" | "response.incomplete" => {
                 if let Some(response) = event_value.get("response").cloned() {
                     completed_response = Some(response);
                 }
@@ -90,6 +69,72 @@ pub(crate) fn parse_sse_response(raw: &str) -> Result<Value, ModelError> {
     Err(ModelError::MissingStreamResponse)
 }
 
+/// Incremental SSE decoder used while the response body is still arriving.
+///
+/// Bytes are buffered until a complete `\n\n`-terminated block is available;
+/// blocks that fail to parse are skipped here and surfaced by the final
+/// `parse_sse_response` pass over the full body.
+#[derive(Default)]
+pub(crate) struct SseEventDecoder {
+    buffer: Vec<u8>,
+}
+
+impl SseEventDecoder {
+    pub(crate) fn push(&mut self, bytes: &[u8]) -> Vec<(String, Value)> {
+        self.buffer
+            .extend(bytes.iter().copied().filter(|byte| *byte != b'\r'));
+        let mut events = Vec::new();
+        while let Some(end) = self.buffer.windows(2).position(|window| window == b"\n\n") {
+            let block: Vec<u8> = self.buffer.drain(..end + 2).collect();
+            let block = String::from_utf8_lossy(&block);
+            let Some((event_name, data)) = split_sse_block(&block) else {
+                continue;
+            };
+            let Ok(event_value) = serde_json::from_str::<Value>(&data) else {
+                continue;
+            };
+            let event_type =
+                resolve_sse_event_type(&event_value, event_name.as_deref()).to_string();
+            events.push((event_type, event_value));
+        }
+        events
+    }
+}
+
+pub(crate) fn split_sse_block(chunk: &str) -> Option<(Option<String>, String)> {
+    let mut data_lines: Vec<&str> = Vec::new();
+    let mut event_name: Option<String> = None;
+    for line in chunk.lines() {
+        if let Some(rest) = line.strip_prefix("event:") {
+            let normalized_event = rest.trim();
+            if !normalized_event.is_empty() {
+                event_name = Some(normalized_event.to_string());
+            }
+            continue;
+        }
+        if let Some(rest) = line.strip_prefix("data:") {
+            data_lines.push(rest.trim_start());
+        }
+    }
+    if data_lines.is_empty() {
+        return None;
+    }
+
+    let data = data_lines.join("\n");
+    if data.trim() == "[DONE]" {
+        return None;
+    }
+    Some((event_name, data))
+}
+
+fn resolve_sse_event_type<'a>(event_value: &'a Value, event_name: Option<&'a str>) -> &'a str {
+    event_value
+        .get("type")
+        .and_then(Value::as_str)
+        .or(event_name)
+        .unwrap_or_default()
+}
+
 fn extract_response_failed_message(event_value: &Value) -> String {
     if let Some(message) = event_value
         .get("response")
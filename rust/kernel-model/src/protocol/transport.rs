@@ -1,85 +1,513 @@
 use std::time::Duration;
-use tokio::time::sleep;
 
-use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use reqwest::header::{HeaderMap, HeaderValue, ACCEPT, CONTENT_TYPE, RETRY_AFTER};
+use reqwest::{RequestBuilder, StatusCode};
 use serde_json::Value;
 
-use crate::protocol::response::WireResponseBody;
+use crate::protocol::anthropic::transport::{
+    ANTHROPIC_VERSION_HEADER, ANTHROPIC_VERSION_VALUE, X_API_KEY_HEADER,
+};
+use crate::protocol::response::{SseEventDecoder, WireResponseBody};
 use crate::ModelError;
+use finger_kernel_config::{LocalModelConfig, WireApi};
 
+pub(crate) const RESPONSES_ENDPOINT_PATH: &str = "/v1/responses";
+pub(crate) const CHAT_COMPLETIONS_ENDPOINT_PATH: &str = "/v1/chat/completions";
+pub(crate) const ANTHROPIC_MESSAGES_ENDPOINT_PATH: &str = "/v1/messages";
+pub(crate) const MODELS_ENDPOINT_PATH: &str = "/v1/models";
+pub(crate) const AZURE_API_KEY_HEADER: &str = "api-key";
+pub(crate) const REQUEST_ID_HEADER: &str = "x-request-id";
+pub(crate) const USER_AGENT: &str = concat!("finger-kernel/", env!("CARGO_PKG_VERSION"));
+
+/// Correlation id sent as `x-request-id`, so one request can be found in a
+/// proxy's logs.
+pub(crate) fn new_request_id() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
+/// The request id header followed by the extra headers, which may override it.
+pub(crate) fn headers_with_request_id(request_id: &str, extra_headers: &HeaderMap) -> HeaderMap {
+    let mut headers = HeaderMap::with_capacity(extra_headers.len() + 1);
+    headers.insert(
+        REQUEST_ID_HEADER,
+        HeaderValue::from_str(request_id).expect("request id is a valid header value"),
+    );
+    headers.extend(extra_headers.clone());
+    headers
+}
+
+/// How the API key is presented to the provider.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ApiKeyAuth {
+    Bearer,
+    /// `x-api-key` plus the pinned `anthropic-version`.
+    Anthropic,
+    /// Azure OpenAI's `api-key` header.
+    Azure,
+}
+
+impl ApiKeyAuth {
+    pub(crate) fn for_config(config: &LocalModelConfig) -> Self {
+        match config.wire_api {
+            WireApi::Anthropic => Self::Anthropic,
+            WireApi::Responses if config.azure.is_some() => Self::Azure,
+            _ => Self::Bearer,
+        }
+    }
+
+    fn apply(self, request: RequestBuilder, api_key: &str) -> RequestBuilder {
+        match self {
+            Self::Bearer => request.bearer_auth(api_key),
+            Self::Anthropic => request
+                .header(X_API_KEY_HEADER, api_key)
+                .header(ANTHROPIC_VERSION_HEADER, ANTHROPIC_VERSION_VALUE),
+            Self::Azure => request.header(AZURE_API_KEY_HEADER, api_key),
+        }
+    }
+}
+
+/// Joins a base URL and an endpoint path with exactly one `/` between them,
+/// whether or not either side carries its own.
+pub(crate) fn join_endpoint(base_url: &str, path: &str) -> String {
+    format!(
+        "{}/{}",
+        base_url.trim_end_matches('/'),
+        path.trim_start_matches('/')
+    )
+}
+
+/// Sends one request. Retries are the caller's call, since only it knows
+/// which failures its retry budget should cover.
+#[allow(clippy::too_many_arguments)]
 pub(crate) async fn send_responses_http(
     client: &reqwest::Client,
     base_url: &str,
+    endpoint_path: &str,
+    wire_api: WireApi,
+    auth: ApiKeyAuth,
     api_key: &str,
+    extra_headers: &HeaderMap,
     payload: &Value,
     expect_sse: bool,
+    sse_idle_timeout: Option<Duration>,
+    max_response_bytes: u64,
+    on_sse_event: &mut (dyn FnMut(&str, &Value) + Send),
 ) -> Result<WireResponseBody, ModelError> {
-    const MAX_RETRIES: u32 = 10;
-    const INITIAL_BACKOFF_MS: u64 = 500;
-
-    let endpoint = format!("{}/v1/responses", base_url.trim_end_matches('/'));
+    let endpoint = join_endpoint(base_url, endpoint_path);
     let accept_header = if expect_sse {
         "text/event-stream"
     } else {
         "application/json"
     };
-    let mut last_error = None;
-    for attempt in 0..MAX_RETRIES {
-        let response = client
-            .post(&endpoint)
-            .header(CONTENT_TYPE, "application/json")
-            .header(ACCEPT, accept_header)
-            .header("OpenAI-Beta", "responses=experimental")
-            .bearer_auth(api_key)
-            .json(payload)
-            .send()
-            .await;
+    let mut request = client
+        .post(&endpoint)
+        .header(CONTENT_TYPE, "application/json")
+        .header(ACCEPT, accept_header);
+    if wire_api == WireApi::Responses {
+        request = request.header("OpenAI-Beta", "responses=experimental");
+    }
+    request = auth.apply(request, api_key);
+    // Extra headers go last so a gateway can override the defaults.
+    let mut resp = match request
+        .headers(extra_headers.clone())
+        .json(payload)
+        .send()
+        .await
+    {
+        Ok(resp) => resp,
+        Err(e) if e.is_timeout() => return Err(map_transport_error(e)),
+        Err(e) => return Err(ModelError::Request(e)),
+    };
 
-        match response {
-            Ok(resp) => {
-                let status = resp.status();
-                let body = resp.bytes().await?;
-                if !status.is_success() {
-                    // Retry on server errors (5xx) with exponential backoff
-                    if status.is_server_error() && attempt < MAX_RETRIES - 1 {
-                        let backoff_ms = INITIAL_BACKOFF_MS * (1 << attempt.min(6)); // cap at 64x
-                        sleep(Duration::from_millis(backoff_ms)).await;
-                        last_error = Some(ModelError::HttpStatus {
-                            status: status.as_u16(),
-                            body: String::from_utf8_lossy(&body).to_string(),
-                        });
-                        continue;
-                    }
-                    return Err(ModelError::HttpStatus {
-                        status: status.as_u16(),
-                        body: String::from_utf8_lossy(&body).to_string(),
-                    });
-                }
-
-                if expect_sse {
-                    return Ok(WireResponseBody::Sse(
-                        String::from_utf8_lossy(&body).to_string(),
-                    ));
-                }
-
-                return Ok(WireResponseBody::Json(body.to_vec()));
+    let status = resp.status();
+    if status.is_success() && expect_sse {
+        // Stream SSE events to the observer as they arrive; the raw
+        // body is still returned so final parsing stays unchanged.
+        // Chunks arrive already decoded from any gzip/deflate
+        // `Content-Encoding` a proxy applied.
+        let mut decoder = SseEventDecoder::default();
+        let mut raw = Vec::new();
+        loop {
+            let next_chunk = match sse_idle_timeout {
+                Some(limit) => tokio::time::timeout(limit, resp.chunk())
+                    .await
+                    .map_err(|_| ModelError::StreamFailed {
+                        message: "stream idle timeout".to_string(),
+                    })?,
+                None => resp.chunk().await,
+            };
+            let Some(chunk) = next_chunk.map_err(map_transport_error)? else {
+                break;
+            };
+            if (raw.len() + chunk.len()) as u64 > max_response_bytes {
+                return Err(ModelError::ResponseTooLarge {
+                    limit: max_response_bytes,
+                });
+            }
+            raw.extend_from_slice(&chunk);
+            for (event_type, event) in decoder.push(&chunk) {
+                on_sse_event(&event_type, &event);
             }
-            Err(e) => {
-                // Retry on connection errors with exponential backoff
-                if attempt < MAX_RETRIES - 1 {
-                    let backoff_ms = INITIAL_BACKOFF_MS * (1 << attempt.min(6));
-                    sleep(Duration::from_millis(backoff_ms)).await;
-                    last_error = Some(ModelError::Request(e));
-                    continue;
-                }
-                return Err(ModelError::Request(e));
+        }
+        return Ok(WireResponseBody::Sse(
+            String::from_utf8_lossy(&raw).to_string(),
+        ));
+    }
+
+    let retry_after_secs = parse_retry_after_secs(resp.headers());
+    let body = read_body_capped(&mut resp, max_response_bytes, map_transport_error).await?;
+    if status == StatusCode::TOO_MANY_REQUESTS
+        || (status == StatusCode::SERVICE_UNAVAILABLE && retry_after_secs.is_some())
+    {
+        return Err(ModelError::RateLimited {
+            status: status.as_u16(),
+            retry_after_secs,
+            body: String::from_utf8_lossy(&body).to_string(),
+        });
+    }
+    if !status.is_success() {
+        return Err(ModelError::HttpStatus {
+            status: status.as_u16(),
+            body: String::from_utf8_lossy(&body).to_string(),
+        });
+    }
+
+    Ok(WireResponseBody::Json(body))
+}
+
+/// Reads a whole non-streamed body, failing with `ResponseTooLarge` as soon
+/// as it grows past `max_bytes` instead of buffering the rest.
+pub(crate) async fn read_body_capped<E: From<ModelError>>(
+    response: &mut reqwest::Response,
+    max_bytes: u64,
+    map_error: impl Fn(reqwest::Error) -> E,
+) -> Result<Vec<u8>, E> {
+    let too_large = || ModelError::ResponseTooLarge { limit: max_bytes }.into();
+    if response
+        .content_length()
+        .is_some_and(|length| length > max_bytes)
+    {
+        return Err(too_large());
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(&map_error)? {
+        if (body.len() + chunk.len()) as u64 > max_bytes {
+            return Err(too_large());
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+/// Maps a non-success response carrying the common
+/// `{"error":{"type","code","message","param"}}` shape to a typed error.
+/// Bodies without that shape stay `HttpStatus`.
+pub(crate) fn classify_http_error(status: u16, body: String) -> ModelError {
+    let Some(error) = serde_json::from_str::<Value>(&body)
+        .ok()
+        .and_then(|payload| {
+            payload
+                .get("error")
+                .filter(|error| error.is_object())
+                .cloned()
+        })
+    else {
+        return ModelError::HttpStatus { status, body };
+    };
+    let field = |name: &str| {
+        error
+            .get(name)
+            .and_then(Value::as_str)
+            .map(|value| value.to_ascii_lowercase())
+            .unwrap_or_default()
+    };
+    let (error_type, code) = (field("type"), field("code"));
+    let message = error
+        .get("message")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string();
+    let is = |kind: &str| error_type == kind || code == kind;
+
+    if is("context_length_exceeded") || message.contains("maximum context length") {
+        ModelError::ContextLengthExceeded { message }
+    } else if is("model_not_found") {
+        ModelError::ModelNotFound { message }
+    } else if status == 401
+        || status == 403
+        || is("authentication_error")
+        || is("permission_error")
+        || is("invalid_api_key")
+    {
+        ModelError::AuthFailed { status, message }
+    } else if status == 429 || is("rate_limit_error") || is("rate_limit_exceeded") {
+        ModelError::RateLimited {
+            status,
+            retry_after_secs: None,
+            body,
+        }
+    } else if status >= 500 || is("server_error") || is("api_error") || is("overloaded_error") {
+        ModelError::ServerError { status, message }
+    } else if (400..500).contains(&status) || is("invalid_request_error") {
+        ModelError::InvalidRequest {
+            message,
+            param: error
+                .get("param")
+                .and_then(Value::as_str)
+                .map(str::to_string),
+        }
+    } else {
+        ModelError::HttpStatus { status, body }
+    }
+}
+
+/// Lists the provider's models, which proves the base URL and key work
+/// without generating anything. Returns the status and body untouched.
+pub(crate) async fn send_models_probe(
+    client: &reqwest::Client,
+    base_url: &str,
+    api_key: &str,
+    extra_headers: &HeaderMap,
+    auth: ApiKeyAuth,
+    timeout: Duration,
+    max_response_bytes: u64,
+) -> Result<(StatusCode, String), ModelError> {
+    let endpoint = join_endpoint(base_url, MODELS_ENDPOINT_PATH);
+    let request = client
+        .get(endpoint)
+        .header(ACCEPT, "application/json")
+        .timeout(timeout);
+    let request = auth.apply(request, api_key);
+    let mut response = request
+        .headers(extra_headers.clone())
+        .send()
+        .await
+        .map_err(ModelError::Request)?;
+    let status = response.status();
+    let body = read_body_capped(&mut response, max_response_bytes, ModelError::Request).await?;
+    Ok((status, String::from_utf8_lossy(&body).to_string()))
+}
+
+/// Only the delay-seconds form of `Retry-After` is honored; HTTP dates fall
+/// back to the caller's backoff.
+fn parse_retry_after_secs(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()
+}
+
+/// Timeouts are surfaced separately so callers can decide whether to retry.
+fn map_transport_error(error: reqwest::Error) -> ModelError {
+    if error.is_timeout() {
+        ModelError::Timeout {
+            message: error.to_string(),
+        }
+    } else {
+        ModelError::Request(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        classify_http_error, join_endpoint, send_responses_http, ApiKeyAuth,
+        RESPONSES_ENDPOINT_PATH,
+    };
+    use crate::protocol::response::{parse_wire_response, WireResponseBody};
+    use crate::ModelError;
+    use finger_kernel_config::WireApi;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use mockito::Server;
+    use reqwest::header::HeaderMap;
+    use serde_json::json;
+    use std::io::Write;
+    use std::time::Duration;
+
+    fn gzip(body: &str) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(body.as_bytes()).expect("gzip body");
+        encoder.finish().expect("finish gzip")
+    }
+
+    #[test]
+    fn join_endpoint_normalizes_slashes() {
+        for (base, path) in [
+            ("https://gateway.example.com/openai", "/responses"),
+            ("https://gateway.example.com/openai/", "/responses"),
+            ("https://gateway.example.com/openai//", "responses"),
+            ("https://gateway.example.com/openai", "responses"),
+        ] {
+            assert_eq!(
+                join_endpoint(base, path),
+                "https://gateway.example.com/openai/responses"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn gzip_encoded_bodies_are_decompressed_before_parsing() {
+        let mut server = Server::new_async().await;
+        let json_mock = server
+            .mock("POST", RESPONSES_ENDPOINT_PATH)
+            .match_body(mockito::Matcher::PartialJson(json!({ "stream": false })))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_header("content-encoding", "gzip")
+            .with_body(gzip(
+                r#"{"id":"resp_json","status":"completed","output":[]}"#,
+            ))
+            .create_async()
+            .await;
+        let sse_mock = server
+            .mock("POST", RESPONSES_ENDPOINT_PATH)
+            .match_body(mockito::Matcher::PartialJson(json!({ "stream": true })))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_header("content-encoding", "gzip")
+            .with_body(gzip(concat!(
+                "event: response.completed\n",
+                "data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_sse\",\"output\":[]}}\n\n",
+                "data: [DONE]\n\n"
+            )))
+            .create_async()
+            .await;
+
+        let client = reqwest::Client::new();
+        let mut sse_events = Vec::new();
+        for stream in [false, true] {
+            let body = send_responses_http(
+                &client,
+                &server.url(),
+                RESPONSES_ENDPOINT_PATH,
+                WireApi::Responses,
+                ApiKeyAuth::Bearer,
+                "test-key",
+                &HeaderMap::new(),
+                &json!({ "stream": stream }),
+                stream,
+                None,
+                u64::MAX,
+                &mut |event_type, _| sse_events.push(event_type.to_string()),
+            )
+            .await
+            .expect("decompressed response");
+            assert_eq!(matches!(body, WireResponseBody::Sse(_)), stream);
+            let parsed = parse_wire_response(body).expect("parse decompressed body");
+            let expected_id = if stream { "resp_sse" } else { "resp_json" };
+            assert_eq!(parsed["id"], expected_id);
+        }
+        assert!(sse_events.iter().any(|event| event == "response.completed"));
+
+        json_mock.assert_async().await;
+        sse_mock.assert_async().await;
+    }
+
+    #[test]
+    fn provider_error_bodies_map_to_typed_errors() {
+        let classify = |status: u16, body: &str| classify_http_error(status, body.to_string());
+
+        assert!(matches!(
+            classify(
+                400,
+                r#"{"error":{"type":"invalid_request_error","code":"context_length_exceeded","message":"This model's maximum context length is 8192 tokens.","param":"input"}}"#,
+            ),
+            ModelError::ContextLengthExceeded { .. }
+        ));
+        assert!(matches!(
+            classify(
+                404,
+                r#"{"error":{"type":"invalid_request_error","code":"model_not_found","message":"The model `gpt-9` does not exist"}}"#,
+            ),
+            ModelError::ModelNotFound { .. }
+        ));
+        assert!(matches!(
+            classify(
+                401,
+                r#"{"error":{"type":"invalid_request_error","code":"invalid_api_key","message":"Incorrect API key provided"}}"#,
+            ),
+            ModelError::AuthFailed { status: 401, .. }
+        ));
+        assert!(matches!(
+            classify(
+                429,
+                r#"{"error":{"type":"requests","code":"rate_limit_exceeded","message":"Rate limit reached"}}"#,
+            ),
+            ModelError::RateLimited { status: 429, .. }
+        ));
+        assert!(matches!(
+            classify(
+                529,
+                r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#,
+            ),
+            ModelError::ServerError { status: 529, .. }
+        ));
+        match classify(
+            400,
+            r#"{"error":{"type":"invalid_request_error","code":null,"message":"Unsupported value: 'temperature'","param":"temperature"}}"#,
+        ) {
+            ModelError::InvalidRequest { message, param } => {
+                assert_eq!(message, "Unsupported value: 'temperature'");
+                assert_eq!(param.as_deref(), Some("temperature"));
             }
+            other => panic!("expected invalid request, got {other:?}"),
         }
+        assert!(matches!(
+            classify(502, "<html>Bad Gateway</html>"),
+            ModelError::HttpStatus { status: 502, .. }
+        ));
     }
 
-    // Should never reach here, but return the last error if we do
-    Err(last_error.unwrap_or_else(|| ModelError::HttpStatus {
-        status: 0,
-        body: "Max retries exceeded".to_string(),
-    }))
+    #[tokio::test]
+    async fn stalled_stream_fails_after_idle_timeout() {
+        let mut server = Server::new_async().await;
+        let _mock = server
+            .mock("POST", RESPONSES_ENDPOINT_PATH)
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_chunked_body(|writer| {
+                writer.write_all(
+                    concat!(
+                        "event: response.created\n",
+                        "data: {\"type\":\"response.created\",\"response\":{\"id\":\"resp_stall\"}}\n\n",
+                    )
+                    .as_bytes(),
+                )?;
+                writer.flush()?;
+                std::thread::sleep(std::time::Duration::from_millis(800));
+                Ok(())
+            })
+            .create_async()
+            .await;
+
+        let mut sse_events = Vec::new();
+        let result = send_responses_http(
+            &reqwest::Client::new(),
+            &server.url(),
+            RESPONSES_ENDPOINT_PATH,
+            WireApi::Responses,
+            ApiKeyAuth::Bearer,
+            "test-key",
+            &HeaderMap::new(),
+            &json!({ "stream": true }),
+            true,
+            Some(Duration::from_millis(150)),
+            u64::MAX,
+            &mut |event_type, _| sse_events.push(event_type.to_string()),
+        )
+        .await;
+        let Err(error) = result else {
+            panic!("stalled stream should hit the idle timeout");
+        };
+
+        assert!(
+            matches!(&error, ModelError::StreamFailed { message } if message == "stream idle timeout"),
+            "unexpected error: {error}"
+        );
+        assert_eq!(sse_events, vec!["response.created".to_string()]);
+    }
 }
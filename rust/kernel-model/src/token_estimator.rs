@@ -0,0 +1,207 @@
+use serde_json::Value;
+use tiktoken_rs::CoreBPE;
+
+use crate::ModelError;
+
+/// Fixed estimate for image parts, which are billed separately from text.
+const IMAGE_PLACEHOLDER_CHARS: usize = 16;
+const IMAGE_PLACEHOLDER_TOKENS: u64 = 4;
+
+/// Estimates how many input tokens a history item occupies in the context window.
+pub trait TokenEstimator: Send + Sync {
+    fn estimate_item_tokens(&self, item: &Value) -> u64;
+
+    /// Tokens for a whole history; the default sums the per-item estimates.
+    fn estimate_history_tokens(&self, items: &[&Value]) -> u64 {
+        items
+            .iter()
+            .map(|item| self.estimate_item_tokens(item))
+            .sum()
+    }
+}
+
+/// Default estimator: serialized characters divided by four.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HeuristicTokenEstimator;
+
+impl TokenEstimator for HeuristicTokenEstimator {
+    fn estimate_item_tokens(&self, item: &Value) -> u64 {
+        ((estimate_chars_in_json(item) as f64) / 4.0).ceil() as u64
+    }
+
+    /// Rounds once over the whole history rather than per item, so many
+    /// small items do not inflate the estimate the compaction thresholds use.
+    fn estimate_history_tokens(&self, items: &[&Value]) -> u64 {
+        let chars: usize = items.iter().map(|item| estimate_chars_in_json(item)).sum();
+        ((chars as f64) / 4.0).ceil() as u64
+    }
+}
+
+/// BPE estimator backed by `tiktoken-rs`, selected by model name.
+pub struct TiktokenEstimator {
+    bpe: CoreBPE,
+}
+
+impl TiktokenEstimator {
+    pub fn for_model(model: &str) -> Result<Self, ModelError> {
+        let bpe =
+            tiktoken_rs::get_bpe_from_model(model).map_err(|error| ModelError::Tokenizer {
+                model: model.to_string(),
+                message: error.to_string(),
+            })?;
+        Ok(Self { bpe })
+    }
+
+    fn count_json_tokens(&self, value: &Value) -> u64 {
+        match value {
+            Value::Null => 0,
+            Value::Bool(_) => 1,
+            Value::Number(number) => self.count_text_tokens(&number.to_string()),
+            Value::String(text) => self.count_text_tokens(text),
+            Value::Array(items) => items.iter().map(|item| self.count_json_tokens(item)).sum(),
+            Value::Object(map) => {
+                if is_image_part(map) {
+                    return IMAGE_PLACEHOLDER_TOKENS;
+                }
+                map.iter()
+                    .map(|(key, item)| self.count_text_tokens(key) + self.count_json_tokens(item))
+                    .sum()
+            }
+        }
+    }
+
+    fn count_text_tokens(&self, text: &str) -> u64 {
+        self.bpe.encode_ordinary(text).len() as u64
+    }
+}
+
+impl TokenEstimator for TiktokenEstimator {
+    fn estimate_item_tokens(&self, item: &Value) -> u64 {
+        self.count_json_tokens(item)
+    }
+}
+
+fn estimate_chars_in_json(value: &Value) -> usize {
+    match value {
+        Value::Null => 0,
+        Value::Bool(_) => 1,
+        Value::Number(_) => 8,
+        Value::String(text) => text.len(),
+        Value::Array(items) => items.iter().map(estimate_chars_in_json).sum(),
+        Value::Object(map) => {
+            // Image attachments should not inflate text-context budget.
+            // They are non-text multimodal payloads and are billed/handled separately by model providers.
+            if is_image_part(map) {
+                return IMAGE_PLACEHOLDER_CHARS;
+            }
+
+            map.iter()
+                .map(|(key, item)| key.len() + estimate_chars_in_json(item))
+                .sum()
+        }
+    }
+}
+
+fn is_image_part(map: &serde_json::Map<String, Value>) -> bool {
+    matches!(
+        map.get("type").and_then(Value::as_str),
+        Some("input_image") | Some("image") | Some("local_image")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn mixed_history() -> Vec<Value> {
+        vec![
+            json!({
+                "role": "user",
+                "content": [{
+                    "type": "input_text",
+                    "text": "请帮我检查这个函数为什么在处理中文输入时会出错，并解释原因。",
+                }],
+            }),
+            json!({
+                "role": "assistant",
+                "content": [{
+                    "type": "output_text",
+                    "text": format!(
+                        "fn main() {{\n{}\n}}",
+                        "        let value = compute(input);\n".repeat(40)
+                    ),
+                }],
+            }),
+        ]
+    }
+
+    #[test]
+    fn heuristic_matches_chars_divided_by_four() {
+        let item = json!({ "role": "user", "content": "abcdefgh" });
+        // "role" + "user" + "content" + "abcdefgh" = 23 chars.
+        assert_eq!(HeuristicTokenEstimator.estimate_item_tokens(&item), 6);
+    }
+
+    #[test]
+    fn heuristic_rounds_once_over_the_whole_history() {
+        // Each item is 5 chars: 2 tokens alone, but 10 chars overall is 3.
+        let first = json!({ "a": "bcde" });
+        let second = json!({ "f": "ghij" });
+        assert_eq!(HeuristicTokenEstimator.estimate_item_tokens(&first), 2);
+        assert_eq!(
+            HeuristicTokenEstimator.estimate_history_tokens(&[&first, &second]),
+            3
+        );
+    }
+
+    #[test]
+    fn tokenizer_diverges_from_heuristic_on_code_and_prose() {
+        let tokenizer = TiktokenEstimator::for_model("gpt-4o").expect("load tokenizer");
+        let history = mixed_history();
+
+        // Repeated indentation collapses into a few BPE tokens, so chars/4 overcounts code.
+        let code_heuristic = HeuristicTokenEstimator.estimate_item_tokens(&history[1]);
+        let code_tokenizer = tokenizer.estimate_item_tokens(&history[1]);
+        assert!(code_tokenizer < code_heuristic);
+
+        let prose_heuristic = HeuristicTokenEstimator.estimate_item_tokens(&history[0]);
+        let prose_tokenizer = tokenizer.estimate_item_tokens(&history[0]);
+        assert!(prose_tokenizer > 0);
+        assert_ne!(prose_tokenizer, prose_heuristic);
+
+        let heuristic_total: u64 = history
+            .iter()
+            .map(|item| HeuristicTokenEstimator.estimate_item_tokens(item))
+            .sum();
+        let tokenizer_total: u64 = history
+            .iter()
+            .map(|item| tokenizer.estimate_item_tokens(item))
+            .sum();
+        assert_eq!(heuristic_total, code_heuristic + prose_heuristic);
+        assert_eq!(tokenizer_total, code_tokenizer + prose_tokenizer);
+        assert!(tokenizer_total < heuristic_total);
+    }
+
+    #[test]
+    fn tokenizer_counts_images_as_fixed_placeholder() {
+        let tokenizer = TiktokenEstimator::for_model("gpt-4o").expect("load tokenizer");
+        let image = json!({
+            "type": "input_image",
+            "image_url": format!("data:image/png;base64,{}", "A".repeat(10_000)),
+        });
+        assert_eq!(
+            tokenizer.estimate_item_tokens(&image),
+            IMAGE_PLACEHOLDER_TOKENS
+        );
+    }
+
+    #[test]
+    fn unknown_model_reports_tokenizer_error() {
+        let error = match TiktokenEstimator::for_model("not-a-real-model") {
+            Ok(_) => panic!("unknown model should not load a tokenizer"),
+            Err(error) => error,
+        };
+        assert!(matches!(error, ModelError::Tokenizer { .. }));
+    }
+}
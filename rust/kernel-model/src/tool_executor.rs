@@ -0,0 +1,53 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde_json::Value;
+
+/// Runs tool calls in-process instead of posting them to the tool daemon.
+/// Calls for tools the executor does not handle still go to the daemon.
+#[async_trait]
+pub trait ToolExecutor: Send + Sync {
+    fn handles(&self, _name: &str) -> bool {
+        true
+    }
+
+    async fn execute(&self, name: &str, input: Value) -> Result<Value, String>;
+}
+
+type ToolFn = dyn Fn(Value) -> Result<Value, String> + Send + Sync;
+
+/// Executor backed by closures registered per runtime tool name.
+#[derive(Clone, Default)]
+pub struct FnToolExecutor {
+    tools: HashMap<String, Arc<ToolFn>>,
+}
+
+impl FnToolExecutor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(
+        mut self,
+        name: impl Into<String>,
+        tool: impl Fn(Value) -> Result<Value, String> + Send + Sync + 'static,
+    ) -> Self {
+        self.tools.insert(name.into(), Arc::new(tool));
+        self
+    }
+}
+
+#[async_trait]
+impl ToolExecutor for FnToolExecutor {
+    fn handles(&self, name: &str) -> bool {
+        self.tools.contains_key(name)
+    }
+
+    async fn execute(&self, name: &str, input: Value) -> Result<Value, String> {
+        match self.tools.get(name) {
+            Some(tool) => tool(input),
+            None => Err(format!("no in-process tool registered as {name}")),
+        }
+    }
+}
@@ -15,7 +15,12 @@ pub enum Op {
         #[serde(default, skip_serializing_if = "UserTurnOptions::is_empty")]
         options: UserTurnOptions,
     },
-    Interrupt,
+    /// Aborts the task started by submission `target` (or running for that
+    /// session id); without a target every running task is aborted.
+    Interrupt {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        target: Option<String>,
+    },
     Shutdown,
     ExecApproval {
         id: String,
@@ -49,6 +54,14 @@ pub struct UserTurnOptions {
     pub environment_context: Option<String>,
     #[serde(default)]
     pub turn_context: Option<TurnContext>,
+    /// Caller-named blocks (e.g. `repo_map`) injected alongside the built-in
+    /// ones, in order within each position.
+    #[serde(default)]
+    pub extra_context_blocks: Vec<ContextBlock>,
+    /// Replace context blocks already in `history_items` with this turn's
+    /// version instead of skipping the new one.
+    #[serde(default)]
+    pub refresh_context_blocks: bool,
     #[serde(default)]
     pub context_window: Option<ContextWindowConfig>,
     #[serde(default)]
@@ -61,6 +74,17 @@ pub struct UserTurnOptions {
     pub responses: Option<ResponsesRequestOptions>,
     #[serde(default)]
     pub anthropic: Option<AnthropicRequestOptions>,
+    /// Upper bound on model rounds in one turn; a turn that is still calling
+    /// tools at the limit ends without a final message.
+    #[serde(default)]
+    pub max_tool_rounds: Option<u32>,
+    /// Model used for this turn instead of the engine's configured one.
+    #[serde(default)]
+    pub model_override: Option<String>,
+    /// Leave reasoning items out of the `api_history` returned in metadata,
+    /// keeping only their count; the turn itself still replays them.
+    #[serde(default)]
+    pub strip_reasoning_from_history: bool,
 }
 
 impl UserTurnOptions {
@@ -76,11 +100,186 @@ impl UserTurnOptions {
             && options.anthropic.is_none()
             && options.environment_context.is_none()
             && options.turn_context.is_none()
+            && options.extra_context_blocks.is_empty()
+            && !options.refresh_context_blocks
             && options.context_window.is_none()
             && options.compact.is_none()
             && options.fork_user_message_index.is_none()
             && options.context_ledger.is_none()
             && options.responses.is_none()
+            && options.max_tool_rounds.is_none()
+            && options.model_override.is_none()
+            && !options.strip_reasoning_from_history
+    }
+
+    pub fn builder() -> UserTurnOptionsBuilder {
+        UserTurnOptionsBuilder::default()
+    }
+}
+
+/// Chainable construction of [`UserTurnOptions`]; fields left untouched keep
+/// their defaults.
+///
+/// ```
+/// use finger_kernel_protocol::{ContextLedgerOptions, ToolSpec, UserTurnOptions};
+///
+/// let options = UserTurnOptions::builder()
+///     .system_prompt("You are a coding agent.")
+///     .session_id("session-1")
+///     .tool(ToolSpec {
+///         name: "shell.exec".to_string(),
+///         description: Some("Run a shell command".to_string()),
+///         input_schema: None,
+///         approval: None,
+///     })
+///     .with_ledger(ContextLedgerOptions {
+///         root_dir: Some("/tmp/ledger".to_string()),
+///         focus_enabled: true,
+///         ..ContextLedgerOptions::default()
+///     })
+///     .reasoning_effort("high")
+///     .build();
+///
+/// assert_eq!(options.tools.len(), 1);
+/// assert!(options.context_ledger.as_ref().is_some_and(|ledger| ledger.enabled));
+/// assert_eq!(
+///     options.responses.and_then(|r| r.reasoning).and_then(|r| r.effort).as_deref(),
+///     Some("high")
+/// );
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct UserTurnOptionsBuilder {
+    options: UserTurnOptions,
+}
+
+impl UserTurnOptionsBuilder {
+    pub fn system_prompt(mut self, system_prompt: impl Into<String>) -> Self {
+        self.options.system_prompt = Some(system_prompt.into());
+        self
+    }
+
+    pub fn tool(mut self, tool: ToolSpec) -> Self {
+        self.options.tools.push(tool);
+        self
+    }
+
+    pub fn tools(mut self, tools: impl IntoIterator<Item = ToolSpec>) -> Self {
+        self.options.tools.extend(tools);
+        self
+    }
+
+    pub fn tool_execution(mut self, tool_execution: ToolExecutionConfig) -> Self {
+        self.options.tool_execution = Some(tool_execution);
+        self
+    }
+
+    pub fn session_id(mut self, session_id: impl Into<String>) -> Self {
+        self.options.session_id = Some(session_id.into());
+        self
+    }
+
+    pub fn mode(mut self, mode: impl Into<String>) -> Self {
+        self.options.mode = Some(mode.into());
+        self
+    }
+
+    pub fn history_items(mut self, history_items: Vec<Value>) -> Self {
+        self.options.history_items = history_items;
+        self
+    }
+
+    pub fn developer_instructions(mut self, instructions: impl Into<String>) -> Self {
+        self.options.developer_instructions = Some(instructions.into());
+        self
+    }
+
+    pub fn user_instructions(mut self, instructions: impl Into<String>) -> Self {
+        self.options.user_instructions = Some(instructions.into());
+        self
+    }
+
+    pub fn environment_context(mut self, environment_context: impl Into<String>) -> Self {
+        self.options.environment_context = Some(environment_context.into());
+        self
+    }
+
+    pub fn turn_context(mut self, turn_context: TurnContext) -> Self {
+        self.options.turn_context = Some(turn_context);
+        self
+    }
+
+    pub fn context_block(mut self, block: ContextBlock) -> Self {
+        self.options.extra_context_blocks.push(block);
+        self
+    }
+
+    pub fn refresh_context_blocks(mut self, refresh: bool) -> Self {
+        self.options.refresh_context_blocks = refresh;
+        self
+    }
+
+    pub fn context_window(mut self, context_window: ContextWindowConfig) -> Self {
+        self.options.context_window = Some(context_window);
+        self
+    }
+
+    pub fn compact(mut self, compact: CompactConfig) -> Self {
+        self.options.compact = Some(compact);
+        self
+    }
+
+    pub fn fork_user_message_index(mut self, index: usize) -> Self {
+        self.options.fork_user_message_index = Some(index);
+        self
+    }
+
+    /// Sets the ledger options and turns the ledger on.
+    pub fn with_ledger(mut self, ledger: ContextLedgerOptions) -> Self {
+        self.options.context_ledger = Some(ContextLedgerOptions {
+            enabled: true,
+            ..ledger
+        });
+        self
+    }
+
+    pub fn responses(mut self, responses: ResponsesRequestOptions) -> Self {
+        self.options.responses = Some(responses);
+        self
+    }
+
+    /// Sets `responses.reasoning.effort`, keeping any other responses options
+    /// already set.
+    pub fn reasoning_effort(mut self, effort: impl Into<String>) -> Self {
+        let responses = self.options.responses.get_or_insert_with(Default::default);
+        responses
+            .reasoning
+            .get_or_insert_with(Default::default)
+            .effort = Some(effort.into());
+        self
+    }
+
+    pub fn anthropic(mut self, anthropic: AnthropicRequestOptions) -> Self {
+        self.options.anthropic = Some(anthropic);
+        self
+    }
+
+    pub fn max_tool_rounds(mut self, max_tool_rounds: u32) -> Self {
+        self.options.max_tool_rounds = Some(max_tool_rounds);
+        self
+    }
+
+    pub fn model_override(mut self, model: impl Into<String>) -> Self {
+        self.options.model_override = Some(model.into());
+        self
+    }
+
+    pub fn strip_reasoning_from_history(mut self, strip: bool) -> Self {
+        self.options.strip_reasoning_from_history = strip;
+        self
+    }
+
+    pub fn build(self) -> UserTurnOptions {
+        self.options
     }
 }
 
@@ -96,6 +295,116 @@ pub struct ResponsesRequestOptions {
     pub store: Option<bool>,
     #[serde(default)]
     pub parallel_tool_calls: Option<bool>,
+    /// Sampling temperature in `0..=2`; omitted from the request when unset.
+    #[serde(default)]
+    pub temperature: Option<f64>,
+    /// Nucleus sampling mass in `0..=1`; omitted from the request when unset.
+    #[serde(default)]
+    pub top_p: Option<f64>,
+    /// Cap on generated tokens, including reasoning tokens.
+    #[serde(default)]
+    pub max_output_tokens: Option<u64>,
+    /// Defaults to `auto` when tools are present.
+    #[serde(default)]
+    pub tool_choice: Option<ToolChoice>,
+    /// With `store` set, continue tool-loop rounds from the previous
+    /// response id and send only the items added since.
+    #[serde(default)]
+    pub use_previous_response_id: bool,
+    /// Key/value tags (e.g. `user_id`, `trace_id`) the provider echoes back;
+    /// sent as-is.
+    #[serde(default)]
+    pub metadata: Option<serde_json::Map<String, Value>>,
+    /// Sent as `prompt_cache_key` instead of the key derived from the
+    /// turn's stable prefix (system prompt, tools and instructions).
+    #[serde(default)]
+    pub prompt_cache_key: Option<String>,
+    /// Joined before the system prompt with a newline, e.g. a mandatory
+    /// policy preamble.
+    #[serde(default)]
+    pub instructions_prefix: Option<String>,
+    /// Joined after the system prompt with a newline, e.g. a formatting
+    /// directive.
+    #[serde(default)]
+    pub instructions_suffix: Option<String>,
+    /// Keep nothing provider-side: forces `store = false`, drops the
+    /// encrypted reasoning include and leaves reasoning items out of every
+    /// request, since their ids cannot be resolved without storage.
+    #[serde(default)]
+    pub stateless: bool,
+}
+
+impl ResponsesRequestOptions {
+    /// Fills every field left unset here from `defaults`; nested reasoning
+    /// and text options are merged the same way.
+    pub fn with_defaults(self, defaults: &ResponsesRequestOptions) -> Self {
+        Self {
+            reasoning: merge_nested(self.reasoning, defaults.reasoning.as_ref(), |turn, base| {
+                ResponsesReasoningOptions {
+                    enabled: turn.enabled.or(base.enabled),
+                    effort: turn.effort.or_else(|| base.effort.clone()),
+                    summary: turn.summary.or_else(|| base.summary.clone()),
+                    include_encrypted_content: turn
+                        .include_encrypted_content
+                        .or(base.include_encrypted_content),
+                }
+            }),
+            text: merge_nested(self.text, defaults.text.as_ref(), |turn, base| {
+                ResponsesTextOptions {
+                    enabled: turn.enabled.or(base.enabled),
+                    verbosity: turn.verbosity.or_else(|| base.verbosity.clone()),
+                    output_schema: turn.output_schema.or_else(|| base.output_schema.clone()),
+                    max_schema_retries: turn.max_schema_retries.or(base.max_schema_retries),
+                }
+            }),
+            include: if self.include.is_empty() {
+                defaults.include.clone()
+            } else {
+                self.include
+            },
+            store: self.store.or(defaults.store),
+            parallel_tool_calls: self.parallel_tool_calls.or(defaults.parallel_tool_calls),
+            temperature: self.temperature.or(defaults.temperature),
+            top_p: self.top_p.or(defaults.top_p),
+            max_output_tokens: self.max_output_tokens.or(defaults.max_output_tokens),
+            tool_choice: self.tool_choice.or_else(|| defaults.tool_choice.clone()),
+            use_previous_response_id: self.use_previous_response_id
+                || defaults.use_previous_response_id,
+            metadata: self.metadata.or_else(|| defaults.metadata.clone()),
+            prompt_cache_key: self
+                .prompt_cache_key
+                .or_else(|| defaults.prompt_cache_key.clone()),
+            instructions_prefix: self
+                .instructions_prefix
+                .or_else(|| defaults.instructions_prefix.clone()),
+            instructions_suffix: self
+                .instructions_suffix
+                .or_else(|| defaults.instructions_suffix.clone()),
+            stateless: self.stateless || defaults.stateless,
+        }
+    }
+}
+
+fn merge_nested<T: Clone>(
+    turn: Option<T>,
+    base: Option<&T>,
+    merge: impl FnOnce(T, &T) -> T,
+) -> Option<T> {
+    match (turn, base) {
+        (Some(turn), Some(base)) => Some(merge(turn, base)),
+        (turn, base) => turn.or_else(|| base.cloned()),
+    }
+}
+
+/// Whether the model may, must, or must not call tools in a turn. `Function`
+/// takes the runtime tool name (e.g. `shell.exec`).
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ToolChoice {
+    Auto,
+    None,
+    Required,
+    Function { name: String },
 }
 
 #[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
@@ -118,6 +427,10 @@ pub struct ResponsesTextOptions {
     pub verbosity: Option<String>,
     #[serde(default)]
     pub output_schema: Option<Value>,
+    /// Re-asks allowed when a reply fails `output_schema`; counted apart
+    /// from tool rounds.
+    #[serde(default)]
+    pub max_schema_retries: Option<u8>,
 }
 
 /// Anthropic Messages API-specific request options.
@@ -168,6 +481,14 @@ pub struct ContextLedgerOptions {
     pub focus_enabled: bool,
     #[serde(default)]
     pub focus_max_chars: Option<usize>,
+    #[serde(default)]
+    pub max_segment_bytes: Option<u64>,
+    #[serde(default)]
+    pub max_retained_segments: Option<usize>,
+    /// Role of the message carrying the recalled focus: `user` (default),
+    /// `developer` or `system`.
+    #[serde(default)]
+    pub focus_injection_role: Option<String>,
 }
 
 #[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
@@ -182,6 +503,37 @@ pub struct TurnContext {
     pub model: Option<String>,
 }
 
+/// A custom context block, sent as `<name>\ncontent\n</name>` and
+/// deduplicated by name against history like the built-in blocks.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct ContextBlock {
+    pub name: String,
+    pub content: String,
+    #[serde(default)]
+    pub role: ContextBlockRole,
+    #[serde(default)]
+    pub position: ContextBlockPosition,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ContextBlockRole {
+    #[default]
+    Developer,
+    User,
+}
+
+/// `AfterInstructions` follows the developer and user instructions;
+/// `BeforeUserInput` follows the environment context, right before the
+/// turn's user message.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ContextBlockPosition {
+    #[default]
+    AfterInstructions,
+    BeforeUserInput,
+}
+
 #[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
 pub struct ContextWindowConfig {
     #[serde(default)]
@@ -200,6 +552,23 @@ pub struct CompactConfig {
     pub preserve_user_messages: bool,
     #[serde(default)]
     pub summary_hint: Option<String>,
+    /// Replace the compacted task digests with a prose summary written by
+    /// the model; falls back to the digests if that request fails.
+    #[serde(default)]
+    pub use_model_summary: bool,
+    /// How many of the most recent tool calls keep their call and output
+    /// verbatim; older ones are folded into the task digests.
+    #[serde(default)]
+    pub preserve_recent_tool_rounds: Option<usize>,
+    /// Estimated tokens the compact summary may spend on task and recent
+    /// lines, filled newest first; unset keeps the last 12 tasks and 4
+    /// recent items.
+    #[serde(default)]
+    pub summary_token_budget: Option<u64>,
+    /// With `preserve_user_messages` off, how many of the newest task
+    /// requests the compact summary keeps; unset keeps 12 and 0 keeps none.
+    #[serde(default)]
+    pub max_preserved_user_messages: Option<usize>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
@@ -209,12 +578,61 @@ pub struct ToolSpec {
     pub description: Option<String>,
     #[serde(default)]
     pub input_schema: Option<Value>,
+    /// Calls wait for a matching `ExecApproval`/`PatchApproval` before running.
+    #[serde(default)]
+    pub approval: Option<ApprovalKind>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ApprovalKind {
+    Exec,
+    Patch,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
 pub struct ToolExecutionConfig {
     pub daemon_url: String,
     pub agent_id: String,
+    /// Upper bound on tool calls from one model round executed at the same time.
+    #[serde(default)]
+    pub max_concurrency: Option<usize>,
+    /// Per-call deadline for the tool daemon request; unset means the client default.
+    #[serde(default)]
+    pub tool_timeout_ms: Option<u64>,
+    /// Sent as `Authorization: Bearer` on tool daemon requests.
+    #[serde(default)]
+    pub auth_token: Option<String>,
+    /// Retry daemon connection errors and 5xx responses with backoff. Off by
+    /// default, since a retried call may repeat its side effects.
+    #[serde(default)]
+    pub retry_transient_errors: bool,
+    /// Retries per call when `retry_transient_errors` is set (default 2).
+    #[serde(default)]
+    pub tool_max_retries: Option<u8>,
+    /// Sends tools to other daemons by runtime name prefix; the longest
+    /// matching prefix wins and unmatched tools use `daemon_url`.
+    #[serde(default)]
+    pub routes: Vec<ToolDaemonRoute>,
+    /// Tool execute endpoint under each daemon URL; unset means
+    /// `/api/v1/tools/execute`.
+    #[serde(default)]
+    pub tool_execute_path: Option<String>,
+    /// Reuse the result of an earlier identical call (same tool, same
+    /// arguments) within the turn. Off by default, since not every tool is
+    /// idempotent.
+    #[serde(default)]
+    pub cache_identical_calls: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct ToolDaemonRoute {
+    /// Runtime tool name prefix, e.g. `browser.`.
+    pub prefix: String,
+    pub daemon_url: String,
+    /// Overrides the config's `agent_id` for tools on this daemon.
+    #[serde(default)]
+    pub agent_id: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
@@ -223,11 +641,25 @@ pub enum InputItem {
     Text { text: String },
     Image { image_url: String },
     LocalImage { path: String },
+    /// A document attachment, inlined from a local `path` or referenced by a
+    /// provider-side `file_id`.
+    File {
+        #[serde(default)]
+        path: Option<String>,
+        #[serde(default)]
+        file_id: Option<String>,
+        #[serde(default)]
+        filename: Option<String>,
+    },
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub struct Event {
     pub id: String,
+    /// Position in the runtime's event stream: 1 for the first event, then
+    /// one more per event, so a consumer can order events and spot gaps.
+    #[serde(default)]
+    pub seq: u64,
     pub msg: EventMsg,
 }
 
@@ -237,9 +669,15 @@ pub enum EventMsg {
     SessionConfigured(SessionConfiguredEvent),
     TaskStarted(TaskStartedEvent),
     ModelRound(ModelRoundEvent),
+    OutputTextDelta(OutputTextDeltaEvent),
+    Reasoning(ReasoningEvent),
     ToolCall(ToolCallEvent),
+    ExecApprovalRequest(ApprovalRequestEvent),
+    PatchApprovalRequest(ApprovalRequestEvent),
     ToolResult(ToolResultEvent),
     ToolError(ToolErrorEvent),
+    Usage(UsageEvent),
+    Compaction(CompactionEvent),
     TaskComplete(TaskCompleteEvent),
     TurnAborted(TurnAbortedEvent),
     ShutdownComplete,
@@ -272,6 +710,9 @@ pub struct ModelRoundEvent {
     pub response_incomplete_reason: Option<String>,
     #[serde(default)]
     pub response_id: Option<String>,
+    /// `x-request-id` sent with the request that produced this round.
+    #[serde(default)]
+    pub request_id: Option<String>,
     #[serde(default)]
     pub input_tokens: Option<u64>,
     #[serde(default)]
@@ -290,6 +731,18 @@ pub struct ModelRoundEvent {
     pub threshold_percent: Option<u64>,
 }
 
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct OutputTextDeltaEvent {
+    pub seq: u64,
+    pub delta: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct ReasoningEvent {
+    pub seq: u64,
+    pub text: String,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub struct ToolCallEvent {
     pub seq: u64,
@@ -298,6 +751,15 @@ pub struct ToolCallEvent {
     pub input: Value,
 }
 
+/// Asks the client to answer with an approval op whose `id` is `call_id`.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct ApprovalRequestEvent {
+    pub seq: u64,
+    pub call_id: String,
+    pub tool_name: String,
+    pub input: Value,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub struct ToolResultEvent {
     pub seq: u64,
@@ -314,6 +776,42 @@ pub struct ToolErrorEvent {
     pub tool_name: String,
     pub error: String,
     pub duration_ms: u64,
+    /// The tool daemon could not be reached; the tool itself never ran.
+    #[serde(default)]
+    pub transport: bool,
+}
+
+/// Token accounting summed over every model round of a turn; rounds whose
+/// provider reported no usage count as zero.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct UsageEvent {
+    pub seq: u64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub total_tokens: u64,
+    #[serde(default)]
+    pub reasoning_tokens: Option<u64>,
+    pub rounds: u64,
+}
+
+/// Emitted when a turn's history is compacted; token counts are estimates
+/// of the context window just before and after.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct CompactionEvent {
+    pub seq: u64,
+    pub triggered_by: CompactionTrigger,
+    pub before_tokens: u64,
+    pub after_tokens: u64,
+    pub summary_chars: u64,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CompactionTrigger {
+    Manual,
+    Auto,
+    /// The provider rejected the request as too long for the model.
+    ContextLengthExceeded,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
@@ -334,6 +832,8 @@ pub enum TurnAbortReason {
     UserInterrupt,
     TaskReplaced,
     Shutdown,
+    ApprovalAborted,
+    Timeout,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
@@ -382,10 +882,69 @@ mod tests {
         assert_eq!(decoded, submission);
     }
 
+    #[test]
+    fn interrupt_target_is_optional() {
+        let untargeted: Op = serde_json::from_str(r#"{"type":"interrupt"}"#).expect("parse");
+        assert_eq!(untargeted, Op::Interrupt { target: None });
+
+        let targeted = Op::Interrupt {
+            target: Some("sub-2".to_string()),
+        };
+        let json = serde_json::to_string(&targeted).expect("serialize interrupt");
+        assert_eq!(json, r#"{"type":"interrupt","target":"sub-2"}"#);
+    }
+
+    #[test]
+    fn responses_options_override_defaults_field_by_field() {
+        let defaults = ResponsesRequestOptions {
+            reasoning: Some(ResponsesReasoningOptions {
+                effort: Some("high".to_string()),
+                summary: Some("concise".to_string()),
+                ..ResponsesReasoningOptions::default()
+            }),
+            text: Some(ResponsesTextOptions {
+                verbosity: Some("low".to_string()),
+                ..ResponsesTextOptions::default()
+            }),
+            include: vec!["reasoning.encrypted_content".to_string()],
+            store: Some(false),
+            temperature: Some(0.2),
+            ..ResponsesRequestOptions::default()
+        };
+        let turn = ResponsesRequestOptions {
+            reasoning: Some(ResponsesReasoningOptions {
+                effort: Some("low".to_string()),
+                ..ResponsesReasoningOptions::default()
+            }),
+            store: Some(true),
+            max_output_tokens: Some(512),
+            ..ResponsesRequestOptions::default()
+        };
+
+        let merged = turn.with_defaults(&defaults);
+
+        let reasoning = merged.reasoning.expect("reasoning");
+        assert_eq!(reasoning.effort.as_deref(), Some("low"));
+        assert_eq!(reasoning.summary.as_deref(), Some("concise"));
+        assert_eq!(
+            merged.text.and_then(|text| text.verbosity).as_deref(),
+            Some("low")
+        );
+        assert_eq!(
+            merged.include,
+            vec!["reasoning.encrypted_content".to_string()]
+        );
+        assert_eq!(merged.store, Some(true));
+        assert_eq!(merged.temperature, Some(0.2));
+        assert_eq!(merged.max_output_tokens, Some(512));
+        assert_eq!(merged.tool_choice, None);
+    }
+
     #[test]
     fn event_roundtrip_uses_tagged_variant() {
         let event = Event {
             id: "sub-1".to_string(),
+            seq: 1,
             msg: EventMsg::TurnAborted(TurnAbortedEvent {
                 reason: TurnAbortReason::UserInterrupt,
             }),
@@ -397,4 +956,42 @@ mod tests {
         let decoded: Event = serde_json::from_str(&json).expect("deserialize event");
         assert_eq!(decoded, event);
     }
+
+    #[test]
+    fn builder_reasoning_effort_keeps_other_responses_options() {
+        let options = UserTurnOptions::builder()
+            .responses(ResponsesRequestOptions {
+                store: Some(true),
+                ..ResponsesRequestOptions::default()
+            })
+            .reasoning_effort("low")
+            .tool(ToolSpec {
+                name: "file.read".to_string(),
+                description: None,
+                input_schema: None,
+                approval: None,
+            })
+            .build();
+
+        assert_eq!(
+            options,
+            UserTurnOptions {
+                tools: vec![ToolSpec {
+                    name: "file.read".to_string(),
+                    description: None,
+                    input_schema: None,
+                    approval: None,
+                }],
+                responses: Some(ResponsesRequestOptions {
+                    reasoning: Some(ResponsesReasoningOptions {
+                        effort: Some("low".to_string()),
+                        ..ResponsesReasoningOptions::default()
+                    }),
+                    store: Some(true),
+                    ..ResponsesRequestOptions::default()
+                }),
+                ..UserTurnOptions::default()
+            }
+        );
+    }
 }